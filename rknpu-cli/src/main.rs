@@ -1,10 +1,7 @@
 use std::time::Instant;
 
 use anyhow::Result;
-use rknpu::{
-    context::{inputs::RknnInput, RknnContext},
-    flags::RknnExtendedFlag,
-};
+use rknpu_runtime::backend;
 
 use crate::utils::yolo::{
     iou, load_image, process_result, render_detections, Anchor, YoloDetection,
@@ -19,41 +16,19 @@ fn main() -> Result<()> {
     let min_confidence = args[3].parse::<f32>()?;
     let max_iou = args[4].parse::<f32>()?;
 
+    // The backend is picked from the model file extension, `.onnx` models run on the CPU.
     let loading_start = Instant::now();
-    let mut rknn_runtime =
-        RknnContext::from_model_path(model_path, RknnExtendedFlag::RKNN_FLAG_PRIOR_HIGH)?;
+    let mut backend = backend::load(model_path)?;
     let loading_duration = loading_start.elapsed();
 
-    dbg!(&loading_duration);
-    let input_attribute = rknn_runtime.input_attribute(0)?;
-    dbg!(&input_attribute);
-
-    for out_idx in 0..rknn_runtime.num_input_outputs()?.n_output {
-        let out_attribute = rknn_runtime.output_attribute(out_idx)?;
-        dbg!(&out_attribute.dims);
-    }
-
-    let input_quant_fmt = input_attribute.quant_type;
-    let input_raw_data = load_image(image_path, input_quant_fmt)?;
-    let input = RknnInput {
-        index: 0,
-        buffer: input_raw_data,
-        pass_through: true,
-        dtype: input_attribute.data_type,
-        fmt: input_attribute.format,
-    };
-
-    let start_set_input = Instant::now();
-    rknn_runtime.set_inputs(vec![input])?;
-    dbg!(start_set_input.elapsed());
-
-    let start_run = Instant::now();
-    rknn_runtime.run()?;
-    dbg!(start_run.elapsed());
-
-    let outputs = rknn_runtime.get_outputs()?;
-    let img_w = input_attribute.dims[1];
-    let img_h = input_attribute.dims[2];
+    println!("Backend: {}", backend.kind());
+    let input = backend.inputs()[0].clone();
+    let image = load_image(image_path)?;
+    let img_w = image.shape()[3];
+    let img_h = image.shape()[2];
+    let run_start = Instant::now();
+    let outputs = backend.run(vec![(input.name.as_str(), image)])?;
+    println!("Inference: {:?}", run_start.elapsed());
 
     // 10, 13, 16, 30, 33, 23
     let anchors0 = vec![
@@ -103,28 +78,15 @@ fn main() -> Result<()> {
 
     let outputs_anchors = vec![(anchors0, 80_usize), (anchors1, 40), (anchors2, 20)];
     let detections = outputs
+        .into_tensors()
         .into_iter()
-        .zip(outputs_anchors.into_iter())
-        .enumerate()
-        .flat_map(|(idx, (out, (anchors, grid)))| {
-            let (zp, scale) = rknn_runtime
-                .output_attribute(idx as u32)
-                .unwrap()
-                .quant_type
-                .zp_scale()
-                .unwrap();
-            process_result(
-                out.buffer,
-                img_w as usize,
-                img_h as usize,
-                Some(anchors),
-                grid,
-                grid,
-                zp,
-                scale,
-            )
-            .unwrap()
+        .zip(outputs_anchors)
+        .map(|(out, (anchors, grid))| {
+            process_result(out.into_vec()?, img_w, img_h, Some(anchors), grid, grid)
         })
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .flatten()
         .collect::<Vec<YoloDetection>>();
 
     let most_probable_detections = detections
@@ -152,7 +114,13 @@ fn main() -> Result<()> {
         "Number of detections adter max_iou filtering: {}",
         &non_duplicated_detections.len()
     );
-    render_detections(&image_path, &non_duplicated_detections, "out.jpg")?;
+    for detection in &non_duplicated_detections {
+        println!(
+            "Class {} with confidence {:.2}",
+            detection.class_index, detection.confidence
+        );
+    }
+    render_detections(image_path, &non_duplicated_detections, "out.jpg")?;
     println!("Loading: {loading_duration:?}");
     Ok(())
 }
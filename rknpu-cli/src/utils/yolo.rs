@@ -5,46 +5,18 @@ use image::Rgb;
 use imageproc::drawing::draw_hollow_rect_mut;
 use imageproc::rect::Rect;
 use ndarray::{Array3, Array4, Axis};
-use rknpu::tensors::types::RknnTensorQuantFormat;
+use rknpu_runtime::Tensor;
 
-pub fn quantize_value(x: f32, zp: i32, scale: f32) -> u8 {
-    (((x / scale).round() as i32) + zp).clamp(u8::min_value() as i32, u8::max_value() as i32) as u8
-}
-
-pub fn dequantize_value(x: u8, zp: i32, scale: f32) -> f32 {
-    (x as i32 - zp) as f32 * scale
-}
-
-pub fn quantize_data(data: Array4<f32>, quant_fmt: RknnTensorQuantFormat) -> Result<Array4<u8>> {
-    match quant_fmt {
-        RknnTensorQuantFormat::None => bail!("Quantization expected but quant format is None."),
-        RknnTensorQuantFormat::AffineScale(zp, scale) => {
-            let mut qdata = Array4::from_elem(data.raw_dim(), 0u8);
-            qdata
-                .as_slice_mut()
-                .unwrap()
-                .into_iter()
-                .zip(data.as_slice().unwrap().into_iter())
-                .for_each(|(qx, x)| *qx = quantize_value(*x, zp, scale));
-            Ok(qdata)
-        }
-        RknnTensorQuantFormat::DynamicFixedPoint(_) => unimplemented!(),
-    }
-}
-
-pub fn load_image<P: AsRef<Path>>(
-    image_path: P,
-    quant_fmt: RknnTensorQuantFormat,
-) -> Result<Vec<u8>> {
+/// Load an image as a `[1, 3, 640, 640]` tensor of values in `[0, 1]`, quantized by the session.
+pub fn load_image<P: AsRef<Path>>(image_path: P) -> Result<Tensor> {
     // Input pre-processing
-    let image = image::open(image_path).unwrap().to_rgb8();
+    let image = image::open(image_path)?.to_rgb8();
     let resized =
         image::imageops::resize(&image, 640, 640, ::image::imageops::FilterType::Triangle);
-    let image = ndarray::Array4::from_shape_fn((1, 3, 640, 640), |(_, c, y, x)| {
+    let image = Array4::from_shape_fn((1, 3, 640, 640), |(_, c, y, x)| {
         resized[(x as _, y as _)][c] as f32 / 255.0
     });
-    let quant_image = quantize_data(image, quant_fmt)?;
-    Ok(quant_image.into_raw_vec())
+    Tensor::new(image.shape().to_vec(), image.into_raw_vec())
 }
 
 const OBJ_CLASS_NUM: usize = 80;
@@ -68,41 +40,35 @@ impl YoloDetection {
     }
 
     // Apply adjustment is not systematic as it can be applied in the model directly
-    fn from_raw_u8(
-        raw: &[u8],
+    fn from_raw(
+        raw: &[f32],
         anchor: Option<&Anchor>,
         img_w: f32,
         img_h: f32,
-        zp: i32,
-        scale: f32,
         apply_adjustment: bool,
     ) -> Result<Self> {
-        let ([cx, cy, w, h, conf], classes_confidences) = raw.split_at(5) else {
+        let ([box_x, box_y, box_w, box_h, conf], classes_confidences) = raw.split_at(5) else {
             bail!("Unexpected number of elements in detection output")
         };
-        let box_x = dequantize_value(*cx, zp, scale);
         let box_x = if apply_adjustment {
-            Self::grid_sensitivity_adjustment_pos(box_x)
+            Self::grid_sensitivity_adjustment_pos(*box_x)
         } else {
-            box_x
+            *box_x
         };
-        let box_y = dequantize_value(*cy, zp, scale);
         let box_y = if apply_adjustment {
-            Self::grid_sensitivity_adjustment_pos(box_y)
+            Self::grid_sensitivity_adjustment_pos(*box_y)
         } else {
-            box_y
+            *box_y
         };
-        let box_w = dequantize_value(*w, zp, scale);
         let box_w = if let Some(anchor) = anchor {
-            Self::grid_sensitivity_adjustment_size(box_w, anchor.width)
+            Self::grid_sensitivity_adjustment_size(*box_w, anchor.width)
         } else {
-            box_w
+            *box_w
         };
-        let box_h = dequantize_value(*h, zp, scale);
         let box_h = if let Some(anchor) = anchor {
-            Self::grid_sensitivity_adjustment_size(box_h, anchor.height)
+            Self::grid_sensitivity_adjustment_size(*box_h, anchor.height)
         } else {
-            box_h
+            *box_h
         };
         Ok(YoloDetection {
             x: (box_x - box_w / 2.0) / img_w,
@@ -112,10 +78,10 @@ impl YoloDetection {
             class_index: classes_confidences
                 .iter()
                 .enumerate()
-                .max()
+                .max_by(|a, b| a.1.total_cmp(b.1))
                 .map(|(idx, _)| idx)
                 .unwrap(),
-            confidence: dequantize_value(*conf, zp, scale),
+            confidence: *conf,
         })
     }
 
@@ -149,14 +115,12 @@ pub struct Anchor {
 // - 255: anchor infos [....]
 // - (80, 80): Output grid resolution (it means 6400 hypotheses)
 pub fn process_result(
-    out_buffer: Vec<u8>,
+    output: Vec<f32>,
     img_w: usize,
     img_h: usize,
     anchors: Option<Vec<Anchor>>,
     grid_x: usize,
     grid_y: usize,
-    zp: i32,
-    scale: f32,
 ) -> Result<Vec<YoloDetection>> {
     let n_anchors = anchors.as_ref().map_or_else(
         || Ok(1),
@@ -169,10 +133,9 @@ pub fn process_result(
             Ok(n_anchors)
         },
     )?;
-    let output =
-        Array3::from_shape_vec((1, PROP_BOX_SIZE * n_anchors, grid_x * grid_y), out_buffer)?;
+    let output = Array3::from_shape_vec((1, PROP_BOX_SIZE * n_anchors, grid_x * grid_y), output)?;
     // We iterate other each grid point
-    Ok(output
+    output
         .axis_iter(Axis(2))
         .flat_map(|anchors_data| -> Vec<_> {
             anchors_data
@@ -182,34 +145,31 @@ pub fn process_result(
                 .chunks(PROP_BOX_SIZE)
                 .enumerate()
                 .map(|(idx, it)| {
-                    YoloDetection::from_raw_u8(
+                    YoloDetection::from_raw(
                         it,
                         anchors.as_ref().map(|it| &it[idx]),
                         img_w as f32,
                         img_h as f32,
-                        zp,
-                        scale,
                         true,
                     )
                 })
                 .collect()
         })
-        .collect::<Result<Vec<_>>>()?)
+        .collect::<Result<Vec<_>>>()
 }
 
 pub fn render_detections(
     image_path: &str,
-    detections: &Vec<YoloDetection>,
+    detections: &[YoloDetection],
     output_path: &str,
 ) -> Result<()> {
-    let image = image::open(image_path).unwrap();
+    let image = image::open(image_path)?;
     let mut image = image.to_rgb8();
     for detection in detections.iter() {
         let x = (detection.x) * image.width() as f32;
         let y = (detection.y) * image.height() as f32;
         let width = (detection.width) * image.width() as f32;
         let height = (detection.height) * image.height() as f32;
-        dbg!(&detection.class_index);
         draw_hollow_rect_mut(
             &mut image,
             Rect::at(x as i32, y as i32).of_size(width as u32, height as u32),
@@ -217,7 +177,7 @@ pub fn render_detections(
         );
     }
 
-    image.save(output_path).unwrap();
+    image.save(output_path)?;
 
     Ok(())
 }
@@ -0,0 +1,35 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use rknpu::{context::RknnContext, flags::RknnExtendedFlag};
+use rknpu_ipc::DaemonBuilder;
+
+/// Usage: `rknpu-daemon <socket> <contexts> <model>...`, every model being loaded in `contexts`
+/// runtime contexts, and given as `name=path` or as a path, served under its file name then.
+fn main() -> Result<()> {
+    let args: Vec<_> = std::env::args().collect();
+    let usage = || anyhow!("Usage: rknpu-daemon <socket> <contexts> <model>...");
+    let socket = args.get(1).ok_or_else(usage)?;
+    let contexts = args.get(2).ok_or_else(usage)?.parse::<usize>()?;
+
+    let mut builder = DaemonBuilder::new();
+    for model in &args[3..] {
+        let (name, path) = match model.split_once('=') {
+            Some((name, path)) => (name.to_string(), path),
+            None => {
+                let name = Path::new(model).file_stem().unwrap_or_default();
+                (name.to_string_lossy().into_owned(), model.as_str())
+            }
+        };
+        let pool = (0..contexts)
+            .map(|_| RknnContext::from_model_path(path, RknnExtendedFlag::RKNN_FLAG_PRIOR_HIGH))
+            .collect::<Result<Vec<_>>>()?;
+        println!("Model '{name}': {path} in {contexts} contexts");
+        builder = builder.with_pool(name, pool);
+    }
+
+    let daemon = builder.bind(socket)?;
+    println!("Listening on {}", daemon.path().display());
+    daemon.join();
+    Ok(())
+}
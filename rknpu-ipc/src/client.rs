@@ -0,0 +1,261 @@
+use std::{
+    collections::HashMap,
+    net::Shutdown,
+    os::{
+        fd::{BorrowedFd, OwnedFd},
+        unix::net::UnixStream,
+    },
+    path::Path,
+};
+
+use anyhow::Result;
+use rknpu::{
+    context::{
+        info::ModelInfo,
+        inputs::{validate_inputs, RknnInput},
+        outputs::RknnOuput,
+    },
+    error::RknnTensorLookupError,
+    tensors::attributes::RknnTensorAttribute,
+};
+
+use crate::{
+    error::IpcError,
+    protocol::{self, InputHeader, Reply, Request},
+    shm::SharedBuffer,
+};
+
+/// A model served by a [`Daemon`](crate::Daemon), used as an [`RknnContext`] would be.
+///
+/// Inputs are written to buffers shared with the daemon, from which the runtime reads them, and
+/// the runtime writes the outputs to buffers shared with the client, allocated once for the
+/// connection. Tensors are never sent through the socket. [`set_inputs`](Self::set_inputs) and
+/// [`get_outputs`](Self::get_outputs) copy, as they take and return owned buffers; to avoid
+/// these copies, write the inputs in place with [`input_buffer_mut`](Self::input_buffer_mut) and
+/// read the outputs in place with [`output_buffer`](Self::output_buffer).
+///
+/// A client runs one request at a time; open several clients to run in parallel on the contexts
+/// of the daemon.
+///
+/// [`RknnContext`]: rknpu::context::RknnContext
+pub struct RknnClient {
+    stream: UnixStream,
+    info: ModelInfo,
+    /// Inputs set for the following runs.
+    inputs: Vec<(InputHeader, SharedBuffer)>,
+    /// Output buffers, written by every run.
+    outputs: Vec<SharedBuffer>,
+    /// Whether the output buffers hold the outputs of a run.
+    ran: bool,
+}
+
+impl RknnClient {
+    /// Connect to the daemon listening on `socket` and open its model named `model`.
+    pub fn connect<P: AsRef<Path>>(socket: P, model: &str) -> Result<Self> {
+        let stream = UnixStream::connect(socket)?;
+        let request = Request::Open {
+            model: model.to_string(),
+        };
+        let (info, outputs) = match request_reply(&stream, &request, &[])? {
+            (Reply::Opened { info, outputs }, fds) => (info, map_buffers(outputs, fds)?),
+            _ => return Err(IpcError::UnexpectedReply.into()),
+        };
+        Ok(Self {
+            stream,
+            info,
+            inputs: vec![],
+            outputs,
+            ran: false,
+        })
+    }
+
+    /// Model information queried by the daemon when it loaded the model.
+    pub fn model_info(&self) -> &ModelInfo {
+        &self.info
+    }
+
+    pub fn input_attribute(&self, index: u32) -> Result<RknnTensorAttribute> {
+        let attribute = self.info.inputs.get(index as usize).ok_or(
+            RknnTensorLookupError::InputIndexOutOfRange {
+                index,
+                n_input: self.info.inputs.len(),
+            },
+        )?;
+        Ok(attribute.clone())
+    }
+
+    pub fn output_attribute(&self, index: u32) -> Result<RknnTensorAttribute> {
+        let attribute = self.info.outputs.get(index as usize).ok_or(
+            RknnTensorLookupError::OutputIndexOutOfRange {
+                index,
+                n_output: self.info.outputs.len(),
+            },
+        )?;
+        Ok(attribute.clone())
+    }
+
+    /// Index of the model input named `name`.
+    pub fn input_index(&self, name: &str) -> Result<u32> {
+        let index = self.info.inputs.iter().position(|it| it.name == name);
+        let index = index.ok_or_else(|| RknnTensorLookupError::UnknownInput {
+            name: name.to_string(),
+        })?;
+        Ok(index as u32)
+    }
+
+    /// Index of the model output named `name`.
+    pub fn output_index(&self, name: &str) -> Result<u32> {
+        let index = self.info.outputs.iter().position(|it| it.name == name);
+        let index = index.ok_or_else(|| RknnTensorLookupError::UnknownOutput {
+            name: name.to_string(),
+        })?;
+        Ok(index as u32)
+    }
+
+    /// Set the model inputs of the following runs. Inputs are validated against the model input
+    /// attributes, as by [`RknnContext::set_inputs`](rknpu::context::RknnContext::set_inputs),
+    /// then copied to shared buffers.
+    pub fn set_inputs(&mut self, inputs: Vec<RknnInput>) -> Result<()> {
+        validate_inputs(&inputs, &self.info.inputs)?;
+        // Buffers of the previous inputs are reused when of the same length.
+        let mut previous = std::mem::take(&mut self.inputs)
+            .into_iter()
+            .map(|(_, buffer)| buffer)
+            .collect::<Vec<_>>();
+        for input in inputs {
+            let len = input.buffer.len();
+            let mut buffer = match previous.iter().position(|it| it.len() == len) {
+                Some(index) => previous.swap_remove(index),
+                None => SharedBuffer::new(len)?,
+            };
+            buffer.as_mut_slice().copy_from_slice(&input.buffer);
+            let header = InputHeader {
+                index: input.index,
+                len,
+                pass_through: input.pass_through,
+                dtype: input.dtype,
+                fmt: input.fmt,
+            };
+            self.inputs.push((header, buffer));
+        }
+        Ok(())
+    }
+
+    /// Set the model inputs, addressed by tensor name. The `index` of each [`RknnInput`] is
+    /// replaced by the index of the named input.
+    pub fn set_inputs_by_name<'a, I>(&mut self, inputs: I) -> Result<()>
+    where
+        I: IntoIterator<Item = (&'a str, RknnInput)>,
+    {
+        let inputs = inputs
+            .into_iter()
+            .map(|(name, input)| {
+                Ok(RknnInput {
+                    index: self.input_index(name)?,
+                    ..input
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        self.set_inputs(inputs)
+    }
+
+    /// Buffer of the input `index` last set, shared with the daemon. The following inputs of the
+    /// same type and size can be written in place before calling [`run`](Self::run) again,
+    /// without copy.
+    pub fn input_buffer_mut(&mut self, index: u32) -> Option<&mut [u8]> {
+        self.inputs
+            .iter_mut()
+            .find(|(header, _)| header.index == index)
+            .map(|(_, buffer)| buffer.as_mut_slice())
+    }
+
+    /// Run the model on the daemon, on the inputs last set.
+    pub fn run(&mut self) -> Result<()> {
+        self.ran = false;
+        let request = Request::Run {
+            inputs: self.inputs.iter().map(|(it, _)| it.clone()).collect(),
+        };
+        let fds = self
+            .inputs
+            .iter()
+            .map(|(_, it)| it.fd())
+            .collect::<Vec<_>>();
+        match request_reply(&self.stream, &request, &fds)? {
+            (Reply::Ran, _) => self.ran = true,
+            _ => return Err(IpcError::UnexpectedReply.into()),
+        }
+        Ok(())
+    }
+
+    /// Output `index` of the last run, read in place from the buffer shared with the daemon. The
+    /// buffer is overwritten by the following runs.
+    pub fn output_buffer(&self, index: u32) -> Option<&[u8]> {
+        match self.ran {
+            true => self.outputs.get(index as usize).map(SharedBuffer::as_slice),
+            false => None,
+        }
+    }
+
+    /// Get the model outputs, keyed by tensor name.
+    pub fn get_outputs_by_name(&mut self) -> Result<HashMap<String, RknnOuput>> {
+        let outputs = self.get_outputs()?;
+        let outputs = self
+            .info
+            .outputs
+            .iter()
+            .map(|it| it.name.clone())
+            .zip(outputs)
+            .collect();
+        Ok(outputs)
+    }
+
+    /// Get the outputs of the last run, copied out of the shared buffers.
+    pub fn get_outputs(&mut self) -> Result<Vec<RknnOuput>> {
+        if !self.ran {
+            return Err(IpcError::NoOutputs.into());
+        }
+        let outputs = self
+            .outputs
+            .iter()
+            .enumerate()
+            .map(|(index, it)| RknnOuput::new(index as u32, it.as_slice().to_vec()))
+            .collect();
+        Ok(outputs)
+    }
+
+    /// Disconnect from the daemon. Dropping the client disconnects it too.
+    pub fn close(self) -> Result<()> {
+        self.stream.shutdown(Shutdown::Both)?;
+        Ok(())
+    }
+}
+
+/// Send a request and receive its reply, turning the errors answered by the daemon into
+/// [`IpcError::Daemon`].
+fn request_reply(
+    stream: &UnixStream,
+    request: &Request,
+    fds: &[BorrowedFd],
+) -> Result<(Reply, Vec<OwnedFd>)> {
+    protocol::send(stream, request, fds)?;
+    match protocol::recv::<Reply>(stream)? {
+        Some((Reply::Error { message }, _)) => Err(IpcError::Daemon(message).into()),
+        Some(reply) => Ok(reply),
+        None => Err(IpcError::Closed.into()),
+    }
+}
+
+/// Map the buffers of the given lengths received from the daemon.
+fn map_buffers(lens: Vec<usize>, fds: Vec<OwnedFd>) -> Result<Vec<SharedBuffer>> {
+    if fds.len() != lens.len() {
+        return Err(IpcError::BufferCount {
+            expected: lens.len(),
+            actual: fds.len(),
+        }
+        .into());
+    }
+    lens.into_iter()
+        .zip(fds)
+        .map(|(len, fd)| SharedBuffer::from_fd(fd, len))
+        .collect()
+}
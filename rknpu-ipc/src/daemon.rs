@@ -0,0 +1,309 @@
+use std::{
+    collections::HashMap,
+    io::ErrorKind,
+    net::Shutdown,
+    os::{
+        fd::OwnedFd,
+        unix::{
+            fs::FileTypeExt,
+            net::{UnixListener, UnixStream},
+        },
+    },
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+use anyhow::Result;
+use rknpu::context::{info::ModelInfo, inputs::RknnInput, RknnContext};
+
+use crate::{
+    error::IpcError,
+    protocol::{self, Reply, Request},
+    shm::SharedBuffer,
+};
+
+/// Contexts of a model shared by the clients: each run takes an idle context, waiting for one if
+/// they are all busy.
+struct ContextPool {
+    info: ModelInfo,
+    /// Length in bytes of the outputs.
+    output_lens: Vec<usize>,
+    idle: Mutex<Vec<RknnContext>>,
+    released: Condvar,
+}
+
+impl ContextPool {
+    /// Create a pool of contexts of the same model, `None` without context.
+    fn new(contexts: Vec<RknnContext>) -> Result<Option<Self>> {
+        let Some(first) = contexts.first() else {
+            return Ok(None);
+        };
+        let info = first.model_info().clone();
+        let output_lens = (0..info.outputs.len() as u32)
+            .map(|index| first.output_len(index))
+            .collect::<Result<_>>()?;
+        Ok(Some(Self {
+            info,
+            output_lens,
+            idle: Mutex::new(contexts),
+            released: Condvar::new(),
+        }))
+    }
+
+    /// Set the inputs, run the model and get the outputs on an idle context. The runtime reads
+    /// the inputs from and writes the outputs to the shared buffers.
+    fn infer(
+        &self,
+        inputs: &[RknnInput<SharedBuffer>],
+        outputs: &mut [SharedBuffer],
+    ) -> Result<()> {
+        let mut ctx = {
+            let mut idle = self.idle.lock().unwrap();
+            loop {
+                match idle.pop() {
+                    Some(ctx) => break ctx,
+                    None => idle = self.released.wait(idle).unwrap(),
+                }
+            }
+        };
+        let result = (|| {
+            ctx.set_input_buffers(inputs)?;
+            ctx.run()?;
+            let mut outputs = outputs
+                .iter_mut()
+                .map(SharedBuffer::as_mut_slice)
+                .collect::<Vec<_>>();
+            ctx.get_outputs_into(&mut outputs)
+        })();
+        self.idle.lock().unwrap().push(ctx);
+        self.released.notify_one();
+        result
+    }
+}
+
+/// Configures and starts a [`Daemon`].
+#[derive(Default)]
+pub struct DaemonBuilder {
+    models: Vec<(String, Vec<RknnContext>)>,
+}
+
+impl DaemonBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serve a model under the given name, running one request at a time.
+    pub fn with_model(self, name: impl Into<String>, ctx: RknnContext) -> Self {
+        self.with_pool(name, vec![ctx])
+    }
+
+    /// Serve a model under the given name, running as many requests at a time as there are
+    /// contexts. The contexts must be of the same model.
+    pub fn with_pool(mut self, name: impl Into<String>, contexts: Vec<RknnContext>) -> Self {
+        self.models.push((name.into(), contexts));
+        self
+    }
+
+    /// Listen on a socket at the given path and start the threads of the daemon. A socket file
+    /// left by a daemon that is gone is replaced.
+    pub fn bind(self, path: impl AsRef<Path>) -> Result<Daemon> {
+        let models = self
+            .models
+            .into_iter()
+            .map(|(name, contexts)| match ContextPool::new(contexts)? {
+                Some(pool) => Ok((name, pool)),
+                None => Err(IpcError::NoContext(name).into()),
+            })
+            .collect::<Result<HashMap<_, _>>>()?;
+
+        let path = path.as_ref().to_path_buf();
+        if let Err(error) = UnixStream::connect(&path) {
+            // Connecting to any other kind of file is refused too, only sockets are removed.
+            if error.kind() == ErrorKind::ConnectionRefused {
+                if !std::fs::symlink_metadata(&path)?.file_type().is_socket() {
+                    return Err(IpcError::NotASocket(path).into());
+                }
+                std::fs::remove_file(&path)?;
+            }
+        }
+        let listener = UnixListener::bind(&path)?;
+        let shared = Arc::new(Shared {
+            models,
+            stopped: AtomicBool::new(false),
+            clients: Mutex::new(vec![]),
+        });
+        let accept = {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name("rknpu-daemon".to_string())
+                .spawn(move || shared.accept(listener))?
+        };
+        Ok(Daemon {
+            path,
+            shared,
+            accept: Some(accept),
+        })
+    }
+}
+
+/// Serves models to the processes of the board over a Unix socket, so that they share the
+/// contexts loaded once by the daemon. See the [crate](crate) documentation.
+///
+/// Dropping the daemon disconnects its clients, once done with their current run, and removes
+/// the socket file.
+pub struct Daemon {
+    path: PathBuf,
+    shared: Arc<Shared>,
+    accept: Option<JoinHandle<()>>,
+}
+
+impl Daemon {
+    /// Path of the socket the daemon listens on.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Serve until the process ends.
+    pub fn join(mut self) {
+        if let Some(accept) = self.accept.take() {
+            let _ = accept.join();
+        }
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        self.shared.stopped.store(true, Ordering::SeqCst);
+        if let Some(accept) = self.accept.take() {
+            // Wake the accepting thread up.
+            let _ = UnixStream::connect(&self.path);
+            let _ = accept.join();
+        }
+        let clients = std::mem::take(&mut *self.shared.clients.lock().unwrap());
+        for (stream, _) in &clients {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+        for (_, client) in clients {
+            let _ = client.join();
+        }
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// State shared by the threads of the daemon.
+struct Shared {
+    models: HashMap<String, ContextPool>,
+    stopped: AtomicBool,
+    /// Connections of the clients, to close them when stopping, and their threads.
+    clients: Mutex<Vec<(UnixStream, JoinHandle<()>)>>,
+}
+
+impl Shared {
+    fn accept(self: Arc<Self>, listener: UnixListener) {
+        for stream in listener.incoming() {
+            if self.stopped.load(Ordering::SeqCst) {
+                return;
+            }
+            let Ok(stream) = stream else { continue };
+            let Ok(handle) = stream.try_clone() else {
+                continue;
+            };
+            let shared = self.clone();
+            let client = thread::Builder::new()
+                .name("rknpu-daemon-client".to_string())
+                .spawn(move || shared.serve(stream));
+            if let Ok(client) = client {
+                let mut clients = self.clients.lock().unwrap();
+                clients.retain(|(_, client)| !client.is_finished());
+                clients.push((handle, client));
+            }
+        }
+    }
+
+    /// Answer the requests of a client until it disconnects.
+    fn serve(&self, stream: UnixStream) {
+        let mut connection = None;
+        loop {
+            let (request, fds) = match protocol::recv::<Request>(&stream) {
+                Ok(Some(request)) => request,
+                // The client is gone or doesn't speak the protocol.
+                Ok(None) | Err(_) => return,
+            };
+            let reply = self
+                .handle(&mut connection, request, fds)
+                .unwrap_or_else(|error| Reply::Error {
+                    message: format!("{error:#}"),
+                });
+            // The output buffers are passed once, when the model is opened.
+            let fds = match (&reply, &connection) {
+                (Reply::Opened { .. }, Some(connection)) => {
+                    connection.outputs.iter().map(SharedBuffer::fd).collect()
+                }
+                _ => vec![],
+            };
+            if protocol::send(&stream, &reply, &fds).is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Answer a request of a client, whose connection is set once it opened a model.
+    fn handle<'a>(
+        &'a self,
+        connection: &mut Option<Connection<'a>>,
+        request: Request,
+        fds: Vec<OwnedFd>,
+    ) -> Result<Reply> {
+        match request {
+            Request::Open { model: name } => {
+                let pool = self.models.get(&name).ok_or(IpcError::UnknownModel(name))?;
+                let outputs = pool
+                    .output_lens
+                    .iter()
+                    .map(|len| SharedBuffer::new(*len))
+                    .collect::<Result<_>>()?;
+                *connection = Some(Connection { pool, outputs });
+                Ok(Reply::Opened {
+                    info: pool.info.clone(),
+                    outputs: pool.output_lens.clone(),
+                })
+            }
+            Request::Run { inputs } => {
+                let connection = connection.as_mut().ok_or(IpcError::NotOpened)?;
+                if fds.len() != inputs.len() {
+                    return Err(IpcError::BufferCount {
+                        expected: inputs.len(),
+                        actual: fds.len(),
+                    }
+                    .into());
+                }
+                let inputs = inputs
+                    .into_iter()
+                    .zip(fds)
+                    .map(|(input, fd)| {
+                        Ok(RknnInput {
+                            index: input.index,
+                            buffer: SharedBuffer::from_fd(fd, input.len)?,
+                            pass_through: input.pass_through,
+                            dtype: input.dtype,
+                            fmt: input.fmt,
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                connection.pool.infer(&inputs, &mut connection.outputs)?;
+                Ok(Reply::Ran)
+            }
+        }
+    }
+}
+
+/// A client bound to a model.
+struct Connection<'a> {
+    pool: &'a ContextPool,
+    /// Buffers shared with the client, written by every run.
+    outputs: Vec<SharedBuffer>,
+}
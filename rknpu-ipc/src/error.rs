@@ -0,0 +1,35 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+use crate::protocol::{MAX_MESSAGE, MAX_TENSORS};
+
+/// Errors of the daemon and its clients.
+#[derive(Debug, Error, PartialEq)]
+pub enum IpcError {
+    #[error("Unknown model '{0}'.")]
+    UnknownModel(String),
+    #[error("Model '{0}' has no context.")]
+    NoContext(String),
+    #[error("'{0}' exists and is not a socket.")]
+    NotASocket(PathBuf),
+    #[error("The connection is not bound to a model.")]
+    NotOpened,
+    #[error("Expected {expected} tensor buffers, got {actual}.")]
+    BufferCount { expected: usize, actual: usize },
+    #[error("Tensor buffer of {len} bytes is not a sealed memory file of at least that size.")]
+    InvalidBuffer { len: usize },
+    #[error("At most {MAX_TENSORS} tensors can be passed at once, got {0}.")]
+    TooManyTensors(usize),
+    #[error("Message of {0} bytes is larger than {MAX_MESSAGE} bytes.")]
+    MessageTooLarge(usize),
+    #[error("Unexpected reply from the daemon.")]
+    UnexpectedReply,
+    #[error("The daemon closed the connection.")]
+    Closed,
+    #[error("No outputs, the model was not run.")]
+    NoOutputs,
+    /// An error raised by the daemon, such as an invalid input or a runtime failure.
+    #[error("{0}")]
+    Daemon(String),
+}
@@ -0,0 +1,25 @@
+//! Sharing of RKNN models between the processes of a board.
+//!
+//! Processes loading the same model each take their share of NPU memory. Instead, a [`Daemon`]
+//! owns the contexts of the models and serves them over a Unix socket to [`RknnClient`]s, which
+//! mirror the [`RknnContext`] API so that code using a context can switch with few changes.
+//!
+//! Input and output tensors are exchanged in shared memory files (memfd), whose file
+//! descriptors are passed over the socket: only small JSON messages go through it. The runtime
+//! of the daemon reads the inputs from the buffers of the client and writes the outputs to
+//! buffers shared with the client, allocated once per connection, so the daemon doesn't copy
+//! tensors. The client copies the inputs and outputs given and returned as owned buffers, as
+//! the context API does, unless they are written and read in place.
+//!
+//! [`RknnContext`]: rknpu::context::RknnContext
+
+pub use self::{
+    client::RknnClient,
+    daemon::{Daemon, DaemonBuilder},
+};
+
+mod client;
+mod daemon;
+pub mod error;
+mod protocol;
+pub mod shm;
@@ -0,0 +1,168 @@
+//! Messages between the daemon and its clients.
+//!
+//! A message is a little endian `u32` length followed by that many bytes of JSON. The file
+//! descriptors of the tensor buffers of a message are passed along with its first bytes, as
+//! `SCM_RIGHTS` ancillary data: tensors never go through the socket.
+
+use std::{
+    io::{IoSlice, IoSliceMut, Read, Write},
+    os::{
+        fd::{AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+        unix::net::UnixStream,
+    },
+};
+
+use anyhow::Result;
+use nix::sys::socket::{recvmsg, sendmsg, ControlMessage, ControlMessageOwned, MsgFlags};
+use rknpu::{
+    context::info::ModelInfo,
+    tensors::types::{RknnTensorFormat, RknnTensorType},
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::error::IpcError;
+
+/// Most tensor buffers passed with a message.
+pub const MAX_TENSORS: usize = 64;
+
+/// Largest message, in bytes.
+pub const MAX_MESSAGE: usize = 1 << 24;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Request {
+    /// Bind the connection to a served model.
+    Open { model: String },
+    /// Run the model on inputs whose buffers are passed along, in the same order.
+    Run { inputs: Vec<InputHeader> },
+}
+
+/// An input of a run, all of [`RknnInput`](rknpu::context::inputs::RknnInput) but its buffer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InputHeader {
+    pub index: u32,
+    /// Length of the buffer, which may be larger.
+    pub len: usize,
+    pub pass_through: bool,
+    pub dtype: RknnTensorType,
+    pub fmt: RknnTensorFormat,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Reply {
+    /// The model is opened. Every run writes the outputs to the buffers of the connection, of the
+    /// given lengths, passed along in the order of the model outputs.
+    Opened {
+        info: ModelInfo,
+        outputs: Vec<usize>,
+    },
+    /// The outputs of the run were written.
+    Ran,
+    Error {
+        message: String,
+    },
+}
+
+/// Send a message, passing the file descriptors `fds` along.
+pub fn send<T: Serialize>(stream: &UnixStream, message: &T, fds: &[BorrowedFd]) -> Result<()> {
+    if fds.len() > MAX_TENSORS {
+        return Err(IpcError::TooManyTensors(fds.len()).into());
+    }
+    let body = serde_json::to_vec(message)?;
+    if body.len() > MAX_MESSAGE {
+        return Err(IpcError::MessageTooLarge(body.len()).into());
+    }
+    let mut message = (body.len() as u32).to_le_bytes().to_vec();
+    message.extend(body);
+
+    let fds = fds.iter().map(AsRawFd::as_raw_fd).collect::<Vec<_>>();
+    let rights = [ControlMessage::ScmRights(&fds)];
+    let cmsgs = match fds.is_empty() {
+        true => &[][..],
+        false => &rights[..],
+    };
+    let sent = sendmsg::<()>(
+        stream.as_raw_fd(),
+        &[IoSlice::new(&message)],
+        cmsgs,
+        MsgFlags::MSG_NOSIGNAL,
+        None,
+    )?;
+    // The file descriptors went with the first bytes, the rest of a large message follows.
+    let mut stream = stream;
+    stream.write_all(&message[sent..])?;
+    Ok(())
+}
+
+/// Receive a message and the file descriptors passed along, `None` when the peer closed the
+/// connection.
+pub fn recv<T: DeserializeOwned>(stream: &UnixStream) -> Result<Option<(T, Vec<OwnedFd>)>> {
+    let mut length = [0; 4];
+    let mut cmsg_buffer = nix::cmsg_space!([RawFd; MAX_TENSORS]);
+    let mut fds = vec![];
+    let read = {
+        let mut iov = [IoSliceMut::new(&mut length)];
+        let message = recvmsg::<()>(
+            stream.as_raw_fd(),
+            &mut iov,
+            Some(&mut cmsg_buffer),
+            MsgFlags::MSG_CMSG_CLOEXEC,
+        )?;
+        for cmsg in message.cmsgs()? {
+            if let ControlMessageOwned::ScmRights(received) = cmsg {
+                // Received descriptors are new ones, owned by nothing else in this process.
+                fds.extend(
+                    received
+                        .into_iter()
+                        .map(|it| unsafe { OwnedFd::from_raw_fd(it) }),
+                );
+            }
+        }
+        message.bytes
+    };
+    if read == 0 {
+        return Ok(None);
+    }
+    let mut stream = stream;
+    stream.read_exact(&mut length[read..])?;
+    let length = u32::from_le_bytes(length) as usize;
+    if length > MAX_MESSAGE {
+        return Err(IpcError::MessageTooLarge(length).into());
+    }
+    let mut body = vec![0; length];
+    stream.read_exact(&mut body)?;
+    Ok(Some((serde_json::from_slice(&body)?, fds)))
+}
+
+#[cfg(test)]
+mod test {
+    use std::os::unix::net::UnixStream;
+
+    use super::{recv, send, Reply, Request};
+    use crate::shm::SharedBuffer;
+
+    #[test]
+    fn test_message_with_fds() {
+        let (client, daemon) = UnixStream::pair().unwrap();
+        let mut buffer = SharedBuffer::new(3).unwrap();
+        buffer.as_mut_slice().copy_from_slice(&[1, 2, 3]);
+        let request = Request::Open {
+            model: "m".repeat(1 << 20),
+        };
+        let sender = std::thread::spawn(move || {
+            send(&client, &request, &[buffer.fd()]).unwrap();
+            send(&client, &Reply::Ran, &[]).unwrap();
+            client
+        });
+
+        let (request, fds) = recv::<Request>(&daemon).unwrap().unwrap();
+        assert!(matches!(request, Request::Open { model } if model.len() == 1 << 20));
+        let fd = fds.into_iter().next().unwrap();
+        assert_eq!(SharedBuffer::from_fd(fd, 3).unwrap().as_slice(), [1, 2, 3]);
+        let (reply, fds) = recv::<Reply>(&daemon).unwrap().unwrap();
+        assert!(matches!(reply, Reply::Ran));
+        assert!(fds.is_empty());
+
+        drop(sender.join().unwrap());
+        assert!(recv::<Request>(&daemon).unwrap().is_none());
+    }
+}
@@ -0,0 +1,151 @@
+//! Tensor buffers shared between processes, in memory files passed by file descriptor.
+
+use std::{
+    ffi::c_void,
+    num::NonZeroUsize,
+    os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd},
+    ptr::NonNull,
+};
+
+use anyhow::Result;
+use nix::{
+    fcntl::{fcntl, FcntlArg, SealFlag},
+    sys::{
+        memfd::{memfd_create, MemFdCreateFlag},
+        mman::{mmap, munmap, MapFlags, ProtFlags},
+        stat::fstat,
+    },
+    unistd::ftruncate,
+};
+
+use crate::error::IpcError;
+
+/// Seals of the memory files: their size is fixed once created, so that the other process can't
+/// make a mapping fault by truncating the file under it.
+const SEALS: SealFlag = SealFlag::F_SEAL_SHRINK
+    .union(SealFlag::F_SEAL_GROW)
+    .union(SealFlag::F_SEAL_SEAL);
+
+/// A buffer of a memory file, mapped in this process and shared with others by its file
+/// descriptor.
+///
+/// Nothing prevents the processes mapping a buffer from writing it at the same time; the protocol
+/// hands the buffers over, a process only touches them while it is its turn.
+pub struct SharedBuffer {
+    fd: OwnedFd,
+    ptr: NonNull<c_void>,
+    len: usize,
+}
+
+// The mapping is owned by the buffer, and only borrowed through it.
+unsafe impl Send for SharedBuffer {}
+unsafe impl Sync for SharedBuffer {}
+
+impl SharedBuffer {
+    /// Create a zeroed buffer of `len` bytes.
+    pub fn new(len: usize) -> Result<Self> {
+        let fd = memfd_create(
+            c"rknpu-tensor",
+            MemFdCreateFlag::MFD_CLOEXEC | MemFdCreateFlag::MFD_ALLOW_SEALING,
+        )?;
+        ftruncate(&fd, len.try_into()?)?;
+        fcntl(fd.as_raw_fd(), FcntlArg::F_ADD_SEALS(SEALS))?;
+        Self::map(fd, len)
+    }
+
+    /// Map a buffer of `len` bytes received from another process. The file must be a memory file
+    /// sealed against shrinking, of at least `len` bytes.
+    pub fn from_fd(fd: OwnedFd, len: usize) -> Result<Self> {
+        let invalid = || IpcError::InvalidBuffer { len };
+        let seals = fcntl(fd.as_raw_fd(), FcntlArg::F_GET_SEALS).map_err(|_| invalid())?;
+        let size = fstat(fd.as_raw_fd())?.st_size;
+        if !SealFlag::from_bits_truncate(seals).contains(SealFlag::F_SEAL_SHRINK)
+            || usize::try_from(size).map_or(true, |size| size < len)
+        {
+            return Err(invalid().into());
+        }
+        Self::map(fd, len)
+    }
+
+    fn map(fd: OwnedFd, len: usize) -> Result<Self> {
+        // Mappings can't be empty, the one of an empty buffer has a byte that is never accessed.
+        let length = NonZeroUsize::new(len.max(1)).unwrap();
+        let ptr = unsafe {
+            mmap(
+                None,
+                length,
+                ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
+                MapFlags::MAP_SHARED,
+                &fd,
+                0,
+            )?
+        };
+        Ok(Self { fd, ptr, len })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// File descriptor of the buffer, to pass to another process.
+    pub fn fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr() as *const u8, self.len) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr() as *mut u8, self.len) }
+    }
+}
+
+impl AsRef<[u8]> for SharedBuffer {
+    fn as_ref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl Drop for SharedBuffer {
+    fn drop(&mut self) {
+        if let Err(error) = unsafe { munmap(self.ptr, self.len.max(1)) } {
+            log::error!("Failed to unmap shared buffer: {error}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs::File, os::fd::OwnedFd};
+
+    use super::SharedBuffer;
+
+    #[test]
+    fn test_shared_mapping() {
+        let mut buffer = SharedBuffer::new(4).unwrap();
+        assert_eq!(buffer.as_slice(), [0; 4]);
+        buffer.as_mut_slice().copy_from_slice(&[1, 2, 3, 4]);
+
+        // A second mapping of the file, as made by the receiving process, sees the same bytes.
+        let fd = buffer.fd().try_clone_to_owned().unwrap();
+        let mut shared = SharedBuffer::from_fd(fd, 4).unwrap();
+        assert_eq!(shared.as_slice(), [1, 2, 3, 4]);
+        shared.as_mut_slice()[0] = 42;
+        assert_eq!(buffer.as_slice(), [42, 2, 3, 4]);
+
+        let fd = buffer.fd().try_clone_to_owned().unwrap();
+        assert!(SharedBuffer::from_fd(fd, 8).is_err());
+        assert!(SharedBuffer::new(0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_unsealed_file() {
+        let file = File::open("/dev/null").unwrap();
+        assert!(SharedBuffer::from_fd(OwnedFd::from(file), 0).is_err());
+    }
+}
@@ -0,0 +1,195 @@
+use std::{
+    path::PathBuf,
+    sync::{Arc, Barrier},
+    thread,
+};
+
+use rknpu::{
+    context::{inputs::RknnInput, RknnContext},
+    driver::stub::{tensor_attribute, StubCall, StubDriver},
+    flags::RknnExtendedFlag,
+    tensors::types::{RknnTensorFormat, RknnTensorType},
+};
+use rknpu_ipc::{error::IpcError, Daemon, DaemonBuilder, RknnClient};
+
+/// A stub of a model adding 1 to its input "x", as its output "y".
+fn driver() -> Arc<StubDriver> {
+    Arc::new(StubDriver::new(
+        vec![tensor_attribute(0, "x", &[1, 4], RknnTensorType::U8)],
+        vec![tensor_attribute(0, "y", &[1, 4], RknnTensorType::U8)],
+        |inputs| vec![inputs[0].iter().map(|it| it.wrapping_add(1)).collect()],
+    ))
+}
+
+fn context(driver: &Arc<StubDriver>) -> RknnContext {
+    RknnContext::with_driver(&[], RknnExtendedFlag::RKNN_FLAG_PRIOR_HIGH, driver.clone()).unwrap()
+}
+
+/// A socket path in the temporary directory, unique to the test.
+fn socket(test: &str) -> PathBuf {
+    let name = format!("rknpu-ipc-{}-{test}.sock", std::process::id());
+    std::env::temp_dir().join(name)
+}
+
+/// Serves "add", with `contexts` contexts of the model of `driver`.
+fn daemon(test: &str, driver: &Arc<StubDriver>, contexts: usize) -> Daemon {
+    let pool = (0..contexts).map(|_| context(driver)).collect();
+    DaemonBuilder::new()
+        .with_pool("add", pool)
+        .bind(socket(test))
+        .unwrap()
+}
+
+fn input(buffer: Vec<u8>) -> RknnInput {
+    RknnInput {
+        index: 0,
+        buffer,
+        pass_through: false,
+        dtype: RknnTensorType::U8,
+        fmt: RknnTensorFormat::NHWC,
+    }
+}
+
+#[test]
+fn test_run() {
+    let driver = driver();
+    let daemon = daemon("run", &driver, 1);
+    let mut client = RknnClient::connect(daemon.path(), "add").unwrap();
+    assert_eq!(client.model_info(), context(&driver).model_info());
+    assert_eq!(client.input_attribute(0).unwrap().name, "x");
+    assert_eq!(client.output_index("y").unwrap(), 0);
+
+    client.set_inputs(vec![input(vec![1, 2, 3, 4])]).unwrap();
+    client.run().unwrap();
+    assert_eq!(client.output_buffer(0), Some(&[2, 3, 4, 5][..]));
+    assert_eq!(client.get_outputs().unwrap()[0].buffer, [2, 3, 4, 5]);
+
+    // Inputs stay set for the following runs, until set again.
+    client.run().unwrap();
+    assert_eq!(client.output_buffer(0), Some(&[2, 3, 4, 5][..]));
+    client
+        .set_inputs_by_name([("x", input(vec![9, 9, 9, 255]))])
+        .unwrap();
+    client.run().unwrap();
+    let outputs = client.get_outputs_by_name().unwrap();
+    assert_eq!(outputs["y"].buffer, [10, 10, 10, 0]);
+
+    // Inputs written and outputs read in place, in the same buffers run after run.
+    let output = client.output_buffer(0).unwrap().as_ptr();
+    client
+        .input_buffer_mut(0)
+        .unwrap()
+        .copy_from_slice(&[5, 6, 7, 8]);
+    client.run().unwrap();
+    assert_eq!(client.output_buffer(0), Some(&[6, 7, 8, 9][..]));
+    assert_eq!(client.output_buffer(0).unwrap().as_ptr(), output);
+    assert!(client.input_buffer_mut(1).is_none());
+    assert_eq!(driver.call_count(StubCall::Run), 4);
+    client.close().unwrap();
+}
+
+#[test]
+fn test_shared_contexts() {
+    let driver = driver();
+    let daemon = daemon("shared", &driver, 2);
+    let clients = 6;
+    let barrier = Arc::new(Barrier::new(clients));
+    let handles = (0..clients as u8)
+        .map(|index| {
+            let path = daemon.path().to_path_buf();
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                let mut client = RknnClient::connect(path, "add").unwrap();
+                barrier.wait();
+                for run in 0..10 {
+                    client
+                        .set_inputs(vec![input(vec![index, run, 0, 0])])
+                        .unwrap();
+                    client.run().unwrap();
+                    assert_eq!(
+                        client.output_buffer(0),
+                        Some(&[index + 1, run + 1, 1, 1][..])
+                    );
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    // The clients ran on the contexts of the daemon, no other was created.
+    assert_eq!(driver.live_contexts(), 2);
+    assert_eq!(driver.call_count(StubCall::Run), clients * 10);
+}
+
+#[test]
+fn test_errors() {
+    let driver = driver();
+    let daemon = daemon("errors", &driver, 1);
+    let error = RknnClient::connect(daemon.path(), "detector")
+        .err()
+        .unwrap();
+    assert_eq!(
+        error.downcast_ref::<IpcError>(),
+        Some(&IpcError::Daemon("Unknown model 'detector'.".to_string()))
+    );
+
+    let mut client = RknnClient::connect(daemon.path(), "add").unwrap();
+    assert!(client.input_attribute(1).is_err());
+    assert!(client.input_index("z").is_err());
+    assert!(client.set_inputs(vec![input(vec![1, 2, 3])]).is_err());
+    assert!(client
+        .set_inputs_by_name([("z", input(vec![0; 4]))])
+        .is_err());
+    assert_eq!(
+        client.get_outputs().unwrap_err().downcast_ref::<IpcError>(),
+        Some(&IpcError::NoOutputs)
+    );
+
+    // Errors raised by the daemon are answered, the connection stays usable.
+    let error = client.run().unwrap_err().to_string();
+    assert_eq!(error, "Input 'x' (index 0) is missing.");
+    driver.fail_on(StubCall::Run, -1);
+    client.set_inputs(vec![input(vec![0; 4])]).unwrap();
+    assert!(client.run().is_err());
+    driver.clear_failure(StubCall::Run);
+    client.run().unwrap();
+    assert_eq!(client.output_buffer(0), Some(&[1, 1, 1, 1][..]));
+}
+
+#[test]
+fn test_stop() {
+    let driver = driver();
+    let path = socket("stop");
+    // A socket file left by a daemon that is gone is replaced.
+    drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+    assert!(path.exists());
+    let daemon = daemon("stop", &driver, 1);
+    let mut client = RknnClient::connect(&path, "add").unwrap();
+    client.set_inputs(vec![input(vec![0; 4])]).unwrap();
+
+    // A second daemon can't take the socket of a running one.
+    assert!(DaemonBuilder::new()
+        .with_model("add", context(&driver))
+        .bind(&path)
+        .is_err());
+
+    drop(daemon);
+    assert!(!path.exists());
+
+    // Other files are never replaced, as a model given as the socket path by mistake.
+    std::fs::write(&path, b"model").unwrap();
+    let error = DaemonBuilder::new()
+        .with_model("add", context(&driver))
+        .bind(&path)
+        .err()
+        .unwrap();
+    assert_eq!(
+        error.downcast_ref::<IpcError>(),
+        Some(&IpcError::NotASocket(path.clone()))
+    );
+    assert_eq!(std::fs::read(&path).unwrap(), b"model");
+    std::fs::remove_file(&path).unwrap();
+    assert!(client.run().is_err());
+    assert!(RknnClient::connect(&path, "add").is_err());
+}
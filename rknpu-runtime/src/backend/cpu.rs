@@ -0,0 +1,323 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use rknpu::{
+    error::{RknnInputError, RknnTensorLookupError},
+    tensors::types::RknnTensorType,
+};
+use tract_onnx::prelude::{
+    DatumType, Framework, InferenceModel, InferenceModelExt, OutletId, TValue, TVec,
+    Tensor as TractTensor, TypedModel, TypedRunnableModel,
+};
+
+use crate::{
+    error::{BackendError, RuntimeError},
+    session::Outputs,
+    tensor::{Tensor, TensorData},
+};
+
+use super::{BackendKind, InferenceBackend, TensorInfo};
+
+/// Runs `.onnx` models on the CPU, with tract.
+pub struct CpuBackend {
+    plan: TypedRunnableModel<TypedModel>,
+    inputs: Vec<TensorInfo>,
+    outputs: Vec<TensorInfo>,
+}
+
+impl CpuBackend {
+    /// Load an ONNX model file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let model = tract_onnx::onnx()
+            .model_for_path(path)
+            .with_context(|| format!("Unable to load ONNX model {}", path.display()))?;
+        Self::from_model(model)
+    }
+
+    /// Optimize a model loaded by tract. Its inputs and outputs must have fixed shapes.
+    pub fn from_model(model: InferenceModel) -> Result<Self> {
+        let model = model.into_optimized()?;
+        let inputs = tensor_infos(&model, model.input_outlets()?)?;
+        let outputs = tensor_infos(&model, model.output_outlets()?)?;
+        Ok(Self {
+            plan: model.into_runnable()?,
+            inputs,
+            outputs,
+        })
+    }
+}
+
+fn tensor_infos(model: &TypedModel, outlets: &[OutletId]) -> Result<Vec<TensorInfo>> {
+    outlets
+        .iter()
+        .map(|outlet| {
+            let name = model
+                .outlet_label(*outlet)
+                .unwrap_or(&model.node(outlet.node).name)
+                .to_string();
+            let fact = model.outlet_fact(*outlet)?;
+            let Some(shape) = fact.shape.as_concrete() else {
+                return Err(BackendError::SymbolicShape {
+                    name,
+                    shape: format!("{:?}", fact.shape),
+                }
+                .into());
+            };
+            let Some(dtype) = rknn_type(fact.datum_type) else {
+                return Err(BackendError::UnsupportedType {
+                    name,
+                    dtype: format!("{:?}", fact.datum_type),
+                }
+                .into());
+            };
+            Ok(TensorInfo {
+                name,
+                shape: shape.to_vec(),
+                dtype,
+            })
+        })
+        .collect()
+}
+
+fn rknn_type(dtype: DatumType) -> Option<RknnTensorType> {
+    let dtype = match dtype {
+        DatumType::F32 => RknnTensorType::F32,
+        DatumType::F16 => RknnTensorType::F16,
+        DatumType::I8 => RknnTensorType::I8,
+        DatumType::U8 => RknnTensorType::U8,
+        DatumType::I16 => RknnTensorType::I16,
+        DatumType::U16 => RknnTensorType::U16,
+        DatumType::I32 => RknnTensorType::I32,
+        DatumType::U32 => RknnTensorType::U32,
+        DatumType::I64 => RknnTensorType::I64,
+        DatumType::Bool => RknnTensorType::BOOL,
+        _ => return None,
+    };
+    Some(dtype)
+}
+
+fn datum_type(dtype: RknnTensorType) -> DatumType {
+    match dtype {
+        RknnTensorType::F32 | RknnTensorType::MAX => DatumType::F32,
+        RknnTensorType::F16 => DatumType::F16,
+        RknnTensorType::I8 => DatumType::I8,
+        RknnTensorType::U8 => DatumType::U8,
+        RknnTensorType::I16 => DatumType::I16,
+        RknnTensorType::U16 => DatumType::U16,
+        RknnTensorType::I32 => DatumType::I32,
+        RknnTensorType::U32 => DatumType::U32,
+        RknnTensorType::I64 => DatumType::I64,
+        RknnTensorType::BOOL => DatumType::Bool,
+    }
+}
+
+/// Convert a tensor to the type and shape of the model input `info`.
+fn to_tract(info: &TensorInfo, tensor: Tensor) -> Result<TValue> {
+    if tensor.len() != info.len() {
+        return Err(RuntimeError::ElementCountMismatch {
+            name: info.name.clone(),
+            expected: info.len(),
+            actual: tensor.len(),
+        }
+        .into());
+    }
+    let shape = &info.shape;
+    let tensor = match tensor.data() {
+        TensorData::F32(values) => TractTensor::from_shape(shape, values)?,
+        TensorData::F16(values) => TractTensor::from_shape(shape, values)?,
+        TensorData::I8(values) => TractTensor::from_shape(shape, values)?,
+        TensorData::U8(values) => TractTensor::from_shape(shape, values)?,
+        TensorData::I16(values) => TractTensor::from_shape(shape, values)?,
+        TensorData::U16(values) => TractTensor::from_shape(shape, values)?,
+        TensorData::I32(values) => TractTensor::from_shape(shape, values)?,
+        TensorData::U32(values) => TractTensor::from_shape(shape, values)?,
+        TensorData::I64(values) => TractTensor::from_shape(shape, values)?,
+        TensorData::Bool(values) => TractTensor::from_shape(shape, values)?,
+    };
+    let tensor = tensor.cast_to_dt(datum_type(info.dtype))?.into_owned();
+    Ok(tensor.into())
+}
+
+fn from_tract(info: &TensorInfo, tensor: &TractTensor) -> Result<Tensor> {
+    let data = match tensor.datum_type() {
+        DatumType::F32 => TensorData::F32(tensor.as_slice()?.to_vec()),
+        DatumType::F16 => TensorData::F16(tensor.as_slice()?.to_vec()),
+        DatumType::I8 => TensorData::I8(tensor.as_slice()?.to_vec()),
+        DatumType::U8 => TensorData::U8(tensor.as_slice()?.to_vec()),
+        DatumType::I16 => TensorData::I16(tensor.as_slice()?.to_vec()),
+        DatumType::U16 => TensorData::U16(tensor.as_slice()?.to_vec()),
+        DatumType::I32 => TensorData::I32(tensor.as_slice()?.to_vec()),
+        DatumType::U32 => TensorData::U32(tensor.as_slice()?.to_vec()),
+        DatumType::I64 => TensorData::I64(tensor.as_slice()?.to_vec()),
+        DatumType::Bool => TensorData::Bool(tensor.as_slice()?.to_vec()),
+        dtype => {
+            return Err(BackendError::UnsupportedType {
+                name: info.name.clone(),
+                dtype: format!("{dtype:?}"),
+            }
+            .into())
+        }
+    };
+    Tensor::from_data(tensor.shape().to_vec(), data)
+}
+
+impl InferenceBackend for CpuBackend {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Cpu
+    }
+
+    fn inputs(&self) -> &[TensorInfo] {
+        &self.inputs
+    }
+
+    fn outputs(&self) -> &[TensorInfo] {
+        &self.outputs
+    }
+
+    fn run(&mut self, inputs: Vec<(&str, Tensor)>) -> Result<Outputs> {
+        let mut values: Vec<Option<TValue>> = self.inputs.iter().map(|_| None).collect();
+        for (name, tensor) in inputs {
+            let index = self
+                .inputs
+                .iter()
+                .position(|it| it.name == name)
+                .ok_or_else(|| RknnTensorLookupError::UnknownInput {
+                    name: name.to_string(),
+                })?;
+            if values[index].is_some() {
+                return Err(RknnInputError::Duplicate {
+                    index: index as u32,
+                    name: name.to_string(),
+                }
+                .into());
+            }
+            values[index] = Some(to_tract(&self.inputs[index], tensor)?);
+        }
+        let values = values
+            .into_iter()
+            .zip(&self.inputs)
+            .enumerate()
+            .map(|(index, (value, info))| {
+                value.ok_or_else(|| RknnInputError::Missing {
+                    index: index as u32,
+                    name: info.name.clone(),
+                })
+            })
+            .collect::<Result<TVec<_>, _>>()?;
+        let tensors = self
+            .plan
+            .run(values)?
+            .iter()
+            .zip(&self.outputs)
+            .map(|(value, info)| from_tract(info, value))
+            .collect::<Result<Vec<_>>>()?;
+        let names = self.outputs.iter().map(|it| it.name.clone()).collect();
+        Ok(Outputs::new(names, tensors))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rknpu::{error::RknnInputError, tensors::types::RknnTensorType};
+    use tract_onnx::{
+        pb::{
+            tensor_proto::DataType, tensor_shape_proto, tensor_shape_proto::dimension::Value,
+            type_proto, GraphProto, ModelProto, NodeProto, OperatorSetIdProto, TensorShapeProto,
+            TypeProto, ValueInfoProto,
+        },
+        prelude::Framework,
+    };
+
+    use crate::{
+        backend::{BackendKind, InferenceBackend, TensorInfo},
+        tensor::Tensor,
+    };
+
+    use super::CpuBackend;
+
+    fn value_info(name: &str, shape: &[i64]) -> ValueInfoProto {
+        let dim = shape
+            .iter()
+            .map(|it| tensor_shape_proto::Dimension {
+                value: Some(Value::DimValue(*it)),
+                ..Default::default()
+            })
+            .collect();
+        ValueInfoProto {
+            name: name.to_string(),
+            r#type: Some(TypeProto {
+                value: Some(type_proto::Value::TensorType(type_proto::Tensor {
+                    elem_type: DataType::Float as i32,
+                    shape: Some(TensorShapeProto { dim }),
+                })),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    /// A model adding its two inputs, then applying a relu.
+    fn add_relu() -> CpuBackend {
+        let node = |op_type: &str, inputs: &[&str], output: &str| NodeProto {
+            op_type: op_type.to_string(),
+            input: inputs.iter().map(|it| it.to_string()).collect(),
+            output: vec![output.to_string()],
+            ..Default::default()
+        };
+        let model = ModelProto {
+            ir_version: 7,
+            opset_import: vec![OperatorSetIdProto {
+                domain: String::new(),
+                version: 13,
+            }],
+            graph: Some(GraphProto {
+                node: vec![node("Add", &["a", "b"], "sum"), node("Relu", &["sum"], "y")],
+                input: vec![value_info("a", &[1, 4]), value_info("b", &[1, 4])],
+                output: vec![value_info("y", &[1, 4])],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let model = tract_onnx::onnx().model_for_proto_model(&model).unwrap();
+        CpuBackend::from_model(model).unwrap()
+    }
+
+    #[test]
+    fn test_backend() {
+        let mut backend: Box<dyn InferenceBackend> = Box::new(add_relu());
+        assert_eq!(backend.kind(), BackendKind::Cpu);
+        let info = |name: &str| TensorInfo {
+            name: name.to_string(),
+            shape: vec![1, 4],
+            dtype: RknnTensorType::F32,
+        };
+        assert_eq!(backend.inputs(), [info("a"), info("b")]);
+        assert_eq!(backend.outputs(), [info("y")]);
+
+        let a = Tensor::new(vec![4], vec![1.0_f32, -2.0, 3.0, -4.0]).unwrap();
+        // Integer inputs are converted to the model type.
+        let b = Tensor::new(vec![1, 4], vec![1_i32, 1, 1, 1]).unwrap();
+        let outputs = backend.run(vec![("b", b), ("a", a.clone())]).unwrap();
+        let y = outputs.get("y").unwrap();
+        assert_eq!(y.shape(), [1, 4]);
+        assert_eq!(y.as_slice::<f32>().unwrap(), [2.0, 0.0, 4.0, 0.0]);
+
+        let error = backend.run(vec![("a", a.clone())]).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<RknnInputError>(),
+            Some(&RknnInputError::Missing {
+                index: 1,
+                name: "b".to_string()
+            })
+        );
+        let error = backend.run(vec![("a", a.clone()), ("a", a)]).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<RknnInputError>(),
+            Some(&RknnInputError::Duplicate {
+                index: 0,
+                name: "a".to_string()
+            })
+        );
+    }
+}
@@ -0,0 +1,255 @@
+//! Backends running a model behind a common interface, so that the same application code runs
+//! on the NPU of a board or on the CPU of a development machine.
+//!
+//! The [RKNN backend](RknnBackend) runs `.rknn` models on the NPU. The CPU backend, enabled by
+//! the `cpu` feature, runs the original `.onnx` model with [tract](https://github.com/sonos/tract),
+//! which also gives reference outputs to [compare](compare) the converted model against.
+//! TFLite models are not supported by the tract version the CPU backend uses, they are rejected
+//! with a hint to convert them to ONNX.
+
+use std::{fmt, path::Path, str::FromStr};
+
+use anyhow::Result;
+use rknpu::tensors::types::RknnTensorType;
+
+use crate::{
+    error::{BackendError, RuntimeError},
+    session::Outputs,
+    tensor::Tensor,
+};
+
+#[cfg(feature = "cpu")]
+pub use self::cpu::CpuBackend;
+pub use self::rknn::RknnBackend;
+
+#[cfg(feature = "cpu")]
+mod cpu;
+mod rknn;
+
+/// A loaded model, run on some hardware.
+pub trait InferenceBackend: Send {
+    fn kind(&self) -> BackendKind;
+
+    /// Model inputs, in the order of their indices.
+    fn inputs(&self) -> &[TensorInfo];
+
+    /// Model outputs, in the order of their indices, as returned by [`run`](Self::run).
+    fn outputs(&self) -> &[TensorInfo];
+
+    /// Run the model on inputs addressed by name. Every model input must be given once.
+    ///
+    /// Every backend takes f32 inputs and converts them to the type of the model inputs. Tensors
+    /// must hold as many values as the model inputs, in their layout.
+    fn run(&mut self, inputs: Vec<(&str, Tensor)>) -> Result<Outputs>;
+}
+
+/// Name, shape and element type of a model input or output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TensorInfo {
+    pub name: String,
+    pub shape: Vec<usize>,
+    pub dtype: RknnTensorType,
+}
+
+impl TensorInfo {
+    /// Number of elements.
+    pub fn len(&self) -> usize {
+        self.shape.iter().product()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// The backends a model can be run on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BackendKind {
+    /// `.rknn` models, on the NPU.
+    Rknn,
+    /// `.onnx` models, on the CPU.
+    Cpu,
+}
+
+impl BackendKind {
+    pub const ALL: [Self; 2] = [Self::Rknn, Self::Cpu];
+
+    /// Backend running the model file at `path`, from its extension.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        match path.extension().and_then(|it| it.to_str()) {
+            Some("rknn") => Ok(Self::Rknn),
+            Some("onnx") => Ok(Self::Cpu),
+            Some("tflite") => Err(BackendError::TfliteModel(path.to_path_buf()).into()),
+            _ => Err(BackendError::UnknownModelFormat(path.to_path_buf()).into()),
+        }
+    }
+
+    /// Whether rknpu-runtime was built with this backend.
+    pub fn is_available(self) -> bool {
+        match self {
+            Self::Rknn => true,
+            Self::Cpu => cfg!(feature = "cpu"),
+        }
+    }
+
+    /// Load the model file at `path` in this backend.
+    pub fn load<P: AsRef<Path>>(self, path: P) -> Result<Box<dyn InferenceBackend>> {
+        match self {
+            Self::Rknn => Ok(Box::new(RknnBackend::load(path)?)),
+            #[cfg(feature = "cpu")]
+            Self::Cpu => Ok(Box::new(CpuBackend::load(path)?)),
+            #[cfg(not(feature = "cpu"))]
+            Self::Cpu => Err(BackendError::Unavailable(self).into()),
+        }
+    }
+}
+
+impl FromStr for BackendKind {
+    type Err = BackendError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "rknn" | "npu" => Ok(Self::Rknn),
+            "cpu" => Ok(Self::Cpu),
+            _ => Err(BackendError::UnknownBackend(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for BackendKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Rknn => write!(f, "rknn"),
+            Self::Cpu => write!(f, "cpu"),
+        }
+    }
+}
+
+/// Load the model file at `path` in the backend running its format, see
+/// [`BackendKind::from_path`].
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Box<dyn InferenceBackend>> {
+    BackendKind::from_path(&path)?.load(path)
+}
+
+/// Error of an output against its reference.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputError {
+    pub name: String,
+    pub max_error: f32,
+    pub mean_error: f32,
+}
+
+impl fmt::Display for OutputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: max error {:.6}, mean error {:.6}",
+            self.name, self.max_error, self.mean_error
+        )
+    }
+}
+
+/// Absolute errors of `outputs` against the `reference` outputs of the same name, e.g. of a
+/// RKNN model against the model it was converted from, run on the CPU.
+pub fn compare(reference: &Outputs, outputs: &Outputs) -> Result<Vec<OutputError>> {
+    reference
+        .iter()
+        .map(|(name, expected)| {
+            let expected = expected.to_f32();
+            let actual = outputs.get(name)?.to_f32();
+            if actual.len() != expected.len() {
+                return Err(RuntimeError::ElementCountMismatch {
+                    name: name.to_string(),
+                    expected: expected.len(),
+                    actual: actual.len(),
+                }
+                .into());
+            }
+            let errors = expected.iter().zip(&actual).map(|(a, b)| (a - b).abs());
+            let max_error = errors.clone().fold(0.0, f32::max);
+            let mean_error = errors.sum::<f32>() / expected.len().max(1) as f32;
+            Ok(OutputError {
+                name: name.to_string(),
+                max_error,
+                mean_error,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use rknpu::error::RknnTensorLookupError;
+
+    use crate::{error::BackendError, session::Outputs, tensor::Tensor};
+
+    use super::{compare, BackendKind};
+
+    #[test]
+    fn test_kind() {
+        assert_eq!(
+            BackendKind::from_path("models/yolov5s.rknn").unwrap(),
+            BackendKind::Rknn
+        );
+        assert_eq!(
+            BackendKind::from_path("yolov5s.onnx").unwrap(),
+            BackendKind::Cpu
+        );
+        let error = BackendKind::from_path("yolov5s.tflite").unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<BackendError>(),
+            Some(&BackendError::TfliteModel("yolov5s.tflite".into()))
+        );
+        let error = BackendKind::from_path("yolov5s.pt").unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<BackendError>(),
+            Some(&BackendError::UnknownModelFormat("yolov5s.pt".into()))
+        );
+        for kind in BackendKind::ALL {
+            assert_eq!(kind.to_string().parse::<BackendKind>(), Ok(kind));
+        }
+        assert_eq!("NPU".parse::<BackendKind>(), Ok(BackendKind::Rknn));
+        assert_eq!(
+            "gpu".parse::<BackendKind>(),
+            Err(BackendError::UnknownBackend("gpu".to_string()))
+        );
+        #[cfg(not(feature = "cpu"))]
+        {
+            let error = BackendKind::Cpu.load("yolov5s.onnx").err().unwrap();
+            assert_eq!(
+                error.downcast_ref::<BackendError>(),
+                Some(&BackendError::Unavailable(BackendKind::Cpu))
+            );
+        }
+    }
+
+    #[test]
+    fn test_compare() {
+        let outputs = |logits: Vec<f32>| {
+            Outputs::new(
+                vec!["logits".to_string(), "mask".to_string()],
+                vec![
+                    Tensor::new(vec![1, 4], logits).unwrap(),
+                    Tensor::new(vec![2], vec![1_u8, 0]).unwrap(),
+                ],
+            )
+        };
+        let reference = outputs(vec![0.0, 1.0, 2.0, 3.0]);
+        let errors = compare(&reference, &outputs(vec![0.0, 1.5, 2.0, 2.5])).unwrap();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].name, "logits");
+        assert_eq!(errors[0].max_error, 0.5);
+        assert_eq!(errors[0].mean_error, 0.25);
+        assert_eq!(errors[1].max_error, 0.0);
+
+        let others = Outputs::new(vec!["boxes".to_string()], vec![]);
+        let error = compare(&reference, &others).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<RknnTensorLookupError>(),
+            Some(&RknnTensorLookupError::UnknownOutput {
+                name: "logits".to_string()
+            })
+        );
+    }
+}
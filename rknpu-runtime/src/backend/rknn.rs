@@ -0,0 +1,136 @@
+use std::path::Path;
+
+use anyhow::Result;
+use rknpu::tensors::{attributes::RknnTensorAttribute, types::RknnTensorType};
+
+use crate::{model::Model, session::InferenceSession, session::Outputs, tensor::Tensor};
+
+use super::{BackendKind, InferenceBackend, TensorInfo};
+
+/// Runs `.rknn` models on the NPU, through an [`InferenceSession`].
+pub struct RknnBackend {
+    session: InferenceSession,
+    inputs: Vec<TensorInfo>,
+    outputs: Vec<TensorInfo>,
+}
+
+impl RknnBackend {
+    pub fn new(session: InferenceSession) -> Self {
+        let info = session.model_info();
+        let inputs = info
+            .inputs
+            .iter()
+            .map(|it| tensor_info(it, it.data_type))
+            .collect();
+        let outputs = info
+            .outputs
+            .iter()
+            .map(|it| {
+                let dtype = if session.dequantizes() {
+                    RknnTensorType::F32
+                } else {
+                    it.data_type
+                };
+                tensor_info(it, dtype)
+            })
+            .collect();
+        Self {
+            session,
+            inputs,
+            outputs,
+        }
+    }
+
+    /// Load a model file in a new session.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Self::new(Model::from_path(path)?.session()?))
+    }
+
+    pub fn session(&self) -> &InferenceSession {
+        &self.session
+    }
+
+    pub fn into_session(self) -> InferenceSession {
+        self.session
+    }
+}
+
+fn tensor_info(attribute: &RknnTensorAttribute, dtype: RknnTensorType) -> TensorInfo {
+    TensorInfo {
+        name: attribute.name.clone(),
+        shape: attribute.dims.iter().map(|it| *it as usize).collect(),
+        dtype,
+    }
+}
+
+impl InferenceBackend for RknnBackend {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Rknn
+    }
+
+    fn inputs(&self) -> &[TensorInfo] {
+        &self.inputs
+    }
+
+    fn outputs(&self) -> &[TensorInfo] {
+        &self.outputs
+    }
+
+    fn run(&mut self, inputs: Vec<(&str, Tensor)>) -> Result<Outputs> {
+        self.session.run(inputs)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use rknpu::{
+        driver::stub::{tensor_attribute, StubDriver},
+        tensors::types::{RknnTensorQuantFormat, RknnTensorType},
+    };
+
+    use crate::{
+        backend::{BackendKind, InferenceBackend, TensorInfo},
+        model::Model,
+        tensor::Tensor,
+    };
+
+    use super::RknnBackend;
+
+    #[test]
+    fn test_backend() {
+        let mut input = tensor_attribute(0, "image", &[1, 2, 2], RknnTensorType::U8);
+        input.quant_type = RknnTensorQuantFormat::AffineScale(0, 1.0 / 255.0);
+        let mut output = tensor_attribute(0, "logits", &[1, 4], RknnTensorType::U8);
+        output.quant_type = RknnTensorQuantFormat::AffineScale(0, 0.5);
+        let driver = Arc::new(StubDriver::new(vec![input], vec![output], |inputs| {
+            inputs.to_vec()
+        }));
+        let model = Model::with_driver(vec![], driver);
+
+        let mut backend: Box<dyn InferenceBackend> =
+            Box::new(RknnBackend::new(model.session().unwrap()));
+        assert_eq!(backend.kind(), BackendKind::Rknn);
+        assert_eq!(
+            backend.inputs(),
+            [TensorInfo {
+                name: "image".to_string(),
+                shape: vec![1, 2, 2],
+                dtype: RknnTensorType::U8
+            }]
+        );
+        assert_eq!(backend.outputs()[0].dtype, RknnTensorType::F32);
+        assert_eq!(backend.outputs()[0].len(), 4);
+
+        let image = Tensor::new(vec![1, 2, 2], vec![0.0_f32, 0.2, 0.4, 1.0]).unwrap();
+        let outputs = backend.run(vec![("image", image)]).unwrap();
+        assert_eq!(
+            outputs.get("logits").unwrap().to_f32(),
+            [0.0, 25.5, 51.0, 127.5]
+        );
+
+        let backend = RknnBackend::new(model.session().unwrap().with_dequantize(false));
+        assert_eq!(backend.outputs()[0].dtype, RknnTensorType::U8);
+    }
+}
@@ -0,0 +1,157 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+struct Pending<T> {
+    arrived: Instant,
+    deadline: Option<Instant>,
+    request: T,
+}
+
+/// Decides when to run which requests, given the current time.
+///
+/// A batch is run once it is full, once its oldest request waited for the batching timeout, or
+/// once the earliest deadline leaves just the time of an inference. Batches take the requests
+/// with the earliest deadlines first, requests without deadline last, in arrival order.
+pub(crate) struct Batcher<T> {
+    max_batch: usize,
+    timeout: Duration,
+    /// Estimated duration of an inference.
+    estimate: Duration,
+    pending: VecDeque<Pending<T>>,
+}
+
+impl<T> Batcher<T> {
+    pub fn new(max_batch: usize, timeout: Duration, estimate: Duration) -> Self {
+        Self {
+            max_batch: max_batch.max(1),
+            timeout,
+            estimate,
+            pending: VecDeque::new(),
+        }
+    }
+
+    pub fn push(&mut self, request: T, arrived: Instant, deadline: Option<Instant>) {
+        self.pending.push_back(Pending {
+            arrived,
+            deadline,
+            request,
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Update the estimated duration of an inference with the duration of the last one.
+    pub fn record_run(&mut self, duration: Duration) {
+        self.estimate = (self.estimate * 3 + duration) / 4;
+    }
+
+    /// Remove the requests that can't be run before their deadline.
+    pub fn expire(&mut self, now: Instant) -> Vec<T> {
+        let (expired, pending) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition(|it| it.deadline.is_some_and(|it| now + self.estimate > it));
+        self.pending = pending;
+        expired.into_iter().map(|it| it.request).collect()
+    }
+
+    /// Time at which the next batch is due, `None` if there is no request.
+    pub fn due(&self) -> Option<Instant> {
+        if self.pending.len() >= self.max_batch {
+            return self.pending.iter().map(|it| it.arrived).min();
+        }
+        self.pending
+            .iter()
+            .map(|it| {
+                let due = it.arrived + self.timeout;
+                match it.deadline {
+                    Some(deadline) => {
+                        due.min(deadline.checked_sub(self.estimate).unwrap_or(deadline))
+                    }
+                    None => due,
+                }
+            })
+            .min()
+    }
+
+    /// Take the next batch if it is due.
+    pub fn pop(&mut self, now: Instant) -> Option<Vec<T>> {
+        if self.due()? > now {
+            return None;
+        }
+        Some(self.take())
+    }
+
+    /// Take the next batch, due or not.
+    pub fn take(&mut self) -> Vec<T> {
+        // Stable: requests of the same deadline stay in arrival order.
+        self.pending
+            .make_contiguous()
+            .sort_by_key(|it| (it.deadline.is_none(), it.deadline));
+        let count = self.pending.len().min(self.max_batch);
+        self.pending.drain(..count).map(|it| it.request).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::Batcher;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn test_full_batch() {
+        let t0 = Instant::now();
+        let mut batcher = Batcher::new(3, ms(10), ms(0));
+        assert_eq!(batcher.due(), None);
+        assert_eq!(batcher.pop(t0), None);
+        batcher.push('a', t0, None);
+        batcher.push('b', t0 + ms(1), None);
+        assert_eq!(batcher.due(), Some(t0 + ms(10)));
+        assert_eq!(batcher.pop(t0 + ms(2)), None);
+        batcher.push('c', t0 + ms(2), None);
+        batcher.push('d', t0 + ms(3), None);
+        // Full: the batch is due right away.
+        assert_eq!(batcher.pop(t0 + ms(3)), Some(vec!['a', 'b', 'c']));
+        assert_eq!(batcher.due(), Some(t0 + ms(13)));
+        assert_eq!(batcher.pop(t0 + ms(12)), None);
+        assert_eq!(batcher.pop(t0 + ms(13)), Some(vec!['d']));
+        assert!(batcher.is_empty());
+    }
+
+    #[test]
+    fn test_deadlines() {
+        let t0 = Instant::now();
+        let mut batcher = Batcher::new(3, ms(10), ms(4));
+        batcher.push('a', t0, None);
+        batcher.push('b', t0 + ms(1), Some(t0 + ms(8)));
+        // The batch is run early enough for 'b' to make its deadline.
+        assert_eq!(batcher.due(), Some(t0 + ms(4)));
+        assert_eq!(batcher.pop(t0 + ms(3)), None);
+        batcher.push('c', t0 + ms(2), Some(t0 + ms(6)));
+        batcher.push('d', t0 + ms(3), None);
+        // Earliest deadlines first.
+        assert_eq!(batcher.pop(t0 + ms(3)), Some(vec!['c', 'b', 'a']));
+        assert_eq!(batcher.pop(t0 + ms(13)), Some(vec!['d']));
+
+        batcher.push('e', t0 + ms(20), Some(t0 + ms(30)));
+        batcher.push('f', t0 + ms(20), Some(t0 + ms(22)));
+        batcher.push('g', t0 + ms(20), None);
+        assert_eq!(batcher.expire(t0 + ms(18)), Vec::<char>::new());
+        // 'f' can't make its deadline anymore.
+        assert_eq!(batcher.expire(t0 + ms(19)), vec!['f']);
+        assert_eq!(batcher.take(), vec!['e', 'g']);
+
+        // The estimate follows the inferences.
+        batcher.record_run(ms(8));
+        batcher.push('h', t0 + ms(30), Some(t0 + ms(40)));
+        assert_eq!(batcher.due(), Some(t0 + ms(35)));
+    }
+}
@@ -0,0 +1,639 @@
+//! Dynamic batching of the requests of several clients on a model with a batch dimension.
+//!
+//! Clients submit single-sample requests to a [`BatchScheduler`], whose thread gathers them into
+//! batches of up to the batch dimension of the model, the first dimension of its inputs. A batch
+//! is run when it is full, when its oldest request waited for the batching timeout, or when the
+//! earliest deadline of its requests leaves just the time of an inference. Missing samples are
+//! zeroed, and the outputs are split back into one [`Outputs`] per request.
+
+use std::{
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError, Sender, SyncSender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use rknpu::{
+    error::{RknnInputError, RknnTensorLookupError},
+    tensors::types::RknnTensorType,
+};
+
+use crate::{
+    backend::{InferenceBackend, TensorInfo},
+    error::{BatchError, RuntimeError},
+    pipeline::Inputs,
+    session::Outputs,
+    tensor::{Tensor, TensorData},
+};
+
+use self::batcher::Batcher;
+
+mod batcher;
+
+struct Request {
+    inputs: Inputs,
+    arrived: Instant,
+    deadline: Option<Instant>,
+    reply: SyncSender<Result<Outputs>>,
+}
+
+/// Configures and starts a [`BatchScheduler`].
+pub struct BatchSchedulerBuilder {
+    backend: Box<dyn InferenceBackend>,
+    max_batch: Option<usize>,
+    timeout: Duration,
+    estimate: Duration,
+}
+
+impl BatchSchedulerBuilder {
+    pub fn new(backend: Box<dyn InferenceBackend>) -> Self {
+        Self {
+            backend,
+            max_batch: None,
+            timeout: Duration::from_millis(5),
+            estimate: Duration::ZERO,
+        }
+    }
+
+    /// Maximum number of requests per batch, the batch dimension of the model by default.
+    pub fn with_max_batch(mut self, max_batch: usize) -> Self {
+        self.max_batch = Some(max_batch);
+        self
+    }
+
+    /// Time a request waits for others to fill its batch, 5 ms by default.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Duration of an inference assumed until the first batch is run, to meet the deadlines.
+    pub fn with_run_estimate(mut self, estimate: Duration) -> Self {
+        self.estimate = estimate;
+        self
+    }
+
+    /// Start the thread of the scheduler.
+    pub fn build(self) -> Result<BatchScheduler> {
+        let infos = self.backend.inputs().to_vec();
+        let batch = infos.first().and_then(|it| it.shape.first().copied());
+        let batch = match batch {
+            Some(batch) if batch > 0 && infos.iter().all(|it| it.shape.first() == Some(&batch)) => {
+                batch
+            }
+            _ => return Err(BatchError::NoBatchDimension.into()),
+        };
+        let max_batch = self.max_batch.unwrap_or(batch);
+        if max_batch == 0 || max_batch > batch {
+            return Err(BatchError::InvalidMaxBatch { max_batch, batch }.into());
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let stats = Arc::new(Mutex::new(BatchStats::default()));
+        let mut worker = Worker {
+            backend: self.backend,
+            infos: infos.clone(),
+            batch,
+            batcher: Batcher::new(max_batch, self.timeout, self.estimate),
+            stats: stats.clone(),
+        };
+        let worker = thread::Builder::new()
+            .name("rknpu-batch".to_string())
+            .spawn(move || worker.schedule(rx))?;
+        Ok(BatchScheduler {
+            tx: Some(tx),
+            worker: Some(worker),
+            infos,
+            batch,
+            max_batch,
+            stats,
+        })
+    }
+}
+
+/// Counters of a [`BatchScheduler`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BatchStats {
+    /// Batches run.
+    pub batches: u64,
+    /// Requests run.
+    pub requests: u64,
+    /// Requests failed for missing their deadline.
+    pub expired: u64,
+}
+
+impl BatchStats {
+    pub fn mean_batch_size(&self) -> Option<f64> {
+        (self.batches > 0).then(|| self.requests as f64 / self.batches as f64)
+    }
+}
+
+/// Runs single-sample requests in batches on a backend, see the [module](self) documentation.
+///
+/// The scheduler can be shared between client threads. Dropping it runs the requests already
+/// submitted, then stops its thread.
+pub struct BatchScheduler {
+    /// `None` once dropped, to stop the thread.
+    tx: Option<Sender<Request>>,
+    worker: Option<JoinHandle<()>>,
+    infos: Vec<TensorInfo>,
+    batch: usize,
+    max_batch: usize,
+    stats: Arc<Mutex<BatchStats>>,
+}
+
+impl BatchScheduler {
+    /// Batch dimension of the model.
+    pub fn batch(&self) -> usize {
+        self.batch
+    }
+
+    pub fn max_batch(&self) -> usize {
+        self.max_batch
+    }
+
+    /// Inputs of the model, with their batch dimension.
+    pub fn inputs(&self) -> &[TensorInfo] {
+        &self.infos
+    }
+
+    pub fn stats(&self) -> BatchStats {
+        self.stats.lock().unwrap().clone()
+    }
+
+    /// Submit a request for a single sample: every model input must be given once, with the
+    /// values of one sample. Requests that can't be run before their `deadline` fail with
+    /// [`BatchError::DeadlineExceeded`].
+    pub fn submit(&self, inputs: Inputs, deadline: Option<Instant>) -> Result<BatchTicket> {
+        self.check(&inputs)?;
+        let (reply, rx) = mpsc::sync_channel(1);
+        let request = Request {
+            inputs,
+            arrived: Instant::now(),
+            deadline,
+            reply,
+        };
+        let tx = self.tx.as_ref().ok_or(BatchError::Stopped)?;
+        tx.send(request).map_err(|_| BatchError::Stopped)?;
+        Ok(BatchTicket(rx))
+    }
+
+    /// Submit a request and wait for its outputs.
+    pub fn run(&self, inputs: Inputs, deadline: Option<Instant>) -> Result<Outputs> {
+        self.submit(inputs, deadline)?.wait()
+    }
+
+    fn check(&self, inputs: &Inputs) -> Result<()> {
+        let mut given = vec![false; self.infos.len()];
+        for (name, tensor) in inputs {
+            let index = self
+                .infos
+                .iter()
+                .position(|it| &it.name == name)
+                .ok_or_else(|| RknnTensorLookupError::UnknownInput { name: name.clone() })?;
+            if given[index] {
+                return Err(RknnInputError::Duplicate {
+                    index: index as u32,
+                    name: name.clone(),
+                }
+                .into());
+            }
+            given[index] = true;
+            let expected = self.infos[index].len() / self.batch;
+            if tensor.len() != expected {
+                return Err(RuntimeError::ElementCountMismatch {
+                    name: name.clone(),
+                    expected,
+                    actual: tensor.len(),
+                }
+                .into());
+            }
+        }
+        if let Some(index) = given.iter().position(|it| !it) {
+            return Err(RknnInputError::Missing {
+                index: index as u32,
+                name: self.infos[index].name.clone(),
+            }
+            .into());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for BatchScheduler {
+    fn drop(&mut self) {
+        self.tx = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Outputs of a submitted request, to wait for.
+pub struct BatchTicket(Receiver<Result<Outputs>>);
+
+impl BatchTicket {
+    pub fn wait(self) -> Result<Outputs> {
+        self.0.recv().map_err(|_| BatchError::Stopped)?
+    }
+}
+
+struct Worker {
+    backend: Box<dyn InferenceBackend>,
+    infos: Vec<TensorInfo>,
+    batch: usize,
+    batcher: Batcher<Request>,
+    stats: Arc<Mutex<BatchStats>>,
+}
+
+impl Worker {
+    fn schedule(&mut self, rx: Receiver<Request>) {
+        let mut open = true;
+        while open || !self.batcher.is_empty() {
+            let now = Instant::now();
+            // Once the scheduler is dropped, the remaining requests are run right away.
+            let requests = match open {
+                true => self.batcher.pop(now),
+                false => Some(self.batcher.take()).filter(|it| !it.is_empty()),
+            };
+            if let Some(requests) = requests {
+                self.run(requests, now);
+                continue;
+            }
+            let expired = self.batcher.expire(now);
+            self.expire(expired);
+            if !open {
+                continue;
+            }
+            let received = match self.batcher.due() {
+                Some(due) => rx.recv_timeout(due.saturating_duration_since(now)),
+                None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match received {
+                Ok(request) => {
+                    let (arrived, deadline) = (request.arrived, request.deadline);
+                    self.batcher.push(request, arrived, deadline);
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => open = false,
+            }
+        }
+    }
+
+    fn expire(&self, requests: Vec<Request>) {
+        self.stats.lock().unwrap().expired += requests.len() as u64;
+        for request in requests {
+            let _ = request.reply.send(Err(BatchError::DeadlineExceeded.into()));
+        }
+    }
+
+    /// Run a batch due at `now`, failing its requests whose deadline already passed.
+    fn run(&mut self, requests: Vec<Request>, now: Instant) {
+        let (expired, requests): (Vec<_>, Vec<_>) = requests
+            .into_iter()
+            .partition(|it| it.deadline.is_some_and(|it| it < now));
+        self.expire(expired);
+        if requests.is_empty() {
+            return;
+        }
+        let start = Instant::now();
+        let outputs = batch_inputs(&self.infos, &requests)
+            .and_then(|inputs| {
+                let (names, tensors): (Vec<_>, Vec<_>) = inputs.into_iter().unzip();
+                self.backend
+                    .run(names.iter().map(String::as_str).zip(tensors).collect())
+            })
+            .and_then(|outputs| split_outputs(outputs, self.batch, requests.len()));
+        self.batcher.record_run(start.elapsed());
+        {
+            let mut stats = self.stats.lock().unwrap();
+            stats.batches += 1;
+            stats.requests += requests.len() as u64;
+        }
+        match outputs {
+            Ok(outputs) => {
+                for (request, outputs) in requests.into_iter().zip(outputs) {
+                    let _ = request.reply.send(Ok(outputs));
+                }
+            }
+            Err(error) => {
+                for request in requests {
+                    let _ = request
+                        .reply
+                        .send(Err(anyhow!("Batch inference failed: {error:#}")));
+                }
+            }
+        }
+    }
+}
+
+/// Stack the inputs of the requests into the inputs of a batch, zeroing the missing samples.
+/// Inputs given in different types are converted to f32.
+fn batch_inputs(infos: &[TensorInfo], requests: &[Request]) -> Result<Inputs> {
+    infos
+        .iter()
+        .map(|info| {
+            let tensors = requests
+                .iter()
+                .map(|request| {
+                    let (_, tensor) = request.inputs.iter().find(|(name, _)| *name == info.name)?;
+                    Some(tensor)
+                })
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| RknnTensorLookupError::UnknownInput {
+                    name: info.name.clone(),
+                })?;
+            let dtype = match tensors.first() {
+                Some(first) if tensors.iter().all(|it| it.dtype() == first.dtype()) => {
+                    first.dtype()
+                }
+                _ => RknnTensorType::F32,
+            };
+            let mut bytes = tensors
+                .iter()
+                .flat_map(|it| match it.dtype() == dtype {
+                    true => it.data().to_bytes(),
+                    false => TensorData::F32(it.to_f32()).to_bytes(),
+                })
+                .collect::<Vec<_>>();
+            bytes.resize(info.len() * dtype.size(), 0);
+            let data = TensorData::from_bytes(dtype, &bytes).ok_or_else(|| {
+                RuntimeError::UnsupportedType {
+                    name: info.name.clone(),
+                    dtype,
+                }
+            })?;
+            Ok((
+                info.name.clone(),
+                Tensor::from_data(info.shape.clone(), data)?,
+            ))
+        })
+        .collect()
+}
+
+/// Split the outputs of a batch of `batch` samples into the outputs of the first `count`.
+fn split_outputs(outputs: Outputs, batch: usize, count: usize) -> Result<Vec<Outputs>> {
+    let mut split = vec![(vec![], vec![]); count];
+    for (name, tensor) in outputs.iter() {
+        if tensor.shape().first() != Some(&batch) {
+            return Err(BatchError::UnbatchedOutput {
+                name: name.to_string(),
+                shape: tensor.shape().to_vec(),
+                batch,
+            }
+            .into());
+        }
+        let mut shape = tensor.shape().to_vec();
+        shape[0] = 1;
+        let bytes = tensor.data().to_bytes();
+        let sample = bytes.len() / batch;
+        for (index, (names, tensors)) in split.iter_mut().enumerate() {
+            let bytes = &bytes[index * sample..(index + 1) * sample];
+            let data = TensorData::from_bytes(tensor.dtype(), bytes).ok_or_else(|| {
+                RuntimeError::UnsupportedType {
+                    name: name.to_string(),
+                    dtype: tensor.dtype(),
+                }
+            })?;
+            names.push(name.to_string());
+            tensors.push(Tensor::from_data(shape.clone(), data)?);
+        }
+    }
+    Ok(split
+        .into_iter()
+        .map(|(names, tensors)| Outputs::new(names, tensors))
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{mpsc, Arc, Mutex},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use anyhow::Result;
+    use rknpu::{error::RknnInputError, tensors::types::RknnTensorType};
+
+    use crate::{
+        backend::{BackendKind, InferenceBackend, TensorInfo},
+        error::{BatchError, RuntimeError},
+        pipeline::Inputs,
+        session::Outputs,
+        tensor::Tensor,
+    };
+
+    use super::{batch_inputs, split_outputs, BatchSchedulerBuilder, Request};
+
+    /// Doubles batches of 4 samples of 2 values, recording the batches.
+    struct Double {
+        info: Vec<TensorInfo>,
+        batches: Arc<Mutex<Vec<Vec<f32>>>>,
+    }
+
+    impl Double {
+        fn boxed(batches: &Arc<Mutex<Vec<Vec<f32>>>>) -> Box<dyn InferenceBackend> {
+            Box::new(Self {
+                info: vec![TensorInfo {
+                    name: "x".to_string(),
+                    shape: vec![4, 2],
+                    dtype: RknnTensorType::F32,
+                }],
+                batches: batches.clone(),
+            })
+        }
+    }
+
+    impl InferenceBackend for Double {
+        fn kind(&self) -> BackendKind {
+            BackendKind::Cpu
+        }
+
+        fn inputs(&self) -> &[TensorInfo] {
+            &self.info
+        }
+
+        fn outputs(&self) -> &[TensorInfo] {
+            &self.info
+        }
+
+        fn run(&mut self, inputs: Vec<(&str, Tensor)>) -> Result<Outputs> {
+            let x = inputs[0].1.to_f32();
+            self.batches.lock().unwrap().push(x.clone());
+            let y = Tensor::new(vec![4, 2], x.iter().map(|it| 2.0 * it).collect())?;
+            Ok(Outputs::new(vec!["x".to_string()], vec![y]))
+        }
+    }
+
+    fn sample(value: f32) -> Inputs {
+        let x = Tensor::new(vec![1, 2], vec![value, -value]).unwrap();
+        vec![("x".to_string(), x)]
+    }
+
+    #[test]
+    fn test_batch_tensors() {
+        let (reply, _) = mpsc::sync_channel(1);
+        let request = |inputs| Request {
+            inputs,
+            arrived: Instant::now(),
+            deadline: None,
+            reply: reply.clone(),
+        };
+        let infos = [TensorInfo {
+            name: "x".to_string(),
+            shape: vec![3, 2],
+            dtype: RknnTensorType::U8,
+        }];
+        let u8_sample = |value: u8| {
+            vec![(
+                "x".to_string(),
+                Tensor::new(vec![2], vec![value; 2]).unwrap(),
+            )]
+        };
+        let inputs = batch_inputs(&infos, &[request(u8_sample(1)), request(u8_sample(2))]).unwrap();
+        assert_eq!(inputs[0].1.shape(), [3, 2]);
+        assert_eq!(inputs[0].1.as_slice::<u8>().unwrap(), [1, 1, 2, 2, 0, 0]);
+        // Mixed types are converted to f32.
+        let inputs = batch_inputs(&infos, &[request(u8_sample(1)), request(sample(0.5))]).unwrap();
+        assert_eq!(
+            inputs[0].1.as_slice::<f32>().unwrap(),
+            [1.0, 1.0, 0.5, -0.5, 0.0, 0.0]
+        );
+
+        let y = Tensor::new(vec![3, 1, 2], vec![1_i32, 2, 3, 4, 5, 6]).unwrap();
+        let outputs = Outputs::new(vec!["y".to_string()], vec![y]);
+        let split = split_outputs(outputs.clone(), 3, 2).unwrap();
+        assert_eq!(split.len(), 2);
+        let y = split[1].get("y").unwrap();
+        assert_eq!(y.shape(), [1, 1, 2]);
+        assert_eq!(y.as_slice::<i32>().unwrap(), [3, 4]);
+        let error = split_outputs(outputs, 4, 2).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<BatchError>(),
+            Some(&BatchError::UnbatchedOutput {
+                name: "y".to_string(),
+                shape: vec![3, 1, 2],
+                batch: 4
+            })
+        );
+    }
+
+    #[test]
+    fn test_full_batches() {
+        let batches = Arc::new(Mutex::new(vec![]));
+        // Batches are only run full, or when the scheduler is dropped.
+        let scheduler = BatchSchedulerBuilder::new(Double::boxed(&batches))
+            .with_timeout(Duration::from_secs(60))
+            .build()
+            .unwrap();
+        assert_eq!(scheduler.batch(), 4);
+        let scheduler = Arc::new(scheduler);
+        let clients = (0..4)
+            .map(|client| {
+                let scheduler = scheduler.clone();
+                thread::spawn(move || scheduler.run(sample(client as f32), None).unwrap())
+            })
+            .collect::<Vec<_>>();
+        for (client, handle) in clients.into_iter().enumerate() {
+            let x = handle.join().unwrap().get("x").unwrap().to_f32();
+            assert_eq!(x, [2.0 * client as f32, -2.0 * client as f32]);
+        }
+        assert_eq!(batches.lock().unwrap().len(), 1);
+
+        let tickets = (0..6)
+            .map(|it| scheduler.submit(sample(it as f32), None).unwrap())
+            .collect::<Vec<_>>();
+        let scheduler = Arc::into_inner(scheduler).unwrap();
+        let stats = {
+            let stats = scheduler.stats.clone();
+            drop(scheduler);
+            let stats = stats.lock().unwrap().clone();
+            stats
+        };
+        for (index, ticket) in tickets.into_iter().enumerate() {
+            let x = ticket.wait().unwrap().get("x").unwrap().to_f32();
+            assert_eq!(x[0], 2.0 * index as f32);
+        }
+        assert_eq!(stats.batches, 3);
+        assert_eq!(stats.requests, 10);
+        assert_eq!(stats.mean_batch_size(), Some(10.0 / 3.0));
+        // The last batch of 2 requests is padded with zeros.
+        assert_eq!(
+            batches.lock().unwrap()[2],
+            [4.0, -4.0, 5.0, -5.0, 0.0, 0.0, 0.0, 0.0]
+        );
+    }
+
+    #[test]
+    fn test_deadlines() {
+        let batches = Arc::new(Mutex::new(vec![]));
+        let scheduler = BatchSchedulerBuilder::new(Double::boxed(&batches))
+            .with_max_batch(2)
+            .with_timeout(Duration::from_secs(60))
+            .with_run_estimate(Duration::from_millis(20))
+            .build()
+            .unwrap();
+        // Run early to meet the deadline, despite the timeout.
+        let deadline = Instant::now() + Duration::from_millis(50);
+        let outputs = scheduler.run(sample(1.0), Some(deadline)).unwrap();
+        assert_eq!(outputs.get("x").unwrap().to_f32(), [2.0, -2.0]);
+
+        let error = scheduler
+            .run(sample(1.0), Some(Instant::now()))
+            .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<BatchError>(),
+            Some(&BatchError::DeadlineExceeded)
+        );
+        let stats = scheduler.stats();
+        assert_eq!((stats.batches, stats.requests, stats.expired), (1, 1, 1));
+    }
+
+    #[test]
+    fn test_invalid() {
+        let batches = Arc::new(Mutex::new(vec![]));
+        let error = BatchSchedulerBuilder::new(Double::boxed(&batches))
+            .with_max_batch(5)
+            .build()
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.downcast_ref::<BatchError>(),
+            Some(&BatchError::InvalidMaxBatch {
+                max_batch: 5,
+                batch: 4
+            })
+        );
+
+        let scheduler = BatchSchedulerBuilder::new(Double::boxed(&batches))
+            .build()
+            .unwrap();
+        let x = Tensor::new(vec![4, 2], vec![0.0_f32; 8]).unwrap();
+        let error = scheduler
+            .submit(vec![("x".to_string(), x)], None)
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.downcast_ref::<RuntimeError>(),
+            Some(&RuntimeError::ElementCountMismatch {
+                name: "x".to_string(),
+                expected: 2,
+                actual: 8
+            })
+        );
+        let error = scheduler.submit(vec![], None).err().unwrap();
+        assert_eq!(
+            error.downcast_ref::<RknnInputError>(),
+            Some(&RknnInputError::Missing {
+                index: 0,
+                name: "x".to_string()
+            })
+        );
+        drop(scheduler);
+        assert!(batches.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,92 @@
+use std::path::PathBuf;
+
+use rknpu::tensors::types::RknnTensorType;
+use thiserror::Error;
+
+use crate::backend::BackendKind;
+
+/// Errors raised when tensors don't match their shape or the model tensors they are given to.
+#[derive(Debug, Error, PartialEq)]
+pub enum RuntimeError {
+    #[error("Shape {shape:?} holds {expected} element(s), got {actual}.")]
+    InvalidShape {
+        shape: Vec<usize>,
+        expected: usize,
+        actual: usize,
+    },
+    #[error("Tensor '{name}' expects {expected} element(s), got {actual}.")]
+    ElementCountMismatch {
+        name: String,
+        expected: usize,
+        actual: usize,
+    },
+    #[error("Tensor holds {actual:?} values, not {expected:?}.")]
+    TypeMismatch {
+        expected: RknnTensorType,
+        actual: RknnTensorType,
+    },
+    #[error("Tensor '{name}' has unsupported type {dtype:?}.")]
+    UnsupportedType { name: String, dtype: RknnTensorType },
+}
+
+/// Errors raised when selecting or loading an inference backend.
+#[derive(Debug, Error, PartialEq)]
+pub enum BackendError {
+    #[error("Unknown backend '{0}', expected 'rknn' or 'cpu'.")]
+    UnknownBackend(String),
+    #[error("No backend runs model {0}, expected a .rknn or .onnx file.")]
+    UnknownModelFormat(PathBuf),
+    #[error(
+        "TFLite model {0} can't be run on the CPU, convert it to ONNX first, e.g. with tf2onnx."
+    )]
+    TfliteModel(PathBuf),
+    #[error("Backend '{0}' is not available, enable the `{0}` feature of rknpu-runtime.")]
+    Unavailable(BackendKind),
+    #[error("Tensor '{name}' has no fixed shape, got {shape}.")]
+    SymbolicShape { name: String, shape: String },
+    #[error("Tensor '{name}' has unsupported type {dtype}.")]
+    UnsupportedType { name: String, dtype: String },
+}
+
+/// Errors raised when building or feeding a pipeline.
+#[derive(Debug, Error, PartialEq)]
+pub enum PipelineError {
+    #[error("A pipeline needs at least one backend.")]
+    NoBackend,
+    #[error("The pipeline is closed.")]
+    Closed,
+    #[error("The {0} of the frame panicked.")]
+    Panicked(&'static str),
+}
+
+/// Errors raised when building or feeding a batch scheduler.
+#[derive(Debug, Error, PartialEq)]
+pub enum BatchError {
+    #[error("The model inputs don't share a batch dimension.")]
+    NoBatchDimension,
+    #[error(
+        "Batches of {max_batch} request(s) don't fit the batch dimension {batch} of the model."
+    )]
+    InvalidMaxBatch { max_batch: usize, batch: usize },
+    #[error("Output '{name}' of shape {shape:?} has no batch dimension of {batch}.")]
+    UnbatchedOutput {
+        name: String,
+        shape: Vec<usize>,
+        batch: usize,
+    },
+    #[error("The request can't be run before its deadline.")]
+    DeadlineExceeded,
+    #[error("The batch scheduler is stopped.")]
+    Stopped,
+}
+
+/// Errors raised when building or feeding a multi-model scheduler.
+#[derive(Debug, Error, PartialEq)]
+pub enum SchedulerError {
+    #[error("Model '{0}' is added twice.")]
+    DuplicateModel(String),
+    #[error("Unknown model '{0}'.")]
+    UnknownModel(String),
+    #[error("The scheduler is stopped.")]
+    Stopped,
+}
@@ -1 +1,35 @@
+//! High-level inference on RKNN models, built on [`rknpu::context::RknnContext`].
+//!
+//! A [`Model`] holds the model data and creates [`InferenceSession`]s, one runtime context each.
+//! Sessions take and return [`Tensor`]s addressed by name: f32 inputs are quantized to the type
+//! of the model inputs, and quantized outputs are dequantized to f32, based on the tensor
+//! attributes. Sessions also time their inferences, see [`TimingStats`].
+//!
+//! The [`backend`] module runs models behind the [`InferenceBackend`] trait, on the NPU or, with
+//! the `cpu` feature, on the CPU. The [`pipeline`] module runs the preprocessing, inference and
+//! postprocessing of frames on separate threads, the [`batch`] module gathers the requests of
+//! several clients into batches, and the [`scheduler`] module shares the NPU between several
+//! models by priority.
 
+pub use self::{
+    backend::{BackendKind, InferenceBackend, TensorInfo},
+    batch::{BatchScheduler, BatchSchedulerBuilder},
+    error::RuntimeError,
+    model::Model,
+    pipeline::{Pipeline, PipelineBuilder},
+    scheduler::{Priority, Scheduler, SchedulerBuilder},
+    session::{InferenceSession, Outputs},
+    stats::{InferenceTimings, TimingStats},
+    tensor::{Tensor, TensorData, TensorElement},
+};
+
+pub mod backend;
+pub mod batch;
+pub mod error;
+mod model;
+pub mod pipeline;
+pub mod quant;
+pub mod scheduler;
+mod session;
+mod stats;
+mod tensor;
@@ -0,0 +1,64 @@
+use std::{path::Path, sync::Arc};
+
+use anyhow::{Context, Result};
+use rknpu::{
+    context::RknnContext,
+    driver::{NativeDriver, RknnDriver},
+    flags::RknnExtendedFlag,
+};
+
+use crate::session::InferenceSession;
+
+/// The data of a RKNN model, from which sessions are created.
+///
+/// The model data is shared: every session loads it in its own runtime context, so several
+/// sessions of a model can run in parallel.
+pub struct Model {
+    data: Arc<[u8]>,
+    flag: RknnExtendedFlag,
+    driver: Arc<dyn RknnDriver>,
+}
+
+impl Model {
+    /// Read a model file.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let data = std::fs::read(path)
+            .with_context(|| format!("Unable to read model file {}", path.display()))?;
+        Ok(Self::from_bytes(data))
+    }
+
+    pub fn from_bytes(data: impl Into<Arc<[u8]>>) -> Self {
+        Self::with_driver(data, Arc::new(NativeDriver))
+    }
+
+    /// Create a model whose sessions run through the given driver.
+    pub fn with_driver(data: impl Into<Arc<[u8]>>, driver: Arc<dyn RknnDriver>) -> Self {
+        Self {
+            data: data.into(),
+            flag: RknnExtendedFlag::RKNN_FLAG_PRIOR_HIGH,
+            driver,
+        }
+    }
+
+    /// Flag the contexts are initialized with, [`RknnExtendedFlag::RKNN_FLAG_PRIOR_HIGH`] by
+    /// default as in the runtime.
+    pub fn with_flag(mut self, flag: RknnExtendedFlag) -> Self {
+        self.flag = flag;
+        self
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Load the model in a new runtime context.
+    pub fn context(&self) -> Result<RknnContext> {
+        RknnContext::with_driver(&self.data, self.flag.clone(), self.driver.clone())
+    }
+
+    /// Load the model in a new session.
+    pub fn session(&self) -> Result<InferenceSession> {
+        Ok(InferenceSession::new(self.context()?))
+    }
+}
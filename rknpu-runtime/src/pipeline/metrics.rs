@@ -0,0 +1,77 @@
+use std::{fmt, time::Duration};
+
+/// Time spent by the frames in a stage of a pipeline.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StageMetrics {
+    count: u64,
+    total: Duration,
+    max: Duration,
+}
+
+impl StageMetrics {
+    pub(crate) fn record(&mut self, duration: Duration) {
+        self.count += 1;
+        self.total += duration;
+        self.max = self.max.max(duration);
+    }
+
+    /// Number of frames recorded.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn total(&self) -> Duration {
+        self.total
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        (self.count > 0).then(|| self.total.div_f64(self.count as f64))
+    }
+
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+}
+
+impl fmt::Display for StageMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.mean() {
+            Some(mean) => write!(f, "mean {mean:?}, max {:?} ({})", self.max, self.count),
+            None => write!(f, "-"),
+        }
+    }
+}
+
+/// Counters and timings of a pipeline.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PipelineMetrics {
+    /// Frames accepted by the pipeline.
+    pub submitted: u64,
+    /// Frames dropped by the [drop policy](super::DropPolicy).
+    pub dropped: u64,
+    /// Frames waiting for a preprocessing worker.
+    pub queued: usize,
+    /// Results delivered, failed ones included.
+    pub delivered: u64,
+    /// Results delivered with an error.
+    pub failed: u64,
+    pub preprocess: StageMetrics,
+    pub inference: StageMetrics,
+    pub postprocess: StageMetrics,
+    /// Time from the submission of the frames to the delivery of their results.
+    pub latency: StageMetrics,
+}
+
+impl fmt::Display for PipelineMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Frames: {} submitted, {} dropped, {} queued, {} delivered, {} failed",
+            self.submitted, self.dropped, self.queued, self.delivered, self.failed
+        )?;
+        writeln!(f, "Preprocess: {}", self.preprocess)?;
+        writeln!(f, "Inference: {}", self.inference)?;
+        writeln!(f, "Postprocess: {}", self.postprocess)?;
+        write!(f, "Latency: {}", self.latency)
+    }
+}
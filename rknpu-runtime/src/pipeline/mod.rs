@@ -0,0 +1,598 @@
+//! Pipelines running the preprocessing, the inference and the postprocessing of frames on
+//! separate threads.
+//!
+//! Frames are submitted to a bounded queue, from which preprocessing workers turn them into
+//! model inputs. The inputs are run by one thread per [backend](InferenceBackend), e.g. one per
+//! NPU core, and the outputs turned into results by postprocessing workers. Stages are connected
+//! by bounded channels: a slow stage blocks the previous ones, up to the submission of frames,
+//! which then waits or drops frames depending on the [`DropPolicy`]. Results are delivered in the
+//! order of the frames. A stage panicking on a frame fails that frame only.
+
+use std::{
+    collections::BTreeMap,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        mpsc::{self, Receiver, RecvError, SyncSender, TryRecvError},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+
+use crate::{backend::InferenceBackend, error::PipelineError, session::Outputs, tensor::Tensor};
+
+use self::queue::{Entry, FrameQueue};
+pub use self::{
+    metrics::{PipelineMetrics, StageMetrics},
+    queue::DropPolicy,
+};
+
+mod metrics;
+mod queue;
+
+/// Model inputs, by name.
+pub type Inputs = Vec<(String, Tensor)>;
+
+type Preprocess<I> = dyn Fn(I) -> Result<Inputs> + Send + Sync;
+type Postprocess<O> = dyn Fn(Outputs) -> Result<O> + Send + Sync;
+
+/// A frame between two stages.
+struct Job<T> {
+    id: u64,
+    seq: u64,
+    submitted: Instant,
+    value: Result<T>,
+}
+
+/// Configures and starts a [`Pipeline`].
+pub struct PipelineBuilder<I, O> {
+    backends: Vec<Box<dyn InferenceBackend>>,
+    preprocess: Arc<Preprocess<I>>,
+    postprocess: Arc<Postprocess<O>>,
+    preprocess_workers: usize,
+    postprocess_workers: usize,
+    capacity: usize,
+    drop_policy: DropPolicy,
+    ordered: bool,
+}
+
+impl<I: Send + 'static, O: Send + 'static> PipelineBuilder<I, O> {
+    /// A pipeline turning frames into inputs with `preprocess`, running them on one of
+    /// `backends`, and turning the outputs into results with `postprocess`.
+    pub fn new<P, Q>(
+        backends: Vec<Box<dyn InferenceBackend>>,
+        preprocess: P,
+        postprocess: Q,
+    ) -> Self
+    where
+        P: Fn(I) -> Result<Inputs> + Send + Sync + 'static,
+        Q: Fn(Outputs) -> Result<O> + Send + Sync + 'static,
+    {
+        Self {
+            backends,
+            preprocess: Arc::new(preprocess),
+            postprocess: Arc::new(postprocess),
+            preprocess_workers: 1,
+            postprocess_workers: 1,
+            capacity: 2,
+            drop_policy: DropPolicy::default(),
+            ordered: true,
+        }
+    }
+
+    /// Number of preprocessing threads, 1 by default.
+    pub fn with_preprocess_workers(mut self, workers: usize) -> Self {
+        self.preprocess_workers = workers.max(1);
+        self
+    }
+
+    /// Number of postprocessing threads, 1 by default.
+    pub fn with_postprocess_workers(mut self, workers: usize) -> Self {
+        self.postprocess_workers = workers.max(1);
+        self
+    }
+
+    /// Number of frames waiting for each stage, 2 by default.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    /// What to do with frames submitted while the input queue is full, block by default.
+    pub fn with_drop_policy(mut self, drop_policy: DropPolicy) -> Self {
+        self.drop_policy = drop_policy;
+        self
+    }
+
+    /// Whether results are delivered in the order of the frames, or as soon as they are ready.
+    pub fn with_ordered(mut self, ordered: bool) -> Self {
+        self.ordered = ordered;
+        self
+    }
+
+    /// Start the threads of the pipeline.
+    pub fn build(self) -> Result<Pipeline<I, O>> {
+        if self.backends.is_empty() {
+            return Err(PipelineError::NoBackend.into());
+        }
+        let queue = Arc::new(FrameQueue::new(self.capacity, self.drop_policy));
+        let metrics = Arc::new(Mutex::new(PipelineMetrics::default()));
+        let (inputs_tx, inputs_rx) = mpsc::sync_channel::<Job<Inputs>>(self.capacity);
+        let (outputs_tx, outputs_rx) = mpsc::sync_channel::<Job<Outputs>>(self.capacity);
+        let (results_tx, results_rx) = mpsc::sync_channel::<Job<O>>(self.capacity);
+        let inputs_rx = Arc::new(Mutex::new(inputs_rx));
+        let outputs_rx = Arc::new(Mutex::new(outputs_rx));
+        let mut workers = Vec::new();
+
+        for index in 0..self.preprocess_workers {
+            let queue = queue.clone();
+            let preprocess = self.preprocess.clone();
+            let metrics = metrics.clone();
+            let tx = inputs_tx.clone();
+            workers.push(spawn("preprocess", index, move || {
+                while let Some(entry) = queue.pop() {
+                    let Entry {
+                        id,
+                        seq,
+                        submitted,
+                        frame,
+                    } = entry;
+                    let start = Instant::now();
+                    let value = catch_panic("preprocessing", || preprocess(frame));
+                    metrics.lock().unwrap().preprocess.record(start.elapsed());
+                    let job = Job {
+                        id,
+                        seq,
+                        submitted,
+                        value,
+                    };
+                    if tx.send(job).is_err() {
+                        break;
+                    }
+                }
+            })?);
+        }
+        for (index, mut backend) in self.backends.into_iter().enumerate() {
+            let rx = inputs_rx.clone();
+            let metrics = metrics.clone();
+            let tx = outputs_tx.clone();
+            workers.push(spawn("inference", index, move || {
+                stage(&rx, &tx, "inference", |inputs: Inputs| {
+                    let (names, tensors): (Vec<_>, Vec<_>) = inputs.into_iter().unzip();
+                    let inputs = names.iter().map(String::as_str).zip(tensors).collect();
+                    let start = Instant::now();
+                    let outputs = backend.run(inputs);
+                    metrics.lock().unwrap().inference.record(start.elapsed());
+                    outputs
+                })
+            })?);
+        }
+        for index in 0..self.postprocess_workers {
+            let rx = outputs_rx.clone();
+            let postprocess = self.postprocess.clone();
+            let metrics = metrics.clone();
+            let tx = results_tx.clone();
+            workers.push(spawn("postprocess", index, move || {
+                stage(&rx, &tx, "postprocessing", |outputs| {
+                    let start = Instant::now();
+                    let result = postprocess(outputs);
+                    metrics.lock().unwrap().postprocess.record(start.elapsed());
+                    result
+                })
+            })?);
+        }
+
+        Ok(Pipeline {
+            queue,
+            metrics,
+            results: Some(results_rx),
+            ordered: self.ordered,
+            next_seq: 0,
+            pending: BTreeMap::new(),
+            workers,
+        })
+    }
+}
+
+fn spawn<F>(stage: &str, index: usize, f: F) -> Result<JoinHandle<()>>
+where
+    F: FnOnce() + Send + 'static,
+{
+    Ok(thread::Builder::new()
+        .name(format!("rknpu-{stage}-{index}"))
+        .spawn(f)?)
+}
+
+/// Call `f`, turning a panic into an error so that the frame still gets a result, in its place
+/// among the results.
+fn catch_panic<T>(stage: &'static str, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    panic::catch_unwind(AssertUnwindSafe(f))
+        .unwrap_or_else(|_| Err(PipelineError::Panicked(stage).into()))
+}
+
+/// Run `f` on the jobs received from `rx` and send the results to `tx`, until either channel
+/// is disconnected. Failed jobs are passed on.
+fn stage<T, U>(
+    rx: &Mutex<Receiver<Job<T>>>,
+    tx: &SyncSender<Job<U>>,
+    name: &'static str,
+    mut f: impl FnMut(T) -> Result<U>,
+) {
+    loop {
+        let Ok(job) = rx.lock().unwrap().recv() else {
+            break;
+        };
+        let job = Job {
+            id: job.id,
+            seq: job.seq,
+            submitted: job.submitted,
+            value: job.value.and_then(|value| catch_panic(name, || f(value))),
+        };
+        if tx.send(job).is_err() {
+            break;
+        }
+    }
+}
+
+/// The result of a frame.
+#[derive(Debug)]
+pub struct PipelineOutput<O> {
+    /// Index of the frame among the submitted frames, as returned by [`Pipeline::submit`].
+    pub id: u64,
+    pub result: Result<O>,
+    /// Time from the submission of the frame to the delivery of its result.
+    pub latency: Duration,
+}
+
+/// Frames processed by preprocessing, inference and postprocessing threads, see the
+/// [module](self) documentation.
+///
+/// Dropping the pipeline stops its threads, discarding the frames being processed.
+pub struct Pipeline<I, O> {
+    queue: Arc<FrameQueue<I>>,
+    metrics: Arc<Mutex<PipelineMetrics>>,
+    /// `None` once dropped, to stop the threads.
+    results: Option<Receiver<Job<O>>>,
+    ordered: bool,
+    /// Sequence number of the next result to deliver.
+    next_seq: u64,
+    /// Results received before the results of previous frames.
+    pending: BTreeMap<u64, Job<O>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl<I, O> Pipeline<I, O> {
+    /// Submit a frame, returning its id, or `None` if the pipeline is full and the
+    /// [drop policy](DropPolicy::DropNewest) dropped it.
+    pub fn submit(&self, frame: I) -> Result<Option<u64>> {
+        let pushed = self.queue.push(frame).ok_or(PipelineError::Closed)?;
+        let mut metrics = self.metrics.lock().unwrap();
+        metrics.submitted += pushed.id.is_some() as u64;
+        metrics.dropped += pushed.dropped as u64;
+        Ok(pushed.id)
+    }
+
+    /// Stop accepting frames. The frames already submitted are still processed, after which
+    /// [`recv`](Self::recv) returns `None`.
+    pub fn close(&self) {
+        self.queue.close();
+    }
+
+    /// Wait for the next result. `None` once the pipeline is closed and every result delivered.
+    pub fn recv(&mut self) -> Option<PipelineOutput<O>> {
+        self.next_output(true)
+    }
+
+    /// Next result if ready.
+    pub fn try_recv(&mut self) -> Option<PipelineOutput<O>> {
+        self.next_output(false)
+    }
+
+    /// Close the pipeline and wait for the results of the frames already submitted.
+    pub fn finish(mut self) -> Vec<PipelineOutput<O>> {
+        self.close();
+        std::iter::from_fn(|| self.recv()).collect()
+    }
+
+    pub fn metrics(&self) -> PipelineMetrics {
+        let mut metrics = self.metrics.lock().unwrap().clone();
+        metrics.queued = self.queue.len();
+        metrics
+    }
+
+    fn next_output(&mut self, block: bool) -> Option<PipelineOutput<O>> {
+        loop {
+            if let Some(job) = self.pending.remove(&self.next_seq) {
+                self.next_seq += 1;
+                return Some(self.deliver(job));
+            }
+            let results = self.results.as_ref()?;
+            let received = if block {
+                results
+                    .recv()
+                    .map_err(|RecvError| TryRecvError::Disconnected)
+            } else {
+                results.try_recv()
+            };
+            match received {
+                Ok(job) if !self.ordered => return Some(self.deliver(job)),
+                Ok(job) => {
+                    self.pending.insert(job.seq, job);
+                }
+                Err(TryRecvError::Empty) => return None,
+                // Every thread stopped: the frames of a panicking thread are missing.
+                Err(TryRecvError::Disconnected) => {
+                    let (seq, job) = self.pending.pop_first()?;
+                    self.next_seq = seq + 1;
+                    return Some(self.deliver(job));
+                }
+            }
+        }
+    }
+
+    fn deliver(&mut self, job: Job<O>) -> PipelineOutput<O> {
+        let latency = job.submitted.elapsed();
+        let mut metrics = self.metrics.lock().unwrap();
+        metrics.delivered += 1;
+        metrics.failed += job.value.is_err() as u64;
+        metrics.latency.record(latency);
+        PipelineOutput {
+            id: job.id,
+            result: job.value,
+            latency,
+        }
+    }
+}
+
+impl<I, O> Drop for Pipeline<I, O> {
+    fn drop(&mut self) {
+        self.queue.close();
+        // Threads stop when they can't pass their results on.
+        self.results = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{Arc, Mutex},
+        thread,
+        time::Duration,
+    };
+
+    use anyhow::{bail, Result};
+    use rknpu::tensors::types::RknnTensorType;
+
+    use crate::{
+        backend::{BackendKind, InferenceBackend, TensorInfo},
+        error::PipelineError,
+        session::Outputs,
+        tensor::Tensor,
+    };
+
+    use super::{DropPolicy, Inputs, PipelineBuilder};
+
+    /// Doubles its input, taking longer on odd values so that results come out of order, and
+    /// panics on negative values.
+    struct Double {
+        info: Vec<TensorInfo>,
+        /// Held by tests to stall the inference.
+        gate: Arc<Mutex<()>>,
+    }
+
+    impl Double {
+        fn boxed(gate: &Arc<Mutex<()>>) -> Box<dyn InferenceBackend> {
+            Box::new(Self {
+                info: vec![TensorInfo {
+                    name: "x".to_string(),
+                    shape: vec![1],
+                    dtype: RknnTensorType::F32,
+                }],
+                gate: gate.clone(),
+            })
+        }
+    }
+
+    impl InferenceBackend for Double {
+        fn kind(&self) -> BackendKind {
+            BackendKind::Cpu
+        }
+
+        fn inputs(&self) -> &[TensorInfo] {
+            &self.info
+        }
+
+        fn outputs(&self) -> &[TensorInfo] {
+            &self.info
+        }
+
+        fn run(&mut self, inputs: Vec<(&str, Tensor)>) -> Result<Outputs> {
+            let x = inputs[0].1.to_f32()[0];
+            assert!(x >= 0.0, "negative input");
+            let _gate = self.gate.lock().unwrap();
+            if x as u32 % 2 == 1 {
+                thread::sleep(Duration::from_millis(5));
+            }
+            let y = Tensor::new(vec![1], vec![2.0 * x])?;
+            Ok(Outputs::new(vec!["x".to_string()], vec![y]))
+        }
+    }
+
+    fn preprocess(frame: u32) -> Result<Inputs> {
+        if frame == 3 {
+            bail!("Corrupted frame");
+        }
+        Ok(vec![(
+            "x".to_string(),
+            Tensor::new(vec![1], vec![frame as f32])?,
+        )])
+    }
+
+    fn postprocess(outputs: Outputs) -> Result<u32> {
+        Ok(outputs.get("x")?.to_f32()[0] as u32)
+    }
+
+    #[test]
+    fn test_ordered() {
+        let gate = Arc::new(Mutex::new(()));
+        let mut pipeline = PipelineBuilder::new(
+            vec![Double::boxed(&gate), Double::boxed(&gate)],
+            preprocess,
+            postprocess,
+        )
+        .with_preprocess_workers(2)
+        .with_postprocess_workers(3)
+        .build()
+        .unwrap();
+        for frame in 0..10 {
+            assert_eq!(pipeline.submit(frame).unwrap(), Some(frame as u64));
+        }
+        let first = pipeline.recv().unwrap();
+        assert_eq!(first.id, 0);
+        assert_eq!(first.result.unwrap(), 0);
+        let outputs = pipeline.finish();
+        assert_eq!(
+            outputs.iter().map(|it| it.id).collect::<Vec<_>>(),
+            (1..10).collect::<Vec<_>>()
+        );
+        for output in outputs {
+            match output.id {
+                3 => assert!(output.result.is_err()),
+                id => assert_eq!(output.result.unwrap(), 2 * id as u32),
+            }
+        }
+    }
+
+    #[test]
+    fn test_panics() {
+        let gate = Arc::new(Mutex::new(()));
+        // Frame 2 panics in the preprocessing, 5 in the inference and 7 in the postprocessing.
+        let preprocess = |frame: u32| match frame {
+            2 => panic!("preprocess"),
+            5 => Ok(vec![("x".to_string(), Tensor::new(vec![1], vec![-1.0])?)]),
+            frame => preprocess(frame),
+        };
+        let postprocess = |outputs: Outputs| match postprocess(outputs)? {
+            14 => panic!("postprocess"),
+            y => Ok(y),
+        };
+        let pipeline = PipelineBuilder::new(
+            vec![Double::boxed(&gate), Double::boxed(&gate)],
+            preprocess,
+            postprocess,
+        )
+        .build()
+        .unwrap();
+        for frame in 0..10 {
+            pipeline.submit(frame).unwrap();
+        }
+        // Every frame gets a result, in order, the frames after a panic too.
+        let outputs = pipeline.finish();
+        assert_eq!(
+            outputs.iter().map(|it| it.id).collect::<Vec<_>>(),
+            (0..10).collect::<Vec<_>>()
+        );
+        for (id, stage) in [
+            (2, "preprocessing"),
+            (5, "inference"),
+            (7, "postprocessing"),
+        ] {
+            let error = outputs[id].result.as_ref().unwrap_err();
+            assert_eq!(error.downcast_ref(), Some(&PipelineError::Panicked(stage)));
+        }
+        assert_eq!(outputs[9].result.as_ref().unwrap(), &18);
+    }
+
+    #[test]
+    fn test_drop_newest() {
+        let gate = Arc::new(Mutex::new(()));
+        let stalled = gate.lock().unwrap();
+        let mut pipeline =
+            PipelineBuilder::new(vec![Double::boxed(&gate)], preprocess, postprocess)
+                .with_capacity(1)
+                .with_drop_policy(DropPolicy::DropNewest)
+                .build()
+                .unwrap();
+        // The inference blocks, frames pile up until the input queue drops them.
+        let mut dropped = 0;
+        for frame in 0..20 {
+            if pipeline.submit(frame).unwrap().is_none() {
+                dropped += 1;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(dropped > 0);
+        drop(stalled);
+        pipeline.close();
+        assert_eq!(
+            pipeline.submit(20).unwrap_err().downcast_ref(),
+            Some(&PipelineError::Closed)
+        );
+        let mut ids = vec![];
+        while let Some(output) = pipeline.recv() {
+            ids.push(output.id);
+        }
+        assert_eq!(ids.len() as u64 + dropped, 20);
+        assert!(ids.windows(2).all(|it| it[0] < it[1]));
+
+        let metrics = pipeline.metrics();
+        assert_eq!(metrics.dropped, dropped);
+        assert_eq!(metrics.submitted, 20 - dropped);
+        assert_eq!(metrics.delivered, 20 - dropped);
+        // The preprocessing of frame 3 fails, it is not run.
+        assert_eq!(
+            metrics.inference.count(),
+            metrics.delivered - metrics.failed
+        );
+        assert_eq!(metrics.failed, ids.contains(&3) as u64);
+        assert_eq!(metrics.preprocess.count(), 20 - dropped);
+    }
+
+    #[test]
+    fn test_drop_oldest() {
+        let gate = Arc::new(Mutex::new(()));
+        let stalled = gate.lock().unwrap();
+        let pipeline = PipelineBuilder::new(vec![Double::boxed(&gate)], preprocess, postprocess)
+            .with_capacity(1)
+            .with_drop_policy(DropPolicy::DropOldest)
+            .build()
+            .unwrap();
+        for frame in 0..20 {
+            assert!(pipeline.submit(frame).unwrap().is_some());
+            thread::sleep(Duration::from_millis(1));
+        }
+        drop(stalled);
+        let metrics = pipeline.metrics();
+        let outputs = pipeline.finish();
+        // The last frame is never dropped.
+        assert_eq!(outputs.last().unwrap().id, 19);
+        assert_eq!(outputs.len() as u64 + metrics.dropped, 20);
+    }
+
+    #[test]
+    fn test_no_backend() {
+        let error = PipelineBuilder::new(vec![], preprocess, postprocess)
+            .build()
+            .err()
+            .unwrap();
+        assert_eq!(error.downcast_ref(), Some(&PipelineError::NoBackend));
+    }
+
+    #[test]
+    fn test_drop() {
+        let gate = Arc::new(Mutex::new(()));
+        let pipeline = PipelineBuilder::new(vec![Double::boxed(&gate)], preprocess, postprocess)
+            .build()
+            .unwrap();
+        for frame in 0..4 {
+            pipeline.submit(frame).unwrap();
+        }
+        // Threads blocked on the undelivered results are stopped.
+        drop(pipeline);
+    }
+}
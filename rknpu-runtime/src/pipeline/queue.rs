@@ -0,0 +1,188 @@
+use std::{
+    collections::VecDeque,
+    sync::{Condvar, Mutex},
+    time::Instant,
+};
+
+/// What to do with a frame submitted to a full pipeline.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DropPolicy {
+    /// Wait until the pipeline has room for the frame.
+    #[default]
+    Block,
+    /// Drop the submitted frame.
+    DropNewest,
+    /// Drop the oldest frame waiting to be processed, to keep the latency low on live sources.
+    DropOldest,
+}
+
+/// A frame waiting to be processed.
+pub(crate) struct Entry<T> {
+    /// Index of the frame among the submitted frames.
+    pub id: u64,
+    /// Index of the frame among the processed frames.
+    pub seq: u64,
+    pub submitted: Instant,
+    pub frame: T,
+}
+
+/// Outcome of a push.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Pushed {
+    /// Id of the pushed frame, `None` if it was dropped.
+    pub id: Option<u64>,
+    /// Whether a frame was dropped.
+    pub dropped: bool,
+}
+
+struct State<T> {
+    frames: VecDeque<(u64, Instant, T)>,
+    next_id: u64,
+    next_seq: u64,
+    closed: bool,
+}
+
+/// Bounded queue of the frames submitted to a pipeline.
+///
+/// Frames are numbered twice: by id when pushed, and by sequence number when popped, so that
+/// the popped frames are numbered without gaps whatever frames were dropped.
+pub(crate) struct FrameQueue<T> {
+    state: Mutex<State<T>>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    capacity: usize,
+    policy: DropPolicy,
+}
+
+impl<T> FrameQueue<T> {
+    pub fn new(capacity: usize, policy: DropPolicy) -> Self {
+        Self {
+            state: Mutex::new(State {
+                frames: VecDeque::with_capacity(capacity),
+                next_id: 0,
+                next_seq: 0,
+                closed: false,
+            }),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            capacity: capacity.max(1),
+            policy,
+        }
+    }
+
+    /// Push a frame, `None` if the queue is closed.
+    pub fn push(&self, frame: T) -> Option<Pushed> {
+        let mut state = self.state.lock().unwrap();
+        let mut dropped = false;
+        while !state.closed && state.frames.len() >= self.capacity {
+            match self.policy {
+                DropPolicy::Block => state = self.not_full.wait(state).unwrap(),
+                DropPolicy::DropNewest => {
+                    return Some(Pushed {
+                        id: None,
+                        dropped: true,
+                    })
+                }
+                DropPolicy::DropOldest => {
+                    state.frames.pop_front();
+                    dropped = true;
+                }
+            }
+        }
+        if state.closed {
+            return None;
+        }
+        let id = state.next_id;
+        state.next_id += 1;
+        state.frames.push_back((id, Instant::now(), frame));
+        self.not_empty.notify_one();
+        Some(Pushed {
+            id: Some(id),
+            dropped,
+        })
+    }
+
+    /// Pop the oldest frame, waiting for one. `None` once the queue is closed and empty.
+    pub fn pop(&self) -> Option<Entry<T>> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some((id, submitted, frame)) = state.frames.pop_front() {
+                let seq = state.next_seq;
+                state.next_seq += 1;
+                self.not_full.notify_one();
+                return Some(Entry {
+                    id,
+                    seq,
+                    submitted,
+                    frame,
+                });
+            }
+            if state.closed {
+                return None;
+            }
+            state = self.not_empty.wait(state).unwrap();
+        }
+    }
+
+    /// Refuse new frames. Queued frames can still be popped.
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.not_empty.notify_all();
+        self.not_full.notify_all();
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().frames.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DropPolicy, FrameQueue, Pushed};
+
+    fn pushed(id: Option<u64>, dropped: bool) -> Option<Pushed> {
+        Some(Pushed { id, dropped })
+    }
+
+    #[test]
+    fn test_drop_policies() {
+        let queue = FrameQueue::new(2, DropPolicy::DropNewest);
+        assert_eq!(queue.push('a'), pushed(Some(0), false));
+        assert_eq!(queue.push('b'), pushed(Some(1), false));
+        assert_eq!(queue.push('c'), pushed(None, true));
+        assert_eq!(queue.len(), 2);
+        let entry = queue.pop().unwrap();
+        assert_eq!((entry.id, entry.seq, entry.frame), (0, 0, 'a'));
+        assert_eq!(queue.push('d'), pushed(Some(2), false));
+        let entry = queue.pop().unwrap();
+        assert_eq!((entry.id, entry.seq, entry.frame), (1, 1, 'b'));
+
+        let queue = FrameQueue::new(2, DropPolicy::DropOldest);
+        for frame in ['a', 'b'] {
+            queue.push(frame);
+        }
+        assert_eq!(queue.push('c'), pushed(Some(2), true));
+        let entry = queue.pop().unwrap();
+        assert_eq!((entry.id, entry.seq, entry.frame), (1, 0, 'b'));
+        let entry = queue.pop().unwrap();
+        assert_eq!((entry.id, entry.seq, entry.frame), (2, 1, 'c'));
+
+        queue.push('d');
+        queue.close();
+        assert_eq!(queue.push('e'), None);
+        assert_eq!(queue.pop().unwrap().frame, 'd');
+        assert!(queue.pop().is_none());
+    }
+
+    #[test]
+    fn test_block() {
+        let queue = FrameQueue::new(1, DropPolicy::Block);
+        queue.push(0);
+        std::thread::scope(|scope| {
+            let pusher = scope.spawn(|| queue.push(1));
+            assert_eq!(queue.pop().unwrap().frame, 0);
+            assert_eq!(pusher.join().unwrap(), pushed(Some(1), false));
+        });
+        assert_eq!(queue.pop().unwrap().frame, 1);
+    }
+}
@@ -0,0 +1,124 @@
+//! Conversions between f32 values and the quantized types of the model tensors.
+
+use anyhow::Result;
+use half::f16;
+use rknpu::tensors::{
+    attributes::RknnTensorAttribute,
+    types::{RknnTensorQuantFormat, RknnTensorType},
+};
+
+use crate::{error::RuntimeError, tensor::TensorData};
+
+/// Convert f32 values to the type of `attribute`, quantized with its quantization format.
+/// Integers are rounded and saturated to the range of their type.
+pub fn quantize(values: &[f32], attribute: &RknnTensorAttribute) -> Result<TensorData> {
+    let quant = &attribute.quant_type;
+    let quantized = values.iter().map(|it| quantize_value(*it, quant));
+    let data = match attribute.data_type {
+        RknnTensorType::F32 => TensorData::F32(quantized.collect()),
+        RknnTensorType::F16 => TensorData::F16(quantized.map(f16::from_f32).collect()),
+        // Float to integer casts saturate.
+        RknnTensorType::I8 => TensorData::I8(quantized.map(|it| it.round() as i8).collect()),
+        RknnTensorType::U8 => TensorData::U8(quantized.map(|it| it.round() as u8).collect()),
+        RknnTensorType::I16 => TensorData::I16(quantized.map(|it| it.round() as i16).collect()),
+        RknnTensorType::U16 => TensorData::U16(quantized.map(|it| it.round() as u16).collect()),
+        RknnTensorType::I32 => TensorData::I32(quantized.map(|it| it.round() as i32).collect()),
+        RknnTensorType::U32 => TensorData::U32(quantized.map(|it| it.round() as u32).collect()),
+        RknnTensorType::I64 => TensorData::I64(quantized.map(|it| it.round() as i64).collect()),
+        dtype @ (RknnTensorType::BOOL | RknnTensorType::MAX) => {
+            return Err(RuntimeError::UnsupportedType {
+                name: attribute.name.clone(),
+                dtype,
+            }
+            .into())
+        }
+    };
+    Ok(data)
+}
+
+/// Convert values quantized with `quant` to f32.
+pub fn dequantize(data: &TensorData, quant: &RknnTensorQuantFormat) -> Vec<f32> {
+    let mut values = data.to_f32();
+    match quant {
+        RknnTensorQuantFormat::None => {}
+        RknnTensorQuantFormat::AffineScale(zero_point, scale) => values
+            .iter_mut()
+            .for_each(|it| *it = (*it - *zero_point as f32) * scale),
+        RknnTensorQuantFormat::DynamicFixedPoint(fraction_length) => {
+            let scale = 2_f32.powi(-(*fraction_length as i32));
+            values.iter_mut().for_each(|it| *it *= scale);
+        }
+    }
+    values
+}
+
+/// Quantized value of `value`, before rounding.
+fn quantize_value(value: f32, quant: &RknnTensorQuantFormat) -> f32 {
+    match quant {
+        RknnTensorQuantFormat::None => value,
+        RknnTensorQuantFormat::AffineScale(zero_point, scale) => value / scale + *zero_point as f32,
+        RknnTensorQuantFormat::DynamicFixedPoint(fraction_length) => {
+            value * 2_f32.powi(*fraction_length as i32)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use half::f16;
+    use rknpu::{
+        driver::stub::tensor_attribute,
+        tensors::types::{RknnTensorQuantFormat, RknnTensorType},
+    };
+
+    use crate::{error::RuntimeError, tensor::TensorData};
+
+    use super::{dequantize, quantize};
+
+    #[test]
+    fn test_affine() {
+        let mut attribute = tensor_attribute(0, "x", &[1, 4], RknnTensorType::I8);
+        attribute.quant_type = RknnTensorQuantFormat::AffineScale(-10, 0.5);
+        let data = quantize(&[0.0, 1.2, -100.0, 100.0], &attribute).unwrap();
+        assert_eq!(data, TensorData::I8(vec![-10, -8, -128, 127]));
+        assert_eq!(
+            dequantize(&data, &attribute.quant_type),
+            [0.0, 1.0, -59.0, 68.5]
+        );
+
+        attribute.data_type = RknnTensorType::U8;
+        attribute.quant_type = RknnTensorQuantFormat::AffineScale(0, 1.0 / 255.0);
+        let data = quantize(&[0.0, 0.6, 1.0, 2.0], &attribute).unwrap();
+        assert_eq!(data, TensorData::U8(vec![0, 153, 255, 255]));
+    }
+
+    #[test]
+    fn test_dynamic_fixed_point() {
+        let mut attribute = tensor_attribute(0, "x", &[1, 3], RknnTensorType::I16);
+        attribute.quant_type = RknnTensorQuantFormat::DynamicFixedPoint(4);
+        let data = quantize(&[1.0, -0.5, 0.03], &attribute).unwrap();
+        assert_eq!(data, TensorData::I16(vec![16, -8, 0]));
+        assert_eq!(dequantize(&data, &attribute.quant_type), [1.0, -0.5, 0.0]);
+    }
+
+    #[test]
+    fn test_float() {
+        let attribute = tensor_attribute(0, "x", &[1, 2], RknnTensorType::F16);
+        let data = quantize(&[0.25, -3.0], &attribute).unwrap();
+        assert_eq!(
+            data,
+            TensorData::F16(vec![f16::from_f32(0.25), f16::from_f32(-3.0)])
+        );
+        assert_eq!(dequantize(&data, &attribute.quant_type), [0.25, -3.0]);
+
+        let attribute = tensor_attribute(0, "mask", &[1, 2], RknnTensorType::BOOL);
+        let error = quantize(&[0.0, 1.0], &attribute).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<RuntimeError>(),
+            Some(&RuntimeError::UnsupportedType {
+                name: "mask".to_string(),
+                dtype: RknnTensorType::BOOL
+            })
+        );
+    }
+}
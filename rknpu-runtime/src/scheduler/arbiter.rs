@@ -0,0 +1,138 @@
+use std::sync::{Condvar, Mutex};
+
+use rknpu::flags::RknnCoreMask;
+
+use super::Priority;
+
+/// NPU cores a context runs on, as a bit set. Contexts left to the runtime may run on any core.
+pub(crate) fn cores(core_mask: RknnCoreMask) -> u32 {
+    match core_mask {
+        RknnCoreMask::RKNN_NPU_CORE_AUTO => u32::MAX,
+        core_mask => core_mask as u32,
+    }
+}
+
+struct Waiter {
+    ticket: u64,
+    priority: Priority,
+    cores: u32,
+}
+
+#[derive(Default)]
+struct State {
+    /// Cores of the running jobs.
+    busy: u32,
+    waiting: Vec<Waiter>,
+    next_ticket: u64,
+}
+
+impl State {
+    /// Whether the waiter of `ticket` may run: its cores are idle, and no waiter sharing a core
+    /// with it goes first, by priority then in arrival order.
+    fn ready(&self, ticket: u64) -> bool {
+        let Some(waiter) = self.waiting.iter().find(|it| it.ticket == ticket) else {
+            return false;
+        };
+        self.busy & waiter.cores == 0
+            && !self.waiting.iter().any(|it| {
+                it.cores & waiter.cores != 0
+                    && (it.priority, waiter.ticket) > (waiter.priority, it.ticket)
+            })
+    }
+}
+
+/// Grants the NPU cores to the jobs of several models: jobs on disjoint cores run in parallel,
+/// jobs sharing a core run one at a time, the highest priority first.
+#[derive(Default)]
+pub(crate) struct Arbiter {
+    state: Mutex<State>,
+    released: Condvar,
+}
+
+impl Arbiter {
+    /// Wait until the cores are granted to the job. They are released when the grant is
+    /// dropped.
+    pub fn acquire(&self, priority: Priority, cores: u32) -> Grant<'_> {
+        let mut state = self.state.lock().unwrap();
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+        state.waiting.push(Waiter {
+            ticket,
+            priority,
+            cores,
+        });
+        while !state.ready(ticket) {
+            state = self.released.wait(state).unwrap();
+        }
+        state.waiting.retain(|it| it.ticket != ticket);
+        state.busy |= cores;
+        Grant {
+            arbiter: self,
+            cores,
+        }
+    }
+}
+
+/// Cores granted to a job.
+pub(crate) struct Grant<'a> {
+    arbiter: &'a Arbiter,
+    cores: u32,
+}
+
+impl Drop for Grant<'_> {
+    fn drop(&mut self) {
+        self.arbiter.state.lock().unwrap().busy &= !self.cores;
+        self.arbiter.released.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rknpu::flags::RknnCoreMask;
+
+    use super::{cores, State, Waiter};
+    use crate::scheduler::Priority;
+
+    #[test]
+    fn test_ready() {
+        let core_0 = cores(RknnCoreMask::RKNN_NPU_CORE_0);
+        let core_1 = cores(RknnCoreMask::RKNN_NPU_CORE_1);
+        let mut state = State {
+            busy: core_0,
+            waiting: vec![],
+            next_ticket: 0,
+        };
+        let waiter = |ticket, priority, cores| Waiter {
+            ticket,
+            priority,
+            cores,
+        };
+        state.waiting = vec![
+            waiter(0, Priority::Low, core_0),
+            waiter(1, Priority::High, core_0),
+            waiter(2, Priority::High, core_0),
+            waiter(3, Priority::Low, core_1),
+        ];
+        // Core 0 is busy, core 1 is not.
+        assert!(!(0..3).any(|it| state.ready(it)));
+        assert!(state.ready(3));
+
+        state.busy = 0;
+        // High priority first, in arrival order.
+        assert!(!state.ready(0));
+        assert!(state.ready(1));
+        assert!(!state.ready(2));
+        assert!(state.ready(3));
+
+        // Automatic core selection waits for the jobs on any core.
+        state.waiting.push(waiter(
+            4,
+            Priority::High,
+            cores(RknnCoreMask::RKNN_NPU_CORE_AUTO),
+        ));
+        assert!(!state.ready(3));
+        assert!(!state.ready(4));
+        state.waiting.retain(|it| it.ticket == 4);
+        assert!(state.ready(4));
+    }
+}
@@ -0,0 +1,429 @@
+//! Scheduling of several models sharing the NPU.
+//!
+//! A [`Scheduler`] owns a runtime context per model, each with a [`Priority`] and the NPU cores
+//! it runs on. Contexts are initialized with the `RKNN_FLAG_PRIOR_*` flag of their priority and
+//! bound to their cores. Submissions to a model are run in order on its own thread, and
+//! submissions to different models run in parallel as long as they use different cores. When
+//! they share a core, they take turns: the highest priority first, then in submission order, so
+//! a latency-critical model never waits behind a queue of lower priority requests.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{
+        mpsc::{self, Receiver, Sender, SyncSender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Instant,
+};
+
+use anyhow::Result;
+use rknpu::flags::{RknnCoreMask, RknnExtendedFlag};
+
+use crate::{
+    error::SchedulerError,
+    model::Model,
+    pipeline::{Inputs, StageMetrics},
+    session::{InferenceSession, Outputs},
+};
+
+use self::arbiter::{cores, Arbiter};
+
+mod arbiter;
+
+/// Priority of a model on the NPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    Low,
+    Medium,
+    High,
+}
+
+impl Priority {
+    /// Flag the contexts of the model are initialized with.
+    pub fn flag(self) -> RknnExtendedFlag {
+        match self {
+            Priority::Low => RknnExtendedFlag::RKNN_FLAG_PRIOR_LOW,
+            Priority::Medium => RknnExtendedFlag::RKNN_FLAG_PRIOR_MEDIUM,
+            Priority::High => RknnExtendedFlag::RKNN_FLAG_PRIOR_HIGH,
+        }
+    }
+}
+
+struct Job {
+    inputs: Inputs,
+    submitted: Instant,
+    reply: SyncSender<Result<Outputs>>,
+}
+
+struct ModelConfig {
+    name: String,
+    model: Model,
+    priority: Priority,
+    core_mask: RknnCoreMask,
+}
+
+/// Configures and starts a [`Scheduler`].
+#[derive(Default)]
+pub struct SchedulerBuilder {
+    models: Vec<ModelConfig>,
+}
+
+impl SchedulerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a model, run with the given priority on the given NPU cores.
+    /// [`RknnCoreMask::RKNN_NPU_CORE_AUTO`] lets the runtime pick a core for each run, and
+    /// then waits for all the cores to be free of the other models.
+    pub fn with_model(
+        mut self,
+        name: impl Into<String>,
+        model: Model,
+        priority: Priority,
+        core_mask: RknnCoreMask,
+    ) -> Self {
+        self.models.push(ModelConfig {
+            name: name.into(),
+            model,
+            priority,
+            core_mask,
+        });
+        self
+    }
+
+    /// Load the models and start their threads.
+    pub fn build(self) -> Result<Scheduler> {
+        let mut names = Vec::with_capacity(self.models.len());
+        for config in &self.models {
+            if names.contains(&config.name) {
+                return Err(SchedulerError::DuplicateModel(config.name.clone()).into());
+            }
+            names.push(config.name.clone());
+        }
+        let sessions = self
+            .models
+            .into_iter()
+            .map(|config| {
+                let mut ctx = config.model.with_flag(config.priority.flag()).context()?;
+                if config.core_mask != RknnCoreMask::RKNN_NPU_CORE_AUTO {
+                    ctx.set_core_mask(config.core_mask)?;
+                }
+                Ok((config.name, config.priority, config.core_mask, ctx))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let arbiter = Arc::new(Arbiter::default());
+        let mut models = HashMap::new();
+        let mut workers = vec![];
+        for (name, priority, core_mask, ctx) in sessions {
+            let (tx, rx) = mpsc::channel();
+            let stats = Arc::new(Mutex::new(ModelStats::new(priority, core_mask)));
+            let mut worker = Worker {
+                session: InferenceSession::new(ctx),
+                priority,
+                cores: cores(core_mask),
+                arbiter: arbiter.clone(),
+                stats: stats.clone(),
+            };
+            workers.push(
+                thread::Builder::new()
+                    .name(format!("rknpu-{name}"))
+                    .spawn(move || worker.serve(rx))?,
+            );
+            models.insert(name, ModelQueue { tx, stats });
+        }
+        Ok(Scheduler {
+            names,
+            models,
+            workers,
+        })
+    }
+}
+
+/// Counters and timings of a model of a [`Scheduler`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModelStats {
+    pub priority: Priority,
+    pub core_mask: RknnCoreMask,
+    /// Requests submitted and not started yet.
+    pub queued: usize,
+    /// Largest number of requests queued at once.
+    pub max_queued: usize,
+    /// Requests run, failed ones included.
+    pub completed: u64,
+    /// Requests run with an error.
+    pub failed: u64,
+    /// Time from the submission of the requests to the start of their run, waiting for the
+    /// previous requests of the model and for the NPU cores.
+    pub wait: StageMetrics,
+    /// Time of the inferences.
+    pub run: StageMetrics,
+}
+
+impl ModelStats {
+    fn new(priority: Priority, core_mask: RknnCoreMask) -> Self {
+        Self {
+            priority,
+            core_mask,
+            queued: 0,
+            max_queued: 0,
+            completed: 0,
+            failed: 0,
+            wait: StageMetrics::default(),
+            run: StageMetrics::default(),
+        }
+    }
+}
+
+impl fmt::Display for ModelStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Priority {:?} on {:?}: {} queued (max {}), {} completed, {} failed",
+            self.priority,
+            self.core_mask,
+            self.queued,
+            self.max_queued,
+            self.completed,
+            self.failed
+        )?;
+        writeln!(f, "Wait: {}", self.wait)?;
+        write!(f, "Run: {}", self.run)
+    }
+}
+
+struct ModelQueue {
+    tx: Sender<Job>,
+    stats: Arc<Mutex<ModelStats>>,
+}
+
+/// Runs the requests of several models on the NPU, see the [module](self) documentation.
+///
+/// The scheduler can be shared between client threads. Dropping it runs the requests already
+/// submitted, then stops its threads.
+pub struct Scheduler {
+    /// Names of the models, in the order they were added.
+    names: Vec<String>,
+    models: HashMap<String, ModelQueue>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl Scheduler {
+    /// Names of the models, in the order they were added.
+    pub fn models(&self) -> impl Iterator<Item = &str> {
+        self.names.iter().map(String::as_str)
+    }
+
+    pub fn stats(&self, model: &str) -> Result<ModelStats> {
+        Ok(self.queue(model)?.stats.lock().unwrap().clone())
+    }
+
+    /// Submit a request to a model: every model input must be given once.
+    pub fn submit(&self, model: &str, inputs: Inputs) -> Result<SchedulerTicket> {
+        let queue = self.queue(model)?;
+        let (reply, rx) = mpsc::sync_channel(1);
+        let job = Job {
+            inputs,
+            submitted: Instant::now(),
+            reply,
+        };
+        {
+            let mut stats = queue.stats.lock().unwrap();
+            stats.queued += 1;
+            stats.max_queued = stats.max_queued.max(stats.queued);
+        }
+        if queue.tx.send(job).is_err() {
+            queue.stats.lock().unwrap().queued -= 1;
+            return Err(SchedulerError::Stopped.into());
+        }
+        Ok(SchedulerTicket(rx))
+    }
+
+    /// Submit a request to a model and wait for its outputs.
+    pub fn run(&self, model: &str, inputs: Inputs) -> Result<Outputs> {
+        self.submit(model, inputs)?.wait()
+    }
+
+    fn queue(&self, model: &str) -> Result<&ModelQueue> {
+        Ok(self
+            .models
+            .get(model)
+            .ok_or_else(|| SchedulerError::UnknownModel(model.to_string()))?)
+    }
+}
+
+impl Drop for Scheduler {
+    fn drop(&mut self) {
+        self.models.clear();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Outputs of a submitted request, to wait for.
+pub struct SchedulerTicket(Receiver<Result<Outputs>>);
+
+impl SchedulerTicket {
+    pub fn wait(self) -> Result<Outputs> {
+        self.0.recv().map_err(|_| SchedulerError::Stopped)?
+    }
+}
+
+struct Worker {
+    session: InferenceSession,
+    priority: Priority,
+    cores: u32,
+    arbiter: Arc<Arbiter>,
+    stats: Arc<Mutex<ModelStats>>,
+}
+
+impl Worker {
+    fn serve(&mut self, rx: Receiver<Job>) {
+        for job in rx {
+            let grant = self.arbiter.acquire(self.priority, self.cores);
+            let start = Instant::now();
+            {
+                let mut stats = self.stats.lock().unwrap();
+                stats.queued -= 1;
+                stats.wait.record(start - job.submitted);
+            }
+            let (names, tensors): (Vec<_>, Vec<_>) = job.inputs.into_iter().unzip();
+            let outputs = self
+                .session
+                .run(names.iter().map(String::as_str).zip(tensors));
+            drop(grant);
+            {
+                let mut stats = self.stats.lock().unwrap();
+                stats.run.record(start.elapsed());
+                stats.completed += 1;
+                stats.failed += outputs.is_err() as u64;
+            }
+            let _ = job.reply.send(outputs);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use rknpu::{
+        driver::stub::{tensor_attribute, StubDriver},
+        flags::{RknnCoreMask, RknnExtendedFlag},
+        tensors::types::RknnTensorType,
+    };
+
+    use crate::{error::SchedulerError, model::Model, pipeline::Inputs, tensor::Tensor};
+
+    use super::{Priority, SchedulerBuilder};
+
+    /// A model copying its input to its output.
+    fn stub_model() -> (Arc<StubDriver>, Model) {
+        let input = tensor_attribute(0, "x", &[1, 2], RknnTensorType::F32);
+        let output = tensor_attribute(0, "y", &[1, 2], RknnTensorType::F32);
+        let driver = Arc::new(StubDriver::new(vec![input], vec![output], |inputs| {
+            inputs.to_vec()
+        }));
+        let model = Model::with_driver(vec![], driver.clone());
+        (driver, model)
+    }
+
+    fn inputs(value: f32) -> Inputs {
+        vec![(
+            "x".to_string(),
+            Tensor::new(vec![1, 2], vec![value, -value]).unwrap(),
+        )]
+    }
+
+    #[test]
+    fn test_models() {
+        let (driver, _) = stub_model();
+        let model = || Model::with_driver(vec![], driver.clone());
+        let scheduler = SchedulerBuilder::new()
+            .with_model(
+                "detector",
+                model(),
+                Priority::High,
+                RknnCoreMask::RKNN_NPU_CORE_0,
+            )
+            .with_model("ocr", model(), Priority::Low, RknnCoreMask::RKNN_NPU_CORE_0)
+            .with_model(
+                "classifier",
+                model(),
+                Priority::Medium,
+                RknnCoreMask::RKNN_NPU_CORE_1,
+            )
+            .build()
+            .unwrap();
+        assert_eq!(
+            scheduler.models().collect::<Vec<_>>(),
+            ["detector", "ocr", "classifier"]
+        );
+        assert_eq!(
+            driver.context_flags(),
+            [
+                RknnExtendedFlag::RKNN_FLAG_PRIOR_HIGH as u32,
+                RknnExtendedFlag::RKNN_FLAG_PRIOR_LOW as u32,
+                RknnExtendedFlag::RKNN_FLAG_PRIOR_MEDIUM as u32,
+            ]
+        );
+
+        let tickets = (0..4)
+            .map(|it| scheduler.submit("ocr", inputs(it as f32)).unwrap())
+            .collect::<Vec<_>>();
+        let outputs = scheduler.run("detector", inputs(1.0)).unwrap();
+        assert_eq!(outputs.get("y").unwrap().to_f32(), [1.0, -1.0]);
+        scheduler.run("classifier", inputs(2.0)).unwrap();
+        for (index, ticket) in tickets.into_iter().enumerate() {
+            let y = ticket.wait().unwrap().get("y").unwrap().to_f32();
+            assert_eq!(y, [index as f32, -(index as f32)]);
+        }
+        assert_eq!(driver.context_runs(RknnCoreMask::RKNN_NPU_CORE_0), 5);
+        assert_eq!(driver.context_runs(RknnCoreMask::RKNN_NPU_CORE_1), 1);
+
+        let stats = scheduler.stats("ocr").unwrap();
+        assert_eq!(stats.priority, Priority::Low);
+        assert_eq!((stats.queued, stats.completed, stats.failed), (0, 4, 0));
+        assert!((1..=4).contains(&stats.max_queued));
+        assert_eq!((stats.wait.count(), stats.run.count()), (4, 4));
+
+        let error = scheduler.run("detector", vec![]).unwrap_err();
+        assert!(error.to_string().contains("missing"), "{error}");
+        assert_eq!(scheduler.stats("detector").unwrap().failed, 1);
+        let error = scheduler.submit("tracker", inputs(0.0)).err().unwrap();
+        assert_eq!(
+            error.downcast_ref::<SchedulerError>(),
+            Some(&SchedulerError::UnknownModel("tracker".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_duplicate_model() {
+        let (_, model) = stub_model();
+        let (_, other) = stub_model();
+        let error = SchedulerBuilder::new()
+            .with_model(
+                "detector",
+                model,
+                Priority::High,
+                RknnCoreMask::RKNN_NPU_CORE_AUTO,
+            )
+            .with_model(
+                "detector",
+                other,
+                Priority::Low,
+                RknnCoreMask::RKNN_NPU_CORE_AUTO,
+            )
+            .build()
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.downcast_ref::<SchedulerError>(),
+            Some(&SchedulerError::DuplicateModel("detector".to_string()))
+        );
+    }
+}
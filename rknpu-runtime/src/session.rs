@@ -0,0 +1,325 @@
+use std::time::Instant;
+
+use anyhow::Result;
+use rknpu::{
+    context::{info::ModelInfo, inputs::RknnInput, outputs::RknnOuput, RknnContext},
+    error::RknnTensorLookupError,
+    tensors::{attributes::RknnTensorAttribute, types::RknnTensorType},
+};
+
+use crate::{
+    error::RuntimeError,
+    quant::{dequantize, quantize},
+    stats::{InferenceTimings, TimingStats},
+    tensor::{Tensor, TensorData},
+};
+
+/// A model loaded in a runtime context, taking and returning named tensors.
+///
+/// Inputs are converted to the model inputs:
+/// - tensors of the type of the model input are passed through as is,
+/// - f32 tensors are quantized to the type and quantization of the model input, and passed
+///   through,
+/// - other tensors are converted by the runtime.
+///
+/// Tensors must hold the values in the layout of the model input, and as many values.
+///
+/// Outputs are dequantized to f32 by default, see [`with_dequantize`](Self::with_dequantize).
+pub struct InferenceSession {
+    ctx: RknnContext,
+    dequantize: bool,
+    stats: TimingStats,
+}
+
+impl InferenceSession {
+    pub fn new(ctx: RknnContext) -> Self {
+        Self {
+            ctx,
+            dequantize: true,
+            stats: TimingStats::default(),
+        }
+    }
+
+    /// Whether to dequantize the outputs to f32, or to return them in the type of the model
+    /// outputs.
+    pub fn with_dequantize(mut self, dequantize: bool) -> Self {
+        self.dequantize = dequantize;
+        self
+    }
+
+    /// Whether outputs are dequantized to f32.
+    pub fn dequantizes(&self) -> bool {
+        self.dequantize
+    }
+
+    pub fn model_info(&self) -> &ModelInfo {
+        self.ctx.model_info()
+    }
+
+    pub fn input_names(&self) -> impl Iterator<Item = &str> {
+        self.model_info().inputs.iter().map(|it| it.name.as_str())
+    }
+
+    pub fn output_names(&self) -> impl Iterator<Item = &str> {
+        self.model_info().outputs.iter().map(|it| it.name.as_str())
+    }
+
+    /// Timings of the inferences run so far, warmup runs excluded.
+    pub fn stats(&self) -> &TimingStats {
+        &self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats.reset();
+    }
+
+    pub fn into_context(self) -> RknnContext {
+        self.ctx
+    }
+
+    /// Run the model on inputs addressed by name. Every model input must be given once.
+    pub fn run<'a, I>(&mut self, inputs: I) -> Result<Outputs>
+    where
+        I: IntoIterator<Item = (&'a str, Tensor)>,
+    {
+        let start = Instant::now();
+        let inputs = inputs
+            .into_iter()
+            .map(|(name, tensor)| {
+                let index = self.ctx.input_index(name)?;
+                input(&self.model_info().inputs[index as usize], tensor)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        self.ctx.set_inputs(inputs)?;
+        let run_start = Instant::now();
+        self.ctx.run()?;
+        let outputs_start = Instant::now();
+        let outputs = self.ctx.get_outputs()?;
+        let outputs = self.outputs(outputs)?;
+        self.stats.record(InferenceTimings {
+            inputs: run_start - start,
+            run: outputs_start - run_start,
+            outputs: outputs_start.elapsed(),
+        });
+        Ok(outputs)
+    }
+
+    /// Run the model `runs` times on zeroed inputs, to get the first slower runs out of the way.
+    /// Warmup runs are not recorded in the [stats](Self::stats).
+    pub fn warmup(&mut self, runs: usize) -> Result<()> {
+        for _ in 0..runs {
+            let inputs = self
+                .model_info()
+                .inputs
+                .iter()
+                .map(|attribute| RknnInput {
+                    index: attribute.index as u32,
+                    buffer: vec![0; attribute.len * attribute.data_type.size()],
+                    pass_through: true,
+                    dtype: attribute.data_type,
+                    fmt: attribute.format,
+                })
+                .collect();
+            self.ctx.set_inputs(inputs)?;
+            self.ctx.run()?;
+            self.ctx.get_outputs()?;
+        }
+        Ok(())
+    }
+
+    fn outputs(&self, outputs: Vec<RknnOuput>) -> Result<Outputs> {
+        let attributes = &self.model_info().outputs;
+        let tensors = attributes
+            .iter()
+            .zip(outputs)
+            .map(|(attribute, output)| {
+                let data = TensorData::from_bytes(attribute.data_type, &output.buffer).ok_or_else(
+                    || RuntimeError::UnsupportedType {
+                        name: attribute.name.clone(),
+                        dtype: attribute.data_type,
+                    },
+                )?;
+                let data = match data.dtype() {
+                    RknnTensorType::F32 => data,
+                    _ if self.dequantize => {
+                        TensorData::F32(dequantize(&data, &attribute.quant_type))
+                    }
+                    _ => data,
+                };
+                let shape = attribute.dims.iter().map(|it| *it as usize).collect();
+                Tensor::from_data(shape, data)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let names = attributes.iter().map(|it| it.name.clone()).collect();
+        Ok(Outputs::new(names, tensors))
+    }
+}
+
+/// Convert a tensor to an input of the model input of `attribute`.
+fn input(attribute: &RknnTensorAttribute, tensor: Tensor) -> Result<RknnInput> {
+    if tensor.len() != attribute.len {
+        return Err(RuntimeError::ElementCountMismatch {
+            name: attribute.name.clone(),
+            expected: attribute.len,
+            actual: tensor.len(),
+        }
+        .into());
+    }
+    let (data, pass_through) = match tensor.data() {
+        data if data.dtype() == attribute.data_type => (data.clone(), true),
+        TensorData::F32(values) => (quantize(values, attribute)?, true),
+        data => (data.clone(), false),
+    };
+    Ok(RknnInput {
+        index: attribute.index as u32,
+        buffer: data.to_bytes(),
+        pass_through,
+        dtype: data.dtype(),
+        fmt: attribute.format,
+    })
+}
+
+/// Output tensors of an inference, in the order of the model outputs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outputs {
+    names: Vec<String>,
+    tensors: Vec<Tensor>,
+}
+
+impl Outputs {
+    pub(crate) fn new(names: Vec<String>, tensors: Vec<Tensor>) -> Self {
+        Self { names, tensors }
+    }
+
+    /// Output named `name`.
+    pub fn get(&self, name: &str) -> Result<&Tensor> {
+        let index = self.names.iter().position(|it| it == name).ok_or_else(|| {
+            RknnTensorLookupError::UnknownOutput {
+                name: name.to_string(),
+            }
+        })?;
+        Ok(&self.tensors[index])
+    }
+
+    /// Output of index `index`.
+    pub fn by_index(&self, index: usize) -> Option<&Tensor> {
+        self.tensors.get(index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.tensors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tensors.is_empty()
+    }
+
+    /// Names and tensors of the outputs.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Tensor)> {
+        self.names.iter().map(String::as_str).zip(&self.tensors)
+    }
+
+    pub fn into_tensors(self) -> Vec<Tensor> {
+        self.tensors
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use rknpu::{
+        driver::stub::{tensor_attribute, StubCall, StubDriver},
+        error::{RknnInputError, RknnTensorLookupError},
+        tensors::types::{RknnTensorQuantFormat, RknnTensorType},
+    };
+
+    use crate::{error::RuntimeError, model::Model, tensor::Tensor};
+
+    /// A model copying its int8 input to its first output, its second output being constant.
+    fn stub_model() -> (Arc<StubDriver>, Model) {
+        let quant = RknnTensorQuantFormat::AffineScale(0, 0.1);
+        let mut input = tensor_attribute(0, "image", &[1, 4], RknnTensorType::I8);
+        input.quant_type = quant.clone();
+        let mut logits = tensor_attribute(0, "logits", &[1, 4], RknnTensorType::I8);
+        logits.quant_type = quant;
+        let mask = tensor_attribute(1, "mask", &[1, 2], RknnTensorType::U8);
+        let driver = Arc::new(StubDriver::new(vec![input], vec![logits, mask], |inputs| {
+            vec![inputs[0].clone(), vec![1, 0]]
+        }));
+        let model = Model::with_driver(vec![], driver.clone());
+        (driver, model)
+    }
+
+    #[test]
+    fn test_run() {
+        let (driver, model) = stub_model();
+        let mut session = model.session().unwrap();
+        assert_eq!(session.input_names().collect::<Vec<_>>(), ["image"]);
+        assert_eq!(
+            session.output_names().collect::<Vec<_>>(),
+            ["logits", "mask"]
+        );
+        session.warmup(2).unwrap();
+        assert_eq!(session.stats().count(), 0);
+
+        let image = Tensor::new(vec![1, 4], vec![0.1_f32, -0.2, 0.35, 12.8]).unwrap();
+        let outputs = session.run([("image", image)]).unwrap();
+        assert_eq!(driver.call_count(StubCall::Run), 3);
+        assert_eq!(session.stats().count(), 1);
+        let logits = outputs.get("logits").unwrap();
+        assert_eq!(logits.shape(), [1, 4]);
+        // 0.35 is rounded to 0.4, and 12.8 saturated to 12.7.
+        let expected = [0.1, -0.2, 0.4, 12.7];
+        for (value, expected) in logits.as_slice::<f32>().unwrap().iter().zip(expected) {
+            assert!((value - expected).abs() < 1e-6, "{value} != {expected}");
+        }
+        assert_eq!(outputs.by_index(1).unwrap().to_f32(), [1.0, 0.0]);
+        assert!(outputs.get("boxes").is_err());
+
+        // Inputs of the model type are passed through, outputs kept in the model types.
+        let mut session = session.with_dequantize(false);
+        let image = Tensor::new(vec![1, 4], vec![1_i8, 2, 3, 4]).unwrap();
+        let outputs = session.run([("image", image)]).unwrap();
+        assert_eq!(outputs.len(), 2);
+        let logits = outputs.into_tensors().remove(0);
+        assert_eq!(logits.into_vec::<i8>().unwrap(), [1, 2, 3, 4]);
+        assert_eq!(session.stats().count(), 2);
+    }
+
+    #[test]
+    fn test_invalid_inputs() {
+        let (_, model) = stub_model();
+        let mut session = model.session().unwrap();
+        let image = || Tensor::new(vec![1, 4], vec![0.0_f32; 4]).unwrap();
+
+        let error = session.run([("input", image())]).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<RknnTensorLookupError>(),
+            Some(&RknnTensorLookupError::UnknownInput {
+                name: "input".to_string()
+            })
+        );
+        let error = session
+            .run([("image", Tensor::new(vec![2], vec![0.0_f32; 2]).unwrap())])
+            .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<RuntimeError>(),
+            Some(&RuntimeError::ElementCountMismatch {
+                name: "image".to_string(),
+                expected: 4,
+                actual: 2
+            })
+        );
+        let error = session.run([]).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<RknnInputError>(),
+            Some(&RknnInputError::Missing {
+                index: 0,
+                name: "image".to_string()
+            })
+        );
+        assert_eq!(session.stats().count(), 0);
+        assert!(session.run([("image", image())]).is_ok());
+    }
+}
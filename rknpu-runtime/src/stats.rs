@@ -0,0 +1,141 @@
+use std::{fmt, ops::Add, time::Duration};
+
+/// Time spent in the steps of an inference.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InferenceTimings {
+    /// Conversion of the input tensors and `rknn_inputs_set`.
+    pub inputs: Duration,
+    /// `rknn_run`.
+    pub run: Duration,
+    /// `rknn_outputs_get` and conversion of the output tensors.
+    pub outputs: Duration,
+}
+
+impl InferenceTimings {
+    pub fn total(&self) -> Duration {
+        self.inputs + self.run + self.outputs
+    }
+
+    fn div(self, count: u32) -> Self {
+        Self {
+            inputs: self.inputs / count,
+            run: self.run / count,
+            outputs: self.outputs / count,
+        }
+    }
+}
+
+impl Add for InferenceTimings {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Self {
+            inputs: self.inputs + rhs.inputs,
+            run: self.run + rhs.run,
+            outputs: self.outputs + rhs.outputs,
+        }
+    }
+}
+
+/// Timings of the inferences of a session, warmup runs excluded.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TimingStats {
+    count: u32,
+    sum: InferenceTimings,
+    min: Option<InferenceTimings>,
+    max: Option<InferenceTimings>,
+    last: Option<InferenceTimings>,
+}
+
+impl TimingStats {
+    pub fn record(&mut self, timings: InferenceTimings) {
+        self.count += 1;
+        self.sum = self.sum + timings;
+        let total = timings.total();
+        if self.min.is_none_or(|it| total < it.total()) {
+            self.min = Some(timings);
+        }
+        if self.max.is_none_or(|it| total > it.total()) {
+            self.max = Some(timings);
+        }
+        self.last = Some(timings);
+    }
+
+    /// Number of inferences recorded.
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn mean(&self) -> Option<InferenceTimings> {
+        (self.count > 0).then(|| self.sum.div(self.count))
+    }
+
+    /// Timings of the fastest inference.
+    pub fn min(&self) -> Option<InferenceTimings> {
+        self.min
+    }
+
+    /// Timings of the slowest inference.
+    pub fn max(&self) -> Option<InferenceTimings> {
+        self.max
+    }
+
+    pub fn last(&self) -> Option<InferenceTimings> {
+        self.last
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+impl fmt::Display for TimingStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (Some(mean), Some(min), Some(max)) = (self.mean(), self.min, self.max) else {
+            return write!(f, "No inference");
+        };
+        writeln!(f, "Inferences: {}", self.count)?;
+        writeln!(
+            f,
+            "Mean: {:?} (inputs {:?}, run {:?}, outputs {:?})",
+            mean.total(),
+            mean.inputs,
+            mean.run,
+            mean.outputs
+        )?;
+        write!(f, "Min: {:?}, max: {:?}", min.total(), max.total())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{InferenceTimings, TimingStats};
+
+    fn timings(inputs: u64, run: u64, outputs: u64) -> InferenceTimings {
+        InferenceTimings {
+            inputs: Duration::from_millis(inputs),
+            run: Duration::from_millis(run),
+            outputs: Duration::from_millis(outputs),
+        }
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut stats = TimingStats::default();
+        assert_eq!(stats.mean(), None);
+        assert_eq!(stats.to_string(), "No inference");
+        stats.record(timings(1, 10, 1));
+        stats.record(timings(3, 20, 1));
+        stats.record(timings(2, 6, 1));
+        assert_eq!(stats.count(), 3);
+        assert_eq!(stats.mean(), Some(timings(2, 12, 1)));
+        assert_eq!(stats.min(), Some(timings(2, 6, 1)));
+        assert_eq!(stats.max(), Some(timings(3, 20, 1)));
+        assert_eq!(stats.last(), Some(timings(2, 6, 1)));
+        assert!(stats.to_string().starts_with("Inferences: 3\nMean: 15ms"));
+        stats.reset();
+        assert_eq!(stats.count(), 0);
+    }
+}
@@ -0,0 +1,287 @@
+use anyhow::Result;
+use half::f16;
+use rknpu::tensors::types::RknnTensorType;
+
+use crate::error::RuntimeError;
+
+/// Values of a tensor, in one of the element types of the runtime.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TensorData {
+    F32(Vec<f32>),
+    F16(Vec<f16>),
+    I8(Vec<i8>),
+    U8(Vec<u8>),
+    I16(Vec<i16>),
+    U16(Vec<u16>),
+    I32(Vec<i32>),
+    U32(Vec<u32>),
+    I64(Vec<i64>),
+    Bool(Vec<bool>),
+}
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// Rust types of the tensor elements.
+pub trait TensorElement: sealed::Sealed + Copy + Sized {
+    const DTYPE: RknnTensorType;
+
+    fn wrap(values: Vec<Self>) -> TensorData;
+
+    fn as_slice(data: &TensorData) -> Option<&[Self]>;
+
+    fn into_vec(data: TensorData) -> Option<Vec<Self>>;
+}
+
+macro_rules! tensor_element {
+    ($ty:ty, $variant:ident, $dtype:ident) => {
+        impl sealed::Sealed for $ty {}
+
+        impl TensorElement for $ty {
+            const DTYPE: RknnTensorType = RknnTensorType::$dtype;
+
+            fn wrap(values: Vec<Self>) -> TensorData {
+                TensorData::$variant(values)
+            }
+
+            fn as_slice(data: &TensorData) -> Option<&[Self]> {
+                match data {
+                    TensorData::$variant(values) => Some(values),
+                    _ => None,
+                }
+            }
+
+            fn into_vec(data: TensorData) -> Option<Vec<Self>> {
+                match data {
+                    TensorData::$variant(values) => Some(values),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+tensor_element!(f32, F32, F32);
+tensor_element!(f16, F16, F16);
+tensor_element!(i8, I8, I8);
+tensor_element!(u8, U8, U8);
+tensor_element!(i16, I16, I16);
+tensor_element!(u16, U16, U16);
+tensor_element!(i32, I32, I32);
+tensor_element!(u32, U32, U32);
+tensor_element!(i64, I64, I64);
+tensor_element!(bool, Bool, BOOL);
+
+impl TensorData {
+    pub fn dtype(&self) -> RknnTensorType {
+        match self {
+            Self::F32(_) => RknnTensorType::F32,
+            Self::F16(_) => RknnTensorType::F16,
+            Self::I8(_) => RknnTensorType::I8,
+            Self::U8(_) => RknnTensorType::U8,
+            Self::I16(_) => RknnTensorType::I16,
+            Self::U16(_) => RknnTensorType::U16,
+            Self::I32(_) => RknnTensorType::I32,
+            Self::U32(_) => RknnTensorType::U32,
+            Self::I64(_) => RknnTensorType::I64,
+            Self::Bool(_) => RknnTensorType::BOOL,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Self::F32(values) => values.len(),
+            Self::F16(values) => values.len(),
+            Self::I8(values) => values.len(),
+            Self::U8(values) => values.len(),
+            Self::I16(values) => values.len(),
+            Self::U16(values) => values.len(),
+            Self::I32(values) => values.len(),
+            Self::U32(values) => values.len(),
+            Self::I64(values) => values.len(),
+            Self::Bool(values) => values.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Decode little endian values of type `dtype`.
+    pub fn from_bytes(dtype: RknnTensorType, bytes: &[u8]) -> Option<Self> {
+        macro_rules! decode {
+            ($ty:ty) => {
+                bytes
+                    .chunks_exact(std::mem::size_of::<$ty>())
+                    .map(|it| <$ty>::from_le_bytes(it.try_into().unwrap()))
+                    .collect()
+            };
+        }
+        Some(match dtype {
+            RknnTensorType::F32 => Self::F32(decode!(f32)),
+            RknnTensorType::F16 => Self::F16(decode!(f16)),
+            RknnTensorType::I8 => Self::I8(decode!(i8)),
+            RknnTensorType::U8 => Self::U8(bytes.to_vec()),
+            RknnTensorType::I16 => Self::I16(decode!(i16)),
+            RknnTensorType::U16 => Self::U16(decode!(u16)),
+            RknnTensorType::I32 => Self::I32(decode!(i32)),
+            RknnTensorType::U32 => Self::U32(decode!(u32)),
+            RknnTensorType::I64 => Self::I64(decode!(i64)),
+            RknnTensorType::BOOL => Self::Bool(bytes.iter().map(|it| *it != 0).collect()),
+            RknnTensorType::MAX => return None,
+        })
+    }
+
+    /// Little endian bytes of the values, as given to the runtime.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Self::U8(values) => values.clone(),
+            Self::Bool(values) => values.iter().map(|it| *it as u8).collect(),
+            Self::F32(values) => values.iter().flat_map(|it| it.to_le_bytes()).collect(),
+            Self::F16(values) => values.iter().flat_map(|it| it.to_le_bytes()).collect(),
+            Self::I8(values) => values.iter().flat_map(|it| it.to_le_bytes()).collect(),
+            Self::I16(values) => values.iter().flat_map(|it| it.to_le_bytes()).collect(),
+            Self::U16(values) => values.iter().flat_map(|it| it.to_le_bytes()).collect(),
+            Self::I32(values) => values.iter().flat_map(|it| it.to_le_bytes()).collect(),
+            Self::U32(values) => values.iter().flat_map(|it| it.to_le_bytes()).collect(),
+            Self::I64(values) => values.iter().flat_map(|it| it.to_le_bytes()).collect(),
+        }
+    }
+
+    /// Values converted to f32, without applying any quantization.
+    pub fn to_f32(&self) -> Vec<f32> {
+        match self {
+            Self::F32(values) => values.clone(),
+            Self::F16(values) => values.iter().map(|it| it.to_f32()).collect(),
+            Self::Bool(values) => values.iter().map(|it| *it as u8 as f32).collect(),
+            Self::I8(values) => values.iter().map(|it| *it as f32).collect(),
+            Self::U8(values) => values.iter().map(|it| *it as f32).collect(),
+            Self::I16(values) => values.iter().map(|it| *it as f32).collect(),
+            Self::U16(values) => values.iter().map(|it| *it as f32).collect(),
+            Self::I32(values) => values.iter().map(|it| *it as f32).collect(),
+            Self::U32(values) => values.iter().map(|it| *it as f32).collect(),
+            Self::I64(values) => values.iter().map(|it| *it as f32).collect(),
+        }
+    }
+}
+
+/// A row major tensor and its shape.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tensor {
+    shape: Vec<usize>,
+    data: TensorData,
+}
+
+impl Tensor {
+    /// Create a tensor of `shape`, which must hold as many elements as `values`.
+    pub fn new<T: TensorElement>(shape: Vec<usize>, values: Vec<T>) -> Result<Self> {
+        Self::from_data(shape, T::wrap(values))
+    }
+
+    pub fn from_data(shape: Vec<usize>, data: TensorData) -> Result<Self> {
+        let expected = shape.iter().product::<usize>();
+        if expected != data.len() {
+            return Err(RuntimeError::InvalidShape {
+                shape,
+                expected,
+                actual: data.len(),
+            }
+            .into());
+        }
+        Ok(Self { shape, data })
+    }
+
+    pub fn shape(&self) -> &[usize] {
+        &self.shape
+    }
+
+    pub fn dtype(&self) -> RknnTensorType {
+        self.data.dtype()
+    }
+
+    pub fn data(&self) -> &TensorData {
+        &self.data
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Values of the tensor, if they are of type `T`.
+    pub fn as_slice<T: TensorElement>(&self) -> Option<&[T]> {
+        T::as_slice(&self.data)
+    }
+
+    /// Values of the tensor, which must be of type `T`.
+    pub fn into_vec<T: TensorElement>(self) -> Result<Vec<T>> {
+        let actual = self.dtype();
+        let values = T::into_vec(self.data).ok_or(RuntimeError::TypeMismatch {
+            expected: T::DTYPE,
+            actual,
+        })?;
+        Ok(values)
+    }
+
+    /// Values converted to f32, without applying any quantization.
+    pub fn to_f32(&self) -> Vec<f32> {
+        self.data.to_f32()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use half::f16;
+    use rknpu::tensors::types::RknnTensorType;
+
+    use crate::error::RuntimeError;
+
+    use super::{Tensor, TensorData};
+
+    #[test]
+    fn test_tensor() {
+        let tensor = Tensor::new(vec![2, 2], vec![1_i8, -2, 3, -4]).unwrap();
+        assert_eq!(tensor.dtype(), RknnTensorType::I8);
+        assert_eq!(tensor.as_slice::<i8>(), Some([1, -2, 3, -4].as_slice()));
+        assert_eq!(tensor.as_slice::<u8>(), None);
+        assert_eq!(tensor.to_f32(), [1.0, -2.0, 3.0, -4.0]);
+        let error = tensor.into_vec::<f32>().unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<RuntimeError>(),
+            Some(&RuntimeError::TypeMismatch {
+                expected: RknnTensorType::F32,
+                actual: RknnTensorType::I8
+            })
+        );
+
+        let error = Tensor::new(vec![1, 3], vec![1.0_f32; 4]).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<RuntimeError>(),
+            Some(&RuntimeError::InvalidShape {
+                shape: vec![1, 3],
+                expected: 3,
+                actual: 4
+            })
+        );
+    }
+
+    #[test]
+    fn test_bytes() {
+        let data = TensorData::F16(vec![f16::from_f32(0.5), f16::from_f32(-2.0)]);
+        let bytes = data.to_bytes();
+        assert_eq!(bytes.len(), 4);
+        assert_eq!(
+            TensorData::from_bytes(RknnTensorType::F16, &bytes),
+            Some(data)
+        );
+        let data = TensorData::I32(vec![-1, 1 << 20]);
+        assert_eq!(
+            TensorData::from_bytes(RknnTensorType::I32, &data.to_bytes()),
+            Some(data)
+        );
+    }
+}
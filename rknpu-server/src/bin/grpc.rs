@@ -0,0 +1,43 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use rknpu_runtime::backend;
+use rknpu_server::grpc::GrpcServiceBuilder;
+use tonic::transport::Server;
+
+/// Usage: `rknpu-grpc-server <address> <contexts> <model>...`, every model being loaded in
+/// `contexts` runtime contexts, and given as `name=path` or as a path, served under its file name
+/// then.
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args: Vec<_> = std::env::args().collect();
+    let usage = || anyhow!("Usage: rknpu-grpc-server <address> <contexts> <model>...");
+    let addr = args.get(1).ok_or_else(usage)?.parse()?;
+    let contexts = args.get(2).ok_or_else(usage)?.parse::<usize>()?;
+    if contexts == 0 {
+        return Err(anyhow!("Every model needs at least one context"));
+    }
+
+    let mut builder = GrpcServiceBuilder::new();
+    for model in &args[3..] {
+        let (name, path) = match model.split_once('=') {
+            Some((name, path)) => (name.to_string(), path),
+            None => {
+                let name = Path::new(model).file_stem().unwrap_or_default();
+                (name.to_string_lossy().into_owned(), model.as_str())
+            }
+        };
+        let backends = (0..contexts)
+            .map(|_| backend::load(path))
+            .collect::<Result<Vec<_>>>()?;
+        println!("Model '{name}': {path} on {}", backends[0].kind());
+        builder = builder.with_pool(name, backends);
+    }
+
+    println!("Listening on {addr}");
+    Server::builder()
+        .add_service(builder.build()?.into_server())
+        .serve(addr)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,67 @@
+use rknpu::error::{RknnInputError, RknnTensorLookupError};
+use rknpu_runtime::error::RuntimeError;
+use thiserror::Error;
+
+/// Errors raised when handling a request, answered with an error status.
+#[derive(Debug, Error, PartialEq)]
+pub enum ServerError {
+    #[error("No route for {method} {url}.")]
+    UnknownRoute { method: String, url: String },
+    #[error("Unknown model '{0}'.")]
+    UnknownModel(String),
+    #[error("Unknown version '{version}' of model '{model}'.")]
+    UnknownVersion { model: String, version: String },
+    #[error("Unknown output '{0}'.")]
+    UnknownOutput(String),
+    #[error("Tensor '{name}' has unsupported datatype '{datatype}'.")]
+    UnsupportedDatatype { name: String, datatype: String },
+    #[error("Tensor '{name}' has invalid shape {shape:?}.")]
+    InvalidShape { name: String, shape: Vec<i64> },
+    #[error("Tensor '{0}' has no data.")]
+    MissingData(String),
+    #[error("Tensor '{name}' has values that are not of datatype {datatype}.")]
+    InvalidData { name: String, datatype: String },
+    #[error("Tensor '{name}' expects {expected} bytes of binary data, got {actual}.")]
+    BinaryDataSize {
+        name: String,
+        expected: usize,
+        actual: usize,
+    },
+    #[error("Invalid Inference-Header-Content-Length '{0}'.")]
+    InvalidHeaderLength(String),
+    #[error("Model '{0}' has no backend.")]
+    NoBackend(String),
+    #[error("Request body is larger than the {limit} bytes taken by model '{model}'.")]
+    BodyTooLarge { model: String, limit: usize },
+    #[error("The backend of model '{0}' panicked and can't be run anymore.")]
+    BackendPanicked(String),
+}
+
+impl ServerError {
+    /// HTTP status answered for the error.
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::UnknownRoute { .. } | Self::UnknownModel(_) | Self::UnknownVersion { .. } => 404,
+            Self::BodyTooLarge { .. } => 413,
+            Self::BackendPanicked(_) => 500,
+            _ => 400,
+        }
+    }
+}
+
+/// HTTP status answered for an error raised when handling a request: the status of a
+/// [`ServerError`], 400 for a request that doesn't parse or whose tensors don't fit the model,
+/// and 500 for the errors of the server and its backends.
+pub fn status(error: &anyhow::Error) -> u16 {
+    if let Some(error) = error.downcast_ref::<ServerError>() {
+        return error.status();
+    }
+    let invalid = error.is::<serde_json::Error>()
+        || error.is::<RuntimeError>()
+        || error.is::<RknnInputError>()
+        || error.is::<RknnTensorLookupError>();
+    match invalid {
+        true => 400,
+        false => 500,
+    }
+}
@@ -0,0 +1,346 @@
+//! The KServe v2 inference protocol over gRPC, with [tonic].
+//!
+//! The service speaks the `inference.GRPCInferenceService` of KServe and Triton, so their
+//! clients can be used. Next to the unary RPCs, `ModelStreamInfer` runs a bidirectional stream of
+//! requests, such as the frames of a video, answering them in order on the same stream.
+//!
+//! Models are served by a pool of backends, typically several runtime contexts of the model, so
+//! that concurrent requests and streams run in parallel up to the size of the pool. Outputs are
+//! always returned as raw little endian values.
+
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::Result;
+use rknpu::tensors::types::RknnTensorType;
+use rknpu_runtime::{InferenceBackend, Tensor, TensorData, TensorInfo};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::{
+    error::{self, ServerError},
+    protocol::{binary_tensor, datatype, parse_datatype, MODEL_VERSION},
+};
+
+use self::{
+    pool::BackendPool,
+    proto::{
+        grpc_inference_service_server::{GrpcInferenceService, GrpcInferenceServiceServer},
+        model_infer_request::InferInputTensor,
+        model_infer_response::InferOutputTensor,
+        model_metadata_response::TensorMetadata,
+        InferTensorContents, ModelInferRequest, ModelInferResponse, ModelMetadataRequest,
+        ModelMetadataResponse, ModelReadyRequest, ModelReadyResponse, ModelStreamInferResponse,
+        ServerLiveRequest, ServerLiveResponse, ServerMetadataRequest, ServerMetadataResponse,
+        ServerReadyRequest, ServerReadyResponse,
+    },
+};
+
+mod pool;
+
+/// Messages, service and client generated from `proto/grpc_predict_v2.proto`.
+pub mod proto {
+    tonic::include_proto!("inference");
+}
+
+/// Requests of a stream processed ahead of the client reading the responses.
+const STREAM_BUFFER: usize = 4;
+
+/// Configures a [`GrpcService`].
+#[derive(Default)]
+pub struct GrpcServiceBuilder {
+    models: Vec<(String, Vec<Box<dyn InferenceBackend>>)>,
+}
+
+impl GrpcServiceBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serve a model under the given name, running one request at a time.
+    pub fn with_model(self, name: impl Into<String>, backend: Box<dyn InferenceBackend>) -> Self {
+        self.with_pool(name, vec![backend])
+    }
+
+    /// Serve a model under the given name, running as many requests at a time as there are
+    /// backends. The backends must run the same model.
+    pub fn with_pool(
+        mut self,
+        name: impl Into<String>,
+        backends: Vec<Box<dyn InferenceBackend>>,
+    ) -> Self {
+        self.models.push((name.into(), backends));
+        self
+    }
+
+    pub fn build(self) -> Result<GrpcService> {
+        let models = self
+            .models
+            .into_iter()
+            .map(|(name, backends)| match BackendPool::new(&name, backends) {
+                Some(pool) => Ok((name, Arc::new(pool))),
+                None => Err(ServerError::NoBackend(name)),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(GrpcService {
+            models: Arc::new(models),
+        })
+    }
+}
+
+/// Serves models over gRPC, see the [module](self) documentation.
+pub struct GrpcService {
+    models: Arc<HashMap<String, Arc<BackendPool>>>,
+}
+
+impl GrpcService {
+    /// The service, to add to a [`tonic::transport::Server`].
+    pub fn into_server(self) -> GrpcInferenceServiceServer<Self> {
+        GrpcInferenceServiceServer::new(self)
+    }
+}
+
+fn model<'a>(
+    models: &'a HashMap<String, Arc<BackendPool>>,
+    name: &str,
+    version: &str,
+) -> Result<&'a Arc<BackendPool>, ServerError> {
+    let pool = models
+        .get(name)
+        .ok_or_else(|| ServerError::UnknownModel(name.to_string()))?;
+    if !version.is_empty() && version != MODEL_VERSION {
+        return Err(ServerError::UnknownVersion {
+            model: name.to_string(),
+            version: version.to_string(),
+        });
+    }
+    Ok(pool)
+}
+
+fn status(error: anyhow::Error) -> Status {
+    let message = format!("{error:#}");
+    match error::status(&error) {
+        404 => Status::not_found(message),
+        400 => Status::invalid_argument(message),
+        _ => Status::internal(message),
+    }
+}
+
+/// Run a request on its model, off the async threads.
+async fn infer(
+    models: &HashMap<String, Arc<BackendPool>>,
+    request: ModelInferRequest,
+) -> Result<ModelInferResponse, Status> {
+    let pool = model(models, &request.model_name, &request.model_version)
+        .map_err(|error| status(error.into()))?
+        .clone();
+    tokio::task::spawn_blocking(move || run(&pool, request))
+        .await
+        .map_err(|error| Status::internal(error.to_string()))?
+        .map_err(status)
+}
+
+fn run(pool: &BackendPool, request: ModelInferRequest) -> Result<ModelInferResponse> {
+    let mut inputs = Vec::with_capacity(request.inputs.len());
+    for (index, input) in request.inputs.iter().enumerate() {
+        // Raw contents are given for every input or none.
+        let raw = match request.raw_input_contents.is_empty() {
+            true => None,
+            false => Some(
+                request
+                    .raw_input_contents
+                    .get(index)
+                    .map_or(&[][..], Vec::as_slice),
+            ),
+        };
+        inputs.push((input.name.as_str(), input_tensor(input, raw)?));
+    }
+    let outputs = pool.run(inputs)?;
+
+    let names = match request.outputs.is_empty() {
+        true => outputs.iter().map(|(name, _)| name).collect(),
+        false => request
+            .outputs
+            .iter()
+            .map(|it| it.name.as_str())
+            .collect::<Vec<_>>(),
+    };
+    let mut response = ModelInferResponse {
+        model_name: request.model_name.clone(),
+        model_version: MODEL_VERSION.to_string(),
+        id: request.id.clone(),
+        ..Default::default()
+    };
+    for name in names {
+        let tensor = outputs
+            .get(name)
+            .map_err(|_| ServerError::UnknownOutput(name.to_string()))?;
+        response.outputs.push(InferOutputTensor {
+            name: name.to_string(),
+            datatype: datatype(tensor.dtype()).to_string(),
+            shape: tensor.shape().iter().map(|it| *it as i64).collect(),
+        });
+        response.raw_output_contents.push(tensor.data().to_bytes());
+    }
+    Ok(response)
+}
+
+/// The tensor of an input, its values given in its contents or as the raw values `raw`.
+fn input_tensor(input: &InferInputTensor, raw: Option<&[u8]>) -> Result<Tensor> {
+    let name = input.name.clone();
+    let dtype =
+        parse_datatype(&input.datatype).ok_or_else(|| ServerError::UnsupportedDatatype {
+            name: name.clone(),
+            datatype: input.datatype.clone(),
+        })?;
+    let shape = input
+        .shape
+        .iter()
+        .map(|it| usize::try_from(*it).ok())
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| ServerError::InvalidShape {
+            name: name.clone(),
+            shape: input.shape.clone(),
+        })?;
+    if let Some(raw) = raw {
+        return binary_tensor(&name, dtype, shape, raw);
+    }
+    let contents = input
+        .contents
+        .as_ref()
+        .ok_or_else(|| ServerError::MissingData(name.clone()))?;
+    let data = contents_data(dtype, contents).ok_or_else(|| ServerError::InvalidData {
+        name,
+        datatype: input.datatype.clone(),
+    })?;
+    Tensor::from_data(shape, data)
+}
+
+/// Values of type `dtype` of typed contents, `None` if one doesn't fit the type. FP16 values
+/// are only taken raw.
+fn contents_data(dtype: RknnTensorType, contents: &InferTensorContents) -> Option<TensorData> {
+    fn convert<T: Copy, U: TryFrom<T>>(values: &[T]) -> Option<Vec<U>> {
+        values.iter().map(|it| U::try_from(*it).ok()).collect()
+    }
+    Some(match dtype {
+        RknnTensorType::F32 => TensorData::F32(contents.fp32_contents.clone()),
+        RknnTensorType::I8 => TensorData::I8(convert(&contents.int_contents)?),
+        RknnTensorType::I16 => TensorData::I16(convert(&contents.int_contents)?),
+        RknnTensorType::I32 => TensorData::I32(contents.int_contents.clone()),
+        RknnTensorType::I64 => TensorData::I64(contents.int64_contents.clone()),
+        RknnTensorType::U8 => TensorData::U8(convert(&contents.uint_contents)?),
+        RknnTensorType::U16 => TensorData::U16(convert(&contents.uint_contents)?),
+        RknnTensorType::U32 => TensorData::U32(contents.uint_contents.clone()),
+        RknnTensorType::BOOL => TensorData::Bool(contents.bool_contents.clone()),
+        RknnTensorType::F16 | RknnTensorType::MAX => return None,
+    })
+}
+
+fn tensor_metadata(info: &TensorInfo) -> TensorMetadata {
+    TensorMetadata {
+        name: info.name.clone(),
+        datatype: datatype(info.dtype).to_string(),
+        shape: info.shape.iter().map(|it| *it as i64).collect(),
+    }
+}
+
+#[tonic::async_trait]
+impl GrpcInferenceService for GrpcService {
+    async fn server_live(
+        &self,
+        _request: Request<ServerLiveRequest>,
+    ) -> Result<Response<ServerLiveResponse>, Status> {
+        Ok(Response::new(ServerLiveResponse { live: true }))
+    }
+
+    async fn server_ready(
+        &self,
+        _request: Request<ServerReadyRequest>,
+    ) -> Result<Response<ServerReadyResponse>, Status> {
+        Ok(Response::new(ServerReadyResponse { ready: true }))
+    }
+
+    async fn model_ready(
+        &self,
+        request: Request<ModelReadyRequest>,
+    ) -> Result<Response<ModelReadyResponse>, Status> {
+        let request = request.into_inner();
+        let ready =
+            model(&self.models, &request.name, &request.version).is_ok_and(|pool| pool.is_ready());
+        Ok(Response::new(ModelReadyResponse { ready }))
+    }
+
+    async fn server_metadata(
+        &self,
+        _request: Request<ServerMetadataRequest>,
+    ) -> Result<Response<ServerMetadataResponse>, Status> {
+        Ok(Response::new(ServerMetadataResponse {
+            name: env!("CARGO_PKG_NAME").to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            extensions: vec![],
+        }))
+    }
+
+    async fn model_metadata(
+        &self,
+        request: Request<ModelMetadataRequest>,
+    ) -> Result<Response<ModelMetadataResponse>, Status> {
+        let request = request.into_inner();
+        let pool = model(&self.models, &request.name, &request.version)
+            .map_err(|error| status(error.into()))?;
+        Ok(Response::new(ModelMetadataResponse {
+            name: request.name,
+            versions: vec![MODEL_VERSION.to_string()],
+            platform: pool.kind().to_string(),
+            inputs: pool.inputs().iter().map(tensor_metadata).collect(),
+            outputs: pool.outputs().iter().map(tensor_metadata).collect(),
+        }))
+    }
+
+    async fn model_infer(
+        &self,
+        request: Request<ModelInferRequest>,
+    ) -> Result<Response<ModelInferResponse>, Status> {
+        Ok(Response::new(
+            infer(&self.models, request.into_inner()).await?,
+        ))
+    }
+
+    type ModelStreamInferStream = ReceiverStream<Result<ModelStreamInferResponse, Status>>;
+
+    async fn model_stream_infer(
+        &self,
+        request: Request<Streaming<ModelInferRequest>>,
+    ) -> Result<Response<Self::ModelStreamInferStream>, Status> {
+        let mut requests = request.into_inner();
+        let models = self.models.clone();
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+        tokio::spawn(async move {
+            loop {
+                let request = match requests.message().await {
+                    Ok(Some(request)) => request,
+                    Ok(None) => break,
+                    Err(status) => {
+                        let _ = tx.send(Err(status)).await;
+                        break;
+                    }
+                };
+                // Failed requests are answered on the stream, which goes on.
+                let response = match infer(&models, request).await {
+                    Ok(response) => ModelStreamInferResponse {
+                        error_message: String::new(),
+                        infer_response: Some(response),
+                    },
+                    Err(status) => ModelStreamInferResponse {
+                        error_message: status.message().to_string(),
+                        infer_response: None,
+                    },
+                };
+                if tx.send(Ok(response)).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
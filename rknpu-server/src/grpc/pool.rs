@@ -0,0 +1,96 @@
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{Condvar, Mutex},
+};
+
+use anyhow::Result;
+use rknpu_runtime::{BackendKind, InferenceBackend, Outputs, Tensor, TensorInfo};
+
+use crate::error::ServerError;
+
+/// Backends of a model, such as several runtime contexts of it, shared by the requests: each
+/// request runs on an idle backend, waiting for one if they are all busy.
+///
+/// A backend that panicked is dropped from the pool, as its state can't be trusted anymore, the
+/// same way the HTTP server retires it. The model isn't ready once every backend panicked.
+pub(crate) struct BackendPool {
+    name: String,
+    kind: BackendKind,
+    inputs: Vec<TensorInfo>,
+    outputs: Vec<TensorInfo>,
+    backends: Mutex<Backends>,
+    released: Condvar,
+}
+
+struct Backends {
+    idle: Vec<Box<dyn InferenceBackend>>,
+    /// Idle and busy backends, the ones that panicked being left out.
+    live: usize,
+}
+
+impl BackendPool {
+    /// Create a pool of backends of the model `name`, `None` without backend.
+    pub fn new(name: &str, backends: Vec<Box<dyn InferenceBackend>>) -> Option<Self> {
+        let first = backends.first()?;
+        Some(Self {
+            name: name.to_string(),
+            kind: first.kind(),
+            inputs: first.inputs().to_vec(),
+            outputs: first.outputs().to_vec(),
+            backends: Mutex::new(Backends {
+                live: backends.len(),
+                idle: backends,
+            }),
+            released: Condvar::new(),
+        })
+    }
+
+    pub fn kind(&self) -> BackendKind {
+        self.kind
+    }
+
+    pub fn inputs(&self) -> &[TensorInfo] {
+        &self.inputs
+    }
+
+    pub fn outputs(&self) -> &[TensorInfo] {
+        &self.outputs
+    }
+
+    /// Whether a backend is left to run the model.
+    pub fn is_ready(&self) -> bool {
+        self.backends.lock().unwrap().live > 0
+    }
+
+    /// Run the model on an idle backend. A panic of the backend is returned as an error, and
+    /// the backend is dropped.
+    pub fn run(&self, inputs: Vec<(&str, Tensor)>) -> Result<Outputs> {
+        let mut backend = {
+            let mut backends = self.backends.lock().unwrap();
+            loop {
+                if let Some(backend) = backends.idle.pop() {
+                    break backend;
+                }
+                if backends.live == 0 {
+                    return Err(ServerError::BackendPanicked(self.name.clone()).into());
+                }
+                backends = self.released.wait(backends).unwrap();
+            }
+        };
+        let outputs = panic::catch_unwind(AssertUnwindSafe(|| backend.run(inputs)));
+        let mut backends = self.backends.lock().unwrap();
+        match outputs {
+            Ok(outputs) => {
+                backends.idle.push(backend);
+                self.released.notify_one();
+                outputs
+            }
+            Err(_) => {
+                backends.live -= 1;
+                // Requests waiting for a backend fail if none is left.
+                self.released.notify_all();
+                Err(ServerError::BackendPanicked(self.name.clone()).into())
+            }
+        }
+    }
+}
@@ -0,0 +1,19 @@
+//! Serving of RKNN models to other processes over HTTP, with the KServe v2 inference protocol
+//! also spoken by Triton.
+//!
+//! An [`InferenceServer`] serves models run by [`InferenceBackend`]s, so that it can be tested
+//! without an NPU. Model metadata is derived from the attributes of the model tensors, and
+//! tensors are exchanged as JSON or as binary data.
+//!
+//! With the `grpc` feature, the [`grpc`] module serves the same protocol over gRPC, with a
+//! streaming inference for video frames.
+//!
+//! [`InferenceBackend`]: rknpu_runtime::InferenceBackend
+
+pub use self::server::{InferenceServer, ServerBuilder, HEADER_LENGTH};
+
+pub mod error;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+mod protocol;
+mod server;
@@ -0,0 +1,33 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use rknpu_runtime::backend;
+use rknpu_server::ServerBuilder;
+
+/// Usage: `rknpu-server <address> <model>...`, models given as `name=path` or as a path, served
+/// under their file name then.
+fn main() -> Result<()> {
+    let args: Vec<_> = std::env::args().collect();
+    let addr = args
+        .get(1)
+        .ok_or_else(|| anyhow!("Usage: rknpu-server <address> <model>..."))?;
+
+    let mut builder = ServerBuilder::new();
+    for model in &args[2..] {
+        let (name, path) = match model.split_once('=') {
+            Some((name, path)) => (name.to_string(), path),
+            None => {
+                let name = Path::new(model).file_stem().unwrap_or_default();
+                (name.to_string_lossy().into_owned(), model.as_str())
+            }
+        };
+        let backend = backend::load(path)?;
+        println!("Model '{name}': {} on {}", path, backend.kind());
+        builder = builder.with_model(name, backend);
+    }
+
+    let server = builder.bind(addr.as_str())?;
+    println!("Listening on http://{}", server.addr());
+    server.join();
+    Ok(())
+}
@@ -0,0 +1,271 @@
+//! Messages of the KServe v2 inference protocol, and the conversion of their tensors.
+
+use half::f16;
+use rknpu::tensors::types::RknnTensorType;
+use rknpu_runtime::{Tensor, TensorData};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::error::ServerError;
+
+/// Version of the served models, which have a single one.
+pub const MODEL_VERSION: &str = "1";
+
+/// Protocol name of a tensor type.
+pub fn datatype(dtype: RknnTensorType) -> &'static str {
+    match dtype {
+        RknnTensorType::F32 => "FP32",
+        RknnTensorType::F16 => "FP16",
+        RknnTensorType::I8 => "INT8",
+        RknnTensorType::U8 => "UINT8",
+        RknnTensorType::I16 => "INT16",
+        RknnTensorType::U16 => "UINT16",
+        RknnTensorType::I32 => "INT32",
+        RknnTensorType::U32 => "UINT32",
+        RknnTensorType::I64 => "INT64",
+        RknnTensorType::BOOL => "BOOL",
+        RknnTensorType::MAX => "INVALID",
+    }
+}
+
+/// Tensor type of a protocol name, `None` for the types models don't take.
+pub fn parse_datatype(datatype: &str) -> Option<RknnTensorType> {
+    Some(match datatype {
+        "FP32" => RknnTensorType::F32,
+        "FP16" => RknnTensorType::F16,
+        "INT8" => RknnTensorType::I8,
+        "UINT8" => RknnTensorType::U8,
+        "INT16" => RknnTensorType::I16,
+        "UINT16" => RknnTensorType::U16,
+        "INT32" => RknnTensorType::I32,
+        "UINT32" => RknnTensorType::U32,
+        "INT64" => RknnTensorType::I64,
+        "BOOL" => RknnTensorType::BOOL,
+        _ => return None,
+    })
+}
+
+#[derive(Debug, Serialize)]
+pub struct ServerMetadata {
+    pub name: String,
+    pub version: String,
+    pub extensions: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ModelMetadata {
+    pub name: String,
+    pub versions: Vec<String>,
+    pub platform: String,
+    pub inputs: Vec<TensorMetadata>,
+    pub outputs: Vec<TensorMetadata>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TensorMetadata {
+    pub name: String,
+    pub datatype: &'static str,
+    pub shape: Vec<usize>,
+}
+
+/// Parameters of requests, inputs and outputs. Only the ones of the binary tensor data extension
+/// are read, the others are ignored.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Parameters {
+    /// Whether to return an output, or all of them for a request, as binary data.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub binary_data: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub binary_data_output: Option<bool>,
+    /// Size of the binary data of a tensor, following the JSON header in the body.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub binary_data_size: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct InferRequest {
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub parameters: Parameters,
+    pub inputs: Vec<RequestInput>,
+    /// Outputs to return, all of them by default.
+    #[serde(default)]
+    pub outputs: Option<Vec<RequestOutput>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RequestInput {
+    pub name: String,
+    pub shape: Vec<usize>,
+    pub datatype: String,
+    #[serde(default)]
+    pub parameters: Parameters,
+    /// Values in row-major order, flat or nested. `None` when given as binary data.
+    #[serde(default)]
+    pub data: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RequestOutput {
+    pub name: String,
+    #[serde(default)]
+    pub parameters: Parameters,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InferResponse {
+    pub model_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub outputs: Vec<ResponseOutput>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ResponseOutput {
+    pub name: String,
+    pub shape: Vec<usize>,
+    pub datatype: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<Parameters>,
+    /// Values in row-major order, `None` when returned as binary data.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl RequestInput {
+    /// The tensor of the input, its values given in JSON or as the binary data `binary`.
+    pub fn tensor(&self, binary: Option<&[u8]>) -> anyhow::Result<Tensor> {
+        let dtype =
+            parse_datatype(&self.datatype).ok_or_else(|| ServerError::UnsupportedDatatype {
+                name: self.name.clone(),
+                datatype: self.datatype.clone(),
+            })?;
+        let data = match (binary, &self.data) {
+            (Some(bytes), _) => {
+                return binary_tensor(&self.name, dtype, self.shape.clone(), bytes);
+            }
+            (None, Some(values)) => json_values(dtype, values),
+            (None, None) => return Err(ServerError::MissingData(self.name.clone()).into()),
+        };
+        let data = data.ok_or_else(|| ServerError::InvalidData {
+            name: self.name.clone(),
+            datatype: self.datatype.clone(),
+        })?;
+        Tensor::from_data(self.shape.clone(), data)
+    }
+}
+
+/// Tensor of little endian values of type `dtype`.
+pub fn binary_tensor(
+    name: &str,
+    dtype: RknnTensorType,
+    shape: Vec<usize>,
+    bytes: &[u8],
+) -> anyhow::Result<Tensor> {
+    let expected = shape.iter().product::<usize>() * dtype.size();
+    if bytes.len() != expected {
+        return Err(ServerError::BinaryDataSize {
+            name: name.to_string(),
+            expected,
+            actual: bytes.len(),
+        }
+        .into());
+    }
+    let data =
+        TensorData::from_bytes(dtype, bytes).ok_or_else(|| ServerError::UnsupportedDatatype {
+            name: name.to_string(),
+            datatype: datatype(dtype).to_string(),
+        })?;
+    Tensor::from_data(shape, data)
+}
+
+/// Values of type `dtype` of a flat or nested JSON array, `None` if one doesn't fit the type.
+fn json_values(dtype: RknnTensorType, values: &Value) -> Option<TensorData> {
+    fn flatten<'a>(value: &'a Value, values: &mut Vec<&'a Value>) {
+        match value {
+            Value::Array(items) => items.iter().for_each(|it| flatten(it, values)),
+            value => values.push(value),
+        }
+    }
+    let mut flat = vec![];
+    flatten(values, &mut flat);
+    macro_rules! parse {
+        ($variant:ident, $parse:expr) => {
+            TensorData::$variant(flat.into_iter().map($parse).collect::<Option<_>>()?)
+        };
+    }
+    Some(match dtype {
+        RknnTensorType::F32 => parse!(F32, |it| Some(it.as_f64()? as f32)),
+        RknnTensorType::F16 => parse!(F16, |it| Some(f16::from_f64(it.as_f64()?))),
+        RknnTensorType::I8 => parse!(I8, |it| it.as_i64()?.try_into().ok()),
+        RknnTensorType::U8 => parse!(U8, |it| it.as_u64()?.try_into().ok()),
+        RknnTensorType::I16 => parse!(I16, |it| it.as_i64()?.try_into().ok()),
+        RknnTensorType::U16 => parse!(U16, |it| it.as_u64()?.try_into().ok()),
+        RknnTensorType::I32 => parse!(I32, |it| it.as_i64()?.try_into().ok()),
+        RknnTensorType::U32 => parse!(U32, |it| it.as_u64()?.try_into().ok()),
+        RknnTensorType::I64 => parse!(I64, Value::as_i64),
+        RknnTensorType::BOOL => parse!(Bool, Value::as_bool),
+        RknnTensorType::MAX => return None,
+    })
+}
+
+/// Flat JSON array of the values of a tensor.
+pub fn json_data(data: &TensorData) -> Value {
+    match data {
+        TensorData::F32(values) => values.as_slice().into(),
+        TensorData::F16(values) => values.iter().map(|it| it.to_f32()).collect(),
+        TensorData::I8(values) => values.as_slice().into(),
+        TensorData::U8(values) => values.as_slice().into(),
+        TensorData::I16(values) => values.as_slice().into(),
+        TensorData::U16(values) => values.as_slice().into(),
+        TensorData::I32(values) => values.as_slice().into(),
+        TensorData::U32(values) => values.as_slice().into(),
+        TensorData::I64(values) => values.as_slice().into(),
+        TensorData::Bool(values) => values.as_slice().into(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rknpu::tensors::types::RknnTensorType;
+    use rknpu_runtime::TensorData;
+    use serde_json::json;
+
+    use super::{json_data, json_values, RequestInput};
+
+    #[test]
+    fn test_json_values() {
+        let values = json!([[1, 2], [3, 255]]);
+        assert_eq!(
+            json_values(RknnTensorType::U8, &values),
+            Some(TensorData::U8(vec![1, 2, 3, 255]))
+        );
+        assert_eq!(json_values(RknnTensorType::I8, &values), None);
+        assert_eq!(
+            json_values(RknnTensorType::F32, &json!([0.5, -1])),
+            Some(TensorData::F32(vec![0.5, -1.0]))
+        );
+        assert_eq!(
+            json_values(RknnTensorType::BOOL, &json!([true, false])),
+            Some(TensorData::Bool(vec![true, false]))
+        );
+        assert_eq!(json_values(RknnTensorType::BOOL, &json!([1])), None);
+        assert_eq!(json_data(&TensorData::I16(vec![-1, 2])), json!([-1, 2]));
+    }
+
+    #[test]
+    fn test_binary_input() {
+        let input: RequestInput = serde_json::from_value(json!({
+            "name": "x",
+            "shape": [2],
+            "datatype": "INT16",
+            "parameters": {"binary_data_size": 4},
+        }))
+        .unwrap();
+        let tensor = input.tensor(Some(&[1, 0, 0xff, 0xff])).unwrap();
+        assert_eq!(tensor.as_slice::<i16>().unwrap(), [1, -1]);
+        assert!(input.tensor(Some(&[1, 0])).is_err());
+        assert!(input.tensor(None).is_err());
+    }
+}
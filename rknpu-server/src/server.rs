@@ -0,0 +1,434 @@
+use std::{
+    io::Read,
+    net::{SocketAddr, ToSocketAddrs},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+use anyhow::{anyhow, Result};
+use rknpu_runtime::{InferenceBackend, TensorInfo};
+use serde::Serialize;
+use serde_json::json;
+use tiny_http::{Header, Method, Request, Response};
+
+use crate::{
+    error::{self, ServerError},
+    protocol::{
+        datatype, json_data, InferRequest, InferResponse, ModelMetadata, Parameters,
+        ResponseOutput, ServerMetadata, TensorMetadata, MODEL_VERSION,
+    },
+};
+
+/// Header giving the length of the JSON part of a body followed by binary tensor data.
+pub const HEADER_LENGTH: &str = "Inference-Header-Content-Length";
+
+/// Bytes of an inference body taken on top of the tensor values, for the JSON of the request.
+const BODY_HEADER: usize = 64 << 10;
+
+/// Bytes taken per tensor value in an inference body, enough for any number written as JSON.
+const BODY_PER_VALUE: usize = 32;
+
+struct ServedModel {
+    name: String,
+    metadata: ModelMetadata,
+    /// Largest inference body, in bytes, from the sizes of the model inputs.
+    max_body: usize,
+    backend: Mutex<Box<dyn InferenceBackend>>,
+}
+
+/// Configures and starts an [`InferenceServer`].
+pub struct ServerBuilder {
+    models: Vec<(String, Box<dyn InferenceBackend>)>,
+    workers: usize,
+}
+
+impl Default for ServerBuilder {
+    fn default() -> Self {
+        Self {
+            models: vec![],
+            workers: 4,
+        }
+    }
+}
+
+impl ServerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serve a model under the given name.
+    pub fn with_model(
+        mut self,
+        name: impl Into<String>,
+        backend: Box<dyn InferenceBackend>,
+    ) -> Self {
+        self.models.push((name.into(), backend));
+        self
+    }
+
+    /// Number of threads handling the requests, 4 by default. Requests to the same model are
+    /// run one at a time whatever the number of threads.
+    pub fn with_workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+
+    /// Listen on the given address and start the threads of the server.
+    pub fn bind<A: ToSocketAddrs>(self, addr: A) -> Result<InferenceServer> {
+        let http = tiny_http::Server::http(addr).map_err(|error| anyhow!(error))?;
+        let addr = http
+            .server_addr()
+            .to_ip()
+            .ok_or_else(|| anyhow!("The server doesn't listen on an IP address"))?;
+        let models = self
+            .models
+            .into_iter()
+            .map(|(name, backend)| ServedModel {
+                metadata: ModelMetadata {
+                    name: name.clone(),
+                    versions: vec![MODEL_VERSION.to_string()],
+                    platform: backend.kind().to_string(),
+                    inputs: backend.inputs().iter().map(tensor_metadata).collect(),
+                    outputs: backend.outputs().iter().map(tensor_metadata).collect(),
+                },
+                max_body: max_body(backend.inputs()),
+                name,
+                backend: Mutex::new(backend),
+            })
+            .collect();
+        let handler = Arc::new(Handler { models });
+        let http = Arc::new(http);
+        let stopped = Arc::new(AtomicBool::new(false));
+        let workers = (0..self.workers)
+            .map(|index| {
+                let (handler, http, stopped) = (handler.clone(), http.clone(), stopped.clone());
+                thread::Builder::new()
+                    .name(format!("rknpu-server-{index}"))
+                    .spawn(move || handler.serve(&http, &stopped))
+            })
+            .collect::<std::io::Result<_>>()?;
+        Ok(InferenceServer {
+            http,
+            addr,
+            stopped,
+            workers,
+        })
+    }
+}
+
+/// Largest inference body of a model taking the given inputs, sent as JSON or binary data.
+fn max_body(inputs: &[TensorInfo]) -> usize {
+    let values = inputs.iter().map(TensorInfo::len).sum::<usize>();
+    BODY_HEADER.saturating_add(values.saturating_mul(BODY_PER_VALUE))
+}
+
+fn tensor_metadata(info: &TensorInfo) -> TensorMetadata {
+    TensorMetadata {
+        name: info.name.clone(),
+        datatype: datatype(info.dtype),
+        shape: info.shape.clone(),
+    }
+}
+
+/// Serves models over HTTP with the [KServe v2 inference protocol][v2], and its binary tensor
+/// data extension:
+/// - `GET /v2/health/live` and `GET /v2/health/ready`: server liveness and readiness,
+/// - `GET /v2`: server metadata,
+/// - `GET /v2/models/{name}/ready`: model readiness,
+/// - `GET /v2/models/{name}`: model metadata, the name, datatype and shape of its tensors,
+/// - `POST /v2/models/{name}/infer`: inference.
+///
+/// Model routes also take a version, as in `/v2/models/{name}/versions/1/infer`, models having
+/// the single version 1. Errors are answered with a `{"error": "..."}` body and a 4xx status, or
+/// 500 when the backend fails. Inference bodies larger than the model inputs written as JSON
+/// are refused with 413.
+///
+/// Dropping the server stops its threads once they are done with their current request.
+///
+/// [v2]: https://kserve.github.io/website/latest/modelserving/data_plane/v2_protocol/
+pub struct InferenceServer {
+    http: Arc<tiny_http::Server>,
+    addr: SocketAddr,
+    stopped: Arc<AtomicBool>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl InferenceServer {
+    /// Address the server listens on, with the port picked by the system when bound to port 0.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Serve until the process ends.
+    pub fn join(mut self) {
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl Drop for InferenceServer {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        for _ in &self.workers {
+            self.http.unblock();
+        }
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Answer to a request.
+struct Reply {
+    status: u16,
+    body: Vec<u8>,
+    /// Length of the JSON header when followed by binary data.
+    header_length: Option<usize>,
+}
+
+impl Reply {
+    fn json<T: Serialize>(value: &T) -> Result<Self> {
+        Ok(Self {
+            status: 200,
+            body: serde_json::to_vec(value)?,
+            header_length: None,
+        })
+    }
+
+    fn error(error: anyhow::Error) -> Self {
+        Self {
+            status: error::status(&error),
+            body: json!({"error": format!("{error:#}")})
+                .to_string()
+                .into_bytes(),
+            header_length: None,
+        }
+    }
+}
+
+struct Handler {
+    models: Vec<ServedModel>,
+}
+
+impl Handler {
+    fn serve(&self, http: &tiny_http::Server, stopped: &AtomicBool) {
+        loop {
+            let request = match http.recv() {
+                Ok(request) => request,
+                Err(_) if stopped.load(Ordering::SeqCst) => return,
+                Err(_) => continue,
+            };
+            if stopped.load(Ordering::SeqCst) {
+                return;
+            }
+            self.respond(request);
+        }
+    }
+
+    fn respond(&self, mut request: Request) {
+        // A panic, as of a backend, is answered too and leaves the worker serving.
+        let reply = panic::catch_unwind(AssertUnwindSafe(|| self.handle(&mut request)))
+            .unwrap_or_else(|_| Err(anyhow!("The request handler panicked.")))
+            .unwrap_or_else(Reply::error);
+        let mut response = Response::from_data(reply.body).with_status_code(reply.status);
+        let content_type = match reply.header_length {
+            Some(length) => {
+                response.add_header(header(HEADER_LENGTH, &length.to_string()));
+                "application/octet-stream"
+            }
+            None => "application/json",
+        };
+        response.add_header(header("Content-Type", content_type));
+        // The client may be gone, there is no one to tell.
+        let _ = request.respond(response);
+    }
+
+    fn handle(&self, request: &mut Request) -> Result<Reply> {
+        let method = request.method().clone();
+        let url = request.url().to_string();
+        let path = url.split('?').next().unwrap_or_default();
+        let segments = path
+            .split('/')
+            .filter(|it| !it.is_empty())
+            .collect::<Vec<_>>();
+        let unknown_route = || ServerError::UnknownRoute {
+            method: method.to_string(),
+            url: url.clone(),
+        };
+        match (&method, segments.as_slice()) {
+            (Method::Get, ["v2"]) => Reply::json(&ServerMetadata {
+                name: env!("CARGO_PKG_NAME").to_string(),
+                version: env!("CARGO_PKG_VERSION").to_string(),
+                extensions: vec!["binary_tensor_data".to_string()],
+            }),
+            (Method::Get, ["v2", "health", "live"]) => Reply::json(&json!({"live": true})),
+            (Method::Get, ["v2", "health", "ready"]) => Reply::json(&json!({"ready": true})),
+            (_, ["v2", "models", name, route @ ..]) => {
+                let model = self.model(name)?;
+                let route = match route {
+                    ["versions", version, route @ ..] if *version == MODEL_VERSION => route,
+                    ["versions", version, ..] => {
+                        return Err(ServerError::UnknownVersion {
+                            model: name.to_string(),
+                            version: version.to_string(),
+                        }
+                        .into())
+                    }
+                    route => route,
+                };
+                match (&method, route) {
+                    (Method::Get, []) => Reply::json(&model.metadata),
+                    (Method::Get, ["ready"]) => {
+                        // A backend that panicked is never run again.
+                        let ready = !model.backend.is_poisoned();
+                        Reply::json(&json!({"name": model.name, "ready": ready}))
+                    }
+                    (Method::Post, ["infer"]) => {
+                        let too_large = || ServerError::BodyTooLarge {
+                            model: model.name.clone(),
+                            limit: model.max_body,
+                        };
+                        if request.body_length().unwrap_or(0) > model.max_body {
+                            return Err(too_large().into());
+                        }
+                        // Bodies without a length are read up to one byte past the limit.
+                        let mut body = vec![];
+                        request
+                            .as_reader()
+                            .take(model.max_body as u64 + 1)
+                            .read_to_end(&mut body)?;
+                        if body.len() > model.max_body {
+                            return Err(too_large().into());
+                        }
+                        let header_length = request
+                            .headers()
+                            .iter()
+                            .find(|it| it.field.equiv(HEADER_LENGTH))
+                            .map(|it| it.value.to_string());
+                        model.infer(&body, header_length.as_deref())
+                    }
+                    _ => Err(unknown_route().into()),
+                }
+            }
+            _ => Err(unknown_route().into()),
+        }
+    }
+
+    fn model(&self, name: &str) -> Result<&ServedModel> {
+        Ok(self
+            .models
+            .iter()
+            .find(|it| it.name == name)
+            .ok_or_else(|| ServerError::UnknownModel(name.to_string()))?)
+    }
+}
+
+impl ServedModel {
+    /// Run an inference request, whose body holds a JSON header of `header_length` bytes followed
+    /// by binary tensor data when given.
+    fn infer(&self, body: &[u8], header_length: Option<&str>) -> Result<Reply> {
+        let (header, mut binary) = match header_length {
+            Some(length) => {
+                let length = length
+                    .trim()
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|it| *it <= body.len())
+                    .ok_or_else(|| ServerError::InvalidHeaderLength(length.to_string()))?;
+                body.split_at(length)
+            }
+            None => (body, &[][..]),
+        };
+        let request = serde_json::from_slice::<InferRequest>(header)?;
+
+        let mut inputs = Vec::with_capacity(request.inputs.len());
+        for input in &request.inputs {
+            let bytes = match input.parameters.binary_data_size {
+                Some(size) if size > binary.len() => {
+                    return Err(ServerError::BinaryDataSize {
+                        name: input.name.clone(),
+                        expected: size,
+                        actual: binary.len(),
+                    }
+                    .into())
+                }
+                Some(size) => {
+                    let (bytes, rest) = binary.split_at(size);
+                    binary = rest;
+                    Some(bytes)
+                }
+                None => None,
+            };
+            inputs.push((input.name.as_str(), input.tensor(bytes)?));
+        }
+        // A backend that panicked may be left in any state, it isn't run again.
+        let mut backend = self
+            .backend
+            .lock()
+            .map_err(|_| ServerError::BackendPanicked(self.name.clone()))?;
+        let outputs = backend.run(inputs)?;
+        drop(backend);
+
+        let binary_output = request.parameters.binary_data_output.unwrap_or(false);
+        let requested = match &request.outputs {
+            Some(requested) => requested
+                .iter()
+                .map(|it| {
+                    let binary = it.parameters.binary_data.unwrap_or(binary_output);
+                    (it.name.as_str(), binary)
+                })
+                .collect(),
+            None => outputs
+                .iter()
+                .map(|(name, _)| (name, binary_output))
+                .collect::<Vec<_>>(),
+        };
+        let mut binary = vec![];
+        let outputs = requested
+            .into_iter()
+            .map(|(name, as_binary)| {
+                let tensor = outputs
+                    .get(name)
+                    .map_err(|_| ServerError::UnknownOutput(name.to_string()))?;
+                let mut output = ResponseOutput {
+                    name: name.to_string(),
+                    shape: tensor.shape().to_vec(),
+                    datatype: datatype(tensor.dtype()),
+                    parameters: None,
+                    data: None,
+                };
+                if as_binary {
+                    let bytes = tensor.data().to_bytes();
+                    output.parameters = Some(Parameters {
+                        binary_data_size: Some(bytes.len()),
+                        ..Default::default()
+                    });
+                    binary.extend(bytes);
+                } else {
+                    output.data = Some(json_data(tensor.data()));
+                }
+                Ok(output)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let outputs_binary = outputs.iter().any(|it| it.parameters.is_some());
+        let mut reply = Reply::json(&InferResponse {
+            model_name: self.name.clone(),
+            id: request.id,
+            outputs,
+        })?;
+        if outputs_binary {
+            reply.header_length = Some(reply.body.len());
+            reply.body.extend(binary);
+        }
+        Ok(reply)
+    }
+}
+
+fn header(field: &str, value: &str) -> Header {
+    Header::from_bytes(field.as_bytes(), value.as_bytes()).unwrap()
+}
@@ -0,0 +1,267 @@
+#![cfg(feature = "grpc")]
+
+use std::sync::Arc;
+
+use rknpu::{
+    driver::stub::{tensor_attribute, StubDriver},
+    tensors::types::{RknnTensorQuantFormat, RknnTensorType},
+};
+use rknpu_runtime::{
+    backend::RknnBackend, BackendKind, InferenceBackend, Model, Outputs, Tensor, TensorInfo,
+};
+use rknpu_server::grpc::{
+    proto::{
+        grpc_inference_service_client::GrpcInferenceServiceClient,
+        model_infer_request::{InferInputTensor, InferRequestedOutputTensor},
+        InferTensorContents, ModelInferRequest, ModelMetadataRequest, ModelReadyRequest,
+        ServerLiveRequest,
+    },
+    GrpcServiceBuilder,
+};
+use tokio::net::TcpListener;
+use tokio_stream::{wrappers::TcpListenerStream, StreamExt};
+use tonic::{
+    transport::{Channel, Server},
+    Code,
+};
+
+/// Backends of "echo", an int8 model copying its input to its output, both scaled by 0.5, which
+/// panic on an input starting with 50.
+fn backends(count: usize) -> Vec<Box<dyn InferenceBackend>> {
+    let quant = RknnTensorQuantFormat::AffineScale(0, 0.5);
+    let mut input = tensor_attribute(0, "x", &[1, 4], RknnTensorType::I8);
+    input.quant_type = quant.clone();
+    let mut output = tensor_attribute(0, "y", &[1, 4], RknnTensorType::I8);
+    output.quant_type = quant;
+    let driver = Arc::new(StubDriver::new(vec![input], vec![output], |inputs| {
+        inputs.to_vec()
+    }));
+    let model = Model::with_driver(vec![], driver);
+    (0..count)
+        .map(|_| Box::new(Panicking(RknnBackend::new(model.session().unwrap()))) as _)
+        .collect()
+}
+
+/// A backend panicking on an input starting with 50.
+struct Panicking(RknnBackend);
+
+impl InferenceBackend for Panicking {
+    fn kind(&self) -> BackendKind {
+        self.0.kind()
+    }
+
+    fn inputs(&self) -> &[TensorInfo] {
+        self.0.inputs()
+    }
+
+    fn outputs(&self) -> &[TensorInfo] {
+        self.0.outputs()
+    }
+
+    fn run(&mut self, inputs: Vec<(&str, Tensor)>) -> anyhow::Result<Outputs> {
+        assert_ne!(inputs[0].1.to_f32().first(), Some(&50.0), "echo panicked");
+        self.0.run(inputs)
+    }
+}
+
+/// Start a server on a free port and connect a client to it.
+async fn client() -> GrpcInferenceServiceClient<Channel> {
+    let service = GrpcServiceBuilder::new()
+        .with_pool("echo", backends(2))
+        .build()
+        .unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(
+        Server::builder()
+            .add_service(service.into_server())
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+    GrpcInferenceServiceClient::connect(format!("http://{addr}"))
+        .await
+        .unwrap()
+}
+
+fn fp32_request(values: &[f32]) -> ModelInferRequest {
+    ModelInferRequest {
+        model_name: "echo".to_string(),
+        inputs: vec![InferInputTensor {
+            name: "x".to_string(),
+            datatype: "FP32".to_string(),
+            shape: vec![1, 4],
+            contents: Some(InferTensorContents {
+                fp32_contents: values.to_vec(),
+                ..Default::default()
+            }),
+        }],
+        ..Default::default()
+    }
+}
+
+fn f32_values(raw: &[u8]) -> Vec<f32> {
+    raw.chunks_exact(4)
+        .map(|it| f32::from_le_bytes(it.try_into().unwrap()))
+        .collect()
+}
+
+#[tokio::test]
+async fn test_metadata() {
+    let mut client = client().await;
+    let live = client.server_live(ServerLiveRequest {}).await.unwrap();
+    assert!(live.into_inner().live);
+    let ready = |name: &str, version: &str| ModelReadyRequest {
+        name: name.to_string(),
+        version: version.to_string(),
+    };
+    assert!(
+        client
+            .model_ready(ready("echo", ""))
+            .await
+            .unwrap()
+            .into_inner()
+            .ready
+    );
+    assert!(
+        client
+            .model_ready(ready("echo", "1"))
+            .await
+            .unwrap()
+            .into_inner()
+            .ready
+    );
+    assert!(
+        !client
+            .model_ready(ready("echo", "2"))
+            .await
+            .unwrap()
+            .into_inner()
+            .ready
+    );
+    assert!(
+        !client
+            .model_ready(ready("ocr", ""))
+            .await
+            .unwrap()
+            .into_inner()
+            .ready
+    );
+
+    let metadata = client
+        .model_metadata(ModelMetadataRequest {
+            name: "echo".to_string(),
+            version: String::new(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(metadata.platform, "rknn");
+    assert_eq!(metadata.versions, ["1"]);
+    let input = &metadata.inputs[0];
+    assert_eq!(
+        (input.name.as_str(), input.datatype.as_str()),
+        ("x", "INT8")
+    );
+    assert_eq!(input.shape, [1, 4]);
+    // Outputs are dequantized.
+    assert_eq!(metadata.outputs[0].datatype, "FP32");
+
+    let status = client
+        .model_metadata(ModelMetadataRequest {
+            name: "ocr".to_string(),
+            version: String::new(),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+    assert_eq!(status.message(), "Unknown model 'ocr'.");
+}
+
+#[tokio::test]
+async fn test_infer() {
+    let mut client = client().await;
+    let mut request = fp32_request(&[1.0, -2.0, 0.5, 3.0]);
+    request.id = "42".to_string();
+    let response = client.model_infer(request).await.unwrap().into_inner();
+    assert_eq!(response.id, "42");
+    assert_eq!(response.outputs[0].name, "y");
+    assert_eq!(response.outputs[0].shape, [1, 4]);
+    assert_eq!(
+        f32_values(&response.raw_output_contents[0]),
+        [1.0, -2.0, 0.5, 3.0]
+    );
+
+    // Raw inputs of the type of the model input are passed as is.
+    let mut request = fp32_request(&[]);
+    request.inputs[0].datatype = "INT8".to_string();
+    request.inputs[0].contents = None;
+    request.raw_input_contents = vec![vec![2, -4_i8 as u8, 1, 6]];
+    request.outputs = vec![InferRequestedOutputTensor {
+        name: "y".to_string(),
+    }];
+    let response = client.model_infer(request).await.unwrap().into_inner();
+    assert_eq!(
+        f32_values(&response.raw_output_contents[0]),
+        [1.0, -2.0, 0.5, 3.0]
+    );
+
+    let status = client.model_infer(fp32_request(&[1.0])).await.unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+
+    // A panic of a backend is an internal error, and the backend is dropped from the pool of 2.
+    let panicked = "The backend of model 'echo' panicked and can't be run anymore.";
+    let ready = ModelReadyRequest {
+        name: "echo".to_string(),
+        version: String::new(),
+    };
+    let request = fp32_request(&[50.0, 0.0, 0.0, 0.0]);
+    let status = client.model_infer(request.clone()).await.unwrap_err();
+    assert_eq!(status.code(), Code::Internal);
+    assert_eq!(status.message(), panicked);
+    let response = client.model_ready(ready.clone()).await.unwrap();
+    assert!(response.into_inner().ready);
+    client
+        .model_infer(fp32_request(&[1.0, -2.0, 0.5, 3.0]))
+        .await
+        .unwrap();
+
+    // Once every backend panicked, the model isn't ready anymore.
+    let status = client.model_infer(request).await.unwrap_err();
+    assert_eq!(status.message(), panicked);
+    let ready = client.model_ready(ready).await.unwrap();
+    assert!(!ready.into_inner().ready);
+    let request = fp32_request(&[1.0, -2.0, 0.5, 3.0]);
+    let status = client.model_infer(request).await.unwrap_err();
+    assert_eq!(status.code(), Code::Internal);
+    assert_eq!(status.message(), panicked);
+}
+
+#[tokio::test]
+async fn test_stream_infer() {
+    let mut client = client().await;
+    let frames = (0..8).map(|it| match it {
+        // A failed frame doesn't end the stream.
+        3 => fp32_request(&[]),
+        it => fp32_request(&[it as f32; 4]),
+    });
+    let mut responses = client
+        .model_stream_infer(tokio_stream::iter(frames))
+        .await
+        .unwrap()
+        .into_inner();
+    let mut index = 0;
+    while let Some(response) = responses.next().await {
+        let response = response.unwrap();
+        match index {
+            3 => {
+                assert!(response.infer_response.is_none());
+                assert!(!response.error_message.is_empty());
+            }
+            index => {
+                let outputs = response.infer_response.unwrap().raw_output_contents;
+                assert_eq!(f32_values(&outputs[0]), [index as f32; 4]);
+            }
+        }
+        index += 1;
+    }
+    assert_eq!(index, 8);
+}
@@ -0,0 +1,263 @@
+use std::{io::Read, sync::Arc};
+
+use rknpu::{
+    driver::stub::{tensor_attribute, StubCall, StubDriver},
+    tensors::types::{RknnTensorQuantFormat, RknnTensorType},
+};
+use rknpu_runtime::{backend::RknnBackend, Model};
+use rknpu_server::{InferenceServer, ServerBuilder, HEADER_LENGTH};
+use serde_json::{json, Value};
+
+/// Serves "echo", an int8 model copying its input to its output, both scaled by 0.5.
+fn server() -> InferenceServer {
+    server_with_driver(driver())
+}
+
+/// The driver of "echo", which panics on an input starting with 50.
+fn driver() -> Arc<StubDriver> {
+    let quant = RknnTensorQuantFormat::AffineScale(0, 0.5);
+    let mut input = tensor_attribute(0, "x", &[1, 4], RknnTensorType::I8);
+    input.quant_type = quant.clone();
+    let mut output = tensor_attribute(0, "y", &[1, 4], RknnTensorType::I8);
+    output.quant_type = quant;
+    Arc::new(StubDriver::new(vec![input], vec![output], |inputs| {
+        assert_ne!(inputs[0][0], 100, "echo panicked");
+        inputs.to_vec()
+    }))
+}
+
+fn server_with_driver(driver: Arc<StubDriver>) -> InferenceServer {
+    let session = Model::with_driver(vec![], driver).session().unwrap();
+    ServerBuilder::new()
+        .with_model("echo", Box::new(RknnBackend::new(session)))
+        .bind("127.0.0.1:0")
+        .unwrap()
+}
+
+fn url(server: &InferenceServer, path: &str) -> String {
+    format!("http://{}{path}", server.addr())
+}
+
+/// Status and JSON body of a response, error statuses included.
+fn json_response(response: Result<ureq::Response, ureq::Error>) -> (u16, Value) {
+    let response = match response {
+        Ok(response) => response,
+        Err(ureq::Error::Status(_, response)) => response,
+        Err(error) => panic!("{error}"),
+    };
+    let status = response.status();
+    (
+        status,
+        serde_json::from_str(&response.into_string().unwrap()).unwrap(),
+    )
+}
+
+fn get(server: &InferenceServer, path: &str) -> (u16, Value) {
+    json_response(ureq::get(&url(server, path)).call())
+}
+
+fn infer(server: &InferenceServer, request: Value) -> (u16, Value) {
+    let response =
+        ureq::post(&url(server, "/v2/models/echo/infer")).send_string(&request.to_string());
+    json_response(response)
+}
+
+#[test]
+fn test_health_and_metadata() {
+    let server = server();
+    assert_eq!(
+        get(&server, "/v2/health/live"),
+        (200, json!({"live": true}))
+    );
+    assert_eq!(
+        get(&server, "/v2/health/ready"),
+        (200, json!({"ready": true}))
+    );
+    let (status, metadata) = get(&server, "/v2");
+    assert_eq!(status, 200);
+    assert_eq!(metadata["name"], "rknpu-server");
+    assert_eq!(metadata["extensions"], json!(["binary_tensor_data"]));
+
+    assert_eq!(
+        get(&server, "/v2/models/echo/ready"),
+        (200, json!({"name": "echo", "ready": true}))
+    );
+    let expected = json!({
+        "name": "echo",
+        "versions": ["1"],
+        "platform": "rknn",
+        "inputs": [{"name": "x", "datatype": "INT8", "shape": [1, 4]}],
+        // Outputs are dequantized.
+        "outputs": [{"name": "y", "datatype": "FP32", "shape": [1, 4]}],
+    });
+    assert_eq!(get(&server, "/v2/models/echo"), (200, expected.clone()));
+    assert_eq!(get(&server, "/v2/models/echo/versions/1"), (200, expected));
+
+    let (status, error) = get(&server, "/v2/models/detector/ready");
+    assert_eq!(status, 404);
+    assert_eq!(error["error"], "Unknown model 'detector'.");
+    assert_eq!(get(&server, "/v2/models/echo/versions/2").0, 404);
+    assert_eq!(get(&server, "/v1/models").0, 404);
+}
+
+#[test]
+fn test_infer_json() {
+    let server = server();
+    let (status, response) = infer(
+        &server,
+        json!({
+            "id": "42",
+            "inputs": [{
+                "name": "x",
+                "shape": [1, 4],
+                "datatype": "FP32",
+                "data": [[1.0, -2.0, 0.5, 3.0]],
+            }],
+        }),
+    );
+    assert_eq!(status, 200);
+    assert_eq!(
+        response,
+        json!({
+            "model_name": "echo",
+            "id": "42",
+            "outputs": [{
+                "name": "y",
+                "shape": [1, 4],
+                "datatype": "FP32",
+                "data": [1.0, -2.0, 0.5, 3.0],
+            }],
+        })
+    );
+
+    // Inputs of the type of the model input are passed as is.
+    let (status, response) = infer(
+        &server,
+        json!({
+            "inputs": [{"name": "x", "shape": [1, 4], "datatype": "INT8", "data": [2, -4, 1, 6]}],
+        }),
+    );
+    assert_eq!(status, 200);
+    assert_eq!(response["outputs"][0]["data"], json!([1.0, -2.0, 0.5, 3.0]));
+}
+
+#[test]
+fn test_infer_binary() {
+    let server = server();
+    let header = json!({
+        "inputs": [{
+            "name": "x",
+            "shape": [1, 4],
+            "datatype": "INT8",
+            "parameters": {"binary_data_size": 4},
+        }],
+        "outputs": [{"name": "y", "parameters": {"binary_data": true}}],
+    })
+    .to_string();
+    let mut body = header.clone().into_bytes();
+    body.extend([2, -4_i8 as u8, 1, 6]);
+    let response = ureq::post(&url(&server, "/v2/models/echo/infer"))
+        .set(HEADER_LENGTH, &header.len().to_string())
+        .send_bytes(&body)
+        .unwrap();
+    let header_length = response
+        .header(HEADER_LENGTH)
+        .unwrap()
+        .parse::<usize>()
+        .unwrap();
+    let mut body = vec![];
+    response.into_reader().read_to_end(&mut body).unwrap();
+
+    let (header, binary) = body.split_at(header_length);
+    let header = serde_json::from_slice::<Value>(header).unwrap();
+    assert_eq!(
+        header["outputs"][0],
+        json!({
+            "name": "y",
+            "shape": [1, 4],
+            "datatype": "FP32",
+            "parameters": {"binary_data_size": 16},
+        })
+    );
+    let y = binary
+        .chunks_exact(4)
+        .map(|it| f32::from_le_bytes(it.try_into().unwrap()))
+        .collect::<Vec<_>>();
+    assert_eq!(y, [1.0, -2.0, 0.5, 3.0]);
+}
+
+#[test]
+fn test_infer_errors() {
+    let server = server();
+    let input = |datatype: &str, data: Value| {
+        let input = json!({"name": "x", "shape": [1, 4], "datatype": datatype, "data": data});
+        json!({ "inputs": [input] })
+    };
+    let (status, error) = infer(&server, input("BYTES", json!(["a", "b", "c", "d"])));
+    assert_eq!(status, 400);
+    assert_eq!(
+        error["error"],
+        "Tensor 'x' has unsupported datatype 'BYTES'."
+    );
+    let (status, error) = infer(&server, input("INT8", json!([1, 2, 3, 300])));
+    assert_eq!(status, 400);
+    assert_eq!(
+        error["error"],
+        "Tensor 'x' has values that are not of datatype INT8."
+    );
+    assert_eq!(infer(&server, input("INT8", json!([1, 2, 3]))).0, 400);
+    assert_eq!(infer(&server, json!({"inputs": []})).0, 400);
+    assert_eq!(infer(&server, json!({"outputs": []})).0, 400);
+
+    let mut request = input("FP32", json!([0.0, 0.0, 0.0, 0.0]));
+    request["outputs"] = json!([{"name": "z"}]);
+    let (status, error) = infer(&server, request);
+    assert_eq!(status, 400);
+    assert_eq!(error["error"], "Unknown output 'z'.");
+
+    // The server keeps serving after errors.
+    let (status, _) = infer(&server, input("FP32", json!([0.0, 0.0, 0.0, 0.0])));
+    assert_eq!(status, 200);
+}
+
+#[test]
+fn test_infer_failures() {
+    let driver = driver();
+    let server = server_with_driver(driver.clone());
+    let input = |x: f32| {
+        let input = json!({"name": "x", "shape": [1, 4], "datatype": "FP32", "data": [x, 0, 0, 0]});
+        json!({ "inputs": [input] })
+    };
+
+    // Bodies larger than the inputs of the model written as JSON are refused.
+    let body = " ".repeat(1 << 20);
+    let response = ureq::post(&url(&server, "/v2/models/echo/infer")).send_string(&body);
+    let (status, error) = json_response(response);
+    assert_eq!(status, 413);
+    assert_eq!(
+        error["error"],
+        "Request body is larger than the 65664 bytes taken by model 'echo'."
+    );
+
+    // Failures of the backend are errors of the server.
+    driver.fail_on(StubCall::Run, -1);
+    assert_eq!(infer(&server, input(1.0)).0, 500);
+    driver.clear_failure(StubCall::Run);
+    assert_eq!(infer(&server, input(1.0)).0, 200);
+
+    // A backend that panicked is not run again, the server keeps answering.
+    let (status, error) = infer(&server, input(50.0));
+    assert_eq!(status, 500);
+    assert_eq!(error["error"], "The request handler panicked.");
+    let (status, error) = infer(&server, input(1.0));
+    assert_eq!(status, 500);
+    assert_eq!(
+        error["error"],
+        "The backend of model 'echo' panicked and can't be run anymore."
+    );
+    assert_eq!(
+        get(&server, "/v2/models/echo/ready"),
+        (200, json!({"name": "echo", "ready": false}))
+    );
+    assert_eq!(get(&server, "/v2/health/live").0, 200);
+}
@@ -4,8 +4,15 @@ use std::io::{BufWriter, Write};
 use std::path::PathBuf;
 use std::process::Command;
 
+#[cfg(not(feature = "v2_0"))]
 const GIT_REPOSITORY: &str = "https://github.com/rockchip-linux/rknn-toolkit2";
+#[cfg(not(feature = "v2_0"))]
 const VERSION: &str = "1.6.0";
+// Releases from 2.0 are published in the airockchip organization.
+#[cfg(feature = "v2_0")]
+const GIT_REPOSITORY: &str = "https://github.com/airockchip/rknn-toolkit2";
+#[cfg(feature = "v2_0")]
+const VERSION: &str = "2.0.0-beta0";
 const OS: &str = "Linux";
 
 fn main() {
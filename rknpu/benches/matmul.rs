@@ -2,103 +2,84 @@ use criterion::*;
 use half::f16;
 use rknpu::matmul::*;
 
-fn matmul_benchmark(
+/// Benchmark a matmul using the native layouts, `a` and `b` being already converted to them.
+fn matmul_benchmark<A, C>(
     c: &mut Criterion,
-    m: usize,
-    k: usize,
-    n: usize,
-    t: RknnMatmulType,
+    (m, k, n): (usize, usize, usize),
     label: &str,
-    b_layout: bool,
-    ac_layout: bool,
-) {
+    a: Vec<A>,
+    b: Vec<A>,
+) where
+    A: MatmulTypes<C>,
+    C: MatmulElement,
+{
     let mut group = c.benchmark_group(format!("{}x{}x{} {}", m, k, n, label));
     group.throughput(Throughput::Elements((m * k * n) as _));
 
-    let infos = RknnMatmulInfo::new(m, k, n, t.clone(), b_layout, ac_layout);
-    let mut rknn_matmul = RknnMatmul::new(infos).unwrap();
+    let mut matmul = Matmul::<A, C>::with_layouts(m, k, n, true, true).unwrap();
+    let mut out = vec![C::default(); matmul.as_raw().buffer_sizes()[2] / size_of::<C>()];
 
-    // Initialize matrices A and B based on the RknnMatmulType
-    let (raw_a, raw_b): (&[u8], &[u8]) = match t {
-        RknnMatmulType::RKNN_FLOAT16_MM_FLOAT16_TO_FLOAT32 => {
-            let a = vec![f16::from_f32(0.0); m * k];
-            let b = vec![f16::from_f32(0.0); k * n];
-            let raw_a = unsafe {
-                std::slice::from_raw_parts(
-                    a.as_ptr() as *const u8,
-                    a.len() * std::mem::size_of::<f16>(),
-                )
-            };
-            let raw_b = unsafe {
-                std::slice::from_raw_parts(
-                    b.as_ptr() as *const u8,
-                    b.len() * std::mem::size_of::<f16>(),
-                )
-            };
-            (raw_a, raw_b)
-        }
-        RknnMatmulType::RKNN_INT8_MM_INT8_TO_INT32 => {
-            let a = vec![0_i8; m * k];
-            let b = vec![0_i8; k * n];
-            let raw_a = unsafe {
-                std::slice::from_raw_parts(
-                    a.as_ptr() as *const u8,
-                    a.len() * std::mem::size_of::<i8>(),
-                )
-            };
-            let raw_b = unsafe {
-                std::slice::from_raw_parts(
-                    b.as_ptr() as *const u8,
-                    b.len() * std::mem::size_of::<i8>(),
-                )
-            };
-            (raw_a, raw_b)
-        }
-        RknnMatmulType::RKNN_INT4_MM_INT4_TO_INT16 => {
-            // Adjust buffer sizes for int4
-            let a = vec![0_u8; (m * k) / 2]; // Each u8 stores two int4 values
-            let b = vec![0_u8; (k * n) / 2]; // Each u8 stores two int4 values
-            let raw_a = unsafe { std::slice::from_raw_parts(a.as_ptr(), a.len()) };
-            let raw_b = unsafe { std::slice::from_raw_parts(b.as_ptr(), b.len()) };
-            (raw_a, raw_b)
-        }
-    };
+    matmul.set_inputs(&a, &b).unwrap();
+    group.bench_function("exec", |bencher| {
+        bencher.iter(|| {
+            matmul.exec().unwrap();
+        })
+    });
 
-    rknn_matmul.set_inputs(raw_a, raw_b).unwrap();
-    group.bench_function(label, |b| {
-        b.iter(|| {
-            rknn_matmul.exec().unwrap();
+    // Every call uploads both matrices and binds the buffers again.
+    group.bench_function("set_inputs", |bencher| {
+        bencher.iter(|| {
+            matmul.set_inputs(&a, &b).unwrap();
+            matmul.exec().unwrap();
+            matmul.get_output(&mut out).unwrap();
+        })
+    });
+
+    // B stays resident on the NPU, only A is uploaded.
+    matmul.set_b(&b).unwrap();
+    group.bench_function("resident_b", |bencher| {
+        bencher.iter(|| {
+            matmul.set_a(&a).unwrap();
+            matmul.exec().unwrap();
+            matmul.get_output(&mut out).unwrap();
         })
     });
 
     group.finish();
 }
 
-fn bench_method(c: &mut Criterion, m: usize, k: usize, n: usize) {
-    let matmul_variants = [
-        (
-            "float16",
-            RknnMatmulType::RKNN_FLOAT16_MM_FLOAT16_TO_FLOAT32,
-            true,
-            true,
-        ),
-        (
-            "int8",
-            RknnMatmulType::RKNN_INT8_MM_INT8_TO_INT32,
-            true,
-            true,
-        ),
-        (
-            "int4",
-            RknnMatmulType::RKNN_INT4_MM_INT4_TO_INT16,
-            true,
-            true,
-        ),
-    ];
+fn bench_method(c: &mut Criterion, (m, k, n): (usize, usize, usize)) {
+    let a = (0..m * k).map(|it| (it % 15) as i8 - 7).collect::<Vec<_>>();
+    let b = (0..k * n).map(|it| (it % 13) as i8 - 6).collect::<Vec<_>>();
 
-    for (label, matmul_type, b_layout, ac_layout) in matmul_variants.into_iter() {
-        matmul_benchmark(c, m, k, n, matmul_type, label, b_layout, ac_layout);
-    }
+    let layout = NativeLayout::new(&RknnMatmulType::RKNN_FLOAT16_MM_FLOAT16_TO_FLOAT32);
+    let to_f16 =
+        |values: &[i8]| -> Vec<f16> { values.iter().map(|it| f16::from_f32(*it as f32)).collect() };
+    matmul_benchmark::<f16, f32>(
+        c,
+        (m, k, n),
+        "float16",
+        layout.pack_a(&to_f16(&a), m, k),
+        layout.pack_b(&to_f16(&b), k, n),
+    );
+
+    let layout = NativeLayout::new(&RknnMatmulType::RKNN_INT8_MM_INT8_TO_INT32);
+    matmul_benchmark::<i8, i32>(
+        c,
+        (m, k, n),
+        "int8",
+        layout.pack_a(&a, m, k),
+        layout.pack_b(&b, k, n),
+    );
+
+    let layout = NativeLayout::new(&RknnMatmulType::RKNN_INT4_MM_INT4_TO_INT16);
+    matmul_benchmark::<PackedI4, i16>(
+        c,
+        (m, k, n),
+        "int4",
+        PackedI4::pack(&layout.pack_a(&a, m, k)),
+        PackedI4::pack(&layout.pack_b(&b, k, n)),
+    );
 }
 
 fn matmul(c: &mut Criterion) {
@@ -109,7 +90,7 @@ fn matmul(c: &mut Criterion) {
     for &m in &m_values {
         for &k in &k_values {
             for &n in &n_values {
-                bench_method(c, m, k, n);
+                bench_method(c, (m, k, n));
             }
         }
     }
@@ -0,0 +1,275 @@
+use std::{ffi::CStr, fmt};
+
+use anyhow::Result;
+use rknpu_sys::{_rknn_custom_string, _rknn_mem_size};
+
+use crate::{
+    queries::{QueryObject, RknnQuery},
+    tensors::attributes::{
+        RknnInputTensorAttribute, RknnNativeInputTensorAttribute, RknnNativeOutputTensorAttribute,
+        RknnOutputTensorAttribute, RknnTensorAttribute,
+    },
+};
+
+use super::{RknnContext, RknnInputOutputNum, RknnSdkVersion};
+
+/// Summary of a loaded model, queried once when the context is created.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ModelInfo {
+    /// Version of the runtime and of the NPU driver.
+    pub sdk_version: RknnSdkVersion,
+    /// Number of input and output tensors.
+    pub num_input_outputs: RknnInputOutputNum,
+    /// Attributes of the input tensors, as seen by the user.
+    pub inputs: Vec<RknnTensorAttribute>,
+    /// Attributes of the output tensors, as seen by the user.
+    pub outputs: Vec<RknnTensorAttribute>,
+    /// Attributes of the input tensors, in the layout used by the NPU.
+    pub native_inputs: Vec<RknnTensorAttribute>,
+    /// Attributes of the output tensors, in the layout used by the NPU.
+    pub native_outputs: Vec<RknnTensorAttribute>,
+    /// Memory used by the model.
+    pub mem_size: RknnMemSize,
+    /// Custom string set when converting the model.
+    pub custom_string: String,
+}
+
+impl ModelInfo {
+    pub(crate) fn query(ctx: &RknnContext) -> Result<Self> {
+        let sdk_version = ctx.query_context::<RknnSdkVersion>(None)?;
+        let num_input_outputs = ctx.query_context::<RknnInputOutputNum>(None)?;
+        let inputs = (0..num_input_outputs.n_input)
+            .map(|index| Ok(query_attribute::<RknnInputTensorAttribute>(ctx, index)?.0))
+            .collect::<Result<_>>()?;
+        let outputs = (0..num_input_outputs.n_output)
+            .map(|index| Ok(query_attribute::<RknnOutputTensorAttribute>(ctx, index)?.0))
+            .collect::<Result<_>>()?;
+        let native_inputs = (0..num_input_outputs.n_input)
+            .map(|index| Ok(query_attribute::<RknnNativeInputTensorAttribute>(ctx, index)?.0))
+            .collect::<Result<_>>()?;
+        let native_outputs = (0..num_input_outputs.n_output)
+            .map(|index| Ok(query_attribute::<RknnNativeOutputTensorAttribute>(ctx, index)?.0))
+            .collect::<Result<_>>()?;
+        let mem_size = ctx.query_context::<RknnMemSize>(None)?;
+        let custom_string = ctx.query_context::<RknnCustomString>(None)?.0;
+        Ok(Self {
+            sdk_version,
+            num_input_outputs,
+            inputs,
+            outputs,
+            native_inputs,
+            native_outputs,
+            mem_size,
+            custom_string,
+        })
+    }
+}
+
+fn query_attribute<Q>(ctx: &RknnContext, index: u32) -> Result<Q>
+where
+    Q: QueryObject<RknnPrimitiveType = rknpu_sys::_rknn_tensor_attr>,
+{
+    let mut default = Q::primitive_init_value();
+    default.index = index;
+    ctx.query_context::<Q>(Some(default))
+}
+
+impl fmt::Display for ModelInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "API version:    {}", self.sdk_version.api_version)?;
+        writeln!(f, "Driver version: {}", self.sdk_version.driver_version)?;
+        if !self.custom_string.is_empty() {
+            writeln!(f, "Custom string:  {}", self.custom_string)?;
+        }
+        writeln!(
+            f,
+            "Memory:         weights {} B, internal {} B, DMA {} B",
+            self.mem_size.total_weight_size,
+            self.mem_size.total_internal_size,
+            self.mem_size.total_dma_allocated_size
+        )?;
+        write_attributes(f, "Inputs", &self.inputs)?;
+        write_attributes(f, "Native inputs", &self.native_inputs)?;
+        write_attributes(f, "Outputs", &self.outputs)?;
+        write_attributes(f, "Native outputs", &self.native_outputs)
+    }
+}
+
+fn write_attributes(
+    f: &mut fmt::Formatter<'_>,
+    title: &str,
+    attributes: &[RknnTensorAttribute],
+) -> fmt::Result {
+    let header = ["index", "name", "dims", "format", "type", "quantization"];
+    let rows = attributes
+        .iter()
+        .map(|it| {
+            [
+                it.index.to_string(),
+                it.name.clone(),
+                format!("{:?}", it.dims),
+                format!("{:?}", it.format),
+                format!("{:?}", it.data_type),
+                format!("{:?}", it.quant_type),
+            ]
+        })
+        .collect::<Vec<_>>();
+    let mut widths = header.map(str::len);
+    for row in rows.iter() {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    writeln!(f, "{title} ({}):", attributes.len())?;
+    let header = header.map(str::to_string);
+    for row in std::iter::once(&header).chain(rows.iter()) {
+        let line = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        writeln!(f, "  {}", line.trim_end())?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RknnMemSize {
+    /// Size of the model weights, in bytes.
+    pub total_weight_size: u32,
+    /// Size of the internal tensors, in bytes.
+    pub total_internal_size: u32,
+    /// Total DMA memory allocated by the context, in bytes.
+    pub total_dma_allocated_size: u64,
+    /// Total SRAM size available to the NPU, in bytes.
+    pub total_sram_size: u32,
+    /// Free SRAM size, in bytes.
+    pub free_sram_size: u32,
+}
+
+impl QueryObject for RknnMemSize {
+    type RknnPrimitiveType = _rknn_mem_size;
+
+    fn primitive_init_value() -> Self::RknnPrimitiveType {
+        unsafe { std::mem::zeroed::<_rknn_mem_size>() }
+    }
+
+    fn query_flag() -> RknnQuery {
+        RknnQuery::RKNN_QUERY_MEM_SIZE
+    }
+
+    fn from_primitive_type(value: Self::RknnPrimitiveType) -> Result<Self>
+    where
+        Self: Sized,
+    {
+        Ok(Self {
+            total_weight_size: value.total_weight_size,
+            total_internal_size: value.total_internal_size,
+            total_dma_allocated_size: value.total_dma_allocated_size,
+            total_sram_size: value.total_sram_size,
+            free_sram_size: value.free_sram_size,
+        })
+    }
+}
+
+pub struct RknnCustomString(pub String);
+
+impl QueryObject for RknnCustomString {
+    type RknnPrimitiveType = _rknn_custom_string;
+
+    fn primitive_init_value() -> Self::RknnPrimitiveType {
+        unsafe { std::mem::zeroed::<_rknn_custom_string>() }
+    }
+
+    fn query_flag() -> RknnQuery {
+        RknnQuery::RKNN_QUERY_CUSTOM_STRING
+    }
+
+    fn from_primitive_type(value: Self::RknnPrimitiveType) -> Result<Self>
+    where
+        Self: Sized,
+    {
+        let string = unsafe { CStr::from_ptr(value.string.as_ptr()) };
+        Ok(Self(string.to_str()?.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        context::{RknnInputOutputNum, RknnSdkVersion},
+        tensors::{
+            attributes::RknnTensorAttribute,
+            types::{RknnTensorFormat, RknnTensorQuantFormat, RknnTensorType},
+        },
+    };
+
+    use super::{ModelInfo, RknnMemSize};
+
+    fn model_info() -> ModelInfo {
+        let input = RknnTensorAttribute {
+            index: 0,
+            dims: vec![1, 28, 28],
+            name: "serving_default_input_1:0".to_string(),
+            len: 784,
+            format: RknnTensorFormat::NHWC,
+            data_type: RknnTensorType::I8,
+            quant_type: RknnTensorQuantFormat::AffineScale(-128, 0.003921569),
+            w_stride: 0,
+            h_stride: 0,
+            size_with_stride: 784,
+            pass_through: false,
+        };
+        let output = RknnTensorAttribute {
+            index: 0,
+            dims: vec![1, 10],
+            name: "StatefulPartitionedCall:0".to_string(),
+            len: 10,
+            format: RknnTensorFormat::UNDEFINED,
+            data_type: RknnTensorType::I8,
+            quant_type: RknnTensorQuantFormat::AffineScale(51, 0.17899919),
+            w_stride: 0,
+            h_stride: 0,
+            size_with_stride: 10,
+            pass_through: false,
+        };
+        ModelInfo {
+            sdk_version: RknnSdkVersion {
+                api_version: "1.6.0".to_string(),
+                driver_version: "0.8.2".to_string(),
+            },
+            num_input_outputs: RknnInputOutputNum {
+                n_input: 1,
+                n_output: 1,
+            },
+            inputs: vec![input.clone()],
+            outputs: vec![output.clone()],
+            native_inputs: vec![input],
+            native_outputs: vec![output],
+            mem_size: RknnMemSize::default(),
+            custom_string: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        let table = model_info().to_string();
+        let expected = "  0      serving_default_input_1:0  [1, 28, 28]  NHWC    I8    \
+                        AffineScale(-128, 0.003921569)";
+        assert!(table.lines().any(|it| it == expected), "{table}");
+        assert!(table.contains("Outputs (1):"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_json_round_trip() {
+        let info = model_info();
+        let json = serde_json::to_string(&info).unwrap();
+        let deserialized: ModelInfo = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized, info);
+    }
+}
@@ -2,18 +2,26 @@ use std::ffi::c_void;
 
 use rknpu_sys::_rknn_input;
 
-use crate::tensors::types::{RknnTensorFormat, RknnTensorType};
+use crate::{
+    error::RknnInputError,
+    tensors::{
+        attributes::RknnTensorAttribute,
+        types::{RknnTensorFormat, RknnTensorType},
+    },
+};
 
-pub struct RknnInput {
+/// An input of a run. The buffer is owned by default, any buffer of bytes can be set without
+/// copying it, such as one shared with another process.
+pub struct RknnInput<B = Vec<u8>> {
     /// Input index.
     pub index: u32,
     /// Input data buffer.
-    pub buffer: Vec<u8>,
+    pub buffer: B,
     /// Pass through mode
     /// - true: the data buffer is passed directly to the input node of the rknn model without any
-    /// conversion. The following variables don't need to be set.
+    ///   conversion. The following variables don't need to be set.
     /// - false: the data buffer is converted into an input consistent with the model according to
-    /// the following type and format. The following variables need to be set.
+    ///   the following type and format. The following variables need to be set.
     pub pass_through: bool,
     /// Data type of the input data.
     pub dtype: RknnTensorType,
@@ -22,15 +30,195 @@ pub struct RknnInput {
     pub fmt: RknnTensorFormat,
 }
 
-impl From<RknnInput> for _rknn_input {
-    fn from(value: RknnInput) -> Self {
+impl<B: AsRef<[u8]>> RknnInput<B> {
+    /// Check this input against the attribute of the model input it targets.
+    pub fn validate(&self, attribute: &RknnTensorAttribute) -> Result<(), RknnInputError> {
+        if self.pass_through {
+            if self.dtype != attribute.data_type {
+                return Err(RknnInputError::TypeMismatch {
+                    name: attribute.name.clone(),
+                    expected: attribute.data_type,
+                    actual: self.dtype,
+                });
+            }
+            if attribute.format != RknnTensorFormat::UNDEFINED && self.fmt != attribute.format {
+                return Err(RknnInputError::FormatMismatch {
+                    name: attribute.name.clone(),
+                    expected: attribute.format,
+                    actual: self.fmt,
+                });
+            }
+        }
+
+        let expected = attribute.len * self.dtype.size();
+        let actual = self.buffer.as_ref().len();
+        // Passed through buffers may also carry the stride padding of the model input.
+        let strided = self.pass_through && actual == attribute.size_with_stride as usize;
+        if actual != expected && !strided {
+            return Err(RknnInputError::SizeMismatch {
+                name: attribute.name.clone(),
+                expected,
+                actual,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Check a full set of inputs against the model input attributes: every model input must be set
+/// exactly once, with a buffer matching its attribute.
+pub fn validate_inputs<B: AsRef<[u8]>>(
+    inputs: &[RknnInput<B>],
+    attributes: &[RknnTensorAttribute],
+) -> Result<(), RknnInputError> {
+    let mut is_set = vec![false; attributes.len()];
+    for input in inputs {
+        let attribute =
+            attributes
+                .get(input.index as usize)
+                .ok_or(RknnInputError::IndexOutOfRange {
+                    index: input.index,
+                    n_input: attributes.len(),
+                })?;
+        if is_set[input.index as usize] {
+            return Err(RknnInputError::Duplicate {
+                index: input.index,
+                name: attribute.name.clone(),
+            });
+        }
+        is_set[input.index as usize] = true;
+        input.validate(attribute)?;
+    }
+
+    if let Some(missing) = is_set.iter().position(|it| !it) {
+        return Err(RknnInputError::Missing {
+            index: missing as u32,
+            name: attributes[missing].name.clone(),
+        });
+    }
+    Ok(())
+}
+
+impl<B: AsRef<[u8]>> From<&RknnInput<B>> for _rknn_input {
+    fn from(value: &RknnInput<B>) -> Self {
+        let buffer = value.buffer.as_ref();
         Self {
             index: value.index,
-            buf: value.buffer.as_ptr() as *mut c_void,
-            size: value.buffer.len() as u32,
+            buf: buffer.as_ptr() as *mut c_void,
+            size: buffer.len() as u32,
             pass_through: value.pass_through as u8,
             type_: value.dtype as u32,
             fmt: value.fmt as u32,
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        error::RknnInputError,
+        tensors::{
+            attributes::RknnTensorAttribute,
+            types::{RknnTensorFormat, RknnTensorQuantFormat, RknnTensorType},
+        },
+    };
+
+    use super::{validate_inputs, RknnInput};
+
+    fn attribute(index: usize, name: &str) -> RknnTensorAttribute {
+        RknnTensorAttribute {
+            index,
+            dims: vec![1, 28, 28],
+            name: name.to_string(),
+            len: 784,
+            format: RknnTensorFormat::NHWC,
+            data_type: RknnTensorType::I8,
+            quant_type: RknnTensorQuantFormat::AffineScale(-128, 0.003921569),
+            w_stride: 0,
+            h_stride: 0,
+            size_with_stride: 784,
+            pass_through: false,
+        }
+    }
+
+    fn input(index: u32, size: usize, pass_through: bool, dtype: RknnTensorType) -> RknnInput {
+        RknnInput {
+            index,
+            buffer: vec![0; size],
+            pass_through,
+            dtype,
+            fmt: RknnTensorFormat::NHWC,
+        }
+    }
+
+    #[test]
+    fn test_valid_inputs() {
+        let attributes = vec![attribute(0, "a"), attribute(1, "b")];
+        let inputs = vec![
+            input(1, 784 * 4, false, RknnTensorType::F32),
+            input(0, 784, true, RknnTensorType::I8),
+        ];
+        assert_eq!(validate_inputs(&inputs, &attributes), Ok(()));
+    }
+
+    #[test]
+    fn test_invalid_inputs() {
+        let attributes = vec![attribute(0, "a"), attribute(1, "b")];
+
+        let inputs = vec![input(2, 784, false, RknnTensorType::I8)];
+        assert_eq!(
+            validate_inputs(&inputs, &attributes),
+            Err(RknnInputError::IndexOutOfRange {
+                index: 2,
+                n_input: 2
+            })
+        );
+
+        let inputs = vec![
+            input(0, 784, false, RknnTensorType::I8),
+            input(0, 784, false, RknnTensorType::I8),
+        ];
+        assert_eq!(
+            validate_inputs(&inputs, &attributes),
+            Err(RknnInputError::Duplicate {
+                index: 0,
+                name: "a".to_string()
+            })
+        );
+
+        let inputs = vec![input(0, 784, false, RknnTensorType::I8)];
+        assert_eq!(
+            validate_inputs(&inputs, &attributes),
+            Err(RknnInputError::Missing {
+                index: 1,
+                name: "b".to_string()
+            })
+        );
+
+        let inputs = vec![
+            input(0, 784, false, RknnTensorType::I8),
+            input(1, 784, false, RknnTensorType::F16),
+        ];
+        assert_eq!(
+            validate_inputs(&inputs, &attributes),
+            Err(RknnInputError::SizeMismatch {
+                name: "b".to_string(),
+                expected: 784 * 2,
+                actual: 784
+            })
+        );
+
+        let inputs = vec![
+            input(0, 784, false, RknnTensorType::I8),
+            input(1, 784, true, RknnTensorType::U8),
+        ];
+        assert_eq!(
+            validate_inputs(&inputs, &attributes),
+            Err(RknnInputError::TypeMismatch {
+                name: "b".to_string(),
+                expected: RknnTensorType::I8,
+                actual: RknnTensorType::U8
+            })
+        );
+    }
+}
@@ -1,42 +1,75 @@
 use std::{
+    collections::HashMap,
     ffi::{c_void, CStr},
     io::Read,
     path::Path,
+    sync::Arc,
 };
 
-use anyhow::{Context, Result};
+use anyhow::{ensure, Context, Result};
 use rknpu_sys::{
     _rknn_init_extend, _rknn_input, _rknn_input_output_num, _rknn_output, _rknn_output_extend,
-    _rknn_sdk_version, rknn_context, rknn_destroy, rknn_init, rknn_inputs_set, rknn_outputs_get,
-    rknn_query, rknn_run, rknn_run_extend,
+    _rknn_sdk_version, rknn_context, rknn_run_extend,
 };
 
 use crate::{
-    error::check_result,
-    flags::RknnExtendedFlag,
+    driver::{NativeDriver, RknnDriver},
+    error::{check_result, RknnError, RknnTensorLookupError},
+    flags::{RknnCoreMask, RknnExtendedFlag},
     queries::{QueryObject, RknnQuery},
-    tensors::attributes::{
-        RknnInputTensorAttribute, RknnOutputTensorAttribute, RknnTensorAttribute,
-    },
+    tensors::attributes::RknnTensorAttribute,
 };
 
-use self::{inputs::RknnInput, outputs::RknnOuput};
+use self::{
+    info::ModelInfo,
+    inputs::{validate_inputs, RknnInput},
+    outputs::RknnOuput,
+};
 
+pub mod info;
 pub mod inputs;
 pub mod memory;
 pub mod outputs;
+pub mod session;
 
+/// A model loaded on the NPU.
+///
+/// Methods taking `&self` only read information cached at load and can be called from several
+/// threads. Methods taking `&mut self` drive the runtime and must be serialized, see
+/// [`RknnSession`](session::RknnSession) to share a context between threads.
 pub struct RknnContext {
     raw: rknn_context,
+    driver: Arc<dyn RknnDriver>,
+    /// Model information, queried once at load.
+    info: ModelInfo,
+    /// Input tensor name to input index.
+    input_indices: HashMap<String, u32>,
+    /// Output tensor name to output index.
+    output_indices: HashMap<String, u32>,
+    /// Whether the runtime context was already destroyed.
+    destroyed: bool,
 }
 
 impl RknnContext {
     pub fn from_model_path<P: AsRef<Path>>(model_path: P, flag: RknnExtendedFlag) -> Result<Self> {
         let model_data = load_model_data(&model_path)?;
+        Self::from_model_data(&model_data, flag)
+    }
+
+    pub fn from_model_data(model_data: &[u8], flag: RknnExtendedFlag) -> Result<Self> {
+        Self::with_driver(model_data, flag, Arc::new(NativeDriver))
+    }
+
+    /// Load a model through the given driver.
+    pub fn with_driver(
+        model_data: &[u8],
+        flag: RknnExtendedFlag,
+        driver: Arc<dyn RknnDriver>,
+    ) -> Result<Self> {
         let size = model_data.len() as u32;
         let mut ctx_ptr = unsafe { std::mem::zeroed::<rknn_context>() };
         let ret = unsafe {
-            rknn_init(
+            driver.init(
                 &mut ctx_ptr as *mut rknn_context,
                 model_data.as_ptr() as *mut c_void,
                 size,
@@ -45,7 +78,18 @@ impl RknnContext {
             )
         };
         check_result(ret)?;
-        Ok(Self { raw: ctx_ptr })
+        let mut ctx = Self {
+            raw: ctx_ptr,
+            driver,
+            info: ModelInfo::default(),
+            input_indices: HashMap::new(),
+            output_indices: HashMap::new(),
+            destroyed: false,
+        };
+        ctx.info = ModelInfo::query(&ctx)?;
+        ctx.input_indices = name_indices(&ctx.info.inputs);
+        ctx.output_indices = name_indices(&ctx.info.outputs);
+        Ok(ctx)
     }
 
     pub fn query_context<Q: QueryObject>(
@@ -54,7 +98,7 @@ impl RknnContext {
     ) -> Result<Q> {
         let mut raw_query_result = default.unwrap_or(Q::primitive_init_value());
         let ret = unsafe {
-            rknn_query(
+            self.driver.query(
                 self.raw,
                 Q::query_flag() as u32,
                 &mut raw_query_result as *mut Q::RknnPrimitiveType as *mut c_void,
@@ -65,24 +109,85 @@ impl RknnContext {
         let query_result = Q::from_primitive_type(raw_query_result)?;
         Ok(query_result)
     }
+
+    /// Model information queried when the context was created.
+    pub fn model_info(&self) -> &ModelInfo {
+        &self.info
+    }
+
     pub fn check_version(&self) -> Result<RknnSdkVersion> {
-        self.query_context::<RknnSdkVersion>(None)
+        Ok(self.info.sdk_version.clone())
     }
 
     pub fn num_input_outputs(&self) -> Result<RknnInputOutputNum> {
-        self.query_context::<RknnInputOutputNum>(None)
+        Ok(self.info.num_input_outputs.clone())
     }
 
     pub fn input_attribute(&self, index: u32) -> Result<RknnTensorAttribute> {
-        let mut default = RknnInputTensorAttribute::primitive_init_value();
-        default.index = index;
-        let input_attribute = self.query_context::<RknnInputTensorAttribute>(Some(default))?;
-        Ok(input_attribute.0)
+        let attribute = self.info.inputs.get(index as usize).ok_or(
+            RknnTensorLookupError::InputIndexOutOfRange {
+                index,
+                n_input: self.info.inputs.len(),
+            },
+        )?;
+        Ok(attribute.clone())
+    }
+
+    pub fn output_attribute(&self, index: u32) -> Result<RknnTensorAttribute> {
+        let attribute = self.info.outputs.get(index as usize).ok_or(
+            RknnTensorLookupError::OutputIndexOutOfRange {
+                index,
+                n_output: self.info.outputs.len(),
+            },
+        )?;
+        Ok(attribute.clone())
+    }
+
+    /// Attribute of the input `index` in the layout used by the NPU.
+    pub fn native_input_attribute(&self, index: u32) -> Result<RknnTensorAttribute> {
+        let attribute = self.info.native_inputs.get(index as usize).ok_or(
+            RknnTensorLookupError::InputIndexOutOfRange {
+                index,
+                n_input: self.info.native_inputs.len(),
+            },
+        )?;
+        Ok(attribute.clone())
+    }
+
+    /// Attribute of the output `index` in the layout used by the NPU.
+    pub fn native_output_attribute(&self, index: u32) -> Result<RknnTensorAttribute> {
+        let attribute = self.info.native_outputs.get(index as usize).ok_or(
+            RknnTensorLookupError::OutputIndexOutOfRange {
+                index,
+                n_output: self.info.native_outputs.len(),
+            },
+        )?;
+        Ok(attribute.clone())
+    }
+
+    /// Index of the model input named `name`.
+    pub fn input_index(&self, name: &str) -> Result<u32> {
+        let index = self.input_indices.get(name).copied().ok_or_else(|| {
+            RknnTensorLookupError::UnknownInput {
+                name: name.to_string(),
+            }
+        })?;
+        Ok(index)
+    }
+
+    /// Index of the model output named `name`.
+    pub fn output_index(&self, name: &str) -> Result<u32> {
+        let index = self.output_indices.get(name).copied().ok_or_else(|| {
+            RknnTensorLookupError::UnknownOutput {
+                name: name.to_string(),
+            }
+        })?;
+        Ok(index)
     }
 
     pub fn run(&mut self) -> Result<()> {
         let ret = unsafe {
-            rknn_run(
+            self.driver.run(
                 self.raw,
                 std::ptr::null::<rknn_run_extend>() as *mut rknn_run_extend,
             )
@@ -91,56 +196,160 @@ impl RknnContext {
         Ok(())
     }
 
+    /// Restrict the following runs to the given NPU cores. Only supported on multi-core NPUs
+    /// such as the RK3588.
+    pub fn set_core_mask(&mut self, core_mask: RknnCoreMask) -> Result<()> {
+        let ret = unsafe { self.driver.set_core_mask(self.raw, core_mask as _) };
+        check_result(ret)?;
+        Ok(())
+    }
+
+    /// Set the model inputs. Inputs are validated against the model input attributes before
+    /// being handed to the driver, see [`RknnInputError`](crate::error::RknnInputError).
     pub fn set_inputs(&mut self, inputs: Vec<RknnInput>) -> Result<()> {
-        let raw_inputs: Vec<_rknn_input> = inputs.into_iter().map(|it| it.into()).collect();
+        self.set_input_buffers(&inputs)
+    }
+
+    /// Set the model inputs from borrowed buffers of any type, which the runtime copies before
+    /// returning. Inputs are validated as by [`set_inputs`](Self::set_inputs).
+    pub fn set_input_buffers<B: AsRef<[u8]>>(&mut self, inputs: &[RknnInput<B>]) -> Result<()> {
+        validate_inputs(inputs, &self.info.inputs)?;
+        let mut raw_inputs: Vec<_rknn_input> = inputs.iter().map(|it| it.into()).collect();
         let ret = unsafe {
-            rknn_inputs_set(
-                self.raw,
-                raw_inputs.len() as u32,
-                raw_inputs.as_ptr() as *mut _rknn_input,
-            )
+            self.driver
+                .inputs_set(self.raw, raw_inputs.len() as u32, raw_inputs.as_mut_ptr())
         };
         check_result(ret)?;
         Ok(())
     }
 
+    /// Set the model inputs, addressed by tensor name. The `index` of each [`RknnInput`] is
+    /// replaced by the index of the named input.
+    pub fn set_inputs_by_name<'a, I>(&mut self, inputs: I) -> Result<()>
+    where
+        I: IntoIterator<Item = (&'a str, RknnInput)>,
+    {
+        let inputs = inputs
+            .into_iter()
+            .map(|(name, input)| {
+                Ok(RknnInput {
+                    index: self.input_index(name)?,
+                    ..input
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        self.set_inputs(inputs)
+    }
+
+    /// Get the model outputs, keyed by tensor name.
+    pub fn get_outputs_by_name(&mut self) -> Result<HashMap<String, RknnOuput>> {
+        let outputs = self.get_outputs()?;
+        let outputs = self
+            .info
+            .outputs
+            .iter()
+            .map(|it| it.name.clone())
+            .zip(outputs)
+            .collect();
+        Ok(outputs)
+    }
+
     pub fn get_outputs(&mut self) -> Result<Vec<RknnOuput>> {
-        let n_outputs = self.num_input_outputs()?.n_output;
-        let raw_outputs = vec![unsafe { std::mem::zeroed::<_rknn_output>() }; n_outputs as usize];
-        //let mut raw_outputs: Vec<_rknn_output> = Vec::with_capacity(n_outputs as usize);
-        //raw_outputs
-        //    .iter_mut()
-        //    .for_each(|it| {
-        //        *it = unsafe { std::mem::zeroed::<_rknn_output>() };
-        //    });
+        let n_outputs = self.info.num_input_outputs.n_output;
+        let mut raw_outputs =
+            vec![unsafe { std::mem::zeroed::<_rknn_output>() }; n_outputs as usize];
         let ret = unsafe {
-            rknn_outputs_get(
+            self.driver.outputs_get(
                 self.raw,
                 n_outputs,
-                raw_outputs.as_ptr() as *mut _rknn_output,
+                raw_outputs.as_mut_ptr(),
                 std::ptr::null::<_rknn_output_extend>() as *mut _rknn_output_extend,
             )
         };
         check_result(ret)?;
-        let outputs = raw_outputs.into_iter().map(|it| it.into()).collect();
+        // Output buffers are copied, the runtime buffers can be released right away.
+        let outputs = raw_outputs.iter().map(|it| (*it).into()).collect();
+        let ret = unsafe {
+            self.driver
+                .outputs_release(self.raw, n_outputs, raw_outputs.as_mut_ptr())
+        };
+        check_result(ret)?;
         Ok(outputs)
     }
-    pub fn output_attribute(&self, index: u32) -> Result<RknnTensorAttribute> {
-        let mut default = RknnOutputTensorAttribute::primitive_init_value();
-        default.index = index;
-        let output_attribute = self.query_context::<RknnOutputTensorAttribute>(Some(default))?;
-        Ok(output_attribute.0)
+
+    /// Get the model outputs into preallocated buffers, one per output in index order, written
+    /// directly by the runtime. A buffer must hold at least the
+    /// [`output_len`](Self::output_len) bytes of its output.
+    pub fn get_outputs_into(&mut self, buffers: &mut [&mut [u8]]) -> Result<()> {
+        let n_outputs = self.info.num_input_outputs.n_output;
+        ensure!(
+            buffers.len() == n_outputs as usize,
+            "Expected {n_outputs} output buffers, got {}",
+            buffers.len()
+        );
+        let mut raw_outputs = buffers
+            .iter_mut()
+            .enumerate()
+            .map(|(index, buffer)| _rknn_output {
+                want_float: 0,
+                is_prealloc: 1,
+                index: index as u32,
+                buf: buffer.as_mut_ptr() as *mut c_void,
+                size: buffer.len() as u32,
+            })
+            .collect::<Vec<_>>();
+        let ret = unsafe {
+            self.driver.outputs_get(
+                self.raw,
+                n_outputs,
+                raw_outputs.as_mut_ptr(),
+                std::ptr::null::<_rknn_output_extend>() as *mut _rknn_output_extend,
+            )
+        };
+        check_result(ret)?;
+        let ret = unsafe {
+            self.driver
+                .outputs_release(self.raw, n_outputs, raw_outputs.as_mut_ptr())
+        };
+        check_result(ret)?;
+        Ok(())
+    }
+
+    /// Size in bytes of the output `index` returned by the runtime, in the type of the output.
+    pub fn output_len(&self, index: u32) -> Result<usize> {
+        let attribute = self.output_attribute(index)?;
+        Ok(attribute.len * attribute.data_type.size())
+    }
+}
+
+impl RknnContext {
+    /// Destroy the context, returning the runtime error if any. Dropping the context destroys it
+    /// too, but can only log errors.
+    pub fn close(mut self) -> Result<()> {
+        self.destroy()?;
+        Ok(())
+    }
+
+    fn destroy(&mut self) -> Result<(), RknnError> {
+        if self.destroyed {
+            return Ok(());
+        }
+        self.destroyed = true;
+        let ret = unsafe { self.driver.destroy(self.raw) };
+        check_result(ret)
     }
 }
 
 impl Drop for RknnContext {
     fn drop(&mut self) {
-        let ret = unsafe { rknn_destroy(self.raw) };
-        check_result(ret).unwrap();
+        if let Err(error) = self.destroy() {
+            log::error!("Failed to destroy RKNN context: {error}");
+        }
     }
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RknnSdkVersion {
     pub api_version: String,
     pub driver_version: String,
@@ -170,7 +379,8 @@ impl QueryObject for RknnSdkVersion {
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RknnInputOutputNum {
     pub n_input: u32,
     pub n_output: u32,
@@ -196,11 +406,148 @@ impl QueryObject for RknnInputOutputNum {
     }
 }
 
+fn name_indices(attributes: &[RknnTensorAttribute]) -> HashMap<String, u32> {
+    attributes
+        .iter()
+        .map(|it| (it.name.clone(), it.index as u32))
+        .collect()
+}
+
 fn load_model_data<P: AsRef<Path>>(model_path: P) -> Result<Vec<u8>> {
     let file_metadata =
         std::fs::metadata(&model_path).context("Unable to read model file metadata")?;
     let mut model_file = std::fs::File::open(&model_path)?;
     let mut data = vec![0; file_metadata.len() as usize];
-    model_file.read(&mut data)?;
+    model_file.read_exact(&mut data)?;
     Ok(data)
 }
+
+#[cfg(test)]
+mod test {
+    use std::{panic::AssertUnwindSafe, sync::Arc};
+
+    use rknpu_sys::RKNN_ERR_CTX_INVALID;
+
+    use crate::{
+        driver::stub::{tensor_attribute, StubCall, StubDriver},
+        error::RknnError,
+        flags::{RknnCoreMask, RknnExtendedFlag},
+        tensors::types::{RknnTensorFormat, RknnTensorType},
+    };
+
+    use super::{inputs::RknnInput, RknnContext};
+
+    fn stub_driver() -> Arc<StubDriver> {
+        Arc::new(StubDriver::new(
+            vec![tensor_attribute(0, "input", &[1, 4], RknnTensorType::U8)],
+            vec![tensor_attribute(0, "output", &[1, 4], RknnTensorType::U8)],
+            |inputs| inputs.to_vec(),
+        ))
+    }
+
+    fn load(driver: &Arc<StubDriver>) -> RknnContext {
+        RknnContext::with_driver(
+            &[],
+            RknnExtendedFlag::RKNN_FLAG_PRIOR_HIGH,
+            Arc::clone(driver) as _,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_close() {
+        let driver = stub_driver();
+        let ctx = load(&driver);
+        assert_eq!(driver.live_contexts(), 1);
+        ctx.close().unwrap();
+        assert_eq!(driver.live_contexts(), 0);
+    }
+
+    #[test]
+    fn test_close_error() {
+        let driver = stub_driver();
+        let ctx = load(&driver);
+        driver.fail_on(StubCall::Destroy, RKNN_ERR_CTX_INVALID);
+        let error = ctx.close().unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<RknnError>(),
+            Some(RknnError::InvalidContext)
+        ));
+    }
+
+    #[test]
+    fn test_drop_error() {
+        let driver = stub_driver();
+        let ctx = load(&driver);
+        driver.fail_on(StubCall::Destroy, RKNN_ERR_CTX_INVALID);
+        drop(ctx);
+
+        // A failing teardown while unwinding must not abort the process.
+        let ctx = load(&driver);
+        let result = std::panic::catch_unwind(AssertUnwindSafe(move || {
+            let _ctx = ctx;
+            panic!("Panic while holding a context");
+        }));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_failed_load_destroys_context() {
+        let driver = stub_driver();
+        driver.fail_on(StubCall::Query, RKNN_ERR_CTX_INVALID);
+        let result = RknnContext::with_driver(
+            &[],
+            RknnExtendedFlag::RKNN_FLAG_PRIOR_HIGH,
+            Arc::clone(&driver) as _,
+        );
+        assert!(result.is_err());
+        assert_eq!(driver.live_contexts(), 0);
+    }
+
+    #[test]
+    fn test_core_mask() {
+        let driver = stub_driver();
+        let mut ctx = RknnContext::with_driver(
+            &[],
+            RknnExtendedFlag::RKNN_FLAG_PRIOR_LOW,
+            Arc::clone(&driver) as _,
+        )
+        .unwrap();
+        assert_eq!(
+            driver.context_flags(),
+            [RknnExtendedFlag::RKNN_FLAG_PRIOR_LOW as u32]
+        );
+        ctx.run().unwrap();
+        ctx.set_core_mask(RknnCoreMask::RKNN_NPU_CORE_1).unwrap();
+        ctx.run().unwrap();
+        assert_eq!(driver.context_runs(RknnCoreMask::RKNN_NPU_CORE_AUTO), 1);
+        assert_eq!(driver.context_runs(RknnCoreMask::RKNN_NPU_CORE_1), 1);
+
+        driver.fail_on(StubCall::SetCoreMask, RKNN_ERR_CTX_INVALID);
+        assert!(ctx.set_core_mask(RknnCoreMask::RKNN_NPU_CORE_0).is_err());
+    }
+
+    #[test]
+    fn test_borrowed_buffers() {
+        let driver = stub_driver();
+        let mut ctx = load(&driver);
+        let data = [1, 2, 3, 4];
+        let input = RknnInput {
+            index: 0,
+            buffer: &data[..],
+            pass_through: false,
+            dtype: RknnTensorType::U8,
+            fmt: RknnTensorFormat::NHWC,
+        };
+        ctx.set_input_buffers(&[input]).unwrap();
+        ctx.run().unwrap();
+
+        assert_eq!(ctx.output_len(0).unwrap(), 4);
+        let mut output = [0; 4];
+        ctx.get_outputs_into(&mut [&mut output]).unwrap();
+        assert_eq!(output, data);
+        assert_eq!(driver.held_outputs(), 0);
+        assert!(ctx.get_outputs_into(&mut [&mut [0; 2]]).is_err());
+        assert!(ctx.get_outputs_into(&mut []).is_err());
+    }
+}
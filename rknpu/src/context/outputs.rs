@@ -16,10 +16,23 @@ pub struct RknnOuput {
     pub buffer: Vec<u8>,
 }
 
+impl RknnOuput {
+    /// Output `index` holding `buffer`, for outputs produced outside of the runtime such as the
+    /// ones received from another process.
+    pub fn new(index: u32, buffer: Vec<u8>) -> Self {
+        Self {
+            want_float: false,
+            is_prealloc: false,
+            index,
+            buffer,
+        }
+    }
+}
+
 impl From<_rknn_output> for RknnOuput {
     fn from(value: _rknn_output) -> Self {
-        let want_float = if value.want_float > 0 { true } else { false };
-        let is_prealloc = if value.is_prealloc > 0 { true } else { false };
+        let want_float = value.want_float > 0;
+        let is_prealloc = value.is_prealloc > 0;
         Self {
             want_float,
             is_prealloc,
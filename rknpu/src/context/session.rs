@@ -0,0 +1,107 @@
+use std::sync::{Mutex, PoisonError};
+
+use anyhow::Result;
+
+use super::{info::ModelInfo, inputs::RknnInput, outputs::RknnOuput, RknnContext};
+
+/// A context that can be shared between threads.
+///
+/// The runtime doesn't allow `rknn_inputs_set`, `rknn_run` and `rknn_outputs_get` to be
+/// interleaved on the same context, so [`infer`](Self::infer) holds an internal lock for the
+/// whole sequence. Concurrent calls are executed one after the other; to run inferences in
+/// parallel, create one session per context.
+pub struct RknnSession {
+    info: ModelInfo,
+    ctx: Mutex<RknnContext>,
+}
+
+impl RknnSession {
+    pub fn new(ctx: RknnContext) -> Self {
+        Self {
+            info: ctx.model_info().clone(),
+            ctx: Mutex::new(ctx),
+        }
+    }
+
+    /// Model information, available without locking the context.
+    pub fn model_info(&self) -> &ModelInfo {
+        &self.info
+    }
+
+    /// Set the inputs, run the model and get the outputs as a single operation.
+    pub fn infer(&self, inputs: Vec<RknnInput>) -> Result<Vec<RknnOuput>> {
+        // Every inference sets all the inputs again, so a context left by a panicking thread
+        // can still be used.
+        let mut ctx = self.ctx.lock().unwrap_or_else(PoisonError::into_inner);
+        ctx.set_inputs(inputs)?;
+        ctx.run()?;
+        ctx.get_outputs()
+    }
+
+    pub fn into_inner(self) -> RknnContext {
+        self.ctx
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Arc, thread};
+
+    use crate::{
+        context::{inputs::RknnInput, RknnContext},
+        driver::stub::{tensor_attribute, StubDriver},
+        flags::RknnExtendedFlag,
+        tensors::types::{RknnTensorFormat, RknnTensorType},
+    };
+
+    use super::RknnSession;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_session_is_send_sync() {
+        assert_send_sync::<RknnSession>();
+    }
+
+    #[test]
+    fn test_concurrent_infer() {
+        let driver = Arc::new(StubDriver::new(
+            vec![tensor_attribute(0, "input", &[1, 4], RknnTensorType::U8)],
+            vec![tensor_attribute(0, "output", &[1, 4], RknnTensorType::U8)],
+            |inputs| vec![inputs[0].iter().map(|it| it.wrapping_add(1)).collect()],
+        ));
+        let ctx =
+            RknnContext::with_driver(&[], RknnExtendedFlag::RKNN_FLAG_PRIOR_HIGH, driver.clone())
+                .unwrap();
+        let session = Arc::new(RknnSession::new(ctx));
+
+        let handles = (0..8_u8)
+            .map(|thread_id| {
+                let session = Arc::clone(&session);
+                thread::spawn(move || {
+                    for i in 0..200_u8 {
+                        let value = thread_id.wrapping_mul(31).wrapping_add(i);
+                        let input = RknnInput {
+                            index: 0,
+                            buffer: vec![value; 4],
+                            pass_through: false,
+                            dtype: RknnTensorType::U8,
+                            fmt: RknnTensorFormat::NHWC,
+                        };
+                        let outputs = session.infer(vec![input]).unwrap();
+                        assert_eq!(outputs[0].buffer, vec![value.wrapping_add(1); 4]);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(driver.held_outputs(), 0);
+        drop(session);
+        assert_eq!(driver.live_contexts(), 0);
+    }
+}
@@ -0,0 +1,239 @@
+//! Indirection over the `librknnrt` entry points.
+//!
+//! Contexts call the runtime through a [`RknnDriver`] so that the same code can run against the
+//! real NPU ([`NativeDriver`]) or against a stand-in used in tests ([`stub::StubDriver`]).
+//!
+//! Thread safety of the runtime, as documented by Rockchip:
+//! - `rknn_query` can be called concurrently on the same context.
+//! - `rknn_inputs_set`, `rknn_run` and `rknn_outputs_get` mutate the context and must not be
+//!   interleaved between threads on the same context.
+//! - Different contexts can be used from different threads without synchronization.
+use std::ffi::{c_int, c_void};
+
+use rknpu_sys::{
+    _rknn_init_extend, _rknn_input, _rknn_output, _rknn_output_extend, rknn_context,
+    rknn_core_mask, rknn_create_mem, rknn_destroy, rknn_destroy_mem, rknn_init, rknn_inputs_set,
+    rknn_matmul_create, rknn_matmul_ctx, rknn_matmul_destroy, rknn_matmul_info,
+    rknn_matmul_io_attr, rknn_matmul_run, rknn_matmul_set_core_mask, rknn_matmul_set_io_mem,
+    rknn_matmul_tensor_attr, rknn_outputs_get, rknn_outputs_release, rknn_query, rknn_run,
+    rknn_run_extend, rknn_set_core_mask, rknn_tensor_mem,
+};
+#[cfg(feature = "sdk-v2")]
+use rknpu_sys::{
+    rknn_matmul_create_dyn_shape, rknn_matmul_set_dynamic_shape, rknn_matmul_set_quant_params,
+    rknn_matmul_shape, rknn_quant_params,
+};
+
+#[cfg(any(test, feature = "stub"))]
+pub mod stub;
+
+/// Raw runtime entry points, mirroring the C API.
+///
+/// # Safety
+/// Implementations receive the raw pointers given to the C API and have the same contract.
+#[allow(clippy::missing_safety_doc)]
+pub trait RknnDriver: Send + Sync {
+    unsafe fn init(
+        &self,
+        context: *mut rknn_context,
+        model: *mut c_void,
+        size: u32,
+        flag: u32,
+        extend: *mut _rknn_init_extend,
+    ) -> c_int;
+    unsafe fn destroy(&self, context: rknn_context) -> c_int;
+    unsafe fn query(&self, context: rknn_context, cmd: u32, info: *mut c_void, size: u32) -> c_int;
+    unsafe fn inputs_set(
+        &self,
+        context: rknn_context,
+        n_inputs: u32,
+        inputs: *mut _rknn_input,
+    ) -> c_int;
+    unsafe fn run(&self, context: rknn_context, extend: *mut rknn_run_extend) -> c_int;
+    unsafe fn set_core_mask(&self, context: rknn_context, core_mask: rknn_core_mask) -> c_int;
+    unsafe fn outputs_get(
+        &self,
+        context: rknn_context,
+        n_outputs: u32,
+        outputs: *mut _rknn_output,
+        extend: *mut _rknn_output_extend,
+    ) -> c_int;
+    unsafe fn outputs_release(
+        &self,
+        context: rknn_context,
+        n_outputs: u32,
+        outputs: *mut _rknn_output,
+    ) -> c_int;
+    unsafe fn matmul_create(
+        &self,
+        ctx: *mut rknn_matmul_ctx,
+        info: *mut rknn_matmul_info,
+        io_attr: *mut rknn_matmul_io_attr,
+    ) -> c_int;
+    unsafe fn matmul_set_io_mem(
+        &self,
+        ctx: rknn_matmul_ctx,
+        mem: *mut rknn_tensor_mem,
+        attr: *mut rknn_matmul_tensor_attr,
+    ) -> c_int;
+    unsafe fn matmul_set_core_mask(&self, ctx: rknn_matmul_ctx, core_mask: rknn_core_mask)
+        -> c_int;
+    unsafe fn matmul_run(&self, ctx: rknn_matmul_ctx) -> c_int;
+    unsafe fn matmul_destroy(&self, ctx: rknn_matmul_ctx) -> c_int;
+    #[cfg(feature = "sdk-v2")]
+    unsafe fn matmul_set_quant_params(
+        &self,
+        ctx: rknn_matmul_ctx,
+        params: *mut rknn_quant_params,
+    ) -> c_int;
+    #[cfg(feature = "sdk-v2")]
+    unsafe fn matmul_create_dyn_shape(
+        &self,
+        ctx: *mut rknn_matmul_ctx,
+        info: *mut rknn_matmul_info,
+        shape_num: c_int,
+        dynamic_shapes: *mut rknn_matmul_shape,
+        io_attrs: *mut rknn_matmul_io_attr,
+    ) -> c_int;
+    #[cfg(feature = "sdk-v2")]
+    unsafe fn matmul_set_dynamic_shape(
+        &self,
+        ctx: rknn_matmul_ctx,
+        shape: *mut rknn_matmul_shape,
+    ) -> c_int;
+    unsafe fn create_mem(&self, ctx: rknn_context, size: u32) -> *mut rknn_tensor_mem;
+    unsafe fn destroy_mem(&self, ctx: rknn_context, mem: *mut rknn_tensor_mem) -> c_int;
+}
+
+/// Driver calling the `librknnrt` library the crate is linked against.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NativeDriver;
+
+impl RknnDriver for NativeDriver {
+    unsafe fn init(
+        &self,
+        context: *mut rknn_context,
+        model: *mut c_void,
+        size: u32,
+        flag: u32,
+        extend: *mut _rknn_init_extend,
+    ) -> c_int {
+        rknn_init(context, model, size, flag, extend)
+    }
+
+    unsafe fn destroy(&self, context: rknn_context) -> c_int {
+        rknn_destroy(context)
+    }
+
+    unsafe fn query(&self, context: rknn_context, cmd: u32, info: *mut c_void, size: u32) -> c_int {
+        rknn_query(context, cmd, info, size)
+    }
+
+    unsafe fn inputs_set(
+        &self,
+        context: rknn_context,
+        n_inputs: u32,
+        inputs: *mut _rknn_input,
+    ) -> c_int {
+        rknn_inputs_set(context, n_inputs, inputs)
+    }
+
+    unsafe fn run(&self, context: rknn_context, extend: *mut rknn_run_extend) -> c_int {
+        rknn_run(context, extend)
+    }
+
+    unsafe fn set_core_mask(&self, context: rknn_context, core_mask: rknn_core_mask) -> c_int {
+        rknn_set_core_mask(context, core_mask)
+    }
+
+    unsafe fn outputs_get(
+        &self,
+        context: rknn_context,
+        n_outputs: u32,
+        outputs: *mut _rknn_output,
+        extend: *mut _rknn_output_extend,
+    ) -> c_int {
+        rknn_outputs_get(context, n_outputs, outputs, extend)
+    }
+
+    unsafe fn outputs_release(
+        &self,
+        context: rknn_context,
+        n_outputs: u32,
+        outputs: *mut _rknn_output,
+    ) -> c_int {
+        rknn_outputs_release(context, n_outputs, outputs)
+    }
+
+    unsafe fn matmul_create(
+        &self,
+        ctx: *mut rknn_matmul_ctx,
+        info: *mut rknn_matmul_info,
+        io_attr: *mut rknn_matmul_io_attr,
+    ) -> c_int {
+        rknn_matmul_create(ctx, info, io_attr)
+    }
+
+    unsafe fn matmul_set_io_mem(
+        &self,
+        ctx: rknn_matmul_ctx,
+        mem: *mut rknn_tensor_mem,
+        attr: *mut rknn_matmul_tensor_attr,
+    ) -> c_int {
+        rknn_matmul_set_io_mem(ctx, mem, attr)
+    }
+
+    unsafe fn matmul_set_core_mask(
+        &self,
+        ctx: rknn_matmul_ctx,
+        core_mask: rknn_core_mask,
+    ) -> c_int {
+        rknn_matmul_set_core_mask(ctx, core_mask)
+    }
+
+    unsafe fn matmul_run(&self, ctx: rknn_matmul_ctx) -> c_int {
+        rknn_matmul_run(ctx)
+    }
+
+    unsafe fn matmul_destroy(&self, ctx: rknn_matmul_ctx) -> c_int {
+        rknn_matmul_destroy(ctx)
+    }
+
+    #[cfg(feature = "sdk-v2")]
+    unsafe fn matmul_set_quant_params(
+        &self,
+        ctx: rknn_matmul_ctx,
+        params: *mut rknn_quant_params,
+    ) -> c_int {
+        rknn_matmul_set_quant_params(ctx, params)
+    }
+
+    #[cfg(feature = "sdk-v2")]
+    unsafe fn matmul_create_dyn_shape(
+        &self,
+        ctx: *mut rknn_matmul_ctx,
+        info: *mut rknn_matmul_info,
+        shape_num: c_int,
+        dynamic_shapes: *mut rknn_matmul_shape,
+        io_attrs: *mut rknn_matmul_io_attr,
+    ) -> c_int {
+        rknn_matmul_create_dyn_shape(ctx, info, shape_num, dynamic_shapes, io_attrs)
+    }
+
+    #[cfg(feature = "sdk-v2")]
+    unsafe fn matmul_set_dynamic_shape(
+        &self,
+        ctx: rknn_matmul_ctx,
+        shape: *mut rknn_matmul_shape,
+    ) -> c_int {
+        rknn_matmul_set_dynamic_shape(ctx, shape)
+    }
+
+    unsafe fn create_mem(&self, ctx: rknn_context, size: u32) -> *mut rknn_tensor_mem {
+        rknn_create_mem(ctx, size)
+    }
+
+    unsafe fn destroy_mem(&self, ctx: rknn_context, mem: *mut rknn_tensor_mem) -> c_int {
+        rknn_destroy_mem(ctx, mem)
+    }
+}
@@ -0,0 +1,822 @@
+//! Stand-in runtime used to exercise contexts without an NPU.
+//!
+//! The stub doesn't read the model data given to `rknn_init`: the model is described by its input
+//! and output attributes, and its computation by a closure mapping input buffers to output
+//! buffers. Matmuls are computed on the CPU, in the normal and native layouts.
+use std::{
+    collections::HashMap,
+    ffi::{c_int, c_void},
+    sync::Mutex,
+};
+
+use rknpu_sys::{
+    _rknn_custom_string, _rknn_init_extend, _rknn_input, _rknn_input_output_num, _rknn_mem_size,
+    _rknn_output, _rknn_output_extend, _rknn_sdk_version, _rknn_tensor_attr, rknn_context,
+    rknn_core_mask, rknn_matmul_ctx, rknn_matmul_info, rknn_matmul_io_attr,
+    rknn_matmul_tensor_attr, rknn_run_extend, rknn_tensor_mem, RKNN_ERR_CTX_INVALID,
+    RKNN_ERR_INPUT_INVALID, RKNN_ERR_OUTPUT_INVALID, RKNN_ERR_PARAM_INVALID,
+};
+#[cfg(feature = "sdk-v2")]
+use rknpu_sys::{rknn_matmul_shape, rknn_quant_params};
+
+use crate::{
+    flags::RknnCoreMask,
+    matmul::{NativeLayout, RknnMatmulType},
+    queries::RknnQuery,
+    tensors::{
+        attributes::RknnTensorAttribute,
+        types::{RknnTensorFormat, RknnTensorQuantFormat, RknnTensorType},
+    },
+};
+
+use super::RknnDriver;
+
+type StubRun = dyn Fn(&[Vec<u8>]) -> Vec<Vec<u8>> + Send + Sync;
+
+/// Runtime entry points of the stub, used to inject failures and count calls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StubCall {
+    Init,
+    Destroy,
+    Query,
+    InputsSet,
+    Run,
+    SetCoreMask,
+    OutputsGet,
+    OutputsRelease,
+    MatmulCreate,
+    MatmulSetIoMem,
+    MatmulSetCoreMask,
+    MatmulRun,
+    MatmulDestroy,
+    #[cfg(feature = "sdk-v2")]
+    MatmulSetQuantParams,
+    #[cfg(feature = "sdk-v2")]
+    MatmulSetDynamicShape,
+    CreateMem,
+    DestroyMem,
+}
+
+pub struct StubDriver {
+    inputs: Vec<RknnTensorAttribute>,
+    outputs: Vec<RknnTensorAttribute>,
+    run: Box<StubRun>,
+    state: Mutex<StubState>,
+}
+
+#[derive(Default)]
+struct StubState {
+    next_context: rknn_context,
+    contexts: HashMap<rknn_context, StubContext>,
+    matmuls: HashMap<rknn_matmul_ctx, StubMatmul>,
+    /// Buffers allocated by `rknn_create_mem`, keyed by address of their `rknn_tensor_mem`.
+    mems: HashMap<usize, Vec<u8>>,
+    failures: HashMap<StubCall, c_int>,
+    calls: HashMap<StubCall, usize>,
+    /// Number of matmul runs per core mask.
+    matmul_runs: HashMap<rknn_core_mask, usize>,
+    /// Number of context runs per core mask.
+    context_runs: HashMap<rknn_core_mask, usize>,
+}
+
+impl StubState {
+    fn new_context(&mut self) -> rknn_context {
+        self.next_context += 1;
+        self.next_context
+    }
+
+    /// Count a call to the runtime, and return the error it was set to fail with if any.
+    fn record(&mut self, call: StubCall) -> Option<c_int> {
+        *self.calls.entry(call).or_default() += 1;
+        self.failures.get(&call).copied()
+    }
+}
+
+/// Scales and zero points of a matrix.
+type QuantParams = (Vec<f32>, Vec<i32>);
+
+struct StubMatmul {
+    info: rknn_matmul_info,
+    /// Buffers bound to the A, B and C matrices.
+    bindings: [Option<usize>; 3],
+    /// A normal layout B matrix, converted by the runtime when bound: later writes to its buffer
+    /// are only seen once bound again.
+    bound_b: Option<Vec<u8>>,
+    /// Quantization parameters of the A, B and C matrices.
+    quant_params: [Option<QuantParams>; 3],
+    core_mask: rknn_core_mask,
+    /// Shapes accepted by a dynamic shape matmul.
+    #[cfg(feature = "sdk-v2")]
+    dynamic_shapes: Vec<[i32; 3]>,
+}
+
+impl StubMatmul {
+    fn new(info: rknn_matmul_info) -> Self {
+        Self {
+            info,
+            bindings: [None; 3],
+            bound_b: None,
+            quant_params: [None, None, None],
+            core_mask: RknnCoreMask::RKNN_NPU_CORE_AUTO as rknn_core_mask,
+            #[cfg(feature = "sdk-v2")]
+            dynamic_shapes: Vec::new(),
+        }
+    }
+}
+
+/// Index of a matrix from the name of its attribute.
+fn matrix_index(name: &[std::ffi::c_char]) -> Option<usize> {
+    match name[0] as u8 {
+        b'A' => Some(0),
+        b'B' => Some(1),
+        b'C' => Some(2),
+        _ => None,
+    }
+}
+
+struct StubContext {
+    /// Flag the context was initialized with.
+    flag: u32,
+    core_mask: rknn_core_mask,
+    inputs: Vec<Vec<u8>>,
+    outputs: Vec<Vec<u8>>,
+    held_outputs: usize,
+}
+
+impl StubDriver {
+    /// Create a stub for a model with the given input and output attributes. `run` receives the
+    /// input buffers, ordered by index, and returns the output buffers, ordered by index.
+    pub fn new<F>(
+        inputs: Vec<RknnTensorAttribute>,
+        outputs: Vec<RknnTensorAttribute>,
+        run: F,
+    ) -> Self
+    where
+        F: Fn(&[Vec<u8>]) -> Vec<Vec<u8>> + Send + Sync + 'static,
+    {
+        Self {
+            inputs,
+            outputs,
+            run: Box::new(run),
+            state: Mutex::new(StubState::default()),
+        }
+    }
+
+    /// Create a stub only used for matrix multiplications.
+    pub fn matmul() -> Self {
+        Self::new(vec![], vec![], |_| vec![])
+    }
+
+    /// Make every following call to `call` fail with the error `code`.
+    pub fn fail_on(&self, call: StubCall, code: c_int) {
+        self.state.lock().unwrap().failures.insert(call, code);
+    }
+
+    /// Make calls to `call` succeed again.
+    pub fn clear_failure(&self, call: StubCall) {
+        self.state.lock().unwrap().failures.remove(&call);
+    }
+
+    /// Number of calls made to `call`, including failed ones.
+    pub fn call_count(&self, call: StubCall) -> usize {
+        let state = self.state.lock().unwrap();
+        state.calls.get(&call).copied().unwrap_or_default()
+    }
+
+    /// Number of successful matmul runs on the given cores.
+    pub fn matmul_runs(&self, core_mask: RknnCoreMask) -> usize {
+        let state = self.state.lock().unwrap();
+        let runs = state.matmul_runs.get(&(core_mask as rknn_core_mask));
+        runs.copied().unwrap_or_default()
+    }
+
+    /// Number of successful context runs on the given cores.
+    pub fn context_runs(&self, core_mask: RknnCoreMask) -> usize {
+        let state = self.state.lock().unwrap();
+        let runs = state.context_runs.get(&(core_mask as rknn_core_mask));
+        runs.copied().unwrap_or_default()
+    }
+
+    /// Number of contexts created and not yet destroyed.
+    pub fn live_contexts(&self) -> usize {
+        self.state.lock().unwrap().contexts.len()
+    }
+
+    /// Flags the live contexts were initialized with, in creation order.
+    pub fn context_flags(&self) -> Vec<u32> {
+        let state = self.state.lock().unwrap();
+        let mut contexts = state.contexts.iter().collect::<Vec<_>>();
+        contexts.sort_by_key(|(id, _)| **id);
+        contexts.into_iter().map(|(_, it)| it.flag).collect()
+    }
+
+    /// Number of matmul contexts created and not yet destroyed.
+    pub fn live_matmuls(&self) -> usize {
+        self.state.lock().unwrap().matmuls.len()
+    }
+
+    /// Number of memory buffers created and not yet destroyed.
+    pub fn live_mems(&self) -> usize {
+        self.state.lock().unwrap().mems.len()
+    }
+
+    /// Number of outputs handed out by `rknn_outputs_get` and not yet released.
+    pub fn held_outputs(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        state.contexts.values().map(|it| it.held_outputs).sum()
+    }
+
+    fn with_context<F>(&self, call: StubCall, context: rknn_context, f: F) -> c_int
+    where
+        F: FnOnce(&mut StubContext) -> c_int,
+    {
+        let mut state = self.state.lock().unwrap();
+        if let Some(code) = state.record(call) {
+            return code;
+        }
+        match state.contexts.get_mut(&context) {
+            Some(stub_context) => f(stub_context),
+            None => RKNN_ERR_CTX_INVALID,
+        }
+    }
+}
+
+/// Attribute of a non quantized tensor, with the layout the stub models expose.
+pub fn tensor_attribute(
+    index: usize,
+    name: &str,
+    dims: &[u32],
+    data_type: RknnTensorType,
+) -> RknnTensorAttribute {
+    let len = dims.iter().product::<u32>() as usize;
+    RknnTensorAttribute {
+        index,
+        dims: dims.to_vec(),
+        name: name.to_string(),
+        len,
+        format: RknnTensorFormat::NHWC,
+        data_type,
+        quant_type: RknnTensorQuantFormat::None,
+        w_stride: 0,
+        h_stride: 0,
+        size_with_stride: (len * data_type.size()) as u32,
+        pass_through: false,
+    }
+}
+
+/// Write `value` to the query buffer if it has the expected size.
+unsafe fn write_query<T>(info: *mut c_void, size: u32, value: T) -> c_int {
+    if info.is_null() || size as usize != std::mem::size_of::<T>() {
+        return RKNN_ERR_PARAM_INVALID;
+    }
+    *(info as *mut T) = value;
+    0
+}
+
+unsafe fn write_attribute(
+    attributes: &[RknnTensorAttribute],
+    info: *mut c_void,
+    size: u32,
+) -> c_int {
+    if info.is_null() || size as usize != std::mem::size_of::<_rknn_tensor_attr>() {
+        return RKNN_ERR_PARAM_INVALID;
+    }
+    let index = (*(info as *mut _rknn_tensor_attr)).index as usize;
+    match attributes.get(index) {
+        Some(attribute) => write_query(info, size, raw_attribute(attribute)),
+        None => RKNN_ERR_PARAM_INVALID,
+    }
+}
+
+fn raw_attribute(attribute: &RknnTensorAttribute) -> _rknn_tensor_attr {
+    let mut raw = unsafe { std::mem::zeroed::<_rknn_tensor_attr>() };
+    raw.index = attribute.index as u32;
+    raw.n_dims = attribute.dims.len() as u32;
+    raw.dims[..attribute.dims.len()].copy_from_slice(&attribute.dims);
+    let name_len = attribute.name.len().min(raw.name.len() - 1);
+    for (dst, src) in raw
+        .name
+        .iter_mut()
+        .zip(&attribute.name.as_bytes()[..name_len])
+    {
+        *dst = *src as _;
+    }
+    raw.n_elems = attribute.len as u32;
+    raw.size = (attribute.len * attribute.data_type.size()) as u32;
+    raw.fmt = attribute.format as u32;
+    raw.type_ = attribute.data_type as u32;
+    match attribute.quant_type {
+        RknnTensorQuantFormat::None => {}
+        RknnTensorQuantFormat::DynamicFixedPoint(fl) => {
+            raw.qnt_type = rknpu_sys::_rknn_tensor_qnt_type_RKNN_TENSOR_QNT_DFP;
+            raw.fl = fl;
+        }
+        RknnTensorQuantFormat::AffineScale(zp, scale) => {
+            raw.qnt_type = rknpu_sys::_rknn_tensor_qnt_type_RKNN_TENSOR_QNT_AFFINE_ASYMMETRIC;
+            raw.zp = zp;
+            raw.scale = scale;
+        }
+    }
+    raw.w_stride = attribute.w_stride;
+    raw.h_stride = attribute.h_stride;
+    raw.size_with_stride = attribute.size_with_stride;
+    raw.pass_through = attribute.pass_through as u8;
+    raw
+}
+
+fn stub_sdk_version() -> _rknn_sdk_version {
+    _rknn_sdk_version {
+        api_version: c_string("stub"),
+        drv_version: c_string("stub"),
+    }
+}
+
+fn c_string<const N: usize>(value: &str) -> [std::ffi::c_char; N] {
+    let mut raw = [0; N];
+    for (dst, src) in raw.iter_mut().zip(value.bytes().take(N - 1)) {
+        *dst = src as _;
+    }
+    raw
+}
+
+impl RknnDriver for StubDriver {
+    unsafe fn init(
+        &self,
+        context: *mut rknn_context,
+        _model: *mut c_void,
+        _size: u32,
+        flag: u32,
+        _extend: *mut _rknn_init_extend,
+    ) -> c_int {
+        let mut state = self.state.lock().unwrap();
+        if let Some(code) = state.record(StubCall::Init) {
+            return code;
+        }
+        let id = state.new_context();
+        let stub_context = StubContext {
+            flag,
+            core_mask: RknnCoreMask::RKNN_NPU_CORE_AUTO as rknn_core_mask,
+            inputs: vec![vec![]; self.inputs.len()],
+            outputs: vec![],
+            held_outputs: 0,
+        };
+        state.contexts.insert(id, stub_context);
+        *context = id;
+        0
+    }
+
+    unsafe fn destroy(&self, context: rknn_context) -> c_int {
+        let mut state = self.state.lock().unwrap();
+        if let Some(code) = state.record(StubCall::Destroy) {
+            return code;
+        }
+        match state.contexts.remove(&context) {
+            Some(_) => 0,
+            None => RKNN_ERR_CTX_INVALID,
+        }
+    }
+
+    unsafe fn query(&self, context: rknn_context, cmd: u32, info: *mut c_void, size: u32) -> c_int {
+        if cmd == RknnQuery::RKNN_QUERY_SDK_VERSION as u32 {
+            // Matmul contexts only answer the SDK version.
+            let mut state = self.state.lock().unwrap();
+            if state.matmuls.contains_key(&context) {
+                return match state.record(StubCall::Query) {
+                    Some(code) => code,
+                    None => write_query(info, size, stub_sdk_version()),
+                };
+            }
+        }
+        self.with_context(StubCall::Query, context, |_| {
+            if cmd == RknnQuery::RKNN_QUERY_IN_OUT_NUM as u32 {
+                let value = _rknn_input_output_num {
+                    n_input: self.inputs.len() as u32,
+                    n_output: self.outputs.len() as u32,
+                };
+                write_query(info, size, value)
+            } else if cmd == RknnQuery::RKNN_QUERY_INPUT_ATTR as u32
+                || cmd == RknnQuery::RKNN_QUERY_NATIVE_INPUT_ATTR as u32
+            {
+                write_attribute(&self.inputs, info, size)
+            } else if cmd == RknnQuery::RKNN_QUERY_OUTPUT_ATTR as u32
+                || cmd == RknnQuery::RKNN_QUERY_NATIVE_OUTPUT_ATTR as u32
+            {
+                write_attribute(&self.outputs, info, size)
+            } else if cmd == RknnQuery::RKNN_QUERY_SDK_VERSION as u32 {
+                write_query(info, size, stub_sdk_version())
+            } else if cmd == RknnQuery::RKNN_QUERY_MEM_SIZE as u32 {
+                write_query(info, size, std::mem::zeroed::<_rknn_mem_size>())
+            } else if cmd == RknnQuery::RKNN_QUERY_CUSTOM_STRING as u32 {
+                write_query(info, size, std::mem::zeroed::<_rknn_custom_string>())
+            } else {
+                RKNN_ERR_PARAM_INVALID
+            }
+        })
+    }
+
+    unsafe fn inputs_set(
+        &self,
+        context: rknn_context,
+        n_inputs: u32,
+        inputs: *mut _rknn_input,
+    ) -> c_int {
+        self.with_context(StubCall::InputsSet, context, |stub_context| {
+            let inputs = std::slice::from_raw_parts(inputs, n_inputs as usize);
+            for input in inputs {
+                let Some(buffer) = stub_context.inputs.get_mut(input.index as usize) else {
+                    return RKNN_ERR_INPUT_INVALID;
+                };
+                *buffer = std::slice::from_raw_parts(input.buf as *const u8, input.size as usize)
+                    .to_vec();
+            }
+            0
+        })
+    }
+
+    unsafe fn run(&self, context: rknn_context, _extend: *mut rknn_run_extend) -> c_int {
+        let mut state = self.state.lock().unwrap();
+        if let Some(code) = state.record(StubCall::Run) {
+            return code;
+        }
+        let state = &mut *state;
+        let Some(stub_context) = state.contexts.get_mut(&context) else {
+            return RKNN_ERR_CTX_INVALID;
+        };
+        stub_context.outputs = (self.run)(&stub_context.inputs);
+        *state
+            .context_runs
+            .entry(stub_context.core_mask)
+            .or_default() += 1;
+        0
+    }
+
+    unsafe fn set_core_mask(&self, context: rknn_context, core_mask: rknn_core_mask) -> c_int {
+        self.with_context(StubCall::SetCoreMask, context, |stub_context| {
+            stub_context.core_mask = core_mask;
+            0
+        })
+    }
+
+    unsafe fn outputs_get(
+        &self,
+        context: rknn_context,
+        n_outputs: u32,
+        outputs: *mut _rknn_output,
+        _extend: *mut _rknn_output_extend,
+    ) -> c_int {
+        self.with_context(StubCall::OutputsGet, context, |stub_context| {
+            if n_outputs as usize != stub_context.outputs.len() {
+                return RKNN_ERR_OUTPUT_INVALID;
+            }
+            let outputs = std::slice::from_raw_parts_mut(outputs, n_outputs as usize);
+            for (index, (output, buffer)) in outputs
+                .iter_mut()
+                .zip(stub_context.outputs.iter_mut())
+                .enumerate()
+            {
+                if output.is_prealloc > 0 {
+                    // The runtime fills the buffers of the caller, which must be large enough.
+                    if (output.size as usize) < buffer.len() {
+                        return RKNN_ERR_OUTPUT_INVALID;
+                    }
+                    std::ptr::copy_nonoverlapping(
+                        buffer.as_ptr(),
+                        output.buf as *mut u8,
+                        buffer.len(),
+                    );
+                    continue;
+                }
+                output.index = index as u32;
+                output.buf = buffer.as_mut_ptr() as *mut c_void;
+                output.size = buffer.len() as u32;
+            }
+            stub_context.held_outputs += n_outputs as usize;
+            0
+        })
+    }
+
+    unsafe fn outputs_release(
+        &self,
+        context: rknn_context,
+        n_outputs: u32,
+        _outputs: *mut _rknn_output,
+    ) -> c_int {
+        self.with_context(StubCall::OutputsRelease, context, |stub_context| {
+            stub_context.held_outputs =
+                stub_context.held_outputs.saturating_sub(n_outputs as usize);
+            0
+        })
+    }
+
+    unsafe fn matmul_create(
+        &self,
+        ctx: *mut rknn_matmul_ctx,
+        info: *mut rknn_matmul_info,
+        io_attr: *mut rknn_matmul_io_attr,
+    ) -> c_int {
+        let mut state = self.state.lock().unwrap();
+        if let Some(code) = state.record(StubCall::MatmulCreate) {
+            return code;
+        }
+        let info = *info;
+        let Some(attrs) = matmul_io_attr(&info) else {
+            return RKNN_ERR_PARAM_INVALID;
+        };
+        *io_attr = attrs;
+        let id = state.new_context();
+        state.matmuls.insert(id, StubMatmul::new(info));
+        *ctx = id;
+        0
+    }
+
+    unsafe fn matmul_set_io_mem(
+        &self,
+        ctx: rknn_matmul_ctx,
+        mem: *mut rknn_tensor_mem,
+        attr: *mut rknn_matmul_tensor_attr,
+    ) -> c_int {
+        let mut state = self.state.lock().unwrap();
+        if let Some(code) = state.record(StubCall::MatmulSetIoMem) {
+            return code;
+        }
+        if !state.mems.contains_key(&(mem as usize)) || (*mem).size < (*attr).size {
+            return RKNN_ERR_PARAM_INVALID;
+        }
+        let state = &mut *state;
+        let Some(stub_matmul) = state.matmuls.get_mut(&ctx) else {
+            return RKNN_ERR_CTX_INVALID;
+        };
+        let Some(matrix) = matrix_index(&(*attr).name) else {
+            return RKNN_ERR_PARAM_INVALID;
+        };
+        stub_matmul.bindings[matrix] = Some(mem as usize);
+        if matrix == 1 && stub_matmul.info.B_layout == 0 {
+            stub_matmul.bound_b = state.mems.get(&(mem as usize)).cloned();
+        }
+        0
+    }
+
+    unsafe fn matmul_set_core_mask(
+        &self,
+        ctx: rknn_matmul_ctx,
+        core_mask: rknn_core_mask,
+    ) -> c_int {
+        let mut state = self.state.lock().unwrap();
+        if let Some(code) = state.record(StubCall::MatmulSetCoreMask) {
+            return code;
+        }
+        match state.matmuls.get_mut(&ctx) {
+            Some(stub_matmul) => {
+                stub_matmul.core_mask = core_mask;
+                0
+            }
+            None => RKNN_ERR_CTX_INVALID,
+        }
+    }
+
+    unsafe fn matmul_run(&self, ctx: rknn_matmul_ctx) -> c_int {
+        let mut state = self.state.lock().unwrap();
+        if let Some(code) = state.record(StubCall::MatmulRun) {
+            return code;
+        }
+        let state = &mut *state;
+        let Some(stub_matmul) = state.matmuls.get(&ctx) else {
+            return RKNN_ERR_CTX_INVALID;
+        };
+        let ret = matmul_compute(stub_matmul, &mut state.mems);
+        if ret == 0 {
+            *state.matmul_runs.entry(stub_matmul.core_mask).or_default() += 1;
+        }
+        ret
+    }
+
+    unsafe fn matmul_destroy(&self, ctx: rknn_matmul_ctx) -> c_int {
+        let mut state = self.state.lock().unwrap();
+        if let Some(code) = state.record(StubCall::MatmulDestroy) {
+            return code;
+        }
+        match state.matmuls.remove(&ctx) {
+            Some(_) => 0,
+            None => RKNN_ERR_CTX_INVALID,
+        }
+    }
+
+    #[cfg(feature = "sdk-v2")]
+    unsafe fn matmul_set_quant_params(
+        &self,
+        ctx: rknn_matmul_ctx,
+        params: *mut rknn_quant_params,
+    ) -> c_int {
+        let mut state = self.state.lock().unwrap();
+        if let Some(code) = state.record(StubCall::MatmulSetQuantParams) {
+            return code;
+        }
+        let Some(stub_matmul) = state.matmuls.get_mut(&ctx) else {
+            return RKNN_ERR_CTX_INVALID;
+        };
+        let params = &*params;
+        let Some(matrix) = matrix_index(&params.name) else {
+            return RKNN_ERR_PARAM_INVALID;
+        };
+        let scales = std::slice::from_raw_parts(params.scale, params.scale_len as usize);
+        let zero_points = std::slice::from_raw_parts(params.zp, params.zp_len as usize);
+        // A and C are only quantized per layer.
+        if scales.len() != zero_points.len() || (matrix != 1 && scales.len() != 1) {
+            return RKNN_ERR_PARAM_INVALID;
+        }
+        stub_matmul.quant_params[matrix] = Some((scales.to_vec(), zero_points.to_vec()));
+        0
+    }
+
+    #[cfg(feature = "sdk-v2")]
+    unsafe fn matmul_create_dyn_shape(
+        &self,
+        ctx: *mut rknn_matmul_ctx,
+        info: *mut rknn_matmul_info,
+        shape_num: c_int,
+        dynamic_shapes: *mut rknn_matmul_shape,
+        io_attrs: *mut rknn_matmul_io_attr,
+    ) -> c_int {
+        let mut state = self.state.lock().unwrap();
+        if let Some(code) = state.record(StubCall::MatmulCreate) {
+            return code;
+        }
+        let shapes = std::slice::from_raw_parts(dynamic_shapes, shape_num as usize);
+        let io_attrs = std::slice::from_raw_parts_mut(io_attrs, shape_num as usize);
+        let mut info = *info;
+        for (shape, io_attr) in shapes.iter().zip(io_attrs.iter_mut()) {
+            (info.M, info.K, info.N) = (shape.M, shape.K, shape.N);
+            let Some(attrs) = matmul_io_attr(&info) else {
+                return RKNN_ERR_PARAM_INVALID;
+            };
+            *io_attr = attrs;
+        }
+        let Some(first) = shapes.first() else {
+            return RKNN_ERR_PARAM_INVALID;
+        };
+        (info.M, info.K, info.N) = (first.M, first.K, first.N);
+        let mut stub_matmul = StubMatmul::new(info);
+        stub_matmul.dynamic_shapes = shapes.iter().map(|it| [it.M, it.K, it.N]).collect();
+        let id = state.new_context();
+        state.matmuls.insert(id, stub_matmul);
+        *ctx = id;
+        0
+    }
+
+    #[cfg(feature = "sdk-v2")]
+    unsafe fn matmul_set_dynamic_shape(
+        &self,
+        ctx: rknn_matmul_ctx,
+        shape: *mut rknn_matmul_shape,
+    ) -> c_int {
+        let mut state = self.state.lock().unwrap();
+        if let Some(code) = state.record(StubCall::MatmulSetDynamicShape) {
+            return code;
+        }
+        let Some(stub_matmul) = state.matmuls.get_mut(&ctx) else {
+            return RKNN_ERR_CTX_INVALID;
+        };
+        let shape = *shape;
+        if !stub_matmul
+            .dynamic_shapes
+            .contains(&[shape.M, shape.K, shape.N])
+        {
+            return RKNN_ERR_PARAM_INVALID;
+        }
+        let info = &mut stub_matmul.info;
+        (info.M, info.K, info.N) = (shape.M, shape.K, shape.N);
+        // Bindings are made with the attributes of a shape.
+        stub_matmul.bindings = [None; 3];
+        0
+    }
+
+    unsafe fn create_mem(&self, ctx: rknn_context, size: u32) -> *mut rknn_tensor_mem {
+        let mut state = self.state.lock().unwrap();
+        if state.record(StubCall::CreateMem).is_some()
+            || !(state.matmuls.contains_key(&ctx) || state.contexts.contains_key(&ctx))
+        {
+            return std::ptr::null_mut();
+        }
+        let mut buffer = vec![0_u8; size as usize];
+        let mut mem = std::mem::zeroed::<rknn_tensor_mem>();
+        mem.virt_addr = buffer.as_mut_ptr() as *mut c_void;
+        mem.size = size;
+        let mem = Box::into_raw(Box::new(mem));
+        state.mems.insert(mem as usize, buffer);
+        mem
+    }
+
+    unsafe fn destroy_mem(&self, _ctx: rknn_context, mem: *mut rknn_tensor_mem) -> c_int {
+        let mut state = self.state.lock().unwrap();
+        if let Some(code) = state.record(StubCall::DestroyMem) {
+            return code;
+        }
+        match state.mems.remove(&(mem as usize)) {
+            Some(_) => {
+                drop(Box::from_raw(mem));
+                0
+            }
+            None => RKNN_ERR_PARAM_INVALID,
+        }
+    }
+}
+
+fn matmul_type(info: &rknn_matmul_info) -> Option<RknnMatmulType> {
+    RknnMatmulType::ALL
+        .iter()
+        .find(|it| (*it).clone() as u32 == info.type_)
+        .cloned()
+}
+
+/// Attributes of the A, B and C matrices of a matmul, in the layouts it was created with.
+fn matmul_io_attr(info: &rknn_matmul_info) -> Option<rknn_matmul_io_attr> {
+    let mm_type = matmul_type(info)?;
+    let (m, k, n) = (info.M as usize, info.K as usize, info.N as usize);
+    let layout = NativeLayout::new(&mm_type);
+    let (a_len, c_len) = match info.AC_layout {
+        0 => (m * k, m * n),
+        _ => (layout.a_len(m, k), layout.c_len(m, n)),
+    };
+    let b_len = match info.B_layout {
+        0 => k * n,
+        _ => layout.b_len(k, n),
+    };
+    let [a_bits, b_bits, c_bits] = mm_type.element_types().map(|it| it.bits());
+    let size = |len: usize, bits: usize| (len * bits).div_ceil(8) as u32;
+    Some(rknn_matmul_io_attr {
+        A: matmul_tensor_attr("A", [info.M, info.K], size(a_len, a_bits)),
+        B: matmul_tensor_attr("B", [info.K, info.N], size(b_len, b_bits)),
+        C: matmul_tensor_attr("C", [info.M, info.N], size(c_len, c_bits)),
+    })
+}
+
+/// Compute the C matrix of a matmul from its bound buffers.
+fn matmul_compute(stub_matmul: &StubMatmul, mems: &mut HashMap<usize, Vec<u8>>) -> c_int {
+    let info = stub_matmul.info;
+    let [Some(a), Some(b), Some(c)] = stub_matmul.bindings else {
+        return RKNN_ERR_PARAM_INVALID;
+    };
+    let Some(mm_type) = matmul_type(&info) else {
+        return RKNN_ERR_PARAM_INVALID;
+    };
+    let [a_type, b_type, c_type] = mm_type.element_types();
+    let layout = NativeLayout::new(&mm_type);
+    let (m, k, n) = (info.M as usize, info.K as usize, info.N as usize);
+    let mut a = a_type.decode(&mems[&a]);
+    let mut b = b_type.decode(stub_matmul.bound_b.as_ref().unwrap_or(&mems[&b]));
+    if info.AC_layout != 0 {
+        a = layout.unpack_a(&a, m, k);
+    }
+    if info.B_layout != 0 {
+        b = layout.unpack_b(&b, k, n);
+    }
+    a.truncate(m * k);
+    b.truncate(k * n);
+    if let Some(params) = &stub_matmul.quant_params[0] {
+        dequantize(&mut a, params, m, k);
+    }
+    if let Some(params) = &stub_matmul.quant_params[1] {
+        dequantize(&mut b, params, k, n);
+    }
+    let mut c_values = (0..m * n)
+        .map(|idx| {
+            let (i, j) = (idx / n, idx % n);
+            (0..k).map(|l| a[i * k + l] * b[l * n + j]).sum::<f64>()
+        })
+        .collect::<Vec<_>>();
+    if let Some((scales, zero_points)) = &stub_matmul.quant_params[2] {
+        let (scale, zero_point) = (scales[0] as f64, zero_points[0] as f64);
+        c_values
+            .iter_mut()
+            .for_each(|it| *it = *it / scale + zero_point);
+    }
+    if info.AC_layout != 0 {
+        c_values = layout.pack_c(&c_values, m, n);
+    }
+    c_type.encode(&c_values, mems.get_mut(&c).unwrap());
+    0
+}
+
+/// Dequantize a `rows x cols` matrix, with parameters ordered by column then by group of rows.
+fn dequantize(values: &mut [f64], (scales, zero_points): &QuantParams, rows: usize, cols: usize) {
+    let groups = (scales.len() / cols).max(1);
+    let group_size = rows.div_ceil(groups);
+    for (idx, value) in values.iter_mut().enumerate() {
+        let (row, col) = (idx / cols, idx % cols);
+        let param = match scales.len() {
+            1 => 0,
+            _ => col * groups + row / group_size,
+        };
+        *value = (*value - zero_points[param] as f64) * scales[param] as f64;
+    }
+}
+
+fn matmul_tensor_attr(name: &str, dims: [i32; 2], size: u32) -> rknn_matmul_tensor_attr {
+    let mut attr = unsafe { std::mem::zeroed::<rknn_matmul_tensor_attr>() };
+    attr.name = c_string(name);
+    attr.n_dims = 2;
+    attr.dims[0] = dims[0] as u32;
+    attr.dims[1] = dims[1] as u32;
+    attr.size = size;
+    attr
+}
@@ -9,6 +9,11 @@ use rknpu_sys::{
     RKNN_ERR_PARAM_INVALID, RKNN_ERR_TARGET_PLATFORM_UNMATCH, RKNN_ERR_TIMEOUT,
 };
 
+use crate::{
+    matmul::RknnMatmulType,
+    tensors::types::{RknnTensorFormat, RknnTensorType},
+};
+
 #[derive(Debug, Error)]
 pub enum RknnError {
     #[error("Execution failed.")]
@@ -41,10 +46,117 @@ pub enum RknnError {
     Unknown,
 }
 
+/// Errors raised when the inputs given to a context don't match the model input attributes.
+#[derive(Debug, Error, PartialEq)]
+pub enum RknnInputError {
+    #[error("Input index {index} is out of range, the model has {n_input} input(s).")]
+    IndexOutOfRange { index: u32, n_input: usize },
+    #[error("Input '{name}' (index {index}) is set more than once.")]
+    Duplicate { index: u32, name: String },
+    #[error("Input '{name}' (index {index}) is missing.")]
+    Missing { index: u32, name: String },
+    #[error("Input '{name}' expects a buffer of {expected} bytes, got {actual} bytes.")]
+    SizeMismatch {
+        name: String,
+        expected: usize,
+        actual: usize,
+    },
+    #[error("Input '{name}' is passed through and expects type {expected:?}, got {actual:?}.")]
+    TypeMismatch {
+        name: String,
+        expected: RknnTensorType,
+        actual: RknnTensorType,
+    },
+    #[error("Input '{name}' is passed through and expects format {expected:?}, got {actual:?}.")]
+    FormatMismatch {
+        name: String,
+        expected: RknnTensorFormat,
+        actual: RknnTensorFormat,
+    },
+}
+
+/// Errors raised when looking up a model tensor by name or index.
+#[derive(Debug, Error, PartialEq)]
+pub enum RknnTensorLookupError {
+    #[error("The model has no input named '{name}'.")]
+    UnknownInput { name: String },
+    #[error("The model has no output named '{name}'.")]
+    UnknownOutput { name: String },
+    #[error("Input index {index} is out of range, the model has {n_input} input(s).")]
+    InputIndexOutOfRange { index: u32, n_input: usize },
+    #[error("Output index {index} is out of range, the model has {n_output} output(s).")]
+    OutputIndexOutOfRange { index: u32, n_output: usize },
+}
+
+/// Errors raised when the matrices given to a matmul don't match its shape.
+#[derive(Debug, Error, PartialEq)]
+pub enum RknnMatmulError {
+    #[error("Matrix {matrix} expects {expected} elements, got {actual}.")]
+    LengthMismatch {
+        matrix: char,
+        expected: usize,
+        actual: usize,
+    },
+    #[error("Matrix {matrix} expects a buffer of {expected} bytes, got {actual} bytes.")]
+    SizeMismatch {
+        matrix: char,
+        expected: usize,
+        actual: usize,
+    },
+    #[error("Matrix {matrix} has {scales} quantization scale(s) but {zero_points} zero point(s).")]
+    QuantParamsMismatch {
+        matrix: char,
+        scales: usize,
+        zero_points: usize,
+    },
+    #[error("Matrix {matrix} expects {expected} quantization parameter(s), got {actual}.")]
+    QuantParamsLength {
+        matrix: char,
+        expected: usize,
+        actual: usize,
+    },
+    #[error("Matmul was not created for shape {m}x{k}x{n}.")]
+    UnknownShape { m: usize, k: usize, n: usize },
+    #[error("Matmul has type {actual:?}, expected {expected:?}.")]
+    TypeMismatch {
+        expected: RknnMatmulType,
+        actual: RknnMatmulType,
+    },
+    #[error(
+        "Matmul of type {mm_type:?} can sum at most {max} products without overflow, got k = {k}."
+    )]
+    DepthTooLarge {
+        mm_type: RknnMatmulType,
+        k: usize,
+        max: usize,
+    },
+}
+
+/// Errors raised when loading or running the layers of a network.
+#[derive(Debug, Error, PartialEq)]
+pub enum RknnNnError {
+    #[error("The weights have no tensor named '{name}'.")]
+    MissingTensor { name: String },
+    #[error("Tensor '{name}' has shape {actual:?}, expected {expected:?}.")]
+    ShapeMismatch {
+        name: String,
+        expected: Vec<usize>,
+        actual: Vec<usize>,
+    },
+    #[error("Tensor '{name}' has the unsupported data type {dtype}.")]
+    UnsupportedDtype { name: String, dtype: String },
+    #[error("Layer expects {expected} features per row, got {actual} values for {batch} row(s).")]
+    InputMismatch {
+        expected: usize,
+        actual: usize,
+        batch: usize,
+    },
+}
+
 #[allow(non_snake_case)]
 impl From<c_int> for RknnError {
     fn from(value: c_int) -> Self {
-        match value as i32 {
+        match value {
             RKNN_ERR_FAIL => RknnError::Fail,
             RKNN_ERR_TIMEOUT => RknnError::Timeout,
             RKNN_ERR_DEVICE_UNAVAILABLE => RknnError::UnavailableDevice,
@@ -65,9 +177,9 @@ impl From<c_int> for RknnError {
 
 pub(crate) fn check_result(rknn_result: c_int) -> Result<(), RknnError> {
     if rknn_result == 0 {
-        return Ok(());
+        Ok(())
     } else {
         let error = rknn_result.into();
-        return Err(error);
+        Err(error)
     }
 }
@@ -1,8 +1,12 @@
 use rknpu_sys::{
-    RKNN_FLAG_ASYNC_MASK, RKNN_FLAG_COLLECT_MODEL_INFO_ONLY, RKNN_FLAG_COLLECT_PERF_MASK,
-    RKNN_FLAG_EXECUTE_FALLBACK_PRIOR_DEVICE_GPU, RKNN_FLAG_FENCE_IN_OUTSIDE,
-    RKNN_FLAG_FENCE_OUT_OUTSIDE, RKNN_FLAG_INTERNAL_ALLOC_OUTSIDE, RKNN_FLAG_MEM_ALLOC_OUTSIDE,
-    RKNN_FLAG_PRIOR_HIGH, RKNN_FLAG_PRIOR_LOW, RKNN_FLAG_PRIOR_MEDIUM, RKNN_FLAG_SHARE_WEIGHT_MEM,
+    _rknn_core_mask_RKNN_NPU_CORE_0, _rknn_core_mask_RKNN_NPU_CORE_0_1,
+    _rknn_core_mask_RKNN_NPU_CORE_0_1_2, _rknn_core_mask_RKNN_NPU_CORE_1,
+    _rknn_core_mask_RKNN_NPU_CORE_2, _rknn_core_mask_RKNN_NPU_CORE_ALL,
+    _rknn_core_mask_RKNN_NPU_CORE_AUTO, RKNN_FLAG_ASYNC_MASK, RKNN_FLAG_COLLECT_MODEL_INFO_ONLY,
+    RKNN_FLAG_COLLECT_PERF_MASK, RKNN_FLAG_EXECUTE_FALLBACK_PRIOR_DEVICE_GPU,
+    RKNN_FLAG_FENCE_IN_OUTSIDE, RKNN_FLAG_FENCE_OUT_OUTSIDE, RKNN_FLAG_INTERNAL_ALLOC_OUTSIDE,
+    RKNN_FLAG_MEM_ALLOC_OUTSIDE, RKNN_FLAG_PRIOR_HIGH, RKNN_FLAG_PRIOR_LOW, RKNN_FLAG_PRIOR_MEDIUM,
+    RKNN_FLAG_SHARE_WEIGHT_MEM,
 };
 
 #[derive(Debug, Clone)]
@@ -22,3 +26,30 @@ pub enum RknnExtendedFlag {
     RKNN_FLAG_EXECUTE_FALLBACK_PRIOR_DEVICE_GPU = RKNN_FLAG_EXECUTE_FALLBACK_PRIOR_DEVICE_GPU,
     RKNN_FLAG_INTERNAL_ALLOC_OUTSIDE = RKNN_FLAG_INTERNAL_ALLOC_OUTSIDE,
 }
+
+/// NPU cores a context or a matmul can run on, RK3588 having three cores.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[allow(non_camel_case_types)]
+#[repr(u32)]
+pub enum RknnCoreMask {
+    /// Let the runtime pick an idle core.
+    RKNN_NPU_CORE_AUTO = _rknn_core_mask_RKNN_NPU_CORE_AUTO,
+    RKNN_NPU_CORE_0 = _rknn_core_mask_RKNN_NPU_CORE_0,
+    RKNN_NPU_CORE_1 = _rknn_core_mask_RKNN_NPU_CORE_1,
+    RKNN_NPU_CORE_2 = _rknn_core_mask_RKNN_NPU_CORE_2,
+    RKNN_NPU_CORE_0_1 = _rknn_core_mask_RKNN_NPU_CORE_0_1,
+    RKNN_NPU_CORE_0_1_2 = _rknn_core_mask_RKNN_NPU_CORE_0_1_2,
+    RKNN_NPU_CORE_ALL = _rknn_core_mask_RKNN_NPU_CORE_ALL,
+}
+
+impl RknnCoreMask {
+    pub const ALL: &'static [RknnCoreMask] = &[
+        Self::RKNN_NPU_CORE_AUTO,
+        Self::RKNN_NPU_CORE_0,
+        Self::RKNN_NPU_CORE_1,
+        Self::RKNN_NPU_CORE_2,
+        Self::RKNN_NPU_CORE_0_1,
+        Self::RKNN_NPU_CORE_0_1_2,
+        Self::RKNN_NPU_CORE_ALL,
+    ];
+}
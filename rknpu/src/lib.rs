@@ -1,7 +1,9 @@
 pub mod context;
+pub mod driver;
 pub mod error;
 pub mod flags;
 pub mod matmul;
+pub mod nn;
 pub mod queries;
 pub mod tensors;
 
@@ -26,7 +28,7 @@ mod test {
         Arc::clone(CTX.get_or_init(|| {
             let model_path = "./assets/mnist_model_quant.rknn";
             let ctx =
-                RknnContext::from_model_path(&model_path, RknnExtendedFlag::RKNN_FLAG_PRIOR_HIGH)
+                RknnContext::from_model_path(model_path, RknnExtendedFlag::RKNN_FLAG_PRIOR_HIGH)
                     .unwrap();
             Arc::new(ctx)
         }))
@@ -104,4 +106,13 @@ mod test {
         }
         Ok(())
     }
+
+    #[test]
+    fn test_tensor_names() -> Result<()> {
+        let ctx = load_ctx();
+        assert_eq!(ctx.input_index("serving_default_input_1:0")?, 0);
+        assert_eq!(ctx.output_index("StatefulPartitionedCall:0")?, 0);
+        assert!(ctx.input_index("StatefulPartitionedCall:0").is_err());
+        Ok(())
+    }
 }
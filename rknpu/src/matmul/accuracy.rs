@@ -0,0 +1,404 @@
+//! Accuracy of the matmuls against a CPU reference, swept over shapes, matmul types and layouts.
+//!
+//! Each case multiplies random matrices, encoded in the element types of the matmul type, and
+//! compares C to the product computed in f64 from the encoded values. Errors are relative to the
+//! largest `|A| x |B|` value of C, so that they don't depend on the scale of the inputs.
+
+use std::{fmt, sync::Arc};
+
+use anyhow::Result;
+
+use crate::driver::{NativeDriver, RknnDriver};
+
+use super::{
+    NativeLayout, RknnMatmul, RknnMatmulElementType, RknnMatmulInfo, RknnMatmulShape,
+    RknnMatmulType,
+};
+
+/// A matmul shape, type and layouts to check.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AccuracyCase {
+    pub shape: RknnMatmulShape,
+    pub mm_type: RknnMatmulType,
+    pub b_native_layout: bool,
+    pub ac_native_layout: bool,
+}
+
+impl AccuracyCase {
+    pub fn infos(&self) -> RknnMatmulInfo {
+        RknnMatmulInfo::new(
+            self.shape.m,
+            self.shape.k,
+            self.shape.n,
+            self.mm_type.clone(),
+            self.b_native_layout,
+            self.ac_native_layout,
+        )
+    }
+
+    /// Largest error accepted for the matmul type: integer products are exact, float16 and
+    /// int8 outputs are rounded.
+    pub fn tolerance(&self) -> f64 {
+        match self.mm_type.element_types()[2] {
+            RknnMatmulElementType::I16 | RknnMatmulElementType::I32 => 0.0,
+            RknnMatmulElementType::F32 => 1e-3,
+            RknnMatmulElementType::F16 => 2e-3,
+            RknnMatmulElementType::I8 | RknnMatmulElementType::I4 => 1.0 / 127.0,
+        }
+    }
+}
+
+/// Errors of a case, or the reason it couldn't run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccuracyReport {
+    pub case: AccuracyCase,
+    pub max_error: f64,
+    pub mean_error: f64,
+    /// Error of the runtime.
+    pub failure: Option<String>,
+    /// Whether the runtime rejected the case when creating its matmul, e.g. native layouts of a
+    /// shape it doesn't support, rather than failing to run it.
+    pub unsupported: bool,
+}
+
+impl AccuracyReport {
+    pub fn passed(&self) -> bool {
+        self.failure.is_none() && self.max_error <= self.case.tolerance()
+    }
+
+    /// Whether a case the runtime supports failed, by running out of tolerance or not at all.
+    pub fn failed(&self) -> bool {
+        !self.unsupported && !self.passed()
+    }
+}
+
+impl fmt::Display for AccuracyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let AccuracyCase {
+            shape,
+            mm_type,
+            b_native_layout,
+            ac_native_layout,
+        } = &self.case;
+        let layout = |native| if native { "native" } else { "normal" };
+        write!(
+            f,
+            "{}x{}x{} {mm_type:?} B {} AC {}: ",
+            shape.m,
+            shape.k,
+            shape.n,
+            layout(*b_native_layout),
+            layout(*ac_native_layout)
+        )?;
+        match &self.failure {
+            Some(failure) if self.unsupported => write!(f, "unsupported: {failure}"),
+            Some(failure) => write!(f, "failed: {failure}"),
+            None => write!(
+                f,
+                "max error {:.2e}, mean error {:.2e}, tolerance {:.2e}{}",
+                self.max_error,
+                self.mean_error,
+                self.case.tolerance(),
+                if self.passed() { "" } else { " FAILED" }
+            ),
+        }
+    }
+}
+
+/// Runs every combination of shapes, matmul types and layouts, with random matrices.
+pub struct AccuracySweep {
+    driver: Arc<dyn RknnDriver>,
+    shapes: Vec<RknnMatmulShape>,
+    mm_types: Vec<RknnMatmulType>,
+    layouts: Vec<(bool, bool)>,
+    seed: u64,
+}
+
+impl AccuracySweep {
+    /// Shapes checked by default, aligned or not to the block sizes of the native layouts.
+    pub const DEFAULT_SHAPES: &'static [RknnMatmulShape] = &[
+        RknnMatmulShape::new(1, 32, 32),
+        RknnMatmulShape::new(4, 64, 32),
+        RknnMatmulShape::new(3, 33, 17),
+        RknnMatmulShape::new(7, 100, 50),
+        RknnMatmulShape::new(64, 256, 256),
+        RknnMatmulShape::new(33, 511, 127),
+    ];
+
+    /// Check the default shapes, for every matmul type and layout, on the NPU.
+    pub fn new() -> Self {
+        Self::with_driver(Arc::new(NativeDriver))
+    }
+
+    /// Check the default shapes, for every matmul type and layout, through the given driver.
+    pub fn with_driver(driver: Arc<dyn RknnDriver>) -> Self {
+        Self {
+            driver,
+            shapes: Self::DEFAULT_SHAPES.to_vec(),
+            mm_types: RknnMatmulType::ALL.to_vec(),
+            layouts: vec![(false, false), (true, false), (false, true), (true, true)],
+            seed: 0x5eed,
+        }
+    }
+
+    pub fn with_shapes(mut self, shapes: Vec<RknnMatmulShape>) -> Self {
+        self.shapes = shapes;
+        self
+    }
+
+    pub fn with_mm_types(mut self, mm_types: Vec<RknnMatmulType>) -> Self {
+        self.mm_types = mm_types;
+        self
+    }
+
+    /// Check the given `(b_native_layout, ac_native_layout)` pairs only.
+    pub fn with_layouts(mut self, layouts: Vec<(bool, bool)>) -> Self {
+        self.layouts = layouts;
+        self
+    }
+
+    /// Seed of the random matrices, each case using the same matrices on every run.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn cases(&self) -> Vec<AccuracyCase> {
+        let mut cases = Vec::new();
+        for mm_type in &self.mm_types {
+            for shape in &self.shapes {
+                for (b_native_layout, ac_native_layout) in &self.layouts {
+                    cases.push(AccuracyCase {
+                        shape: *shape,
+                        mm_type: mm_type.clone(),
+                        b_native_layout: *b_native_layout,
+                        ac_native_layout: *ac_native_layout,
+                    });
+                }
+            }
+        }
+        cases
+    }
+
+    /// Run every case. A case failing to run, or that the runtime doesn't support, is reported
+    /// and doesn't stop the sweep.
+    pub fn run(&self) -> Vec<AccuracyReport> {
+        self.cases()
+            .into_iter()
+            .enumerate()
+            .map(|(idx, case)| {
+                let mut rng = Rng::new(self.seed.wrapping_add(idx as u64));
+                let result = RknnMatmul::with_driver(case.infos(), self.driver.clone())
+                    .map(|matmul| Self::run_case(&case, matmul, &mut rng));
+                let unsupported = result.is_err();
+                let (max_error, mean_error, failure) = match result {
+                    Ok(Ok((max_error, mean_error))) => (max_error, mean_error, None),
+                    Ok(Err(error)) | Err(error) => (f64::NAN, f64::NAN, Some(error.to_string())),
+                };
+                AccuracyReport {
+                    case,
+                    max_error,
+                    mean_error,
+                    failure,
+                    unsupported,
+                }
+            })
+            .collect()
+    }
+
+    /// Maximum and mean errors of a case, run on its matmul.
+    fn run_case(case: &AccuracyCase, mut matmul: RknnMatmul, rng: &mut Rng) -> Result<(f64, f64)> {
+        let RknnMatmulShape { m, k, n } = case.shape;
+        let [a_type, b_type, c_type] = case.mm_type.element_types();
+        let layout = NativeLayout::new(&case.mm_type);
+        let a = rng.matrix(a_type, m * k);
+        let b = rng.matrix(b_type, k * n);
+        let (reference, norm) = reference(&a, &b, case.shape);
+
+        let [a_size, b_size, c_size] = matmul.buffer_sizes();
+        let (mut raw_a, mut raw_b, mut raw_c) = (vec![0; a_size], vec![0; b_size], vec![0; c_size]);
+        match case.ac_native_layout {
+            true => a_type.encode(&layout.pack_a(&a, m, k), &mut raw_a),
+            false => a_type.encode(&a, &mut raw_a),
+        }
+        match case.b_native_layout {
+            true => b_type.encode(&layout.pack_b(&b, k, n), &mut raw_b),
+            false => b_type.encode(&b, &mut raw_b),
+        }
+        // Int8 outputs are quantized, with a scale fitting the largest value of C.
+        let c_scale = match c_type {
+            RknnMatmulElementType::I8 => {
+                let max = reference.iter().fold(0.0_f64, |acc, it| acc.max(it.abs()));
+                (max / 127.0).max(f64::MIN_POSITIVE)
+            }
+            _ => 1.0,
+        };
+        #[cfg(feature = "sdk-v2")]
+        if c_type == RknnMatmulElementType::I8 {
+            use super::RknnQuantParams;
+
+            matmul.set_a_quant_params(&RknnQuantParams::per_layer(1.0, 0))?;
+            matmul.set_b_quant_params(&RknnQuantParams::per_layer(1.0, 0))?;
+            matmul.set_c_quant_params(&RknnQuantParams::per_layer(c_scale as f32, 0))?;
+        }
+        matmul.run(&raw_a, &raw_b, &mut raw_c)?;
+        matmul.close()?;
+
+        let mut c = c_type.decode(&raw_c);
+        if case.ac_native_layout {
+            c = layout.unpack_c(&c, m, n);
+        }
+        let errors = c
+            .iter()
+            .zip(&reference)
+            .map(|(c, reference)| (c * c_scale - reference).abs() / norm)
+            .collect::<Vec<_>>();
+        let max_error = errors.iter().fold(0.0_f64, |acc, it| acc.max(*it));
+        let mean_error = errors.iter().sum::<f64>() / errors.len().max(1) as f64;
+        Ok((max_error, mean_error))
+    }
+}
+
+impl Default for AccuracySweep {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Product of the row major A and B matrices, and the largest `|A| x |B|` value of its elements.
+fn reference(a: &[f64], b: &[f64], shape: RknnMatmulShape) -> (Vec<f64>, f64) {
+    let RknnMatmulShape { m, k, n } = shape;
+    let mut c = vec![0.0; m * n];
+    let mut norm = 0.0_f64;
+    for i in 0..m {
+        for j in 0..n {
+            let (mut value, mut magnitude) = (0.0, 0.0);
+            for l in 0..k {
+                value += a[i * k + l] * b[l * n + j];
+                magnitude += (a[i * k + l] * b[l * n + j]).abs();
+            }
+            c[i * n + j] = value;
+            norm = norm.max(magnitude);
+        }
+    }
+    (c, if norm > 0.0 { norm } else { 1.0 })
+}
+
+/// SplitMix64 generator, good enough for test matrices without a dependency.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform value in `[0, 1)`.
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1_u64 << 53) as f64
+    }
+
+    /// Random values exactly representable in `element_type`: floats in `[-1, 1]`, integers over
+    /// the int4 or int8 range.
+    fn matrix(&mut self, element_type: RknnMatmulElementType, len: usize) -> Vec<f64> {
+        let integer = |rng: &mut Self, bits: u32| {
+            let range = (1_u64 << bits) as f64;
+            (rng.next_f64() * range).floor() - range / 2.0
+        };
+        (0..len)
+            .map(|_| match element_type {
+                RknnMatmulElementType::F16 | RknnMatmulElementType::F32 => {
+                    half::f16::from_f64(self.next_f64() * 2.0 - 1.0).to_f64()
+                }
+                integer_type => integer(self, integer_type.bits().min(8) as u32),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use rknpu_sys::RKNN_ERR_PARAM_INVALID;
+
+    use crate::{
+        driver::stub::{StubCall, StubDriver},
+        matmul::{RknnMatmulShape, RknnMatmulType},
+    };
+
+    use super::AccuracySweep;
+
+    #[test]
+    fn test_sweep_stub() {
+        let driver = Arc::new(StubDriver::matmul());
+        let sweep = AccuracySweep::with_driver(driver.clone()).with_shapes(vec![
+            RknnMatmulShape::new(1, 32, 32),
+            RknnMatmulShape::new(3, 33, 17),
+            RknnMatmulShape::new(5, 70, 40),
+        ]);
+        let reports = sweep.run();
+        assert_eq!(reports.len(), 3 * 4 * RknnMatmulType::ALL.len());
+        for report in &reports {
+            assert!(report.passed(), "{report}");
+        }
+        // Random inputs, not only exactly representable sums.
+        assert!(reports.iter().any(|it| it.max_error > 0.0));
+        assert_eq!(driver.live_matmuls(), 0);
+    }
+
+    #[test]
+    fn test_sweep_failure() {
+        let driver = Arc::new(StubDriver::matmul());
+        driver.fail_on(StubCall::MatmulRun, RKNN_ERR_PARAM_INVALID);
+        let reports = AccuracySweep::with_driver(driver)
+            .with_shapes(vec![RknnMatmulShape::new(2, 32, 32)])
+            .with_mm_types(vec![RknnMatmulType::RKNN_INT8_MM_INT8_TO_INT32])
+            .with_layouts(vec![(false, false)])
+            .run();
+        assert_eq!(reports.len(), 1);
+        assert!(reports[0].failed());
+        assert!(reports[0].to_string().contains("failed"), "{}", reports[0]);
+    }
+
+    #[test]
+    fn test_sweep_unsupported() {
+        let driver = Arc::new(StubDriver::matmul());
+        driver.fail_on(StubCall::MatmulCreate, RKNN_ERR_PARAM_INVALID);
+        let reports = AccuracySweep::with_driver(driver)
+            .with_shapes(vec![RknnMatmulShape::new(3, 33, 17)])
+            .with_mm_types(vec![RknnMatmulType::RKNN_INT8_MM_INT8_TO_INT32])
+            .with_layouts(vec![(true, true)])
+            .run();
+        assert!(reports[0].unsupported);
+        assert!(!reports[0].passed() && !reports[0].failed());
+        assert!(
+            reports[0].to_string().contains("unsupported"),
+            "{}",
+            reports[0]
+        );
+    }
+
+    #[test]
+    fn test_sweep_npu() {
+        let reports = AccuracySweep::new().run();
+        // The runtime rejects some layouts of unaligned shapes, only listed.
+        for report in reports.iter().filter(|it| it.unsupported) {
+            println!("{report}");
+        }
+        assert!(reports.iter().any(|it| !it.unsupported), "No case ran");
+        let failed = reports
+            .iter()
+            .filter(|it| it.failed())
+            .map(|it| it.to_string())
+            .collect::<Vec<_>>();
+        assert!(failed.is_empty(), "{}", failed.join("\n"));
+    }
+}
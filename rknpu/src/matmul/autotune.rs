@@ -0,0 +1,733 @@
+//! Selection of the fastest type, layouts and cores for the shapes of a matmul, benchmarked once
+//! and remembered in a cache file.
+
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+
+use crate::{
+    context::RknnSdkVersion,
+    driver::{NativeDriver, RknnDriver},
+    flags::RknnCoreMask,
+};
+
+use super::{RknnMatmul, RknnMatmulInfo, RknnMatmulShape, RknnMatmulType};
+
+/// Type, layouts and cores a matmul is created with.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MatmulConfig {
+    pub mm_type: RknnMatmulType,
+    pub b_native_layout: bool,
+    pub ac_native_layout: bool,
+    pub core_mask: RknnCoreMask,
+}
+
+impl MatmulConfig {
+    pub fn new(
+        mm_type: RknnMatmulType,
+        b_native_layout: bool,
+        ac_native_layout: bool,
+        core_mask: RknnCoreMask,
+    ) -> Self {
+        Self {
+            mm_type,
+            b_native_layout,
+            ac_native_layout,
+            core_mask,
+        }
+    }
+
+    /// Normal layouts on the cores picked by the runtime.
+    pub fn default_for(mm_type: RknnMatmulType) -> Self {
+        Self::new(mm_type, false, false, RknnCoreMask::RKNN_NPU_CORE_AUTO)
+    }
+
+    /// Every combination of the normal and native layouts, for each of the given types on each
+    /// of the given cores.
+    pub fn candidates(mm_types: &[RknnMatmulType], core_masks: &[RknnCoreMask]) -> Vec<Self> {
+        let mut candidates = Vec::new();
+        for mm_type in mm_types {
+            for core_mask in core_masks {
+                for b_native_layout in [false, true] {
+                    for ac_native_layout in [false, true] {
+                        candidates.push(Self::new(
+                            mm_type.clone(),
+                            b_native_layout,
+                            ac_native_layout,
+                            *core_mask,
+                        ));
+                    }
+                }
+            }
+        }
+        candidates
+    }
+
+    /// Informations of a matmul of the given shape with this configuration.
+    pub fn infos(&self, shape: RknnMatmulShape) -> RknnMatmulInfo {
+        RknnMatmulInfo::new(
+            shape.m,
+            shape.k,
+            shape.n,
+            self.mm_type.clone(),
+            self.b_native_layout,
+            self.ac_native_layout,
+        )
+    }
+}
+
+/// Average run time of a configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatmulTiming {
+    pub config: MatmulConfig,
+    pub time: Duration,
+}
+
+/// Benchmarks the candidate configurations of the matmuls of a family of types, and keeps the
+/// fastest one of each shape in a cache file.
+///
+/// The family holds the types the application can run its matmuls with, e.g. int8 and fp16 for
+/// matrices it can quantize, and a tuned matmul may have any of them: its inputs are given in
+/// the type of its [`infos`](RknnMatmul::infos).
+///
+/// The file holds one tab separated line per runtime and driver versions, family and shape,
+/// giving the fastest configuration with its type, so the same file can be shared by several
+/// families and survives runtime updates: the entries of other versions are kept but ignored. A
+/// cached configuration is only used if it is still among the candidates.
+///
+/// The [default cache file](Self::default_cache_path) is also read once per process by
+/// [`RknnMatmul::new`] and [`RknnMatmul::from_cache`], without benchmarking.
+pub struct MatmulAutotuner {
+    mm_types: Vec<RknnMatmulType>,
+    driver: Arc<dyn RknnDriver>,
+    cache_path: PathBuf,
+    sdk_version: RknnSdkVersion,
+    cache: MatmulCache,
+    candidates: Vec<MatmulConfig>,
+    warmup: usize,
+    runs: usize,
+}
+
+impl MatmulAutotuner {
+    const HEADER: &'static str = "# api_version\tdriver_version\tmm_types\tm\tk\tn\t\
+        mm_type\tb_native_layout\tac_native_layout\tcore_mask\tnanoseconds";
+
+    /// Create an autotuner of the family `mm_types` using the
+    /// [default cache file](Self::default_cache_path).
+    pub fn new(mm_types: &[RknnMatmulType]) -> Result<Self> {
+        let cache_path = Self::default_cache_path()
+            .ok_or_else(|| anyhow!("Neither XDG_CACHE_HOME nor HOME is set"))?;
+        Self::with_driver(mm_types, cache_path, Arc::new(NativeDriver))
+    }
+
+    /// Create an autotuner of the family `mm_types` keeping its results in `cache_path` and
+    /// creating its matmuls through the given driver. The runtime version is queried on a small
+    /// matmul, and the cache file is read if it exists.
+    pub fn with_driver(
+        mm_types: &[RknnMatmulType],
+        cache_path: impl Into<PathBuf>,
+        driver: Arc<dyn RknnDriver>,
+    ) -> Result<Self> {
+        if mm_types.is_empty() {
+            return Err(anyhow!("An autotuner needs at least one matmul type"));
+        }
+        let cache_path = cache_path.into();
+        let sdk_version = probe_version(driver.clone())?;
+        let cache = MatmulCache::read(&cache_path, &sdk_version)?;
+        Ok(Self {
+            mm_types: family(mm_types),
+            driver,
+            cache_path,
+            sdk_version,
+            cache,
+            candidates: default_candidates(mm_types),
+            warmup: 2,
+            runs: 10,
+        })
+    }
+
+    /// `$XDG_CACHE_HOME/rknpu/matmul-autotune.tsv`, or the same file in `$HOME/.cache`.
+    pub fn default_cache_path() -> Option<PathBuf> {
+        let cache_dir = std::env::var_os("XDG_CACHE_HOME")
+            .filter(|it| !it.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|it| Path::new(&it).join(".cache")))?;
+        Some(cache_dir.join("rknpu").join("matmul-autotune.tsv"))
+    }
+
+    /// Benchmark the given configurations instead of every type of the family with every layout
+    /// on the automatic and all cores. Candidates of other types are left out.
+    pub fn with_candidates(mut self, candidates: Vec<MatmulConfig>) -> Self {
+        self.candidates = candidates
+            .into_iter()
+            .filter(|it| self.mm_types.contains(&it.mm_type))
+            .collect();
+        self
+    }
+
+    /// Time `runs` runs of each candidate, after `warmup` untimed runs.
+    pub fn with_runs(mut self, warmup: usize, runs: usize) -> Self {
+        self.warmup = warmup;
+        self.runs = runs.max(1);
+        self
+    }
+
+    /// Types of the family, in the order of [`RknnMatmulType::ALL`].
+    pub fn mm_types(&self) -> &[RknnMatmulType] {
+        &self.mm_types
+    }
+
+    pub fn cache_path(&self) -> &Path {
+        &self.cache_path
+    }
+
+    /// Versions of the runtime and driver the cached results are valid for.
+    pub fn sdk_version(&self) -> &RknnSdkVersion {
+        &self.sdk_version
+    }
+
+    pub fn candidates(&self) -> &[MatmulConfig] {
+        &self.candidates
+    }
+
+    /// Cached result of a shape, if it was tuned with the current runtime.
+    pub fn best(&self, shape: RknnMatmulShape) -> Option<MatmulTiming> {
+        self.cache.best(&self.mm_types, shape).cloned()
+    }
+
+    /// Cached configuration of a shape if it is still a candidate, without benchmarking, or the
+    /// [default](MatmulConfig::default_for) configuration of the first type of the family.
+    pub fn config(&self, shape: RknnMatmulShape) -> MatmulConfig {
+        self.best(shape)
+            .map(|it| it.config)
+            .filter(|it| self.candidates.contains(it))
+            .unwrap_or_else(|| MatmulConfig::default_for(self.mm_types[0].clone()))
+    }
+
+    /// Time every candidate on `shape`, fastest first. Candidates the runtime rejects, e.g. core
+    /// masks of cores the NPU doesn't have, are left out.
+    pub fn benchmark(&self, shape: RknnMatmulShape) -> Vec<MatmulTiming> {
+        let mut timings = self
+            .candidates
+            .iter()
+            .filter_map(|config| match self.time(shape, config) {
+                Ok(time) => Some(MatmulTiming {
+                    config: config.clone(),
+                    time,
+                }),
+                Err(error) => {
+                    log::debug!("Skipping matmul configuration {config:?} for {shape:?}: {error}");
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        timings.sort_by_key(|it| it.time);
+        timings
+    }
+
+    /// Fastest configuration for `shape`, from the cache or benchmarked and saved to the cache.
+    pub fn tune(&mut self, shape: RknnMatmulShape) -> Result<MatmulConfig> {
+        if let Some(timing) = self.best(shape) {
+            if self.candidates.contains(&timing.config) {
+                return Ok(timing.config);
+            }
+        }
+        let best = self.benchmark(shape).into_iter().next().ok_or_else(|| {
+            anyhow!(
+                "No candidate configuration can run a {}x{}x{} {:?} matmul",
+                shape.m,
+                shape.k,
+                shape.n,
+                self.mm_types
+            )
+        })?;
+        let config = best.config.clone();
+        self.cache
+            .entries
+            .insert((self.mm_types.clone(), shape), best);
+        self.save()?;
+        Ok(config)
+    }
+
+    /// Create a matmul of `shape` with its fastest configuration.
+    pub fn create(&mut self, shape: RknnMatmulShape) -> Result<RknnMatmul> {
+        let config = self.tune(shape)?;
+        self.create_with(shape, &config)
+    }
+
+    /// Create a matmul of `shape` with its [cached or default](Self::config) configuration,
+    /// without benchmarking.
+    pub fn create_cached(&self, shape: RknnMatmulShape) -> Result<RknnMatmul> {
+        self.create_with(shape, &self.config(shape))
+    }
+
+    fn create_with(&self, shape: RknnMatmulShape, config: &MatmulConfig) -> Result<RknnMatmul> {
+        create(shape, config, self.driver.clone())
+    }
+
+    /// Average time of a run of `config`, with zeroed matrices.
+    fn time(&self, shape: RknnMatmulShape, config: &MatmulConfig) -> Result<Duration> {
+        let mut matmul = self.create_with(shape, config)?;
+        let [a_size, b_size, _] = matmul.buffer_sizes();
+        matmul.set_inputs(&vec![0; a_size], &vec![0; b_size])?;
+        for _ in 0..self.warmup {
+            matmul.exec()?;
+        }
+        let start = Instant::now();
+        for _ in 0..self.runs {
+            matmul.exec()?;
+        }
+        let time = start.elapsed() / self.runs as u32;
+        matmul.close()?;
+        Ok(time)
+    }
+
+    /// Write the cache file, merged with the entries other processes saved since it was read.
+    fn save(&mut self) -> Result<()> {
+        if let Some(dir) = self.cache_path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // The file itself is replaced on each save, so the lock is held on a file next to it.
+        let lock = File::create(self.cache_path.with_extension("lock"))?;
+        lock.lock()?;
+        let saved = MatmulCache::read(&self.cache_path, &self.sdk_version)?;
+        for (key, timing) in saved.entries {
+            self.cache.entries.entry(key).or_insert(timing);
+        }
+        self.cache.other_versions = saved.other_versions;
+        self.cache.write(&self.cache_path, &self.sdk_version)
+    }
+}
+
+/// Fastest configurations of a cache file for the current runtime version.
+#[derive(Debug, Default)]
+struct MatmulCache {
+    entries: HashMap<(Vec<RknnMatmulType>, RknnMatmulShape), MatmulTiming>,
+    /// Cache lines of other runtime or driver versions, written back unchanged.
+    other_versions: Vec<String>,
+}
+
+impl MatmulCache {
+    /// Parse the cache file, empty if it doesn't exist.
+    fn read(path: &Path, sdk_version: &RknnSdkVersion) -> Result<Self> {
+        let mut cache = Self::default();
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(cache),
+            Err(error) => return Err(error.into()),
+        };
+        for line in content.lines() {
+            if line.starts_with('#') || line.trim().is_empty() {
+                continue;
+            }
+            let fields = line.split('\t').collect::<Vec<_>>();
+            if fields.len() >= 2
+                && (fields[0] != sdk_version.api_version || fields[1] != sdk_version.driver_version)
+            {
+                cache.other_versions.push(line.to_string());
+                continue;
+            }
+            match parse_entry(&fields) {
+                Some((mm_types, shape, timing)) => {
+                    cache.entries.insert((mm_types, shape), timing);
+                }
+                None => log::warn!("Ignoring invalid line of {}: {line}", path.display()),
+            }
+        }
+        Ok(cache)
+    }
+
+    /// Write the cache file, through a temporary file so that it is never left half written.
+    fn write(&self, path: &Path, sdk_version: &RknnSdkVersion) -> Result<()> {
+        let mut entries = self
+            .entries
+            .iter()
+            .map(|((mm_types, shape), timing)| {
+                let MatmulTiming { config, time } = timing;
+                let mm_types = mm_types
+                    .iter()
+                    .map(|it| format!("{it:?}"))
+                    .collect::<Vec<_>>();
+                format!(
+                    "{}\t{}\t{}\t{}\t{}\t{}\t{:?}\t{}\t{}\t{:?}\t{}",
+                    sdk_version.api_version,
+                    sdk_version.driver_version,
+                    mm_types.join(","),
+                    shape.m,
+                    shape.k,
+                    shape.n,
+                    config.mm_type,
+                    config.b_native_layout,
+                    config.ac_native_layout,
+                    config.core_mask,
+                    time.as_nanos()
+                )
+            })
+            .collect::<Vec<_>>();
+        entries.sort();
+        let mut content = format!("{}\n", MatmulAutotuner::HEADER);
+        for line in self.other_versions.iter().chain(&entries) {
+            content.push_str(line);
+            content.push('\n');
+        }
+
+        // Named after the process, so that processes saving at once don't write the same file.
+        let temporary = path.with_extension(format!("{}.tmp", std::process::id()));
+        fs::write(&temporary, content)?;
+        fs::rename(&temporary, path)?;
+        Ok(())
+    }
+
+    /// Cached result of a shape for the family `mm_types`.
+    fn best(&self, mm_types: &[RknnMatmulType], shape: RknnMatmulShape) -> Option<&MatmulTiming> {
+        self.entries.get(&(family(mm_types), shape))
+    }
+
+    /// Fastest cached core mask of a matmul, among the results of any family with its shape,
+    /// type and layouts.
+    fn core_mask(&self, infos: &RknnMatmulInfo) -> Option<RknnCoreMask> {
+        let shape = RknnMatmulShape::new(infos.m, infos.k, infos.n);
+        self.entries
+            .iter()
+            .filter(|((_, it), timing)| {
+                *it == shape
+                    && timing.config.mm_type == *infos.mm_type()
+                    && timing.config.b_native_layout == infos.b_native_layout()
+                    && timing.config.ac_native_layout == infos.ac_native_layout()
+            })
+            .min_by_key(|(_, timing)| timing.time)
+            .map(|(_, timing)| timing.config.core_mask)
+    }
+}
+
+/// The [default cache file](MatmulAutotuner::default_cache_path), read once per process. A file
+/// that can't be read is only logged, the matmuls then use the default configurations.
+fn default_cache() -> Option<&'static MatmulCache> {
+    static CACHE: OnceLock<Option<MatmulCache>> = OnceLock::new();
+    CACHE
+        .get_or_init(|| {
+            let path = MatmulAutotuner::default_cache_path()?;
+            probe_version(Arc::new(NativeDriver))
+                .and_then(|sdk_version| MatmulCache::read(&path, &sdk_version))
+                .inspect_err(|error| {
+                    log::warn!("Could not read the cache {}: {error}", path.display())
+                })
+                .ok()
+        })
+        .as_ref()
+}
+
+/// Versions of the runtime and driver, queried on a small matmul.
+fn probe_version(driver: Arc<dyn RknnDriver>) -> Result<RknnSdkVersion> {
+    let probe = RknnMatmulInfo::new(
+        1,
+        32,
+        32,
+        RknnMatmulType::RKNN_FLOAT16_MM_FLOAT16_TO_FLOAT32,
+        false,
+        false,
+    );
+    RknnMatmul::with_driver(probe, driver)?.sdk_version()
+}
+
+/// Every type of the family with every layout, on the automatic and all cores.
+fn default_candidates(mm_types: &[RknnMatmulType]) -> Vec<MatmulConfig> {
+    MatmulConfig::candidates(
+        mm_types,
+        &[
+            RknnCoreMask::RKNN_NPU_CORE_AUTO,
+            RknnCoreMask::RKNN_NPU_CORE_0_1_2,
+        ],
+    )
+}
+
+fn create(
+    shape: RknnMatmulShape,
+    config: &MatmulConfig,
+    driver: Arc<dyn RknnDriver>,
+) -> Result<RknnMatmul> {
+    let mut matmul = RknnMatmul::with_driver(config.infos(shape), driver)?;
+    if config.core_mask != RknnCoreMask::RKNN_NPU_CORE_AUTO {
+        matmul.set_core_mask(config.core_mask)?;
+    }
+    Ok(matmul)
+}
+
+/// Types of a family without duplicates, in the order of [`RknnMatmulType::ALL`].
+fn family(mm_types: &[RknnMatmulType]) -> Vec<RknnMatmulType> {
+    RknnMatmulType::ALL
+        .iter()
+        .filter(|it| mm_types.contains(it))
+        .cloned()
+        .collect()
+}
+
+fn parse_mm_type(name: &str) -> Option<RknnMatmulType> {
+    RknnMatmulType::ALL
+        .iter()
+        .find(|it| format!("{it:?}") == name)
+        .cloned()
+}
+
+/// Family, shape and timing of a cache line of the current runtime version.
+fn parse_entry(fields: &[&str]) -> Option<(Vec<RknnMatmulType>, RknnMatmulShape, MatmulTiming)> {
+    let [_, _, mm_types, m, k, n, mm_type, b_native_layout, ac_native_layout, core_mask, nanos] =
+        fields
+    else {
+        return None;
+    };
+    let mm_types = mm_types
+        .split(',')
+        .map(parse_mm_type)
+        .collect::<Option<Vec<_>>>()?;
+    let core_mask = RknnCoreMask::ALL
+        .iter()
+        .find(|it| format!("{it:?}") == *core_mask)?;
+    let shape = RknnMatmulShape::new(m.parse().ok()?, k.parse().ok()?, n.parse().ok()?);
+    let config = MatmulConfig::new(
+        parse_mm_type(mm_type)?,
+        b_native_layout.parse().ok()?,
+        ac_native_layout.parse().ok()?,
+        *core_mask,
+    );
+    let time = Duration::from_nanos(nanos.parse().ok()?);
+    Some((family(&mm_types), shape, MatmulTiming { config, time }))
+}
+
+impl RknnMatmul {
+    /// Create a matmul of `shape` with the fastest configuration found by `autotuner`, tuning the
+    /// shape first if it isn't in its cache.
+    pub fn autotuned(shape: RknnMatmulShape, autotuner: &mut MatmulAutotuner) -> Result<Self> {
+        autotuner.create(shape)
+    }
+
+    /// Create a matmul of `shape` with one of the types `mm_types`, with the configuration cached
+    /// in the [default cache file](MatmulAutotuner::default_cache_path) for this family, or with
+    /// the normal layouts of the first type when the shape wasn't tuned. Nothing is benchmarked.
+    pub fn from_cache(shape: RknnMatmulShape, mm_types: &[RknnMatmulType]) -> Result<Self> {
+        let Some(mm_type) = family(mm_types).into_iter().next() else {
+            return Err(anyhow!("A matmul needs at least one matmul type"));
+        };
+        let config = default_cache()
+            .and_then(|cache| cache.best(mm_types, shape))
+            .map(|it| it.config.clone())
+            .filter(|it| default_candidates(mm_types).contains(it))
+            .unwrap_or_else(|| MatmulConfig::default_for(mm_type));
+        create(shape, &config, Arc::new(NativeDriver))
+    }
+
+    /// Fastest core mask cached for the shape, type and layouts of `infos` in the default cache
+    /// file.
+    pub(super) fn cached_core_mask(infos: &RknnMatmulInfo) -> Option<RknnCoreMask> {
+        default_cache()?.core_mask(infos)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+        sync::Arc,
+    };
+
+    use rknpu_sys::RKNN_ERR_PARAM_INVALID;
+
+    use crate::{
+        driver::stub::{StubCall, StubDriver},
+        flags::RknnCoreMask,
+        matmul::{RknnMatmul, RknnMatmulShape, RknnMatmulType},
+    };
+
+    use super::{MatmulAutotuner, MatmulCache, MatmulConfig};
+
+    const MM_TYPE: RknnMatmulType = RknnMatmulType::RKNN_FLOAT16_MM_FLOAT16_TO_FLOAT32;
+    const INT8: RknnMatmulType = RknnMatmulType::RKNN_INT8_MM_INT8_TO_INT32;
+
+    fn cache_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("rknpu-{}-{name}.tsv", std::process::id()))
+    }
+
+    fn remove_cache(path: &Path) {
+        fs::remove_file(path).unwrap();
+        fs::remove_file(path.with_extension("lock")).unwrap();
+    }
+
+    #[test]
+    fn test_autotune_cache() {
+        let path = cache_path("cache");
+        let shape = RknnMatmulShape::new(4, 64, 32);
+        let driver = Arc::new(StubDriver::matmul());
+        let mut autotuner = MatmulAutotuner::with_driver(&[MM_TYPE], &path, driver.clone())
+            .unwrap()
+            .with_runs(0, 1);
+        assert_eq!(autotuner.sdk_version().api_version, "stub");
+        let config = autotuner.tune(shape).unwrap();
+        assert!(autotuner.candidates().contains(&config));
+        // The probe and the 8 candidates.
+        assert_eq!(driver.call_count(StubCall::MatmulCreate), 9);
+        assert_eq!(driver.live_matmuls(), 0);
+
+        // A new autotuner finds the shape in the cache instead of benchmarking it again.
+        let driver = Arc::new(StubDriver::matmul());
+        let mut autotuner =
+            MatmulAutotuner::with_driver(&[MM_TYPE], &path, driver.clone()).unwrap();
+        assert_eq!(
+            autotuner.best(shape).map(|it| it.config),
+            Some(config.clone())
+        );
+        let matmul = RknnMatmul::autotuned(shape, &mut autotuner).unwrap();
+        assert_eq!(driver.call_count(StubCall::MatmulCreate), 2);
+        assert_eq!(matmul.shape(), shape);
+        assert_eq!(matmul.infos().b_native_layout(), config.b_native_layout);
+        assert_eq!(matmul.infos().ac_native_layout(), config.ac_native_layout);
+
+        // Other matmul types are tuned separately.
+        let mut autotuner = MatmulAutotuner::with_driver(&[INT8], &path, driver).unwrap();
+        assert_eq!(autotuner.best(shape), None);
+        autotuner.tune(shape).unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        remove_cache(&path);
+        assert_eq!(
+            content.lines().filter(|it| it.starts_with("stub")).count(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_autotune_versions() {
+        let path = cache_path("versions");
+        let shape = RknnMatmulShape::new(4, 64, 32);
+        let other_version = "1.6.0\t0.9.3\tRKNN_FLOAT16_MM_FLOAT16_TO_FLOAT32\t4\t64\t32\t\
+            RKNN_FLOAT16_MM_FLOAT16_TO_FLOAT32\ttrue\ttrue\tRKNN_NPU_CORE_0\t1000";
+        fs::write(&path, format!("{other_version}\nstub\tstub\tinvalid\n")).unwrap();
+
+        let driver = Arc::new(StubDriver::matmul());
+        let mut autotuner = MatmulAutotuner::with_driver(&[MM_TYPE], &path, driver)
+            .unwrap()
+            .with_runs(0, 1);
+        assert_eq!(autotuner.best(shape), None);
+        autotuner.tune(shape).unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        remove_cache(&path);
+        let lines = content.lines().skip(1).collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], other_version);
+        assert!(lines[1].starts_with("stub\tstub\tRKNN_FLOAT16_MM_FLOAT16_TO_FLOAT32\t4\t64\t32"));
+    }
+
+    #[test]
+    fn test_autotune_core_mask() {
+        let path = cache_path("core-mask");
+        let shape = RknnMatmulShape::new(2, 32, 32);
+        let core_0 = MatmulConfig::new(MM_TYPE, false, false, RknnCoreMask::RKNN_NPU_CORE_0);
+        let auto = MatmulConfig::default_for(MM_TYPE);
+        let driver = Arc::new(StubDriver::matmul());
+        let mut autotuner = MatmulAutotuner::with_driver(&[MM_TYPE], &path, driver.clone())
+            .unwrap()
+            .with_candidates(vec![core_0.clone()])
+            .with_runs(0, 1);
+        let mut matmul = autotuner.create(shape).unwrap();
+        let runs = driver.matmul_runs(RknnCoreMask::RKNN_NPU_CORE_0);
+        matmul.exec().unwrap();
+        assert_eq!(driver.matmul_runs(RknnCoreMask::RKNN_NPU_CORE_0), runs + 1);
+
+        // The cached configuration is not a candidate anymore.
+        let mut autotuner = autotuner.with_candidates(vec![auto.clone()]);
+        assert_eq!(autotuner.tune(shape).unwrap(), auto);
+
+        // Candidates the runtime rejects are skipped.
+        driver.fail_on(StubCall::MatmulSetCoreMask, RKNN_ERR_PARAM_INVALID);
+        let mut autotuner = autotuner.with_candidates(vec![core_0.clone(), auto.clone()]);
+        assert_eq!(
+            autotuner.tune(RknnMatmulShape::new(1, 32, 32)).unwrap(),
+            auto
+        );
+        let mut autotuner = autotuner.with_candidates(vec![core_0]);
+        assert!(autotuner.tune(RknnMatmulShape::new(3, 32, 32)).is_err());
+        remove_cache(&path);
+    }
+
+    #[test]
+    fn test_autotune_types() {
+        let path = cache_path("types");
+        let shape = RknnMatmulShape::new(4, 64, 32);
+        let driver = Arc::new(StubDriver::matmul());
+        let mut autotuner = MatmulAutotuner::with_driver(&[INT8, MM_TYPE], &path, driver.clone())
+            .unwrap()
+            .with_runs(0, 1);
+        assert_eq!(autotuner.mm_types(), [MM_TYPE, INT8]);
+        // Before tuning, the first type of the family with the normal layouts.
+        assert_eq!(autotuner.config(shape), MatmulConfig::default_for(MM_TYPE));
+
+        // The probe and the 8 candidates of each type.
+        let config = autotuner.tune(shape).unwrap();
+        assert_eq!(driver.call_count(StubCall::MatmulCreate), 17);
+        assert_eq!(autotuner.config(shape), config);
+        let content = fs::read_to_string(&path).unwrap();
+        let line = content.lines().find(|it| it.starts_with("stub")).unwrap();
+        let fields = line.split('\t').collect::<Vec<_>>();
+        assert_eq!(
+            fields[2],
+            "RKNN_FLOAT16_MM_FLOAT16_TO_FLOAT32,RKNN_INT8_MM_INT8_TO_INT32"
+        );
+        assert_eq!(fields[6], format!("{:?}", config.mm_type));
+
+        // Matmuls created from the cache have the cached type, without benchmarking. Other
+        // families, even of one of these types, are tuned separately.
+        let driver = Arc::new(StubDriver::matmul());
+        let autotuner =
+            MatmulAutotuner::with_driver(&[MM_TYPE, INT8], &path, driver.clone()).unwrap();
+        let matmul = autotuner.create_cached(shape).unwrap();
+        assert_eq!(*matmul.infos().mm_type(), config.mm_type);
+        assert_eq!(matmul.infos().b_native_layout(), config.b_native_layout);
+        assert_eq!(driver.call_count(StubCall::MatmulCreate), 2);
+        let autotuner = MatmulAutotuner::with_driver(&[INT8], &path, driver).unwrap();
+        assert_eq!(autotuner.best(shape), None);
+        assert_eq!(autotuner.config(shape), MatmulConfig::default_for(INT8));
+        remove_cache(&path);
+
+        // The cache file is written through a temporary file of the process.
+        assert!(!path
+            .with_extension(format!("{}.tmp", std::process::id()))
+            .exists());
+    }
+
+    #[test]
+    fn test_autotune_merge() {
+        let path = cache_path("merge");
+        let shape = RknnMatmulShape::new(4, 64, 32);
+        let other_shape = RknnMatmulShape::new(2, 32, 32);
+        let core_0 = MatmulConfig::new(MM_TYPE, true, false, RknnCoreMask::RKNN_NPU_CORE_0);
+        let driver = Arc::new(StubDriver::matmul());
+        let autotuner = |driver| {
+            MatmulAutotuner::with_driver(&[MM_TYPE], &path, driver)
+                .unwrap()
+                .with_candidates(vec![core_0.clone()])
+                .with_runs(0, 1)
+        };
+        let mut first = autotuner(driver.clone());
+        let mut second = autotuner(driver);
+
+        // Both autotuners read the file before either saved, the second one keeps the shape of
+        // the first one.
+        first.tune(shape).unwrap();
+        second.tune(other_shape).unwrap();
+        let cache = MatmulCache::read(&path, second.sdk_version()).unwrap();
+        remove_cache(&path);
+        assert_eq!(cache.entries.len(), 2);
+        assert_eq!(cache.best(&[MM_TYPE], shape).unwrap().config, core_0);
+
+        // Matmuls of the same type and layouts get the cached cores.
+        let infos = core_0.infos(other_shape);
+        assert_eq!(cache.core_mask(&infos), Some(RknnCoreMask::RKNN_NPU_CORE_0));
+        let infos = MatmulConfig::default_for(MM_TYPE).infos(other_shape);
+        assert_eq!(cache.core_mask(&infos), None);
+    }
+}
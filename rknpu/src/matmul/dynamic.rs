@@ -0,0 +1,153 @@
+use std::{ffi::c_int, sync::Arc};
+
+use anyhow::{ensure, Result};
+use rknpu_sys::{rknn_matmul_ctx, rknn_matmul_info, rknn_matmul_io_attr, rknn_matmul_shape};
+
+use crate::{
+    driver::{NativeDriver, RknnDriver},
+    error::{check_result, RknnMatmulError},
+};
+
+use super::{RknnMatmul, RknnMatmulInfo, RknnMatmulShape};
+
+impl From<RknnMatmulShape> for rknn_matmul_shape {
+    fn from(value: RknnMatmulShape) -> Self {
+        Self {
+            M: value.m as i32,
+            K: value.k as i32,
+            N: value.n as i32,
+        }
+    }
+}
+
+impl RknnMatmul {
+    /// Create a matmul context accepting each of `shapes`, starting with the first one. `infos`
+    /// gives the matmul type, layouts and quantization, its shape is ignored.
+    ///
+    /// The buffers are allocated for the largest matrices, and switching between shapes with
+    /// [`set_shape`](Self::set_shape) doesn't create a new context.
+    pub fn new_dynamic(infos: RknnMatmulInfo, shapes: &[RknnMatmulShape]) -> Result<Self> {
+        Self::new_dynamic_with_driver(infos, shapes, Arc::new(NativeDriver))
+    }
+
+    /// Create a dynamic shape matmul context through the given driver.
+    pub fn new_dynamic_with_driver(
+        mut infos: RknnMatmulInfo,
+        shapes: &[RknnMatmulShape],
+        driver: Arc<dyn RknnDriver>,
+    ) -> Result<Self> {
+        ensure!(!shapes.is_empty(), "A dynamic shape matmul needs a shape");
+        let mut ctx_ptr = unsafe { std::mem::zeroed::<rknn_matmul_ctx>() };
+        let mut rknn_input_infos: rknn_matmul_info = infos.clone().into();
+        let mut raw_shapes = shapes.iter().map(|it| (*it).into()).collect::<Vec<_>>();
+        let mut io_attrs = vec![unsafe { std::mem::zeroed::<rknn_matmul_io_attr>() }; shapes.len()];
+        let ret = unsafe {
+            driver.matmul_create_dyn_shape(
+                &mut ctx_ptr,
+                &mut rknn_input_infos,
+                shapes.len() as c_int,
+                raw_shapes.as_mut_ptr(),
+                io_attrs.as_mut_ptr(),
+            )
+        };
+        check_result(ret)?;
+
+        let sizes = io_attrs.iter().fold([0; 3], |sizes, it| {
+            [
+                sizes[0].max(it.A.size),
+                sizes[1].max(it.B.size),
+                sizes[2].max(it.C.size),
+            ]
+        });
+        (infos.m, infos.k, infos.n) = (shapes[0].m, shapes[0].k, shapes[0].n);
+        let mut matmul = Self::from_context(driver, ctx_ptr, infos, io_attrs[0], sizes)?;
+        matmul.dynamic_shapes = shapes.iter().copied().zip(io_attrs).collect();
+        Ok(matmul)
+    }
+
+    /// Shapes accepted by a dynamic shape matmul, empty for a fixed shape matmul.
+    pub fn dynamic_shapes(&self) -> impl Iterator<Item = RknnMatmulShape> + '_ {
+        self.dynamic_shapes.iter().map(|it| it.0)
+    }
+
+    /// Switch a dynamic shape matmul to one of the shapes it was created with. The matrices must
+    /// be set again before running it.
+    pub fn set_shape(&mut self, shape: RknnMatmulShape) -> Result<()> {
+        let Some((_, io_attr)) = self.dynamic_shapes.iter().find(|it| it.0 == shape) else {
+            return Err(RknnMatmulError::UnknownShape {
+                m: shape.m,
+                k: shape.k,
+                n: shape.n,
+            }
+            .into());
+        };
+        let io_attr = *io_attr;
+        let mut raw_shape = shape.into();
+        let ret = unsafe {
+            self.driver
+                .matmul_set_dynamic_shape(self.ctx_ptr, &mut raw_shape)
+        };
+        check_result(ret)?;
+        self.io_attr = io_attr;
+        (self.infos.m, self.infos.k, self.infos.n) = (shape.m, shape.k, shape.n);
+        // The buffers are bound with the attributes of a shape.
+        self.bound = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::{
+        driver::stub::StubDriver,
+        error::RknnMatmulError,
+        matmul::{Matmul, RknnMatmul, RknnMatmulInfo, RknnMatmulShape, RknnMatmulType},
+    };
+
+    #[test]
+    fn test_dynamic_shapes() {
+        let shapes = [
+            RknnMatmulShape::new(1, 64, 32),
+            RknnMatmulShape::new(8, 64, 32),
+        ];
+        let infos = RknnMatmulInfo::new(
+            0,
+            0,
+            0,
+            RknnMatmulType::RKNN_INT8_MM_INT8_TO_INT32,
+            false,
+            false,
+        );
+        let driver = Arc::new(StubDriver::matmul());
+        let raw =
+            RknnMatmul::new_dynamic_with_driver(infos, &shapes, Arc::clone(&driver) as _).unwrap();
+        assert_eq!(raw.shape(), shapes[0]);
+        assert_eq!(raw.dynamic_shapes().collect::<Vec<_>>(), shapes);
+
+        let mut matmul = Matmul::<i8, i32>::from_raw(raw).unwrap();
+        let b = vec![1; 64 * 32];
+        for shape in shapes.into_iter().rev() {
+            matmul.as_raw().set_shape(shape).unwrap();
+            let a = vec![2; shape.m * 64];
+            let mut c = vec![0; shape.m * 32];
+            matmul.run(&a, &b, &mut c).unwrap();
+            assert!(c.iter().all(|it| *it == 128));
+        }
+
+        let clone = matmul.as_raw().try_clone().unwrap();
+        assert_eq!(clone.shape(), shapes[0]);
+
+        let error = matmul
+            .as_raw()
+            .set_shape(RknnMatmulShape::new(4, 64, 32))
+            .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<RknnMatmulError>(),
+            Some(&RknnMatmulError::UnknownShape { m: 4, k: 64, n: 32 })
+        );
+        drop((matmul, clone));
+        assert_eq!((driver.live_matmuls(), driver.live_mems()), (0, 0));
+    }
+}
@@ -0,0 +1,316 @@
+//! Conversions between the normal (row major) layout of the matmul matrices and the native
+//! layouts of the NPU, selected by [`RknnMatmulInfo::b_native_layout`] and
+//! [`RknnMatmulInfo::ac_native_layout`].
+//!
+//! The native layouts split a matrix in blocks, as documented for the RK3588 matmul API:
+//! - A `M x K` is stored as `[K / sub_k, M, sub_k]`.
+//! - B `K x N` is stored as `[N / sub_n, K / sub_k, sub_n, sub_k]`.
+//! - C `M x N` is stored as `[N / sub_n, M, sub_n]`.
+//!
+//! Dimensions which are not a multiple of the block size are padded with zeros.
+//!
+//! The conversions work on matrix values: int4 matrices are converted as `i8` values, and
+//! packed with [`PackedI4::pack`](super::PackedI4::pack) afterwards.
+//!
+//! [`RknnMatmulInfo::b_native_layout`]: super::RknnMatmulInfo::b_native_layout
+//! [`RknnMatmulInfo::ac_native_layout`]: super::RknnMatmulInfo::ac_native_layout
+
+use super::RknnMatmulType;
+
+/// Block sizes of the native layouts of a matmul type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NativeLayout {
+    /// K values per block of the A matrix.
+    pub a_sub_k: usize,
+    /// N values per block of the B matrix.
+    pub b_sub_n: usize,
+    /// K values per block of the B matrix.
+    pub b_sub_k: usize,
+    /// N values per block of the C matrix.
+    pub c_sub_n: usize,
+}
+
+impl NativeLayout {
+    /// Block sizes for a matmul type. Blocks of A and C rows hold 128 bits, blocks of B hold 32
+    /// rows of 256 bits.
+    pub fn new(mm_type: &RknnMatmulType) -> Self {
+        let [a, b, c] = mm_type.element_types().map(|it| it.bits());
+        Self {
+            a_sub_k: 128 / a,
+            b_sub_n: 256 / b,
+            b_sub_k: 32,
+            c_sub_n: 128 / c,
+        }
+    }
+
+    /// Number of values of an A matrix in the native layout, padding included.
+    pub fn a_len(&self, m: usize, k: usize) -> usize {
+        m * k.next_multiple_of(self.a_sub_k)
+    }
+
+    /// Number of values of a B matrix in the native layout, padding included.
+    pub fn b_len(&self, k: usize, n: usize) -> usize {
+        k.next_multiple_of(self.b_sub_k) * n.next_multiple_of(self.b_sub_n)
+    }
+
+    /// Number of values of a C matrix in the native layout, padding included.
+    pub fn c_len(&self, m: usize, n: usize) -> usize {
+        m * n.next_multiple_of(self.c_sub_n)
+    }
+
+    /// Convert a row major `m x k` A matrix to the native layout.
+    pub fn pack_a<T: Copy + Default>(&self, a: &[T], m: usize, k: usize) -> Vec<T> {
+        pack_rows(a, m, k, self.a_sub_k)
+    }
+
+    /// Convert an A matrix in the native layout to a row major `m x k` matrix.
+    pub fn unpack_a<T: Copy + Default>(&self, a: &[T], m: usize, k: usize) -> Vec<T> {
+        unpack_rows(a, m, k, self.a_sub_k)
+    }
+
+    /// Convert a row major `k x n` B matrix to the native layout.
+    pub fn pack_b<T: Copy + Default>(&self, b: &[T], k: usize, n: usize) -> Vec<T> {
+        let mut packed = vec![T::default(); self.b_len(k, n)];
+        for (dst, src) in self.b_indices(k, n) {
+            packed[dst] = b[src];
+        }
+        packed
+    }
+
+    /// Convert a B matrix in the native layout to a row major `k x n` matrix.
+    pub fn unpack_b<T: Copy + Default>(&self, b: &[T], k: usize, n: usize) -> Vec<T> {
+        let mut unpacked = vec![T::default(); k * n];
+        for (src, dst) in self.b_indices(k, n) {
+            unpacked[dst] = b[src];
+        }
+        unpacked
+    }
+
+    /// Convert a row major `m x n` C matrix to the native layout.
+    pub fn pack_c<T: Copy + Default>(&self, c: &[T], m: usize, n: usize) -> Vec<T> {
+        pack_rows(c, m, n, self.c_sub_n)
+    }
+
+    /// Convert a C matrix in the native layout to a row major `m x n` matrix.
+    pub fn unpack_c<T: Copy + Default>(&self, c: &[T], m: usize, n: usize) -> Vec<T> {
+        unpack_rows(c, m, n, self.c_sub_n)
+    }
+
+    /// Pairs of native and row major indices of the values of a `k x n` B matrix.
+    fn b_indices(&self, k: usize, n: usize) -> impl Iterator<Item = (usize, usize)> {
+        let (sub_n, sub_k) = (self.b_sub_n, self.b_sub_k);
+        let k_blocks = k.div_ceil(sub_k);
+        (0..k).flat_map(move |row| {
+            (0..n).map(move |col| {
+                let block = (col / sub_n) * k_blocks + row / sub_k;
+                let native = (block * sub_n + col % sub_n) * sub_k + row % sub_k;
+                (native, row * n + col)
+            })
+        })
+    }
+}
+
+/// Split the columns of a row major matrix in blocks of `sub` columns, stored one after the
+/// other.
+fn pack_rows<T: Copy + Default>(values: &[T], rows: usize, cols: usize, sub: usize) -> Vec<T> {
+    let mut packed = vec![T::default(); rows * cols.next_multiple_of(sub)];
+    for row in 0..rows {
+        for col in 0..cols {
+            packed[((col / sub) * rows + row) * sub + col % sub] = values[row * cols + col];
+        }
+    }
+    packed
+}
+
+fn unpack_rows<T: Copy + Default>(values: &[T], rows: usize, cols: usize, sub: usize) -> Vec<T> {
+    let mut unpacked = vec![T::default(); rows * cols];
+    for row in 0..rows {
+        for col in 0..cols {
+            unpacked[row * cols + col] = values[((col / sub) * rows + row) * sub + col % sub];
+        }
+    }
+    unpacked
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use half::f16;
+
+    use crate::{
+        driver::stub::StubDriver,
+        matmul::{
+            Matmul, MatmulElement, MatmulTypes, PackedI4, RknnMatmul, RknnMatmulInfo,
+            RknnMatmulType,
+        },
+    };
+
+    use super::NativeLayout;
+
+    #[test]
+    fn test_round_trip() {
+        for mm_type in RknnMatmulType::ALL {
+            let layout = NativeLayout::new(mm_type);
+            // Both aligned and padded shapes.
+            for (m, k, n) in [(4, 64, 128), (3, 40, 70)] {
+                let a = (0..m * k).map(|it| it as i32 + 1).collect::<Vec<_>>();
+                let packed = layout.pack_a(&a, m, k);
+                assert_eq!(packed.len(), layout.a_len(m, k));
+                assert_eq!(layout.unpack_a(&packed, m, k), a);
+
+                let b = (0..k * n).map(|it| it as i32 + 1).collect::<Vec<_>>();
+                let packed = layout.pack_b(&b, k, n);
+                assert_eq!(packed.len(), layout.b_len(k, n));
+                assert_eq!(layout.unpack_b(&packed, k, n), b);
+
+                let c = (0..m * n).map(|it| it as i32 + 1).collect::<Vec<_>>();
+                let packed = layout.pack_c(&c, m, n);
+                assert_eq!(packed.len(), layout.c_len(m, n));
+                assert_eq!(layout.unpack_c(&packed, m, n), c);
+            }
+        }
+    }
+
+    #[test]
+    fn test_block_sizes() {
+        let layout = NativeLayout::new(&RknnMatmulType::RKNN_INT4_MM_INT4_TO_INT16);
+        let expected = NativeLayout {
+            a_sub_k: 32,
+            b_sub_n: 64,
+            b_sub_k: 32,
+            c_sub_n: 8,
+        };
+        assert_eq!(layout, expected);
+    }
+
+    #[test]
+    fn test_pack_a() {
+        let layout = NativeLayout::new(&RknnMatmulType::RKNN_FLOAT16_MM_FLOAT16_TO_FLOAT32);
+        let (m, k) = (2, 16);
+        let a = (0..m * k).collect::<Vec<_>>();
+        let packed = layout.pack_a(&a, m, k);
+        // First block of 8 columns of both rows, then the second one.
+        let expected = [0..8, 16..24, 8..16, 24..32]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+        assert_eq!(packed, expected);
+    }
+
+    #[test]
+    fn test_pack_b() {
+        // Blocks of 2 x 2 values, so that both K and N cross a block and are padded.
+        let layout = NativeLayout {
+            a_sub_k: 2,
+            b_sub_n: 2,
+            b_sub_k: 2,
+            c_sub_n: 2,
+        };
+        let (k, n) = (3, 3);
+        let b = (1..=k * n).collect::<Vec<_>>();
+        let packed = layout.pack_b(&b, k, n);
+        // Blocks of columns 0-1 then 2-3, each split in rows 0-1 then 2-3, stored column by
+        // column.
+        let expected = [1, 4, 2, 5, 7, 0, 8, 0, 3, 6, 0, 0, 9, 0, 0, 0];
+        assert_eq!(packed, expected);
+        assert_eq!(layout.unpack_b(&packed, k, n), b);
+    }
+
+    #[test]
+    fn test_pack_c() {
+        let layout = NativeLayout::new(&RknnMatmulType::RKNN_FLOAT16_MM_FLOAT16_TO_FLOAT32);
+        let (m, n) = (2, 6);
+        let c = (1..=m * n).collect::<Vec<_>>();
+        let packed = layout.pack_c(&c, m, n);
+        // Blocks of 4 columns of both rows, the second one padded.
+        let expected = [1, 2, 3, 4, 7, 8, 9, 10, 5, 6, 0, 0, 11, 12, 0, 0];
+        assert_eq!(packed, expected);
+        assert_eq!(layout.unpack_c(&packed, m, n), c);
+    }
+
+    /// A matmul on the stub driver, which reads and writes native buffers with [`NativeLayout`]
+    /// too: the tests running it check that native buffers go through the matmul, the layouts
+    /// themselves are checked against the hand-written buffers above.
+    fn matmul<A, C>(
+        m: usize,
+        k: usize,
+        n: usize,
+        mm_type: RknnMatmulType,
+        native: bool,
+    ) -> Matmul<A, C>
+    where
+        A: MatmulTypes<C>,
+        C: MatmulElement,
+    {
+        let infos = RknnMatmulInfo::new(m, k, n, mm_type, native, native);
+        let raw = RknnMatmul::with_driver(infos, Arc::new(StubDriver::matmul())).unwrap();
+        Matmul::from_raw(raw).unwrap()
+    }
+
+    #[test]
+    fn test_native_matches_normal_f16() {
+        let (m, k, n) = (3, 40, 70);
+        let mm_type = RknnMatmulType::RKNN_FLOAT16_MM_FLOAT16_TO_FLOAT32;
+        let layout = NativeLayout::new(&mm_type);
+        let a = (0..m * k)
+            .map(|it| f16::from_f32((it % 7) as f32 - 3.0))
+            .collect::<Vec<_>>();
+        let b = (0..k * n)
+            .map(|it| f16::from_f32((it % 5) as f32 * 0.5))
+            .collect::<Vec<_>>();
+
+        let mut normal = matmul::<f16, f32>(m, k, n, mm_type.clone(), false);
+        let mut expected = vec![0.0; m * n];
+        normal.run(&a, &b, &mut expected).unwrap();
+
+        let mut native = matmul::<f16, f32>(m, k, n, mm_type, true);
+        let mut c = vec![0.0; layout.c_len(m, n)];
+        native
+            .run(&layout.pack_a(&a, m, k), &layout.pack_b(&b, k, n), &mut c)
+            .unwrap();
+        assert_eq!(layout.unpack_c(&c, m, n), expected);
+    }
+
+    #[test]
+    fn test_native_matches_normal_i8() {
+        let (m, k, n) = (3, 40, 70);
+        let mm_type = RknnMatmulType::RKNN_INT8_MM_INT8_TO_INT32;
+        let layout = NativeLayout::new(&mm_type);
+        let a = (0..m * k).map(|it| (it % 11) as i8 - 5).collect::<Vec<_>>();
+        let b = (0..k * n).map(|it| (it % 13) as i8 - 6).collect::<Vec<_>>();
+
+        let mut normal = matmul::<i8, i32>(m, k, n, mm_type.clone(), false);
+        let mut expected = vec![0; m * n];
+        normal.run(&a, &b, &mut expected).unwrap();
+
+        let mut native = matmul::<i8, i32>(m, k, n, mm_type, true);
+        let mut c = vec![0; layout.c_len(m, n)];
+        native
+            .run(&layout.pack_a(&a, m, k), &layout.pack_b(&b, k, n), &mut c)
+            .unwrap();
+        assert_eq!(layout.unpack_c(&c, m, n), expected);
+    }
+
+    #[test]
+    fn test_native_matches_normal_i4() {
+        let (m, k, n) = (3, 64, 70);
+        let mm_type = RknnMatmulType::RKNN_INT4_MM_INT4_TO_INT16;
+        let layout = NativeLayout::new(&mm_type);
+        let a = (0..m * k).map(|it| (it % 5) as i8 - 2).collect::<Vec<_>>();
+        let b = (0..k * n).map(|it| (it % 7) as i8 - 3).collect::<Vec<_>>();
+
+        let mut normal = matmul::<PackedI4, i16>(m, k, n, mm_type.clone(), false);
+        let mut expected = vec![0; m * n];
+        normal
+            .run(&PackedI4::pack(&a), &PackedI4::pack(&b), &mut expected)
+            .unwrap();
+
+        let mut native = matmul::<PackedI4, i16>(m, k, n, mm_type, true);
+        let mut c = vec![0; layout.c_len(m, n)];
+        let packed_a = PackedI4::pack(&layout.pack_a(&a, m, k));
+        let packed_b = PackedI4::pack(&layout.pack_b(&b, k, n));
+        native.run(&packed_a, &packed_b, &mut c).unwrap();
+        assert_eq!(layout.unpack_c(&c, m, n), expected);
+    }
+}
@@ -1,25 +1,238 @@
 use std::{
     ffi::c_void,
+    fmt,
     ptr::{copy_nonoverlapping, NonNull},
+    sync::Arc,
 };
 
 use anyhow::{anyhow, Result};
+use half::f16;
 use rknpu_sys::{
     _rknn_matmul_type, _rknn_matmul_type_RKNN_FLOAT16_MM_FLOAT16_TO_FLOAT32,
     _rknn_matmul_type_RKNN_INT4_MM_INT4_TO_INT16, _rknn_matmul_type_RKNN_INT8_MM_INT8_TO_INT32,
-    rknn_create_mem, rknn_matmul_create, rknn_matmul_ctx, rknn_matmul_info, rknn_matmul_io_attr,
-    rknn_matmul_run, rknn_matmul_set_io_mem, rknn_tensor_mem,
+    rknn_core_mask, rknn_matmul_ctx, rknn_matmul_info, rknn_matmul_io_attr, rknn_tensor_mem,
+};
+#[cfg(feature = "sdk-v2")]
+use rknpu_sys::{
+    _rknn_matmul_type_RKNN_FLOAT16_MM_FLOAT16_TO_FLOAT16,
+    _rknn_matmul_type_RKNN_FLOAT16_MM_INT4_TO_FLOAT16,
+    _rknn_matmul_type_RKNN_FLOAT16_MM_INT4_TO_FLOAT32,
+    _rknn_matmul_type_RKNN_FLOAT16_MM_INT8_TO_FLOAT16,
+    _rknn_matmul_type_RKNN_FLOAT16_MM_INT8_TO_FLOAT32,
+    _rknn_matmul_type_RKNN_INT8_MM_INT4_TO_INT32, _rknn_matmul_type_RKNN_INT8_MM_INT8_TO_FLOAT32,
+    _rknn_matmul_type_RKNN_INT8_MM_INT8_TO_INT8,
+};
+
+use crate::{
+    context::RknnSdkVersion,
+    driver::{NativeDriver, RknnDriver},
+    error::{check_result, RknnError, RknnMatmulError},
+    flags::RknnCoreMask,
+    queries::QueryObject,
 };
 
-use crate::error::check_result;
+pub use self::{
+    accuracy::{AccuracyCase, AccuracyReport, AccuracySweep},
+    autotune::{MatmulAutotuner, MatmulConfig, MatmulTiming},
+    layout::NativeLayout,
+    quantized::{QuantGranularity, QuantScheme, QuantizedMatmul, QuantizedTypes},
+    tiled::{TileAccumulator, TiledMatmul},
+    typed::{Matmul, MatmulElement, MatmulTypes, PackedI4},
+};
 
-#[derive(Debug, Clone)]
+#[cfg(feature = "sdk-v2")]
+pub use self::quant::{RknnMatmulQuantType, RknnQuantParams};
+
+mod accuracy;
+mod autotune;
+#[cfg(feature = "sdk-v2")]
+mod dynamic;
+pub mod layout;
+#[cfg(feature = "sdk-v2")]
+mod quant;
+mod quantized;
+mod tiled;
+mod typed;
+
+/// Element types of the A and B matrices and of the C matrix.
+///
+/// The types only available from the 2.0 runtime need the `sdk-v2` feature.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 #[allow(non_camel_case_types)]
 #[repr(u32)]
 pub enum RknnMatmulType {
     RKNN_FLOAT16_MM_FLOAT16_TO_FLOAT32 = _rknn_matmul_type_RKNN_FLOAT16_MM_FLOAT16_TO_FLOAT32,
     RKNN_INT8_MM_INT8_TO_INT32 = _rknn_matmul_type_RKNN_INT8_MM_INT8_TO_INT32,
     RKNN_INT4_MM_INT4_TO_INT16 = _rknn_matmul_type_RKNN_INT4_MM_INT4_TO_INT16,
+    #[cfg(feature = "sdk-v2")]
+    RKNN_INT8_MM_INT8_TO_INT8 = _rknn_matmul_type_RKNN_INT8_MM_INT8_TO_INT8,
+    #[cfg(feature = "sdk-v2")]
+    RKNN_FLOAT16_MM_FLOAT16_TO_FLOAT16 = _rknn_matmul_type_RKNN_FLOAT16_MM_FLOAT16_TO_FLOAT16,
+    #[cfg(feature = "sdk-v2")]
+    RKNN_FLOAT16_MM_INT8_TO_FLOAT32 = _rknn_matmul_type_RKNN_FLOAT16_MM_INT8_TO_FLOAT32,
+    #[cfg(feature = "sdk-v2")]
+    RKNN_FLOAT16_MM_INT8_TO_FLOAT16 = _rknn_matmul_type_RKNN_FLOAT16_MM_INT8_TO_FLOAT16,
+    #[cfg(feature = "sdk-v2")]
+    RKNN_FLOAT16_MM_INT4_TO_FLOAT32 = _rknn_matmul_type_RKNN_FLOAT16_MM_INT4_TO_FLOAT32,
+    #[cfg(feature = "sdk-v2")]
+    RKNN_FLOAT16_MM_INT4_TO_FLOAT16 = _rknn_matmul_type_RKNN_FLOAT16_MM_INT4_TO_FLOAT16,
+    #[cfg(feature = "sdk-v2")]
+    RKNN_INT8_MM_INT8_TO_FLOAT32 = _rknn_matmul_type_RKNN_INT8_MM_INT8_TO_FLOAT32,
+    #[cfg(feature = "sdk-v2")]
+    RKNN_INT8_MM_INT4_TO_INT32 = _rknn_matmul_type_RKNN_INT8_MM_INT4_TO_INT32,
+}
+
+impl RknnMatmulType {
+    /// Every matmul type supported by the runtime the crate is built against.
+    pub const ALL: &'static [RknnMatmulType] = &[
+        Self::RKNN_FLOAT16_MM_FLOAT16_TO_FLOAT32,
+        Self::RKNN_INT8_MM_INT8_TO_INT32,
+        Self::RKNN_INT4_MM_INT4_TO_INT16,
+        #[cfg(feature = "sdk-v2")]
+        Self::RKNN_INT8_MM_INT8_TO_INT8,
+        #[cfg(feature = "sdk-v2")]
+        Self::RKNN_FLOAT16_MM_FLOAT16_TO_FLOAT16,
+        #[cfg(feature = "sdk-v2")]
+        Self::RKNN_FLOAT16_MM_INT8_TO_FLOAT32,
+        #[cfg(feature = "sdk-v2")]
+        Self::RKNN_FLOAT16_MM_INT8_TO_FLOAT16,
+        #[cfg(feature = "sdk-v2")]
+        Self::RKNN_FLOAT16_MM_INT4_TO_FLOAT32,
+        #[cfg(feature = "sdk-v2")]
+        Self::RKNN_FLOAT16_MM_INT4_TO_FLOAT16,
+        #[cfg(feature = "sdk-v2")]
+        Self::RKNN_INT8_MM_INT8_TO_FLOAT32,
+        #[cfg(feature = "sdk-v2")]
+        Self::RKNN_INT8_MM_INT4_TO_INT32,
+    ];
+
+    /// Element types of the A, B and C matrices.
+    pub fn element_types(&self) -> [RknnMatmulElementType; 3] {
+        use RknnMatmulElementType::*;
+
+        match self {
+            Self::RKNN_FLOAT16_MM_FLOAT16_TO_FLOAT32 => [F16, F16, F32],
+            Self::RKNN_INT8_MM_INT8_TO_INT32 => [I8, I8, I32],
+            Self::RKNN_INT4_MM_INT4_TO_INT16 => [I4, I4, I16],
+            #[cfg(feature = "sdk-v2")]
+            Self::RKNN_INT8_MM_INT8_TO_INT8 => [I8, I8, I8],
+            #[cfg(feature = "sdk-v2")]
+            Self::RKNN_FLOAT16_MM_FLOAT16_TO_FLOAT16 => [F16, F16, F16],
+            #[cfg(feature = "sdk-v2")]
+            Self::RKNN_FLOAT16_MM_INT8_TO_FLOAT32 => [F16, I8, F32],
+            #[cfg(feature = "sdk-v2")]
+            Self::RKNN_FLOAT16_MM_INT8_TO_FLOAT16 => [F16, I8, F16],
+            #[cfg(feature = "sdk-v2")]
+            Self::RKNN_FLOAT16_MM_INT4_TO_FLOAT32 => [F16, I4, F32],
+            #[cfg(feature = "sdk-v2")]
+            Self::RKNN_FLOAT16_MM_INT4_TO_FLOAT16 => [F16, I4, F16],
+            #[cfg(feature = "sdk-v2")]
+            Self::RKNN_INT8_MM_INT8_TO_FLOAT32 => [I8, I8, F32],
+            #[cfg(feature = "sdk-v2")]
+            Self::RKNN_INT8_MM_INT4_TO_INT32 => [I8, I4, I32],
+        }
+    }
+}
+
+/// Element type of a matmul matrix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RknnMatmulElementType {
+    F16,
+    F32,
+    /// Signed 4 bits integers, packed two per byte as [`PackedI4`].
+    I4,
+    I8,
+    I16,
+    I32,
+}
+
+impl RknnMatmulElementType {
+    /// Size of an element, in bits.
+    pub fn bits(&self) -> usize {
+        match self {
+            Self::I4 => 4,
+            Self::I8 => 8,
+            Self::F16 | Self::I16 => 16,
+            Self::F32 | Self::I32 => 32,
+        }
+    }
+
+    /// Values of a matrix buffer, in the order they are stored.
+    pub fn decode(&self, buffer: &[u8]) -> Vec<f64> {
+        match self {
+            Self::F16 => buffer
+                .chunks_exact(2)
+                .map(|it| f16::from_le_bytes([it[0], it[1]]).to_f64())
+                .collect(),
+            Self::F32 => buffer
+                .chunks_exact(4)
+                .map(|it| f32::from_le_bytes([it[0], it[1], it[2], it[3]]) as f64)
+                .collect(),
+            Self::I4 => buffer
+                .iter()
+                .flat_map(|it| [PackedI4(*it).low(), PackedI4(*it).high()])
+                .map(f64::from)
+                .collect(),
+            Self::I8 => buffer.iter().map(|it| *it as i8 as f64).collect(),
+            Self::I16 => buffer
+                .chunks_exact(2)
+                .map(|it| i16::from_le_bytes([it[0], it[1]]) as f64)
+                .collect(),
+            Self::I32 => buffer
+                .chunks_exact(4)
+                .map(|it| i32::from_le_bytes([it[0], it[1], it[2], it[3]]) as f64)
+                .collect(),
+        }
+    }
+
+    /// Write matrix values to a buffer, rounding and saturating integers. Int4 values are packed
+    /// two per byte, as [`PackedI4`].
+    pub fn encode(&self, values: &[f64], buffer: &mut [u8]) {
+        let bytes: Vec<u8> = match self {
+            Self::F16 => values
+                .iter()
+                .flat_map(|it| f16::from_f64(*it).to_le_bytes())
+                .collect(),
+            Self::F32 => values
+                .iter()
+                .flat_map(|it| (*it as f32).to_le_bytes())
+                .collect(),
+            Self::I4 => values
+                .chunks(2)
+                .map(|it| {
+                    let [low, high] = [it[0], it.get(1).copied().unwrap_or(0.0)]
+                        .map(|it| it.round().clamp(-8.0, 7.0) as i8);
+                    PackedI4::new(low, high).0
+                })
+                .collect(),
+            Self::I8 => values.iter().map(|it| it.round() as i8 as u8).collect(),
+            Self::I16 => values
+                .iter()
+                .flat_map(|it| (it.round() as i16).to_le_bytes())
+                .collect(),
+            Self::I32 => values
+                .iter()
+                .flat_map(|it| (it.round() as i32).to_le_bytes())
+                .collect(),
+        };
+        for (dst, src) in buffer.iter_mut().zip(bytes) {
+            *dst = src;
+        }
+    }
+}
+
+/// Shape of a matmul, with A of shape `m x k`, B of shape `k x n` and C of shape `m x n`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RknnMatmulShape {
+    pub m: usize,
+    pub k: usize,
+    pub n: usize,
+}
+
+impl RknnMatmulShape {
+    pub const fn new(m: usize, k: usize, n: usize) -> Self {
+        Self { m, k, n }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -30,6 +243,12 @@ pub struct RknnMatmulInfo {
     mm_type: RknnMatmulType,
     b_native_layout: bool,
     ac_native_layout: bool,
+    #[cfg(feature = "sdk-v2")]
+    b_quant_type: RknnMatmulQuantType,
+    #[cfg(feature = "sdk-v2")]
+    ac_quant_type: RknnMatmulQuantType,
+    #[cfg(feature = "sdk-v2")]
+    group_size: usize,
 }
 
 impl RknnMatmulInfo {
@@ -48,10 +267,76 @@ impl RknnMatmulInfo {
             mm_type,
             b_native_layout,
             ac_native_layout,
+            #[cfg(feature = "sdk-v2")]
+            b_quant_type: RknnMatmulQuantType::RKNN_QUANT_TYPE_PER_LAYER_SYM,
+            #[cfg(feature = "sdk-v2")]
+            ac_quant_type: RknnMatmulQuantType::RKNN_QUANT_TYPE_PER_LAYER_SYM,
+            #[cfg(feature = "sdk-v2")]
+            group_size: 0,
         }
     }
+
+    /// Set the quantization of the B matrix and of the A and C matrices, per layer symmetric by
+    /// default. Per group quantization also needs a [group size](Self::with_group_size).
+    #[cfg(feature = "sdk-v2")]
+    pub fn with_quant_types(
+        mut self,
+        b_quant_type: RknnMatmulQuantType,
+        ac_quant_type: RknnMatmulQuantType,
+    ) -> Self {
+        self.b_quant_type = b_quant_type;
+        self.ac_quant_type = ac_quant_type;
+        self
+    }
+
+    /// Set the number of K values sharing a quantization parameter, for per group quantization.
+    #[cfg(feature = "sdk-v2")]
+    pub fn with_group_size(mut self, group_size: usize) -> Self {
+        self.group_size = group_size;
+        self
+    }
+
+    pub fn m(&self) -> usize {
+        self.m
+    }
+
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    pub fn n(&self) -> usize {
+        self.n
+    }
+
+    pub fn mm_type(&self) -> &RknnMatmulType {
+        &self.mm_type
+    }
+
+    pub fn b_native_layout(&self) -> bool {
+        self.b_native_layout
+    }
+
+    pub fn ac_native_layout(&self) -> bool {
+        self.ac_native_layout
+    }
+
+    #[cfg(feature = "sdk-v2")]
+    pub fn b_quant_type(&self) -> RknnMatmulQuantType {
+        self.b_quant_type
+    }
+
+    #[cfg(feature = "sdk-v2")]
+    pub fn ac_quant_type(&self) -> RknnMatmulQuantType {
+        self.ac_quant_type
+    }
+
+    #[cfg(feature = "sdk-v2")]
+    pub fn group_size(&self) -> usize {
+        self.group_size
+    }
 }
 
+#[cfg(not(feature = "sdk-v2"))]
 impl From<RknnMatmulInfo> for rknn_matmul_info {
     fn from(v: RknnMatmulInfo) -> Self {
         Self {
@@ -65,41 +350,104 @@ impl From<RknnMatmulInfo> for rknn_matmul_info {
     }
 }
 
-#[derive(Debug, Clone)]
+#[cfg(feature = "sdk-v2")]
+impl From<RknnMatmulInfo> for rknn_matmul_info {
+    fn from(v: RknnMatmulInfo) -> Self {
+        let mut info = unsafe { std::mem::zeroed::<rknn_matmul_info>() };
+        info.M = v.m as i32;
+        info.K = v.k as i32;
+        info.N = v.n as i32;
+        info.type_ = v.mm_type as _rknn_matmul_type;
+        info.B_layout = v.b_native_layout as i16;
+        info.B_quant_type = v.b_quant_type as i16;
+        info.AC_layout = v.ac_native_layout as i16;
+        info.AC_quant_type = v.ac_quant_type as i16;
+        info.group_size = v.group_size as i16;
+        info
+    }
+}
+
+/// A matrix multiplication context and its A, B and C buffers.
+///
+/// The matmul owns its runtime resources: they are freed when it is dropped or closed, and
+/// [`try_clone`](Self::try_clone) creates new ones. A matmul can be moved to another thread, but
+/// all the runtime calls take `&mut self` since the runtime doesn't allow concurrent calls on the
+/// same matmul context. Run several matmuls to use several threads.
 pub struct RknnMatmul {
+    driver: Arc<dyn RknnDriver>,
     ctx_ptr: rknn_matmul_ctx,
     infos: RknnMatmulInfo,
     io_attr: rknn_matmul_io_attr,
     a_buffer: NonNull<rknn_tensor_mem>,
     b_buffer: NonNull<rknn_tensor_mem>,
     c_buffer: NonNull<rknn_tensor_mem>,
+    /// Shapes a dynamic shape matmul was created with, and their matrix attributes.
+    #[cfg(feature = "sdk-v2")]
+    dynamic_shapes: Vec<(RknnMatmulShape, rknn_matmul_io_attr)>,
+    /// Whether the buffers are bound to the matmul context.
+    bound: bool,
+    /// Whether the runtime resources were already destroyed.
+    destroyed: bool,
 }
 
 impl RknnMatmul {
+    /// Create a matmul context, on the cores found the fastest for its shape, type and layouts
+    /// if they were [autotuned](MatmulAutotuner) in the default cache file.
     pub fn new(infos: RknnMatmulInfo) -> Result<Self> {
+        let core_mask = Self::cached_core_mask(&infos);
+        let mut matmul = Self::with_driver(infos, Arc::new(NativeDriver))?;
+        if let Some(core_mask) = core_mask.filter(|it| *it != RknnCoreMask::RKNN_NPU_CORE_AUTO) {
+            matmul.set_core_mask(core_mask)?;
+        }
+        Ok(matmul)
+    }
+
+    /// Create a matmul context through the given driver.
+    pub fn with_driver(infos: RknnMatmulInfo, driver: Arc<dyn RknnDriver>) -> Result<Self> {
         let mut ctx_ptr = unsafe { std::mem::zeroed::<rknn_matmul_ctx>() };
         let mut io_attr = unsafe { std::mem::zeroed::<rknn_matmul_io_attr>() };
         let mut rknn_input_infos: rknn_matmul_info = infos.clone().into();
-        let ret = unsafe { rknn_matmul_create(&mut ctx_ptr, &mut rknn_input_infos, &mut io_attr) };
+        let ret =
+            unsafe { driver.matmul_create(&mut ctx_ptr, &mut rknn_input_infos, &mut io_attr) };
         check_result(ret)?;
+        let sizes = [io_attr.A.size, io_attr.B.size, io_attr.C.size];
+        Self::from_context(driver, ctx_ptr, infos, io_attr, sizes)
+    }
 
-        let (a_buffer, b_buffer, c_buffer) = unsafe {
-            (
-                NonNull::new(rknn_create_mem(ctx_ptr, io_attr.A.size))
-                    .ok_or(anyhow!("Could not create memory buffer for matrix A"))?,
-                NonNull::new(rknn_create_mem(ctx_ptr, io_attr.B.size))
-                    .ok_or(anyhow!("Could not create memory buffer for matrix B"))?,
-                NonNull::new(rknn_create_mem(ctx_ptr, io_attr.C.size))
-                    .ok_or(anyhow!("Could not create memory buffer for matrix C"))?,
-            )
+    /// Allocate the matrix buffers of a created matmul context, destroying it on failure.
+    fn from_context(
+        driver: Arc<dyn RknnDriver>,
+        ctx_ptr: rknn_matmul_ctx,
+        infos: RknnMatmulInfo,
+        io_attr: rknn_matmul_io_attr,
+        sizes: [u32; 3],
+    ) -> Result<Self> {
+        let buffers = sizes.map(|size| NonNull::new(unsafe { driver.create_mem(ctx_ptr, size) }));
+        let [Some(a_buffer), Some(b_buffer), Some(c_buffer)] = buffers else {
+            // Release what was created before reporting the failure.
+            unsafe {
+                buffers.iter().flatten().for_each(|it| {
+                    driver.destroy_mem(ctx_ptr, it.as_ptr());
+                });
+                driver.matmul_destroy(ctx_ptr);
+            }
+            let matrix = ["A", "B", "C"][buffers.iter().position(Option::is_none).unwrap()];
+            return Err(anyhow!(
+                "Could not create memory buffer for matrix {matrix}"
+            ));
         };
         Ok(Self {
+            driver,
             ctx_ptr,
             infos,
             io_attr,
             a_buffer,
             b_buffer,
             c_buffer,
+            #[cfg(feature = "sdk-v2")]
+            dynamic_shapes: Vec::new(),
+            bound: false,
+            destroyed: false,
         })
     }
 
@@ -107,51 +455,162 @@ impl RknnMatmul {
         &self.infos
     }
 
+    /// Current shape of the matmul.
+    pub fn shape(&self) -> RknnMatmulShape {
+        RknnMatmulShape::new(self.infos.m, self.infos.k, self.infos.n)
+    }
+
+    /// Restrict the NPU cores running the matmul.
+    pub fn set_core_mask(&mut self, core_mask: RknnCoreMask) -> Result<()> {
+        let ret = unsafe {
+            self.driver
+                .matmul_set_core_mask(self.ctx_ptr, core_mask as rknn_core_mask)
+        };
+        check_result(ret)?;
+        Ok(())
+    }
+
+    /// Versions of the runtime and of the NPU driver running the matmul.
+    pub fn sdk_version(&self) -> Result<RknnSdkVersion> {
+        let mut raw = RknnSdkVersion::primitive_init_value();
+        let ret = unsafe {
+            self.driver.query(
+                self.ctx_ptr,
+                RknnSdkVersion::query_flag() as u32,
+                &mut raw as *mut _ as *mut c_void,
+                std::mem::size_of_val(&raw) as u32,
+            )
+        };
+        check_result(ret)?;
+        RknnSdkVersion::from_primitive_type(raw)
+    }
+
+    /// Byte sizes of the A, B and C buffers, as reported by the runtime for the matmul shape, type
+    /// and layouts.
+    pub fn buffer_sizes(&self) -> [usize; 3] {
+        [
+            self.io_attr.A.size,
+            self.io_attr.B.size,
+            self.io_attr.C.size,
+        ]
+        .map(|it| it as usize)
+    }
+
+    /// Create a new matmul context with the same configuration, and copy the content of the
+    /// matrix buffers to it.
+    pub fn try_clone(&self) -> Result<Self> {
+        let clone = self.create_like()?;
+        let buffers = [
+            (self.a_buffer, clone.a_buffer),
+            (self.b_buffer, clone.b_buffer),
+            (self.c_buffer, clone.c_buffer),
+        ];
+        for (src, dst) in buffers {
+            unsafe {
+                copy_nonoverlapping(
+                    (*src.as_ptr()).virt_addr as *const u8,
+                    (*dst.as_ptr()).virt_addr as *mut u8,
+                    (*src.as_ptr()).size.min((*dst.as_ptr()).size) as usize,
+                );
+            }
+        }
+        Ok(clone)
+    }
+
+    /// Create a matmul context with the same configuration, and the same current shape for
+    /// dynamic shape matmuls.
+    #[cfg(not(feature = "sdk-v2"))]
+    fn create_like(&self) -> Result<Self> {
+        Self::with_driver(self.infos.clone(), Arc::clone(&self.driver))
+    }
+
+    #[cfg(feature = "sdk-v2")]
+    fn create_like(&self) -> Result<Self> {
+        if self.dynamic_shapes.is_empty() {
+            return Self::with_driver(self.infos.clone(), Arc::clone(&self.driver));
+        }
+        let shapes = self
+            .dynamic_shapes
+            .iter()
+            .map(|it| it.0)
+            .collect::<Vec<_>>();
+        let mut clone =
+            Self::new_dynamic_with_driver(self.infos.clone(), &shapes, Arc::clone(&self.driver))?;
+        clone.set_shape(self.shape())?;
+        Ok(clone)
+    }
+
+    /// Copy the raw A and B matrices to the NPU buffers. The slices must have the sizes expected
+    /// by the runtime for the matmul shape, type and layouts.
     pub fn set_inputs(&mut self, a: &[u8], b: &[u8]) -> Result<()> {
+        self.set_a(a)?;
+        self.set_b(b)?;
+        self.bind()
+    }
+
+    /// Copy the raw A matrix to its NPU buffer.
+    ///
+    /// Buffers stay bound to the matmul until B is set again, so a resident B matrix only needs
+    /// to be copied once, and each following run only copies A.
+    pub fn set_a(&mut self, a: &[u8]) -> Result<()> {
+        check_size('A', self.io_attr.A.size, a.len())?;
+        unsafe { copy_to_buffer(a, self.a_buffer) };
+        Ok(())
+    }
+
+    /// Copy the raw B matrix to its NPU buffer. The runtime converts a normal layout B matrix
+    /// when its buffer is bound, so the buffers are bound again by the next [`exec`](Self::exec).
+    pub fn set_b(&mut self, b: &[u8]) -> Result<()> {
+        check_size('B', self.io_attr.B.size, b.len())?;
+        unsafe { copy_to_buffer(b, self.b_buffer) };
+        self.bound = false;
+        Ok(())
+    }
+
+    /// Bind the A, B and C buffers to the matmul context. This is needed again after setting B,
+    /// [`exec`] binds them itself if they are not bound.
+    ///
+    /// [`exec`]: Self::exec
+    pub fn bind(&mut self) -> Result<()> {
         unsafe {
-            copy_nonoverlapping(
-                a.as_ptr() as *mut c_void,
-                (*self.a_buffer.as_ptr()).virt_addr,
-                (*self.a_buffer.as_ptr()).size as usize,
-            );
-            copy_nonoverlapping(
-                b.as_ptr() as *mut c_void,
-                (*self.b_buffer.as_ptr()).virt_addr,
-                (*self.b_buffer.as_ptr()).size as usize,
-            );
-            check_result(rknn_matmul_set_io_mem(
+            check_result(self.driver.matmul_set_io_mem(
                 self.ctx_ptr,
                 self.a_buffer.as_mut(),
                 &mut self.io_attr.A,
             ))?;
-            check_result(rknn_matmul_set_io_mem(
+            check_result(self.driver.matmul_set_io_mem(
                 self.ctx_ptr,
                 self.b_buffer.as_mut(),
                 &mut self.io_attr.B,
             ))?;
-            check_result(rknn_matmul_set_io_mem(
+            check_result(self.driver.matmul_set_io_mem(
                 self.ctx_ptr,
                 self.c_buffer.as_mut(),
                 &mut self.io_attr.C,
             ))?;
         };
-
+        self.bound = true;
         Ok(())
     }
 
+    /// Copy the raw C matrix from the NPU buffer.
     pub fn get_output(&mut self, c: &mut [u8]) -> Result<()> {
+        check_size('C', self.io_attr.C.size, c.len())?;
         unsafe {
             copy_nonoverlapping(
                 (*self.c_buffer.as_ptr()).virt_addr,
                 c.as_ptr() as *mut c_void,
-                (*self.c_buffer.as_ptr()).size as usize,
+                c.len(),
             );
         };
         Ok(())
     }
 
     pub fn exec(&mut self) -> Result<()> {
-        let ret = unsafe { rknn_matmul_run(self.ctx_ptr) };
+        if !self.bound {
+            self.bind()?;
+        }
+        let ret = unsafe { self.driver.matmul_run(self.ctx_ptr) };
         check_result(ret)?;
         Ok(())
     }
@@ -162,17 +621,92 @@ impl RknnMatmul {
         self.get_output(c)?;
         Ok(())
     }
+
+    /// Free the matrix buffers and destroy the matmul context, returning the first runtime error
+    /// if any. Dropping the matmul frees them too, but can only log errors.
+    pub fn close(mut self) -> Result<()> {
+        self.destroy()?;
+        Ok(())
+    }
+
+    fn destroy(&mut self) -> Result<(), RknnError> {
+        if self.destroyed {
+            return Ok(());
+        }
+        self.destroyed = true;
+        // Every resource is released even if one of them fails.
+        let results = unsafe {
+            [
+                self.driver
+                    .destroy_mem(self.ctx_ptr, self.a_buffer.as_ptr()),
+                self.driver
+                    .destroy_mem(self.ctx_ptr, self.b_buffer.as_ptr()),
+                self.driver
+                    .destroy_mem(self.ctx_ptr, self.c_buffer.as_ptr()),
+                self.driver.matmul_destroy(self.ctx_ptr),
+            ]
+        };
+        results.into_iter().try_for_each(check_result)
+    }
+}
+
+fn check_size(matrix: char, expected: u32, actual: usize) -> Result<(), RknnMatmulError> {
+    if expected as usize != actual {
+        return Err(RknnMatmulError::SizeMismatch {
+            matrix,
+            expected: expected as usize,
+            actual,
+        });
+    }
+    Ok(())
+}
+
+/// Copy `data` to a runtime buffer of at least the same size.
+unsafe fn copy_to_buffer(data: &[u8], buffer: NonNull<rknn_tensor_mem>) {
+    copy_nonoverlapping(
+        data.as_ptr() as *const c_void,
+        (*buffer.as_ptr()).virt_addr,
+        data.len(),
+    );
+}
+
+// The matmul context and buffers are owned by the matmul and not tied to the creating thread.
+unsafe impl Send for RknnMatmul {}
+
+impl Drop for RknnMatmul {
+    fn drop(&mut self) {
+        if let Err(error) = self.destroy() {
+            log::error!("Failed to destroy RKNN matmul context: {error}");
+        }
+    }
+}
+
+impl fmt::Debug for RknnMatmul {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RknnMatmul")
+            .field("ctx_ptr", &self.ctx_ptr)
+            .field("infos", &self.infos)
+            .finish_non_exhaustive()
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use std::ops::{AddAssign, Mul};
+    use std::{
+        ops::{AddAssign, Mul},
+        sync::Arc,
+    };
 
     use half::f16;
+    use rknpu_sys::RKNN_ERR_CTX_INVALID;
+
+    use crate::driver::stub::{StubCall, StubDriver};
 
     use super::{RknnMatmul, RknnMatmulInfo, RknnMatmulType};
 
-    fn matmul<T: Mul<T, Output = T> + Default + AddAssign<T> + Into<f32> + Copy + Clone>(
+    pub(super) fn matmul<
+        T: Mul<T, Output = T> + Default + AddAssign<T> + Into<f32> + Copy + Clone,
+    >(
         a: &[T],
         b: &[T],
         m: usize,
@@ -255,4 +789,108 @@ mod test {
         let ref_c = matmul(a.as_slice(), b.as_slice(), m, k, n);
         assert_eq!(c, ref_c);
     }
+
+    fn stub_matmul(driver: &Arc<StubDriver>) -> RknnMatmul {
+        let infos = RknnMatmulInfo::new(
+            4,
+            32,
+            32,
+            RknnMatmulType::RKNN_FLOAT16_MM_FLOAT16_TO_FLOAT32,
+            false,
+            false,
+        );
+        RknnMatmul::with_driver(infos, Arc::clone(driver) as _).unwrap()
+    }
+
+    #[test]
+    fn test_matmul_close() {
+        let driver = Arc::new(StubDriver::matmul());
+        let rknn_matmul = stub_matmul(&driver);
+        assert_eq!((driver.live_matmuls(), driver.live_mems()), (1, 3));
+        rknn_matmul.close().unwrap();
+        assert_eq!((driver.live_matmuls(), driver.live_mems()), (0, 0));
+    }
+
+    #[test]
+    fn test_matmul_close_error() {
+        let driver = Arc::new(StubDriver::matmul());
+        let rknn_matmul = stub_matmul(&driver);
+        driver.fail_on(StubCall::MatmulDestroy, RKNN_ERR_CTX_INVALID);
+        assert!(rknn_matmul.close().is_err());
+        // Buffers are freed even if the context can't be destroyed.
+        assert_eq!(driver.live_mems(), 0);
+    }
+
+    #[test]
+    fn test_matmul_drop_error() {
+        let driver = Arc::new(StubDriver::matmul());
+        let rknn_matmul = stub_matmul(&driver);
+        driver.fail_on(StubCall::DestroyMem, RKNN_ERR_CTX_INVALID);
+        drop(rknn_matmul);
+        assert_eq!(driver.live_matmuls(), 0);
+    }
+
+    #[test]
+    fn test_matmul_try_clone() {
+        fn assert_send<T: Send>() {}
+        assert_send::<RknnMatmul>();
+
+        let driver = Arc::new(StubDriver::matmul());
+        let rknn_matmul = stub_matmul(&driver);
+        let clone = rknn_matmul.try_clone().unwrap();
+        assert_eq!((driver.live_matmuls(), driver.live_mems()), (2, 6));
+        drop(rknn_matmul);
+        assert_eq!((driver.live_matmuls(), driver.live_mems()), (1, 3));
+        drop(clone);
+        assert_eq!((driver.live_matmuls(), driver.live_mems()), (0, 0));
+    }
+
+    #[test]
+    fn test_matmul_set_b() {
+        let driver = Arc::new(StubDriver::matmul());
+        let mut rknn_matmul = stub_matmul(&driver);
+        let a = f16_bytes(&[1.0; 4 * 32]);
+        let mut c = vec![0; 4 * 32 * 4];
+        rknn_matmul
+            .run(&a, &f16_bytes(&[1.0; 32 * 32]), &mut c)
+            .unwrap();
+        assert_eq!(f32_values(&c)[0], 32.0);
+
+        // A new B is converted again by the runtime, not only copied.
+        rknn_matmul.set_b(&f16_bytes(&[2.0; 32 * 32])).unwrap();
+        rknn_matmul.exec().unwrap();
+        rknn_matmul.get_output(&mut c).unwrap();
+        assert_eq!(f32_values(&c)[0], 64.0);
+        assert_eq!(driver.call_count(StubCall::MatmulSetIoMem), 6);
+    }
+
+    fn f16_bytes(values: &[f32]) -> Vec<u8> {
+        values
+            .iter()
+            .flat_map(|it| f16::from_f32(*it).to_le_bytes())
+            .collect()
+    }
+
+    fn f32_values(bytes: &[u8]) -> Vec<f32> {
+        bytes
+            .chunks_exact(4)
+            .map(|it| f32::from_le_bytes(it.try_into().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_matmul_create_error() {
+        let driver = Arc::new(StubDriver::matmul());
+        driver.fail_on(StubCall::CreateMem, RKNN_ERR_CTX_INVALID);
+        let infos = RknnMatmulInfo::new(
+            4,
+            32,
+            32,
+            RknnMatmulType::RKNN_FLOAT16_MM_FLOAT16_TO_FLOAT32,
+            false,
+            false,
+        );
+        assert!(RknnMatmul::with_driver(infos, Arc::clone(&driver) as _).is_err());
+        assert_eq!((driver.live_matmuls(), driver.live_mems()), (0, 0));
+    }
 }
@@ -0,0 +1,300 @@
+use anyhow::Result;
+use rknpu_sys::{
+    _rknn_matmul_quant_type_RKNN_QUANT_TYPE_PER_CHANNEL_ASYM,
+    _rknn_matmul_quant_type_RKNN_QUANT_TYPE_PER_CHANNEL_SYM,
+    _rknn_matmul_quant_type_RKNN_QUANT_TYPE_PER_GROUP_ASYM,
+    _rknn_matmul_quant_type_RKNN_QUANT_TYPE_PER_GROUP_SYM,
+    _rknn_matmul_quant_type_RKNN_QUANT_TYPE_PER_LAYER_ASYM,
+    _rknn_matmul_quant_type_RKNN_QUANT_TYPE_PER_LAYER_SYM, rknn_matmul_tensor_attr,
+    rknn_quant_params,
+};
+
+use crate::error::{check_result, RknnMatmulError};
+
+use super::RknnMatmul;
+
+/// Granularity of the quantization parameters of a matrix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[allow(non_camel_case_types)]
+#[repr(u32)]
+pub enum RknnMatmulQuantType {
+    /// One scale for the whole matrix, zero points are ignored.
+    RKNN_QUANT_TYPE_PER_LAYER_SYM = _rknn_matmul_quant_type_RKNN_QUANT_TYPE_PER_LAYER_SYM,
+    /// One scale and zero point for the whole matrix.
+    RKNN_QUANT_TYPE_PER_LAYER_ASYM = _rknn_matmul_quant_type_RKNN_QUANT_TYPE_PER_LAYER_ASYM,
+    /// One scale per column of B.
+    RKNN_QUANT_TYPE_PER_CHANNEL_SYM = _rknn_matmul_quant_type_RKNN_QUANT_TYPE_PER_CHANNEL_SYM,
+    /// One scale and zero point per column of B.
+    RKNN_QUANT_TYPE_PER_CHANNEL_ASYM = _rknn_matmul_quant_type_RKNN_QUANT_TYPE_PER_CHANNEL_ASYM,
+    /// One scale per group of `group_size` rows in each column of B.
+    RKNN_QUANT_TYPE_PER_GROUP_SYM = _rknn_matmul_quant_type_RKNN_QUANT_TYPE_PER_GROUP_SYM,
+    /// One scale and zero point per group of `group_size` rows in each column of B.
+    RKNN_QUANT_TYPE_PER_GROUP_ASYM = _rknn_matmul_quant_type_RKNN_QUANT_TYPE_PER_GROUP_ASYM,
+}
+
+/// Quantization parameters of a matrix: a real value is `(quantized - zero_point) * scale`.
+///
+/// Per group parameters are ordered by column of B, then by group of rows.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RknnQuantParams {
+    pub scales: Vec<f32>,
+    pub zero_points: Vec<i32>,
+}
+
+impl RknnQuantParams {
+    pub fn new(scales: Vec<f32>, zero_points: Vec<i32>) -> Self {
+        Self {
+            scales,
+            zero_points,
+        }
+    }
+
+    /// Parameters of a per layer quantization.
+    pub fn per_layer(scale: f32, zero_point: i32) -> Self {
+        Self::new(vec![scale], vec![zero_point])
+    }
+
+    /// Symmetric parameters, with zero points all set to 0.
+    pub fn symmetric(scales: Vec<f32>) -> Self {
+        let zero_points = vec![0; scales.len()];
+        Self::new(scales, zero_points)
+    }
+}
+
+impl RknnMatmul {
+    /// Set the quantization parameters of the A matrix, quantized per layer.
+    pub fn set_a_quant_params(&mut self, params: &RknnQuantParams) -> Result<()> {
+        self.check_quant_params('A', params, 1)?;
+        let attr = self.io_attr.A;
+        self.set_quant_params(&attr, params)
+    }
+
+    /// Set the quantization parameters of the B matrix, with as many parameters as the
+    /// [quantization type](super::RknnMatmulInfo::b_quant_type) of B requires. Without a group
+    /// size, per group quantization uses a single group per column.
+    pub fn set_b_quant_params(&mut self, params: &RknnQuantParams) -> Result<()> {
+        self.check_quant_params('B', params, self.b_quant_params_len())?;
+        let attr = self.io_attr.B;
+        self.set_quant_params(&attr, params)
+    }
+
+    /// Set the quantization parameters of the C matrix, quantized per layer.
+    pub fn set_c_quant_params(&mut self, params: &RknnQuantParams) -> Result<()> {
+        self.check_quant_params('C', params, 1)?;
+        let attr = self.io_attr.C;
+        self.set_quant_params(&attr, params)
+    }
+
+    /// Number of quantization parameters of the B matrix.
+    fn b_quant_params_len(&self) -> usize {
+        let infos = &self.infos;
+        match infos.b_quant_type {
+            RknnMatmulQuantType::RKNN_QUANT_TYPE_PER_LAYER_SYM
+            | RknnMatmulQuantType::RKNN_QUANT_TYPE_PER_LAYER_ASYM => 1,
+            RknnMatmulQuantType::RKNN_QUANT_TYPE_PER_CHANNEL_SYM
+            | RknnMatmulQuantType::RKNN_QUANT_TYPE_PER_CHANNEL_ASYM => infos.n,
+            RknnMatmulQuantType::RKNN_QUANT_TYPE_PER_GROUP_SYM
+            | RknnMatmulQuantType::RKNN_QUANT_TYPE_PER_GROUP_ASYM => match infos.group_size {
+                0 => infos.n,
+                group_size => infos.n * infos.k.div_ceil(group_size),
+            },
+        }
+    }
+
+    fn check_quant_params(
+        &self,
+        matrix: char,
+        params: &RknnQuantParams,
+        expected: usize,
+    ) -> Result<(), RknnMatmulError> {
+        let (scales, zero_points) = (params.scales.len(), params.zero_points.len());
+        if scales != zero_points {
+            return Err(RknnMatmulError::QuantParamsMismatch {
+                matrix,
+                scales,
+                zero_points,
+            });
+        }
+        if expected != scales {
+            return Err(RknnMatmulError::QuantParamsLength {
+                matrix,
+                expected,
+                actual: scales,
+            });
+        }
+        Ok(())
+    }
+
+    fn set_quant_params(
+        &mut self,
+        attr: &rknn_matmul_tensor_attr,
+        params: &RknnQuantParams,
+    ) -> Result<()> {
+        // The runtime only reads the parameters, they are copied to keep the inputs immutable.
+        let mut scales = params.scales.clone();
+        let mut zero_points = params.zero_points.clone();
+        let mut raw = unsafe { std::mem::zeroed::<rknn_quant_params>() };
+        raw.name = attr.name;
+        raw.scale = scales.as_mut_ptr();
+        raw.scale_len = scales.len() as i32;
+        raw.zp = zero_points.as_mut_ptr();
+        raw.zp_len = zero_points.len() as i32;
+        let ret = unsafe { self.driver.matmul_set_quant_params(self.ctx_ptr, &mut raw) };
+        check_result(ret)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use half::f16;
+
+    use crate::{
+        driver::stub::StubDriver,
+        error::RknnMatmulError,
+        matmul::{RknnMatmul, RknnMatmulInfo, RknnMatmulType},
+    };
+
+    use super::{RknnMatmulQuantType, RknnQuantParams};
+
+    fn stub_matmul(
+        (m, k, n): (usize, usize, usize),
+        mm_type: RknnMatmulType,
+        b_quant_type: RknnMatmulQuantType,
+    ) -> RknnMatmul {
+        let infos = RknnMatmulInfo::new(m, k, n, mm_type, false, false).with_quant_types(
+            b_quant_type,
+            RknnMatmulQuantType::RKNN_QUANT_TYPE_PER_LAYER_SYM,
+        );
+        RknnMatmul::with_driver(infos, Arc::new(StubDriver::matmul())).unwrap()
+    }
+
+    #[test]
+    fn test_per_channel_b() {
+        let (m, k, n) = (2, 32, 16);
+        let mut matmul = stub_matmul(
+            (m, k, n),
+            RknnMatmulType::RKNN_FLOAT16_MM_INT8_TO_FLOAT32,
+            RknnMatmulQuantType::RKNN_QUANT_TYPE_PER_CHANNEL_SYM,
+        );
+        let scales = (0..n).map(|it| 0.25 * (it + 1) as f32).collect::<Vec<_>>();
+        matmul
+            .set_b_quant_params(&RknnQuantParams::symmetric(scales.clone()))
+            .unwrap();
+
+        let a = (0..m * k)
+            .map(|it| f16::from_f32((it % 3) as f32))
+            .collect::<Vec<_>>();
+        let b = (0..k * n).map(|it| (it % 5) as i8 - 2).collect::<Vec<_>>();
+        let raw_a = a.iter().flat_map(|it| it.to_le_bytes()).collect::<Vec<_>>();
+        let raw_b = b.iter().map(|it| *it as u8).collect::<Vec<_>>();
+        let mut raw_c = vec![0; m * n * 4];
+        matmul.run(&raw_a, &raw_b, &mut raw_c).unwrap();
+
+        let c = raw_c
+            .chunks_exact(4)
+            .map(|it| f32::from_le_bytes(it.try_into().unwrap()))
+            .collect::<Vec<_>>();
+        let expected = (0..m * n)
+            .map(|idx| {
+                let (i, j) = (idx / n, idx % n);
+                (0..k)
+                    .map(|l| a[i * k + l].to_f32() * b[l * n + j] as f32 * scales[j])
+                    .sum::<f32>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(c, expected);
+    }
+
+    #[test]
+    fn test_per_group_b() {
+        let (m, k, n) = (1, 64, 2);
+        let infos = RknnMatmulInfo::new(
+            m,
+            k,
+            n,
+            RknnMatmulType::RKNN_FLOAT16_MM_INT8_TO_FLOAT32,
+            false,
+            false,
+        )
+        .with_quant_types(
+            RknnMatmulQuantType::RKNN_QUANT_TYPE_PER_GROUP_ASYM,
+            RknnMatmulQuantType::RKNN_QUANT_TYPE_PER_LAYER_SYM,
+        )
+        .with_group_size(32);
+        let mut matmul = RknnMatmul::with_driver(infos, Arc::new(StubDriver::matmul())).unwrap();
+        // Column 0 has groups scaled by 1 and 2, column 1 by 3 and 4, all with a zero point of 1.
+        let params = RknnQuantParams::new(vec![1.0, 2.0, 3.0, 4.0], vec![1; 4]);
+        matmul.set_b_quant_params(&params).unwrap();
+
+        let raw_a = [f16::ONE.to_le_bytes(); 64].concat();
+        let raw_b = vec![3; k * n];
+        let mut raw_c = vec![0; m * n * 4];
+        matmul.run(&raw_a, &raw_b, &mut raw_c).unwrap();
+        let c = raw_c
+            .chunks_exact(4)
+            .map(|it| f32::from_le_bytes(it.try_into().unwrap()))
+            .collect::<Vec<_>>();
+        // Each group sums 32 values of (3 - 1) * scale.
+        assert_eq!(c, vec![64.0 * 3.0, 64.0 * 7.0]);
+    }
+
+    #[test]
+    fn test_requantized_c() {
+        let (m, k, n) = (2, 32, 16);
+        let mut matmul = stub_matmul(
+            (m, k, n),
+            RknnMatmulType::RKNN_INT8_MM_INT8_TO_INT8,
+            RknnMatmulQuantType::RKNN_QUANT_TYPE_PER_LAYER_SYM,
+        );
+        matmul
+            .set_c_quant_params(&RknnQuantParams::per_layer(4.0, 3))
+            .unwrap();
+        let raw_a = vec![1_i8 as u8; m * k];
+        let raw_b = vec![2_i8 as u8; k * n];
+        let mut raw_c = vec![0; m * n];
+        matmul.run(&raw_a, &raw_b, &mut raw_c).unwrap();
+        // 32 * 2 = 64, requantized to 64 / 4 + 3.
+        assert!(raw_c.iter().all(|it| *it as i8 == 19));
+
+        // A dequantized to (1 - 3) * 0.5 = -1.
+        matmul
+            .set_a_quant_params(&RknnQuantParams::per_layer(0.5, 3))
+            .unwrap();
+        matmul.run(&raw_a, &raw_b, &mut raw_c).unwrap();
+        assert!(raw_c.iter().all(|it| *it as i8 == -13));
+    }
+
+    #[test]
+    fn test_invalid_quant_params() {
+        let mut matmul = stub_matmul(
+            (2, 64, 16),
+            RknnMatmulType::RKNN_FLOAT16_MM_INT4_TO_FLOAT32,
+            RknnMatmulQuantType::RKNN_QUANT_TYPE_PER_GROUP_ASYM,
+        );
+        let params = RknnQuantParams::new(vec![1.0; 16], vec![0; 8]);
+        let error = matmul.set_b_quant_params(&params).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<RknnMatmulError>(),
+            Some(&RknnMatmulError::QuantParamsMismatch {
+                matrix: 'B',
+                scales: 16,
+                zero_points: 8
+            })
+        );
+
+        // Without a group size, each column is a single group.
+        let params = RknnQuantParams::symmetric(vec![1.0; 32]);
+        let error = matmul.set_b_quant_params(&params).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<RknnMatmulError>(),
+            Some(&RknnMatmulError::QuantParamsLength {
+                matrix: 'B',
+                expected: 16,
+                actual: 32
+            })
+        );
+    }
+}
@@ -0,0 +1,442 @@
+//! Integer matmuls over f32 matrices, quantized and dequantized on the CPU.
+
+use std::sync::Arc;
+
+use anyhow::{ensure, Result};
+
+use crate::{driver::RknnDriver, error::RknnMatmulError};
+
+use super::{Matmul, MatmulElement, MatmulTypes, NativeLayout, PackedI4, RknnMatmulShape};
+
+/// Integer element types of the A and B matrices of a quantized matmul producing `C` elements.
+pub trait QuantizedTypes<C: MatmulElement>: MatmulTypes<C> {
+    /// Smallest quantized value.
+    const MIN: i32;
+    /// Largest quantized value.
+    const MAX: i32;
+    /// Largest `k` whose sums of products always fit in a `C` element.
+    const MAX_K: usize;
+
+    /// Convert quantized values, in `MIN..=MAX`, to matrix elements.
+    fn encode(values: &[i8]) -> Vec<Self>;
+}
+
+impl QuantizedTypes<i32> for i8 {
+    const MIN: i32 = i8::MIN as i32;
+    const MAX: i32 = i8::MAX as i32;
+    const MAX_K: usize = i32::MAX as usize / (128 * 128);
+
+    fn encode(values: &[i8]) -> Vec<Self> {
+        values.to_vec()
+    }
+}
+
+impl QuantizedTypes<i16> for PackedI4 {
+    const MIN: i32 = -8;
+    const MAX: i32 = 7;
+    const MAX_K: usize = i16::MAX as usize / (8 * 8);
+
+    fn encode(values: &[i8]) -> Vec<Self> {
+        PackedI4::pack(values)
+    }
+}
+
+/// How the f32 values are mapped to integers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QuantScheme {
+    /// `x = scale * q`, the range is centered on zero.
+    #[default]
+    Symmetric,
+    /// `x = scale * (q - zero_point)`, the range covers the minimum and maximum values.
+    Asymmetric,
+}
+
+/// Values sharing the same scale and zero point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QuantGranularity {
+    /// One scale for a whole matrix.
+    PerTensor,
+    /// One scale per row of A and per column of B, that is per output row and column.
+    #[default]
+    PerRow,
+}
+
+/// Quantization of a matrix, by line: the rows of A or the columns of B.
+#[derive(Debug, Clone)]
+struct Quantized {
+    values: Vec<i8>,
+    scales: Vec<f32>,
+    zero_points: Vec<i32>,
+    /// Sum of the quantized values of each line, used to remove the zero points from the
+    /// products.
+    sums: Vec<i32>,
+}
+
+/// Matrix multiplication `C = A x B` of f32 matrices, run as an int8 (`QuantizedMatmul<i8, i32>`)
+/// or int4 (`QuantizedMatmul<PackedI4, i16>`) matmul on the NPU.
+///
+/// A and B are quantized with scales computed from their values, and the integer products are
+/// dequantized to f32. The int4 products are summed in 16 bits by the NPU, which limits `k` to
+/// [`QuantizedTypes::MAX_K`]. The quantized matrices are padded with zeros to the block sizes of
+/// the NPU, like in [`TiledMatmul`](super::TiledMatmul).
+#[derive(Debug)]
+pub struct QuantizedMatmul<A, C> {
+    /// Shape of the f32 matrices, the integer matmul having padded `k` and `n`.
+    shape: RknnMatmulShape,
+    matmul: Matmul<A, C>,
+    scheme: QuantScheme,
+    granularity: QuantGranularity,
+    /// Quantization of the B matrix uploaded to the NPU.
+    b: Option<Quantized>,
+}
+
+impl<A, C> QuantizedMatmul<A, C>
+where
+    A: QuantizedTypes<C>,
+    C: MatmulElement + Into<i32>,
+{
+    /// Create a quantized matmul, using symmetric per row scales.
+    pub fn new(m: usize, k: usize, n: usize) -> Result<Self> {
+        let [k_padded, n_padded] = Self::padded(k, n)?;
+        let matmul = Matmul::new(m, k_padded, n_padded)?;
+        Ok(Self::from_matmul(RknnMatmulShape::new(m, k, n), matmul))
+    }
+
+    /// Create a quantized matmul through the given driver.
+    pub fn with_driver(m: usize, k: usize, n: usize, driver: Arc<dyn RknnDriver>) -> Result<Self> {
+        let [k_padded, n_padded] = Self::padded(k, n)?;
+        let matmul = Matmul::with_driver(m, k_padded, n_padded, driver)?;
+        Ok(Self::from_matmul(RknnMatmulShape::new(m, k, n), matmul))
+    }
+
+    /// `k` and `n` padded to the block sizes of the B matrix, checking that `k` products can be
+    /// summed.
+    fn padded(k: usize, n: usize) -> Result<[usize; 2], RknnMatmulError> {
+        if k > A::MAX_K {
+            return Err(RknnMatmulError::DepthTooLarge {
+                mm_type: A::MM_TYPE,
+                k,
+                max: A::MAX_K,
+            });
+        }
+        let layout = NativeLayout::new(&A::MM_TYPE);
+        Ok([
+            k.next_multiple_of(layout.b_sub_k),
+            n.next_multiple_of(layout.b_sub_n),
+        ])
+    }
+
+    fn from_matmul(shape: RknnMatmulShape, matmul: Matmul<A, C>) -> Self {
+        Self {
+            shape,
+            matmul,
+            scheme: QuantScheme::default(),
+            granularity: QuantGranularity::default(),
+            b: None,
+        }
+    }
+
+    /// Use another quantization for the matrices set from now on.
+    pub fn with_quantization(mut self, scheme: QuantScheme, granularity: QuantGranularity) -> Self {
+        self.scheme = scheme;
+        self.granularity = granularity;
+        self
+    }
+
+    /// Shape of the f32 matrices.
+    pub fn shape(&self) -> RknnMatmulShape {
+        self.shape
+    }
+
+    /// Underlying integer matmul, of padded `k` and `n`.
+    pub fn as_typed(&mut self) -> &mut Matmul<A, C> {
+        &mut self.matmul
+    }
+
+    /// Quantize the `k x n` B matrix and copy it to the NPU, where it stays for the next calls to
+    /// [`run_a`](Self::run_a).
+    pub fn set_b(&mut self, b: &[f32]) -> Result<()> {
+        let RknnMatmulShape { k, n, .. } = self.shape;
+        check_len('B', k * n, b.len())?;
+        let quantized = self.quantize(b, n, |idx| idx % n);
+        let infos = self.matmul.infos();
+        let values = padded(&quantized.values, n, infos.k(), infos.n());
+        self.matmul.set_b(&A::encode(&values))?;
+        self.b = Some(quantized);
+        Ok(())
+    }
+
+    /// Multiply the `m x k` A matrix by the B matrix set last, into the `m x n` C matrix.
+    pub fn run_a(&mut self, a: &[f32], c: &mut [f32]) -> Result<()> {
+        let RknnMatmulShape { m, k, n } = self.shape;
+        let (k_padded, n_padded) = (self.matmul.infos().k(), self.matmul.infos().n());
+        check_len('A', m * k, a.len())?;
+        check_len('C', m * n, c.len())?;
+        ensure!(
+            self.b.is_some(),
+            "The B matrix of the quantized matmul is not set"
+        );
+
+        let qa = self.quantize(a, m, |idx| idx / k);
+        self.matmul
+            .set_a(&A::encode(&padded(&qa.values, k, m, k_padded)))?;
+        self.matmul.exec()?;
+        let mut raw = vec![C::default(); m * n_padded];
+        self.matmul.get_output(&mut raw)?;
+
+        let qb = self.b.as_ref().unwrap();
+        for (idx, out) in c.iter_mut().enumerate() {
+            let (i, j) = (idx / n, idx % n);
+            let raw = raw[i * n_padded + j];
+            let (za, zb) = (qa.zero_points[i] as i64, qb.zero_points[j] as i64);
+            // sum((qa - za) * (qb - zb)) from sum(qa * qb) computed by the NPU.
+            let acc = raw.into() as i64 - zb * qa.sums[i] as i64 - za * qb.sums[j] as i64
+                + k as i64 * za * zb;
+            *out = qa.scales[i] * qb.scales[j] * acc as f32;
+        }
+        Ok(())
+    }
+
+    /// Multiply the `m x k` A matrix by the `k x n` B matrix into the `m x n` C matrix.
+    pub fn run(&mut self, a: &[f32], b: &[f32], c: &mut [f32]) -> Result<()> {
+        self.set_b(b)?;
+        self.run_a(a, c)
+    }
+
+    /// Free the runtime resources, see [`RknnMatmul::close`](super::RknnMatmul::close).
+    pub fn close(self) -> Result<()> {
+        self.matmul.close()
+    }
+
+    /// Quantize a matrix made of `lines` lines, `line` giving the line of a value index.
+    fn quantize(&self, values: &[f32], lines: usize, line: impl Fn(usize) -> usize) -> Quantized {
+        let groups = match self.granularity {
+            QuantGranularity::PerTensor => 1,
+            QuantGranularity::PerRow => lines,
+        };
+        let group = |idx| match self.granularity {
+            QuantGranularity::PerTensor => 0,
+            QuantGranularity::PerRow => line(idx),
+        };
+
+        let mut ranges = vec![(0.0_f32, 0.0_f32); groups];
+        for (idx, value) in values.iter().enumerate() {
+            let range = &mut ranges[group(idx)];
+            *range = (range.0.min(*value), range.1.max(*value));
+        }
+        let params = ranges
+            .into_iter()
+            .map(|(min, max)| quant_params(self.scheme, min, max, A::MIN, A::MAX))
+            .collect::<Vec<_>>();
+
+        let mut sums = vec![0; lines];
+        let values = values
+            .iter()
+            .enumerate()
+            .map(|(idx, value)| {
+                let (scale, zero_point) = params[group(idx)];
+                let q = ((value / scale).round() as i32 + zero_point).clamp(A::MIN, A::MAX);
+                sums[line(idx)] += q;
+                q as i8
+            })
+            .collect();
+        let (scales, zero_points) = (0..lines).map(|it| params[it.min(groups - 1)]).unzip();
+        Quantized {
+            values,
+            scales,
+            zero_points,
+            sums,
+        }
+    }
+}
+
+/// Scale and zero point mapping the `min..=max` values to `qmin..=qmax`.
+fn quant_params(scheme: QuantScheme, min: f32, max: f32, qmin: i32, qmax: i32) -> (f32, i32) {
+    let (scale, zero_point) = match scheme {
+        QuantScheme::Symmetric => (min.abs().max(max.abs()) / qmax as f32, 0),
+        QuantScheme::Asymmetric => {
+            // Zero must stay exact, for the padding and the sparse values.
+            let (min, max) = (min.min(0.0), max.max(0.0));
+            let scale = (max - min) / (qmax - qmin) as f32;
+            let zero_point = (qmin as f32 - min / scale).round() as i32;
+            (scale, zero_point.clamp(qmin, qmax))
+        }
+    };
+    if scale > 0.0 {
+        (scale, zero_point)
+    } else {
+        // An all zero matrix.
+        (1.0, 0)
+    }
+}
+
+/// Copy a row major matrix of `cols` columns into a zeroed `rows x padded_cols` matrix.
+fn padded(values: &[i8], cols: usize, rows: usize, padded_cols: usize) -> Vec<i8> {
+    let mut padded = vec![0; rows * padded_cols];
+    for (dst, src) in padded.chunks_mut(padded_cols).zip(values.chunks(cols)) {
+        dst[..cols].copy_from_slice(src);
+    }
+    padded
+}
+
+fn check_len(matrix: char, expected: usize, actual: usize) -> Result<(), RknnMatmulError> {
+    if expected != actual {
+        return Err(RknnMatmulError::LengthMismatch {
+            matrix,
+            expected,
+            actual,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::{
+        driver::stub::{StubCall, StubDriver},
+        error::RknnMatmulError,
+        matmul::{test::matmul, MatmulElement, PackedI4, RknnMatmulShape, RknnMatmulType},
+    };
+
+    use super::{QuantGranularity, QuantScheme, QuantizedMatmul, QuantizedTypes};
+
+    const M: usize = 8;
+    const K: usize = 64;
+    const N: usize = 32;
+
+    /// Largest difference with the f32 product, relative to the magnitude of the summed terms:
+    /// `|c - a x b| / (|a| x |b|)`.
+    fn relative_error<A, C>(
+        scheme: QuantScheme,
+        granularity: QuantGranularity,
+        a: &[f32],
+        b: &[f32],
+    ) -> f32
+    where
+        A: QuantizedTypes<C>,
+        C: MatmulElement + Into<i32>,
+    {
+        let mut quantized =
+            QuantizedMatmul::<A, C>::with_driver(M, K, N, Arc::new(StubDriver::matmul()))
+                .unwrap()
+                .with_quantization(scheme, granularity);
+        let mut c = vec![0.0; M * N];
+        quantized.run(a, b, &mut c).unwrap();
+
+        let expected = matmul(a, b, M, K, N);
+        let abs = |values: &[f32]| values.iter().map(|it| it.abs()).collect::<Vec<_>>();
+        let magnitude = matmul(&abs(a), &abs(b), M, K, N);
+        (0..M * N)
+            .map(|idx| (c[idx] - expected[idx]).abs() / magnitude[idx])
+            .fold(0.0_f32, f32::max)
+    }
+
+    fn matrix(len: usize, seed: f32) -> Vec<f32> {
+        (0..len).map(|it| (it as f32 * seed).sin() * 2.0).collect()
+    }
+
+    #[test]
+    fn test_quantized_i8() {
+        let (a, b) = (matrix(M * K, 0.37), matrix(K * N, 0.73));
+        for scheme in [QuantScheme::Symmetric, QuantScheme::Asymmetric] {
+            for granularity in [QuantGranularity::PerTensor, QuantGranularity::PerRow] {
+                let error = relative_error::<i8, i32>(scheme, granularity, &a, &b);
+                assert!(
+                    error < 0.005,
+                    "{scheme:?} {granularity:?}: relative error {error}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_quantized_i4() {
+        let (a, b) = (matrix(M * K, 0.37), matrix(K * N, 0.73));
+        for scheme in [QuantScheme::Symmetric, QuantScheme::Asymmetric] {
+            let error = relative_error::<PackedI4, i16>(scheme, QuantGranularity::PerRow, &a, &b);
+            assert!(error < 0.05, "{scheme:?}: relative error {error}");
+        }
+    }
+
+    #[test]
+    fn test_quantized_ranges() {
+        // Positive A values, with rows of very different magnitudes.
+        let a = (0..M * K)
+            .map(|it| ((it as f32 * 0.37).sin() + 1.5) * (1 << (it / K)) as f32)
+            .collect::<Vec<_>>();
+        let b = matrix(K * N, 0.73);
+        let error =
+            |scheme, granularity| relative_error::<PackedI4, i16>(scheme, granularity, &a, &b);
+
+        let per_tensor = error(QuantScheme::Symmetric, QuantGranularity::PerTensor);
+        let per_row = error(QuantScheme::Symmetric, QuantGranularity::PerRow);
+        assert!(per_row < per_tensor);
+        let asymmetric = error(QuantScheme::Asymmetric, QuantGranularity::PerRow);
+        assert!(asymmetric < per_row);
+    }
+
+    #[test]
+    fn test_quantized_resident_b() {
+        let driver = Arc::new(StubDriver::matmul());
+        let mut quantized =
+            QuantizedMatmul::<i8, i32>::with_driver(M, K, N, Arc::clone(&driver) as _).unwrap();
+        let mut c = vec![0.0; M * N];
+        let error = quantized.run_a(&matrix(M * K, 0.37), &mut c).unwrap_err();
+        assert!(error.to_string().contains("not set"));
+
+        let error = quantized.set_b(&matrix(K * N - 1, 0.73)).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<RknnMatmulError>(),
+            Some(&RknnMatmulError::LengthMismatch {
+                matrix: 'B',
+                expected: K * N,
+                actual: K * N - 1,
+            })
+        );
+
+        let b = matrix(K * N, 0.73);
+        quantized.set_b(&b).unwrap();
+        for seed in [0.37, 0.51] {
+            let a = matrix(M * K, seed);
+            quantized.run_a(&a, &mut c).unwrap();
+            let expected = matmul(&a, &b, M, K, N);
+            assert!(c.iter().zip(&expected).all(|(c, e)| (c - e).abs() < 0.5));
+        }
+        // B is only uploaded once.
+        assert_eq!(driver.call_count(StubCall::MatmulSetIoMem), 3);
+    }
+
+    #[test]
+    fn test_quantized_padding() {
+        let (m, k, n) = (3, 50, 20);
+        let driver = Arc::new(StubDriver::matmul());
+        let mut quantized =
+            QuantizedMatmul::<PackedI4, i16>::with_driver(m, k, n, driver.clone()).unwrap();
+        assert_eq!(quantized.shape(), RknnMatmulShape::new(m, k, n));
+        let infos = quantized.as_typed().infos();
+        assert_eq!((infos.k(), infos.n()), (64, 64));
+
+        let (a, b) = (matrix(m * k, 0.37), matrix(k * n, 0.73));
+        let mut c = vec![0.0; m * n];
+        quantized.run(&a, &b, &mut c).unwrap();
+        let expected = matmul(&a, &b, m, k, n);
+        let abs = |values: &[f32]| values.iter().map(|it| it.abs()).collect::<Vec<_>>();
+        let magnitude = matmul(&abs(&a), &abs(&b), m, k, n);
+        for ((c, e), magnitude) in c.iter().zip(&expected).zip(&magnitude) {
+            assert!((c - e).abs() / magnitude < 0.05, "{c} instead of {e}");
+        }
+
+        // The int4 products of a deeper matmul may not fit in 16 bits.
+        let error = QuantizedMatmul::<PackedI4, i16>::with_driver(1, 512, 64, driver).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<RknnMatmulError>(),
+            Some(&RknnMatmulError::DepthTooLarge {
+                mm_type: RknnMatmulType::RKNN_INT4_MM_INT4_TO_INT16,
+                k: 512,
+                max: 511,
+            })
+        );
+    }
+}
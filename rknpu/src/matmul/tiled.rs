@@ -0,0 +1,368 @@
+//! Matrix multiplications larger than a single matmul context, split in tiles run one after the
+//! other, or spread over several NPU cores.
+
+use std::{collections::HashMap, ops::AddAssign, sync::Arc, thread};
+
+use anyhow::{anyhow, ensure, Result};
+
+use crate::{
+    driver::{NativeDriver, RknnDriver},
+    error::RknnMatmulError,
+    flags::RknnCoreMask,
+};
+
+use super::{Matmul, MatmulElement, MatmulTypes, NativeLayout, RknnMatmulShape};
+
+/// C matrix element types that partial products can be summed in on the CPU.
+pub trait TileAccumulator: MatmulElement + AddAssign + Send {}
+
+impl TileAccumulator for f32 {}
+impl TileAccumulator for i32 {}
+
+/// Matrix multiplication of any shape, split in tiles of at most [`TiledMatmul::tile`].
+///
+/// Each `M x N` block of C is computed by a worker, summing the products of the tiles along K.
+/// Tiles on the edges of the matrices are padded with zeros to the block sizes of the NPU, and a
+/// worker keeps one context per tile shape, so running the same shapes again doesn't create new
+/// contexts. All matrices use the normal (row major) layout.
+pub struct TiledMatmul<A, C> {
+    tile: RknnMatmulShape,
+    driver: Arc<dyn RknnDriver>,
+    workers: Vec<Worker<A, C>>,
+}
+
+/// Contexts of the tiles run on a set of cores.
+struct Worker<A, C> {
+    core_mask: RknnCoreMask,
+    matmuls: HashMap<RknnMatmulShape, Matmul<A, C>>,
+}
+
+/// Position and size of a block of the C matrix.
+#[derive(Debug, Clone, Copy)]
+struct Block {
+    row: usize,
+    col: usize,
+    rows: usize,
+    cols: usize,
+}
+
+impl<A, C> TiledMatmul<A, C>
+where
+    A: MatmulTypes<C> + Sync,
+    C: TileAccumulator,
+{
+    /// Tile shape used by default, accepted by the matmul contexts of the RK3588.
+    pub const DEFAULT_TILE: RknnMatmulShape = RknnMatmulShape::new(256, 1024, 1024);
+
+    /// Create a tiled matmul running all tiles on the cores picked by the runtime.
+    pub fn new() -> Self {
+        Self::with_driver(Arc::new(NativeDriver))
+    }
+
+    /// Create a tiled matmul creating its contexts through the given driver.
+    pub fn with_driver(driver: Arc<dyn RknnDriver>) -> Self {
+        Self {
+            tile: Self::DEFAULT_TILE,
+            driver,
+            workers: vec![Worker::new(RknnCoreMask::RKNN_NPU_CORE_AUTO)],
+        }
+    }
+
+    /// Use tiles of at most `tile`. K and N are rounded up to the block sizes of the native layout
+    /// of the matmul type.
+    pub fn with_tile(mut self, tile: RknnMatmulShape) -> Self {
+        let layout = NativeLayout::new(&A::MM_TYPE);
+        self.tile = RknnMatmulShape::new(
+            tile.m.max(1),
+            tile.k.max(1).next_multiple_of(layout.b_sub_k),
+            tile.n.max(1).next_multiple_of(layout.b_sub_n),
+        );
+        self.clear();
+        self
+    }
+
+    /// Spread the blocks of C over one worker per core mask. Each worker runs its tiles on a
+    /// thread, restricted to its cores.
+    pub fn with_cores(mut self, core_masks: &[RknnCoreMask]) -> Self {
+        self.workers = core_masks.iter().map(|it| Worker::new(*it)).collect();
+        self
+    }
+
+    /// Spread the blocks of C over the three cores of the RK3588.
+    pub fn with_all_cores(self) -> Self {
+        self.with_cores(&[
+            RknnCoreMask::RKNN_NPU_CORE_0,
+            RknnCoreMask::RKNN_NPU_CORE_1,
+            RknnCoreMask::RKNN_NPU_CORE_2,
+        ])
+    }
+
+    /// Largest tile shape.
+    pub fn tile(&self) -> RknnMatmulShape {
+        self.tile
+    }
+
+    /// Number of matmul contexts kept by the workers.
+    pub fn contexts(&self) -> usize {
+        self.workers.iter().map(|it| it.matmuls.len()).sum()
+    }
+
+    /// Destroy the matmul contexts kept by the workers.
+    pub fn clear(&mut self) {
+        for worker in &mut self.workers {
+            worker.matmuls.clear();
+        }
+    }
+
+    /// Multiply the row major `m x k` A matrix by the `k x n` B matrix of `shape`, into the
+    /// `m x n` C matrix.
+    pub fn run(&mut self, shape: RknnMatmulShape, a: &[A], b: &[A], c: &mut [C]) -> Result<()> {
+        ensure!(!self.workers.is_empty(), "A tiled matmul needs a core mask");
+        let RknnMatmulShape { m, k, n } = shape;
+        check_len('A', m * k, a.len())?;
+        check_len('B', k * n, b.len())?;
+        check_len('C', m * n, c.len())?;
+
+        let (tile, driver) = (self.tile, &self.driver);
+        let blocks = (0..m).step_by(tile.m).flat_map(|row| {
+            (0..n).step_by(tile.n).map(move |col| Block {
+                row,
+                col,
+                rows: tile.m.min(m - row),
+                cols: tile.n.min(n - col),
+            })
+        });
+        let workers = self.workers.len();
+        let mut assigned = vec![vec![]; workers];
+        for (index, block) in blocks.enumerate() {
+            assigned[index % workers].push(block);
+        }
+
+        let results = thread::scope(|scope| {
+            let handles = self
+                .workers
+                .iter_mut()
+                .zip(assigned)
+                .map(|(worker, blocks)| {
+                    scope.spawn(move || -> Result<Vec<(Block, Vec<C>)>> {
+                        blocks
+                            .into_iter()
+                            .map(|block| {
+                                let values = worker.run(driver, tile, shape, block, a, b)?;
+                                Ok((block, values))
+                            })
+                            .collect()
+                    })
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|it| {
+                    it.join()
+                        .map_err(|_| anyhow!("A tiled matmul worker panicked"))?
+                })
+                .collect::<Result<Vec<_>>>()
+        })?;
+
+        for (block, values) in results.into_iter().flatten() {
+            for (row, chunk) in values.chunks(block.cols).enumerate() {
+                let start = (block.row + row) * n + block.col;
+                c[start..start + block.cols].copy_from_slice(chunk);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<A, C> Default for TiledMatmul<A, C>
+where
+    A: MatmulTypes<C> + Sync,
+    C: TileAccumulator,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<A: MatmulTypes<C>, C: TileAccumulator> Worker<A, C> {
+    fn new(core_mask: RknnCoreMask) -> Self {
+        Self {
+            core_mask,
+            matmuls: HashMap::new(),
+        }
+    }
+
+    /// Compute a block of C, summing the products of the tiles along K.
+    fn run(
+        &mut self,
+        driver: &Arc<dyn RknnDriver>,
+        tile: RknnMatmulShape,
+        shape: RknnMatmulShape,
+        block: Block,
+        a: &[A],
+        b: &[A],
+    ) -> Result<Vec<C>> {
+        let layout = NativeLayout::new(&A::MM_TYPE);
+        let mut acc = vec![C::default(); block.rows * block.cols];
+        for depth in (0..shape.k).step_by(tile.k) {
+            let depths = tile.k.min(shape.k - depth);
+            let padded = RknnMatmulShape::new(
+                block.rows,
+                depths.next_multiple_of(layout.b_sub_k),
+                block.cols.next_multiple_of(layout.b_sub_n),
+            );
+
+            let mut tile_a = vec![A::default(); padded.m * padded.k];
+            for row in 0..block.rows {
+                let start = (block.row + row) * shape.k + depth;
+                tile_a[row * padded.k..][..depths].copy_from_slice(&a[start..start + depths]);
+            }
+            let mut tile_b = vec![A::default(); padded.k * padded.n];
+            for row in 0..depths {
+                let start = (depth + row) * shape.n + block.col;
+                tile_b[row * padded.n..][..block.cols]
+                    .copy_from_slice(&b[start..start + block.cols]);
+            }
+
+            let mut tile_c = vec![C::default(); padded.m * padded.n];
+            self.matmul(driver, padded)?
+                .run(&tile_a, &tile_b, &mut tile_c)?;
+            for (acc_row, c_row) in acc.chunks_mut(block.cols).zip(tile_c.chunks(padded.n)) {
+                for (acc, value) in acc_row.iter_mut().zip(c_row) {
+                    *acc += *value;
+                }
+            }
+        }
+        Ok(acc)
+    }
+
+    /// Context of a tile shape, created on first use.
+    fn matmul(
+        &mut self,
+        driver: &Arc<dyn RknnDriver>,
+        shape: RknnMatmulShape,
+    ) -> Result<&mut Matmul<A, C>> {
+        if !self.matmuls.contains_key(&shape) {
+            let mut matmul = Matmul::with_driver(shape.m, shape.k, shape.n, Arc::clone(driver))?;
+            if self.core_mask != RknnCoreMask::RKNN_NPU_CORE_AUTO {
+                matmul.as_raw().set_core_mask(self.core_mask)?;
+            }
+            self.matmuls.insert(shape, matmul);
+        }
+        Ok(self.matmuls.get_mut(&shape).unwrap())
+    }
+}
+
+fn check_len(matrix: char, expected: usize, actual: usize) -> Result<(), RknnMatmulError> {
+    if expected != actual {
+        return Err(RknnMatmulError::LengthMismatch {
+            matrix,
+            expected,
+            actual,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use half::f16;
+
+    use crate::{
+        driver::stub::{StubCall, StubDriver},
+        error::RknnMatmulError,
+        flags::RknnCoreMask,
+        matmul::{test::matmul, RknnMatmulShape},
+    };
+
+    use super::TiledMatmul;
+
+    #[test]
+    fn test_tiled_f16() {
+        // Neither dimension is a multiple of the tile or of the block sizes.
+        let shape = RknnMatmulShape::new(37, 150, 70);
+        let a = (0..shape.m * shape.k)
+            .map(|it| f16::from_f32((it % 7) as f32 - 3.0))
+            .collect::<Vec<_>>();
+        let b = (0..shape.k * shape.n)
+            .map(|it| f16::from_f32((it % 5) as f32 * 0.5))
+            .collect::<Vec<_>>();
+
+        let driver = Arc::new(StubDriver::matmul());
+        let mut tiled = TiledMatmul::<f16, f32>::with_driver(Arc::clone(&driver) as _)
+            .with_tile(RknnMatmulShape::new(16, 64, 32));
+        assert_eq!(tiled.tile(), RknnMatmulShape::new(16, 64, 32));
+        let mut c = vec![0.0; shape.m * shape.n];
+        tiled.run(shape, &a, &b, &mut c).unwrap();
+
+        let to_f32 = |values: &[f16]| values.iter().map(|it| it.to_f32()).collect::<Vec<_>>();
+        let expected = matmul(&to_f32(&a), &to_f32(&b), shape.m, shape.k, shape.n);
+        assert_eq!(c, expected);
+        // 3 blocks of rows by 3 blocks of columns by 3 tiles along K.
+        assert_eq!(driver.call_count(StubCall::MatmulRun), 27);
+        // M: 16 or 5, K: 64 or 32, N: 32 or 16 (70 - 64 rounded up to the block size).
+        assert_eq!(tiled.contexts(), 8);
+        assert_eq!(driver.call_count(StubCall::MatmulSetCoreMask), 0);
+    }
+
+    #[test]
+    fn test_tiled_i8_cores() {
+        let shape = RknnMatmulShape::new(48, 96, 64);
+        let a = (0..shape.m * shape.k)
+            .map(|it| (it % 11) as i8 - 5)
+            .collect::<Vec<_>>();
+        let b = (0..shape.k * shape.n)
+            .map(|it| (it % 13) as i8 - 6)
+            .collect::<Vec<_>>();
+
+        let driver = Arc::new(StubDriver::matmul());
+        let mut tiled = TiledMatmul::<i8, i32>::with_driver(Arc::clone(&driver) as _)
+            .with_tile(RknnMatmulShape::new(16, 64, 32))
+            .with_all_cores();
+        let mut c = vec![0; shape.m * shape.n];
+        tiled.run(shape, &a, &b, &mut c).unwrap();
+
+        let to_f32 = |values: &[i8]| values.iter().map(|it| *it as f32).collect::<Vec<_>>();
+        let expected = matmul(&to_f32(&a), &to_f32(&b), shape.m, shape.k, shape.n);
+        assert_eq!(c.iter().map(|it| *it as f32).collect::<Vec<_>>(), expected);
+
+        // 6 blocks of C, 2 per core, each one running 2 tiles along K.
+        for core in [
+            RknnCoreMask::RKNN_NPU_CORE_0,
+            RknnCoreMask::RKNN_NPU_CORE_1,
+            RknnCoreMask::RKNN_NPU_CORE_2,
+        ] {
+            assert_eq!(driver.matmul_runs(core), 4);
+        }
+        let contexts = tiled.contexts();
+        assert_eq!(contexts, 6);
+
+        // The contexts are reused by the next runs.
+        tiled.run(shape, &a, &b, &mut c).unwrap();
+        assert_eq!(tiled.contexts(), contexts);
+        assert_eq!(driver.call_count(StubCall::MatmulCreate), contexts);
+
+        drop(tiled);
+        assert_eq!((driver.live_matmuls(), driver.live_mems()), (0, 0));
+    }
+
+    #[test]
+    fn test_tiled_length_mismatch() {
+        let driver = Arc::new(StubDriver::matmul());
+        let mut tiled = TiledMatmul::<i8, i32>::with_driver(driver);
+        let shape = RknnMatmulShape::new(4, 32, 32);
+        let error = tiled
+            .run(shape, &[0; 4 * 32], &[0; 32 * 31], &mut [0; 4 * 32])
+            .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<RknnMatmulError>(),
+            Some(&RknnMatmulError::LengthMismatch {
+                matrix: 'B',
+                expected: 32 * 32,
+                actual: 32 * 31,
+            })
+        );
+    }
+}
@@ -0,0 +1,397 @@
+use std::{marker::PhantomData, sync::Arc};
+
+use anyhow::Result;
+use half::f16;
+
+use crate::{driver::RknnDriver, error::RknnMatmulError};
+
+use super::{NativeLayout, RknnMatmul, RknnMatmulInfo, RknnMatmulType};
+
+mod sealed {
+    pub trait Sealed {}
+
+    impl Sealed for half::f16 {}
+    impl Sealed for i8 {}
+    impl Sealed for super::PackedI4 {}
+    impl Sealed for f32 {}
+    impl Sealed for i32 {}
+    impl Sealed for i16 {}
+}
+
+/// Plain data types of the matmul matrices, that can be copied to and from the NPU buffers as
+/// bytes.
+pub trait MatmulElement: sealed::Sealed + Copy + Default {}
+
+impl MatmulElement for f16 {}
+impl MatmulElement for i8 {}
+impl MatmulElement for PackedI4 {}
+impl MatmulElement for f32 {}
+impl MatmulElement for i32 {}
+impl MatmulElement for i16 {}
+
+/// Element type of the A and B matrices of a matmul producing a C matrix of `C` elements.
+pub trait MatmulTypes<C: MatmulElement>: MatmulElement {
+    /// Matmul type of the runtime.
+    const MM_TYPE: RknnMatmulType;
+    /// Number of matrix values stored in one element.
+    const VALUES_PER_ELEMENT: usize = 1;
+}
+
+impl MatmulTypes<f32> for f16 {
+    const MM_TYPE: RknnMatmulType = RknnMatmulType::RKNN_FLOAT16_MM_FLOAT16_TO_FLOAT32;
+}
+
+impl MatmulTypes<i32> for i8 {
+    const MM_TYPE: RknnMatmulType = RknnMatmulType::RKNN_INT8_MM_INT8_TO_INT32;
+}
+
+impl MatmulTypes<i16> for PackedI4 {
+    const MM_TYPE: RknnMatmulType = RknnMatmulType::RKNN_INT4_MM_INT4_TO_INT16;
+    const VALUES_PER_ELEMENT: usize = 2;
+}
+
+/// Two signed 4 bits values packed in a byte, the first value in the low bits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct PackedI4(pub u8);
+
+impl PackedI4 {
+    /// Pack two values in `-8..=7`, higher bits are ignored.
+    pub fn new(low: i8, high: i8) -> Self {
+        Self((low as u8 & 0x0f) | ((high as u8) << 4))
+    }
+
+    pub fn low(&self) -> i8 {
+        ((self.0 << 4) as i8) >> 4
+    }
+
+    pub fn high(&self) -> i8 {
+        (self.0 as i8) >> 4
+    }
+
+    /// Pack a slice of values in `-8..=7`. An odd length is padded with a zero.
+    pub fn pack(values: &[i8]) -> Vec<Self> {
+        values
+            .chunks(2)
+            .map(|it| Self::new(it[0], it.get(1).copied().unwrap_or(0)))
+            .collect()
+    }
+
+    pub fn unpack(values: &[Self]) -> Vec<i8> {
+        values.iter().flat_map(|it| [it.low(), it.high()]).collect()
+    }
+}
+
+/// Matrix multiplication `C = A x B` over typed slices, with A of shape `m x k`, B of shape
+/// `k x n` and C of shape `m x n`.
+///
+/// The slice lengths are checked against the matmul shape before any copy to or from the NPU.
+/// With the normal layouts, they are the matrix sizes. With the native layouts, they include the
+/// block padding and the matrices are converted with [`NativeLayout`].
+#[derive(Debug)]
+pub struct Matmul<A, C> {
+    matmul: RknnMatmul,
+    types: PhantomData<fn(&[A]) -> C>,
+}
+
+impl<A: MatmulTypes<C>, C: MatmulElement> Matmul<A, C> {
+    /// Create a matmul using the normal (row major) layout for all matrices.
+    pub fn new(m: usize, k: usize, n: usize) -> Result<Self> {
+        Self::with_layouts(m, k, n, false, false)
+    }
+
+    pub fn with_layouts(
+        m: usize,
+        k: usize,
+        n: usize,
+        b_native_layout: bool,
+        ac_native_layout: bool,
+    ) -> Result<Self> {
+        let infos = RknnMatmulInfo::new(m, k, n, A::MM_TYPE, b_native_layout, ac_native_layout);
+        Ok(Self::from_matmul(RknnMatmul::new(infos)?))
+    }
+
+    /// Create a matmul through the given driver, using the normal layout for all matrices.
+    pub fn with_driver(m: usize, k: usize, n: usize, driver: Arc<dyn RknnDriver>) -> Result<Self> {
+        let infos = RknnMatmulInfo::new(m, k, n, A::MM_TYPE, false, false);
+        Ok(Self::from_matmul(RknnMatmul::with_driver(infos, driver)?))
+    }
+
+    /// Wrap an untyped matmul, which must have the matmul type of `A`.
+    pub fn from_raw(matmul: RknnMatmul) -> Result<Self> {
+        if *matmul.infos().mm_type() != A::MM_TYPE {
+            return Err(RknnMatmulError::TypeMismatch {
+                expected: A::MM_TYPE,
+                actual: matmul.infos().mm_type().clone(),
+            }
+            .into());
+        }
+        Ok(Self::from_matmul(matmul))
+    }
+
+    fn from_matmul(matmul: RknnMatmul) -> Self {
+        Self {
+            matmul,
+            types: PhantomData,
+        }
+    }
+
+    pub fn infos(&self) -> &RknnMatmulInfo {
+        self.matmul.infos()
+    }
+
+    /// Underlying untyped matmul.
+    pub fn as_raw(&mut self) -> &mut RknnMatmul {
+        &mut self.matmul
+    }
+
+    pub fn into_raw(self) -> RknnMatmul {
+        self.matmul
+    }
+
+    pub fn set_inputs(&mut self, a: &[A], b: &[A]) -> Result<()> {
+        self.set_a(a)?;
+        self.set_b(b)?;
+        self.bind()
+    }
+
+    /// Copy the A matrix, see [`RknnMatmul::set_a`].
+    pub fn set_a(&mut self, a: &[A]) -> Result<()> {
+        self.check_len('A', a)?;
+        self.matmul.set_a(as_bytes(a))
+    }
+
+    /// Copy the B matrix, see [`RknnMatmul::set_b`].
+    pub fn set_b(&mut self, b: &[A]) -> Result<()> {
+        self.check_len('B', b)?;
+        self.matmul.set_b(as_bytes(b))
+    }
+
+    pub fn bind(&mut self) -> Result<()> {
+        self.matmul.bind()
+    }
+
+    pub fn get_output(&mut self, c: &mut [C]) -> Result<()> {
+        self.check_len('C', c)?;
+        self.matmul.get_output(as_bytes_mut(c))
+    }
+
+    pub fn exec(&mut self) -> Result<()> {
+        self.matmul.exec()
+    }
+
+    pub fn run(&mut self, a: &[A], b: &[A], c: &mut [C]) -> Result<()> {
+        self.set_inputs(a, b)?;
+        self.exec()?;
+        self.get_output(c)
+    }
+
+    /// Free the runtime resources, see [`RknnMatmul::close`].
+    pub fn close(self) -> Result<()> {
+        self.matmul.close()
+    }
+
+    /// Number of elements of the A, B and C matrices, from the matmul shape and layouts.
+    pub fn lens(&self) -> [usize; 3] {
+        let infos = self.infos();
+        let (m, k, n) = (infos.m(), infos.k(), infos.n());
+        let layout = NativeLayout::new(infos.mm_type());
+        let (a, c) = match infos.ac_native_layout() {
+            true => (layout.a_len(m, k), layout.c_len(m, n)),
+            false => (m * k, m * n),
+        };
+        let b = match infos.b_native_layout() {
+            true => layout.b_len(k, n),
+            false => k * n,
+        };
+        [
+            a.div_ceil(A::VALUES_PER_ELEMENT),
+            b.div_ceil(A::VALUES_PER_ELEMENT),
+            c,
+        ]
+    }
+
+    /// Check that `values` hold the elements of `matrix`.
+    fn check_len<T>(&self, matrix: char, values: &[T]) -> Result<(), RknnMatmulError> {
+        let index = (matrix as u8 - b'A') as usize;
+        let expected = self.lens()[index];
+        if expected != values.len() {
+            return Err(RknnMatmulError::LengthMismatch {
+                matrix,
+                expected,
+                actual: values.len(),
+            });
+        }
+        Ok(())
+    }
+}
+
+fn as_bytes<T: MatmulElement>(values: &[T]) -> &[u8] {
+    // Matmul elements are plain data without padding.
+    unsafe {
+        std::slice::from_raw_parts(values.as_ptr() as *const u8, std::mem::size_of_val(values))
+    }
+}
+
+fn as_bytes_mut<T: MatmulElement>(values: &mut [T]) -> &mut [u8] {
+    // Matmul elements are plain data without padding, and any bit pattern is a valid value.
+    unsafe {
+        std::slice::from_raw_parts_mut(
+            values.as_mut_ptr() as *mut u8,
+            std::mem::size_of_val(values),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use half::f16;
+
+    use crate::{
+        driver::stub::{StubCall, StubDriver},
+        error::RknnMatmulError,
+    };
+
+    use super::{Matmul, PackedI4};
+    use crate::matmul::{RknnMatmul, RknnMatmulInfo, RknnMatmulType};
+
+    fn reference<T: Copy + Into<f32>>(a: &[T], b: &[T], m: usize, k: usize, n: usize) -> Vec<f32> {
+        (0..m * n)
+            .map(|idx| {
+                let (i, j) = (idx / n, idx % n);
+                (0..k)
+                    .map(|l| a[i * k + l].into() * b[l * n + j].into())
+                    .sum()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_packed_i4() {
+        let values = [-8, 7, 0, -1, 3];
+        let packed = PackedI4::pack(&values);
+        assert_eq!(packed.len(), 3);
+        assert_eq!(PackedI4::unpack(&packed), vec![-8, 7, 0, -1, 3, 0]);
+    }
+
+    #[test]
+    fn test_matmul_f16() {
+        let (m, k, n) = (4, 32, 16);
+        let a = (0..m * k)
+            .map(|it| f16::from_f32((it % 7) as f32 - 3.0))
+            .collect::<Vec<_>>();
+        let b = (0..k * n)
+            .map(|it| f16::from_f32((it % 5) as f32 * 0.5))
+            .collect::<Vec<_>>();
+        let mut c = vec![0.0_f32; m * n];
+        let mut matmul =
+            Matmul::<f16, f32>::with_driver(m, k, n, Arc::new(StubDriver::matmul())).unwrap();
+        matmul.run(&a, &b, &mut c).unwrap();
+        assert_eq!(c, reference(&a, &b, m, k, n));
+    }
+
+    #[test]
+    fn test_matmul_i8() {
+        let (m, k, n) = (4, 32, 16);
+        let a = (0..m * k).map(|it| (it % 11) as i8 - 5).collect::<Vec<_>>();
+        let b = (0..k * n).map(|it| (it % 13) as i8 - 6).collect::<Vec<_>>();
+        let mut c = vec![0_i32; m * n];
+        let mut matmul =
+            Matmul::<i8, i32>::with_driver(m, k, n, Arc::new(StubDriver::matmul())).unwrap();
+        matmul.run(&a, &b, &mut c).unwrap();
+        let expected = reference(&a, &b, m, k, n);
+        assert_eq!(c, expected.iter().map(|it| *it as i32).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_matmul_i4() {
+        let (m, k, n) = (4, 32, 16);
+        let a = (0..m * k).map(|it| (it % 5) as i8 - 2).collect::<Vec<_>>();
+        let b = (0..k * n).map(|it| (it % 7) as i8 - 3).collect::<Vec<_>>();
+        let mut c = vec![0_i16; m * n];
+        let mut matmul =
+            Matmul::<PackedI4, i16>::with_driver(m, k, n, Arc::new(StubDriver::matmul())).unwrap();
+        matmul
+            .run(&PackedI4::pack(&a), &PackedI4::pack(&b), &mut c)
+            .unwrap();
+        let expected = reference(&a, &b, m, k, n);
+        assert_eq!(c, expected.iter().map(|it| *it as i16).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_matmul_resident_b() {
+        let (m, k, n) = (4, 32, 16);
+        let driver = Arc::new(StubDriver::matmul());
+        let mut matmul = Matmul::<i8, i32>::with_driver(m, k, n, Arc::clone(&driver) as _).unwrap();
+        let b = (0..k * n).map(|it| (it % 13) as i8 - 6).collect::<Vec<_>>();
+        matmul.set_b(&b).unwrap();
+        let mut c = vec![0_i32; m * n];
+        for step in 0..3 {
+            let a = (0..m * k)
+                .map(|it| ((it + step) % 11) as i8 - 5)
+                .collect::<Vec<_>>();
+            matmul.set_a(&a).unwrap();
+            matmul.exec().unwrap();
+            matmul.get_output(&mut c).unwrap();
+            let expected = reference(&a, &b, m, k, n);
+            assert_eq!(c, expected.iter().map(|it| *it as i32).collect::<Vec<_>>());
+        }
+        // The buffers are only bound by the first run.
+        assert_eq!(driver.call_count(StubCall::MatmulSetIoMem), 3);
+    }
+
+    #[test]
+    fn test_matmul_length_mismatch() {
+        let (m, k, n) = (4, 32, 16);
+        let mut matmul =
+            Matmul::<f16, f32>::with_driver(m, k, n, Arc::new(StubDriver::matmul())).unwrap();
+        let a = vec![f16::ZERO; m * k - 1];
+        let b = vec![f16::ZERO; k * n];
+        let error = matmul.set_inputs(&a, &b).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<RknnMatmulError>(),
+            Some(&RknnMatmulError::LengthMismatch {
+                matrix: 'A',
+                expected: m * k,
+                actual: m * k - 1
+            })
+        );
+
+        let mut c = vec![0.0; m * n + 1];
+        assert!(matmul.get_output(&mut c).is_err());
+    }
+
+    #[test]
+    fn test_matmul_native_lens() {
+        // Int8 native layouts pad K to blocks of 16 values for A, K and N to blocks of 32 for B,
+        // and N to blocks of 4 for C.
+        let (m, k, n) = (3, 40, 18);
+        let infos = RknnMatmulInfo::new(
+            m,
+            k,
+            n,
+            RknnMatmulType::RKNN_INT8_MM_INT8_TO_INT32,
+            true,
+            true,
+        );
+        let matmul = RknnMatmul::with_driver(infos, Arc::new(StubDriver::matmul())).unwrap();
+        let mut matmul = Matmul::<i8, i32>::from_raw(matmul).unwrap();
+        assert_eq!(matmul.lens(), [3 * 48, 64 * 32, 3 * 20]);
+        let error = matmul.set_a(&[0; 3 * 40]).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<RknnMatmulError>(),
+            Some(&RknnMatmulError::LengthMismatch {
+                matrix: 'A',
+                expected: 3 * 48,
+                actual: m * k
+            })
+        );
+        matmul.set_a(&[0; 3 * 48]).unwrap();
+        matmul.set_b(&[0; 64 * 32]).unwrap();
+
+        let matmul = Matmul::<PackedI4, i16>::with_driver(m, k, n, Arc::new(StubDriver::matmul()));
+        assert_eq!(matmul.unwrap().lens(), [m * k / 2, k * n / 2, m * n]);
+    }
+}
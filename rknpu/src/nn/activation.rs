@@ -0,0 +1,102 @@
+use anyhow::Result;
+
+use super::{features, Layer};
+
+/// Activation functions, computed on the CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Activation {
+    /// `max(x, 0)`.
+    Relu,
+    /// Gaussian error linear unit, with the tanh approximation.
+    Gelu,
+    /// `x * sigmoid(x)`, also known as swish.
+    Silu,
+    /// Softmax over the features of each row.
+    Softmax,
+}
+
+impl Activation {
+    /// Apply the activation in place to rows of `features` values.
+    pub fn apply(&self, values: &mut [f32], features: usize) {
+        match self {
+            Self::Relu => values.iter_mut().for_each(|it| *it = it.max(0.0)),
+            Self::Gelu => values.iter_mut().for_each(|it| *it = gelu(*it)),
+            Self::Silu => values.iter_mut().for_each(|it| *it /= 1.0 + (-*it).exp()),
+            Self::Softmax => values.chunks_mut(features.max(1)).for_each(softmax),
+        }
+    }
+}
+
+impl Layer for Activation {
+    fn forward(&mut self, input: &[f32], batch: usize) -> Result<Vec<f32>> {
+        let features = features(input, batch, None)?;
+        let mut output = input.to_vec();
+        self.apply(&mut output, features);
+        Ok(output)
+    }
+}
+
+fn gelu(x: f32) -> f32 {
+    const SQRT_2_OVER_PI: f32 = 0.797_884_6;
+    0.5 * x * (1.0 + (SQRT_2_OVER_PI * (x + 0.044_715 * x * x * x)).tanh())
+}
+
+fn softmax(row: &mut [f32]) {
+    // Shifted by the maximum to avoid overflows.
+    let max = row.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let mut sum = 0.0;
+    for value in row.iter_mut() {
+        *value = (*value - max).exp();
+        sum += *value;
+    }
+    row.iter_mut().for_each(|it| *it /= sum);
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{error::RknnNnError, nn::Layer};
+
+    use super::Activation;
+
+    fn assert_close(actual: &[f32], expected: &[f32]) {
+        for (actual, expected) in actual.iter().zip(expected) {
+            assert!((actual - expected).abs() < 1e-4, "{actual} != {expected}");
+        }
+    }
+
+    #[test]
+    fn test_activations() {
+        let input = [-2.0, -0.5, 0.0, 1.0, 3.0, 100.0];
+        let mut output = input;
+        Activation::Relu.apply(&mut output, 6);
+        assert_eq!(output, [0.0, 0.0, 0.0, 1.0, 3.0, 100.0]);
+
+        let mut output = input;
+        Activation::Gelu.apply(&mut output, 6);
+        assert_close(&output, &[-0.04540, -0.15429, 0.0, 0.84119, 2.99636, 100.0]);
+
+        let mut output = input;
+        Activation::Silu.apply(&mut output, 6);
+        assert_close(&output, &[-0.23841, -0.18877, 0.0, 0.73106, 2.85772, 100.0]);
+    }
+
+    #[test]
+    fn test_softmax() {
+        let mut activation = Activation::Softmax;
+        let output = activation
+            .forward(&[1.0, 2.0, 3.0, 1000.0, 1000.0, 1000.0], 2)
+            .unwrap();
+        let third = 1.0 / 3.0;
+        assert_close(&output, &[0.09003, 0.24473, 0.66524, third, third, third]);
+
+        let error = activation.forward(&[0.0; 5], 2).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<RknnNnError>(),
+            Some(&RknnNnError::InputMismatch {
+                expected: 2,
+                actual: 5,
+                batch: 2
+            })
+        );
+    }
+}
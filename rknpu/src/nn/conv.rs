@@ -0,0 +1,605 @@
+use std::sync::Arc;
+
+use anyhow::{ensure, Result};
+use half::f16;
+
+use crate::{
+    driver::{NativeDriver, RknnDriver},
+    error::RknnNnError,
+    matmul::{Matmul, MatmulTypes, NativeLayout, QuantizedMatmul, RknnMatmulType},
+};
+
+use super::Weights;
+
+/// Shape and hyper-parameters of a 2D convolution, as in PyTorch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Conv2dParams {
+    pub in_channels: usize,
+    pub out_channels: usize,
+    /// Kernel height and width.
+    pub kernel: [usize; 2],
+    pub stride: [usize; 2],
+    /// Zeros added on both sides of the height and width.
+    pub padding: [usize; 2],
+    pub dilation: [usize; 2],
+    /// Number of groups the channels are split in, each input group producing an output group.
+    pub groups: usize,
+}
+
+impl Conv2dParams {
+    /// Parameters of a convolution with unit stride and dilation, no padding and one group.
+    pub fn new(in_channels: usize, out_channels: usize, kernel: [usize; 2]) -> Self {
+        Self {
+            in_channels,
+            out_channels,
+            kernel,
+            stride: [1, 1],
+            padding: [0, 0],
+            dilation: [1, 1],
+            groups: 1,
+        }
+    }
+
+    pub fn with_stride(mut self, stride: [usize; 2]) -> Self {
+        self.stride = stride;
+        self
+    }
+
+    pub fn with_padding(mut self, padding: [usize; 2]) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    pub fn with_dilation(mut self, dilation: [usize; 2]) -> Self {
+        self.dilation = dilation;
+        self
+    }
+
+    pub fn with_groups(mut self, groups: usize) -> Self {
+        self.groups = groups;
+        self
+    }
+
+    /// Shape of the weight: `[out_channels, in_channels / groups, kernel height, kernel width]`.
+    pub fn weight_shape(&self) -> [usize; 4] {
+        [
+            self.out_channels,
+            self.in_channels / self.groups.max(1),
+            self.kernel[0],
+            self.kernel[1],
+        ]
+    }
+
+    /// Height and width of the output for an input of `size`, or `None` when the dilated kernel
+    /// doesn't fit in the padded input, or a kernel size, stride or dilation is 0.
+    pub fn output_size(&self, size: [usize; 2]) -> Option<[usize; 2]> {
+        let mut output = [0; 2];
+        for dim in 0..2 {
+            if self.dilation[dim] == 0 {
+                return None;
+            }
+            let padded = size[dim] + 2 * self.padding[dim];
+            let extent = self.dilation[dim] * (self.kernel[dim].checked_sub(1)?) + 1;
+            output[dim] = padded.checked_sub(extent)?.checked_div(self.stride[dim])? + 1;
+        }
+        Some(output)
+    }
+
+    fn validate(&self) -> Result<()> {
+        ensure!(
+            self.groups > 0
+                && self.in_channels.is_multiple_of(self.groups)
+                && self.out_channels.is_multiple_of(self.groups),
+            "{} input and {} output channels can't be split in {} groups",
+            self.in_channels,
+            self.out_channels,
+            self.groups
+        );
+        ensure!(
+            self.kernel
+                .iter()
+                .chain(&self.stride)
+                .chain(&self.dilation)
+                .all(|it| *it > 0),
+            "Kernel size, stride and dilation of a convolution must be positive, got {self:?}"
+        );
+        Ok(())
+    }
+}
+
+/// Element types of the matmuls of a convolution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ConvPrecision {
+    /// Float16 inputs and weights, f32 accumulation.
+    #[default]
+    Float16,
+    /// Int8 inputs and weights, quantized symmetrically per output pixel and per output channel
+    /// with a [`QuantizedMatmul`].
+    Int8,
+}
+
+impl ConvPrecision {
+    fn mm_type(&self) -> RknnMatmulType {
+        match self {
+            Self::Float16 => <f16 as MatmulTypes<f32>>::MM_TYPE,
+            Self::Int8 => <i8 as MatmulTypes<i32>>::MM_TYPE,
+        }
+    }
+}
+
+/// Matmul of a group, with its weights resident on the NPU.
+enum GroupMatmul {
+    Float16(Matmul<f16, f32>),
+    Int8(QuantizedMatmul<i8, i32>),
+}
+
+impl GroupMatmul {
+    fn new(
+        precision: ConvPrecision,
+        [m, k, n]: [usize; 3],
+        weight: &[f32],
+        driver: Arc<dyn RknnDriver>,
+    ) -> Result<Self> {
+        Ok(match precision {
+            ConvPrecision::Float16 => {
+                let mut matmul = Matmul::with_driver(m, k, n, driver)?;
+                let weight = weight
+                    .iter()
+                    .map(|it| f16::from_f32(*it))
+                    .collect::<Vec<_>>();
+                matmul.set_b(&weight)?;
+                Self::Float16(matmul)
+            }
+            ConvPrecision::Int8 => {
+                let mut matmul = QuantizedMatmul::with_driver(m, k, n, driver)?;
+                matmul.set_b(weight)?;
+                Self::Int8(matmul)
+            }
+        })
+    }
+
+    fn run(&mut self, a: &[f32], c: &mut [f32]) -> Result<()> {
+        match self {
+            Self::Float16(matmul) => {
+                let a = a.iter().map(|it| f16::from_f32(*it)).collect::<Vec<_>>();
+                matmul.set_a(&a)?;
+                matmul.exec()?;
+                matmul.get_output(c)
+            }
+            Self::Int8(matmul) => matmul.run_a(a, c),
+        }
+    }
+}
+
+/// 2D convolution over NCHW images, lowered to one matmul per group with im2col.
+///
+/// For each group, the input patches form the `(batch * output pixels) x (input channels *
+/// kernel size)` A matrix, and the weights the B matrix, which stays on the NPU. The matmuls are
+/// created for each batch and input size the first time they are seen. Only the matmuls of the
+/// most recently used sizes are kept, see [`with_max_contexts`](Conv2d::with_max_contexts).
+pub struct Conv2d {
+    params: Conv2dParams,
+    precision: ConvPrecision,
+    /// Weights of each group, as a `k x n` matrix padded to the block sizes of the NPU.
+    weights: Vec<Vec<f32>>,
+    bias: Option<Vec<f32>>,
+    driver: Arc<dyn RknnDriver>,
+    /// Matmuls of each group by number of A rows, the least recently used first.
+    matmuls: Vec<(usize, Vec<GroupMatmul>)>,
+    max_contexts: usize,
+}
+
+impl Conv2d {
+    /// Sizes whose matmuls are kept by default.
+    pub const DEFAULT_MAX_CONTEXTS: usize = 4;
+
+    /// Create a convolution from a weight of [`Conv2dParams::weight_shape`], row major.
+    pub fn new(
+        params: Conv2dParams,
+        weight: &[f32],
+        bias: Option<&[f32]>,
+        precision: ConvPrecision,
+    ) -> Result<Self> {
+        Self::with_driver(params, weight, bias, precision, Arc::new(NativeDriver))
+    }
+
+    /// Create a convolution running its matmuls through the given driver.
+    pub fn with_driver(
+        params: Conv2dParams,
+        weight: &[f32],
+        bias: Option<&[f32]>,
+        precision: ConvPrecision,
+        driver: Arc<dyn RknnDriver>,
+    ) -> Result<Self> {
+        params.validate()?;
+        let shape = params.weight_shape();
+        if weight.len() != shape.iter().product::<usize>() {
+            return Err(RknnNnError::ShapeMismatch {
+                name: "weight".to_string(),
+                expected: shape.to_vec(),
+                actual: vec![weight.len()],
+            }
+            .into());
+        }
+        if let Some(bias) = bias.filter(|it| it.len() != params.out_channels) {
+            return Err(RknnNnError::ShapeMismatch {
+                name: "bias".to_string(),
+                expected: vec![params.out_channels],
+                actual: vec![bias.len()],
+            }
+            .into());
+        }
+
+        let [k, n] = padded(&params, precision);
+        let (group_k, group_n) = (shape[1] * shape[2] * shape[3], shape[0] / params.groups);
+        let weights = weight
+            .chunks(group_k * group_n)
+            .map(|group| {
+                let mut transposed = vec![0.0; k * n];
+                for (col, values) in group.chunks(group_k).enumerate() {
+                    for (row, value) in values.iter().enumerate() {
+                        transposed[row * n + col] = *value;
+                    }
+                }
+                transposed
+            })
+            .collect();
+        Ok(Self {
+            params,
+            precision,
+            weights,
+            bias: bias.map(<[f32]>::to_vec),
+            driver,
+            matmuls: Vec::new(),
+            max_contexts: Self::DEFAULT_MAX_CONTEXTS,
+        })
+    }
+
+    /// Load the `{name}.weight` and optional `{name}.bias` tensors.
+    pub fn from_weights(
+        weights: &Weights,
+        name: &str,
+        params: Conv2dParams,
+        precision: ConvPrecision,
+    ) -> Result<Self> {
+        Self::from_weights_with_driver(weights, name, params, precision, Arc::new(NativeDriver))
+    }
+
+    /// Load a convolution running its matmuls through the given driver.
+    pub fn from_weights_with_driver(
+        weights: &Weights,
+        name: &str,
+        params: Conv2dParams,
+        precision: ConvPrecision,
+        driver: Arc<dyn RknnDriver>,
+    ) -> Result<Self> {
+        let weight_name = format!("{name}.weight");
+        let weight = weights.get(&weight_name)?;
+        if weight.shape != params.weight_shape() {
+            return Err(RknnNnError::ShapeMismatch {
+                name: weight_name,
+                expected: params.weight_shape().to_vec(),
+                actual: weight.shape.clone(),
+            }
+            .into());
+        }
+        let bias = weights.get(&format!("{name}.bias")).ok();
+        let bias = bias.map(|it| it.values.as_slice());
+        Self::with_driver(params, &weight.values, bias, precision, driver)
+    }
+
+    pub fn params(&self) -> &Conv2dParams {
+        &self.params
+    }
+
+    /// Keep the matmuls of at most `max_contexts` batch and input sizes,
+    /// [`Self::DEFAULT_MAX_CONTEXTS`] by default, destroying the least recently used ones to run
+    /// another size.
+    pub fn with_max_contexts(mut self, max_contexts: usize) -> Self {
+        self.max_contexts = max_contexts.max(1);
+        let evicted = self.matmuls.len().saturating_sub(self.max_contexts);
+        self.matmuls.drain(..evicted);
+        self
+    }
+
+    /// Number of matmuls kept, one per group for each batch and input size.
+    pub fn contexts(&self) -> usize {
+        self.matmuls.iter().map(|(_, it)| it.len()).sum()
+    }
+
+    /// Destroy all matmuls, they are created again when needed.
+    pub fn clear_contexts(&mut self) {
+        self.matmuls.clear();
+    }
+
+    /// Convolve `batch` images of `size` (height and width), `input` being in the NCHW layout. The
+    /// output is in the NCHW layout too, its size given by [`Conv2dParams::output_size`].
+    pub fn run(&mut self, input: &[f32], batch: usize, size: [usize; 2]) -> Result<Vec<f32>> {
+        let Conv2dParams {
+            in_channels,
+            out_channels,
+            kernel,
+            stride,
+            padding,
+            dilation,
+            groups,
+        } = self.params;
+        let [height, width] = size;
+        if batch == 0 || input.len() != batch * in_channels * height * width {
+            return Err(RknnNnError::InputMismatch {
+                expected: in_channels * height * width,
+                actual: input.len(),
+                batch,
+            }
+            .into());
+        }
+        let Some([out_height, out_width]) = self.params.output_size(size) else {
+            return Err(anyhow::anyhow!(
+                "Kernel of {kernel:?} with dilation {dilation:?} doesn't fit in {size:?} images"
+            ));
+        };
+
+        let pixels = out_height * out_width;
+        let m = batch * pixels;
+        let [k, n] = padded(&self.params, self.precision);
+        let (group_in, group_out) = (in_channels / groups, out_channels / groups);
+        self.use_matmuls(m)?;
+        let (_, matmuls) = self.matmuls.last_mut().unwrap();
+
+        let mut output = vec![0.0; batch * out_channels * pixels];
+        let mut a = vec![0.0; m * k];
+        let mut c = vec![0.0; m * n];
+        for (group, matmul) in matmuls.iter_mut().enumerate() {
+            // im2col: a row per output pixel, a column per input channel and kernel position.
+            for (row, patch) in a.chunks_mut(k).enumerate() {
+                let (image, pixel) = (row / pixels, row % pixels);
+                let (out_y, out_x) = (pixel / out_width, pixel % out_width);
+                for (col, value) in patch[..group_in * kernel[0] * kernel[1]]
+                    .iter_mut()
+                    .enumerate()
+                {
+                    let channel = group * group_in + col / (kernel[0] * kernel[1]);
+                    let (ky, kx) = (col / kernel[1] % kernel[0], col % kernel[1]);
+                    let y = (out_y * stride[0] + ky * dilation[0]).checked_sub(padding[0]);
+                    let x = (out_x * stride[1] + kx * dilation[1]).checked_sub(padding[1]);
+                    *value = match (y, x) {
+                        (Some(y), Some(x)) if y < height && x < width => {
+                            input[((image * in_channels + channel) * height + y) * width + x]
+                        }
+                        _ => 0.0,
+                    };
+                }
+            }
+            matmul.run(&a, &mut c)?;
+
+            for (row, values) in c.chunks(n).enumerate() {
+                let (image, pixel) = (row / pixels, row % pixels);
+                for (col, value) in values[..group_out].iter().enumerate() {
+                    let channel = group * group_out + col;
+                    let bias = self.bias.as_ref().map_or(0.0, |it| it[channel]);
+                    output[(image * out_channels + channel) * pixels + pixel] = value + bias;
+                }
+            }
+        }
+        Ok(output)
+    }
+
+    /// Make the matmuls of the groups for `m` rows the most recently used ones, last of
+    /// `matmuls`, creating them with the weights on first use.
+    fn use_matmuls(&mut self, m: usize) -> Result<()> {
+        let matmuls = match self.matmuls.iter().position(|(it, _)| *it == m) {
+            Some(index) => self.matmuls.remove(index).1,
+            None => {
+                if self.matmuls.len() >= self.max_contexts {
+                    self.matmuls.remove(0);
+                }
+                let [k, n] = padded(&self.params, self.precision);
+                self.weights
+                    .iter()
+                    .map(|it| GroupMatmul::new(self.precision, [m, k, n], it, self.driver.clone()))
+                    .collect::<Result<Vec<_>>>()?
+            }
+        };
+        self.matmuls.push((m, matmuls));
+        Ok(())
+    }
+}
+
+/// Rows and columns of the weight matrices, padded to the block sizes of the NPU.
+fn padded(params: &Conv2dParams, precision: ConvPrecision) -> [usize; 2] {
+    let layout = NativeLayout::new(&precision.mm_type());
+    let [out_channels, in_channels, height, width] = params.weight_shape();
+    [
+        (in_channels * height * width).next_multiple_of(layout.b_sub_k),
+        (out_channels / params.groups).next_multiple_of(layout.b_sub_n),
+    ]
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::{
+        driver::stub::{StubCall, StubDriver},
+        error::RknnNnError,
+    };
+
+    use super::{Conv2d, Conv2dParams, ConvPrecision};
+
+    /// Direct convolution on the CPU.
+    fn reference(
+        params: &Conv2dParams,
+        input: &[f32],
+        weight: &[f32],
+        bias: &[f32],
+        batch: usize,
+        [height, width]: [usize; 2],
+    ) -> Vec<f32> {
+        let [out_height, out_width] = params.output_size([height, width]).unwrap();
+        let [_, group_in, kh, kw] = params.weight_shape();
+        let group_out = params.out_channels / params.groups;
+        let mut output = Vec::new();
+        for image in 0..batch {
+            for out_c in 0..params.out_channels {
+                for out_y in 0..out_height {
+                    for out_x in 0..out_width {
+                        let mut sum = bias[out_c];
+                        for c in 0..group_in {
+                            let in_c = out_c / group_out * group_in + c;
+                            for ky in 0..kh {
+                                for kx in 0..kw {
+                                    let y = (out_y * params.stride[0] + ky * params.dilation[0])
+                                        as isize
+                                        - params.padding[0] as isize;
+                                    let x = (out_x * params.stride[1] + kx * params.dilation[1])
+                                        as isize
+                                        - params.padding[1] as isize;
+                                    if y < 0 || x < 0 || y >= height as isize || x >= width as isize
+                                    {
+                                        continue;
+                                    }
+                                    let (y, x) = (y as usize, x as usize);
+                                    let value =
+                                        input[((image * params.in_channels + in_c) * height + y)
+                                            * width
+                                            + x];
+                                    sum += value
+                                        * weight[((out_c * group_in + c) * kh + ky) * kw + kx];
+                                }
+                            }
+                        }
+                        output.push(sum);
+                    }
+                }
+            }
+        }
+        output
+    }
+
+    fn values(len: usize, seed: f32) -> Vec<f32> {
+        (0..len).map(|it| (it as f32 * seed).sin()).collect()
+    }
+
+    /// Largest difference with the direct convolution, relative to its largest value.
+    fn relative_error(params: Conv2dParams, precision: ConvPrecision, batch: usize) -> f32 {
+        let size = [9, 11];
+        let weight = values(params.weight_shape().iter().product(), 0.37);
+        let bias = values(params.out_channels, 0.91);
+        let input = values(batch * params.in_channels * size[0] * size[1], 0.23);
+
+        let driver = Arc::new(StubDriver::matmul());
+        let mut conv =
+            Conv2d::with_driver(params, &weight, Some(&bias), precision, driver).unwrap();
+        let output = conv.run(&input, batch, size).unwrap();
+        let expected = reference(&params, &input, &weight, &bias, batch, size);
+        assert_eq!(output.len(), expected.len());
+
+        let max = expected.iter().fold(0.0_f32, |max, it| max.max(it.abs()));
+        let error = output
+            .iter()
+            .zip(&expected)
+            .fold(0.0_f32, |error, (o, e)| error.max((o - e).abs()));
+        error / max
+    }
+
+    #[test]
+    fn test_conv2d() {
+        let cases = [
+            Conv2dParams::new(3, 8, [3, 3]),
+            Conv2dParams::new(4, 6, [3, 3])
+                .with_stride([2, 1])
+                .with_padding([1, 2]),
+            Conv2dParams::new(4, 8, [3, 2])
+                .with_dilation([2, 3])
+                .with_padding([2, 1])
+                .with_groups(2),
+            // Depthwise.
+            Conv2dParams::new(6, 6, [3, 3])
+                .with_padding([1, 1])
+                .with_groups(6),
+        ];
+        for params in cases {
+            let error = relative_error(params, ConvPrecision::Float16, 2);
+            assert!(error < 2e-3, "{params:?}: float16 error {error}");
+            let error = relative_error(params, ConvPrecision::Int8, 2);
+            assert!(error < 3e-2, "{params:?}: int8 error {error}");
+        }
+    }
+
+    #[test]
+    fn test_conv2d_resident_weights() {
+        let params = Conv2dParams::new(2, 4, [3, 3]).with_groups(2);
+        let weight = values(4 * 3 * 3, 0.37);
+        let driver = Arc::new(StubDriver::matmul());
+        let mut conv = Conv2d::with_driver(
+            params,
+            &weight,
+            None,
+            ConvPrecision::Float16,
+            driver.clone(),
+        )
+        .unwrap();
+        for _ in 0..3 {
+            conv.run(&values(2 * 5 * 5, 0.23), 1, [5, 5]).unwrap();
+        }
+        assert_eq!(conv.contexts(), 2);
+        // Each group binds its buffers once.
+        assert_eq!(driver.call_count(StubCall::MatmulCreate), 2);
+        assert_eq!(driver.call_count(StubCall::MatmulSetIoMem), 6);
+
+        let error = conv.run(&values(2 * 5 * 4, 0.23), 1, [5, 5]).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<RknnNnError>(),
+            Some(&RknnNnError::InputMismatch {
+                expected: 50,
+                actual: 40,
+                batch: 1
+            })
+        );
+        assert!(conv.run(&values(2 * 2 * 2, 0.23), 1, [2, 2]).is_err());
+        let error = conv.run(&[], 0, [5, 5]).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<RknnNnError>(),
+            Some(&RknnNnError::InputMismatch {
+                expected: 50,
+                actual: 0,
+                batch: 0
+            })
+        );
+
+        // Sizes beyond the limit evict the matmuls of the least recently used one.
+        let mut conv = conv.with_max_contexts(2);
+        for size in [6, 5, 7] {
+            conv.run(&values(2 * size * size, 0.23), 1, [size, size])
+                .unwrap();
+        }
+        assert_eq!(conv.contexts(), 4);
+        assert_eq!(driver.call_count(StubCall::MatmulCreate), 6);
+        assert_eq!(driver.call_count(StubCall::MatmulDestroy), 2);
+        assert_eq!(driver.live_matmuls(), 4);
+        conv.clear_contexts();
+        assert_eq!(driver.live_matmuls(), 0);
+    }
+
+    #[test]
+    fn test_conv2d_params() {
+        let params = Conv2dParams::new(3, 8, [3, 3])
+            .with_stride([2, 2])
+            .with_padding([1, 1]);
+        assert_eq!(params.output_size([224, 224]), Some([112, 112]));
+        assert_eq!(params.weight_shape(), [8, 3, 3, 3]);
+        assert_eq!(params.with_stride([0, 1]).output_size([8, 8]), None);
+        assert_eq!(params.with_dilation([1, 0]).output_size([8, 8]), None);
+        assert_eq!(Conv2dParams::new(3, 8, [0, 3]).output_size([8, 8]), None);
+        assert_eq!(params.output_size([0, 0]), None);
+        let params = Conv2dParams::new(3, 8, [3, 3]).with_groups(2);
+        assert!(Conv2d::with_driver(
+            params,
+            &[0.0; 8 * 3 * 3],
+            None,
+            ConvPrecision::Float16,
+            Arc::new(StubDriver::matmul())
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,329 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use half::f16;
+
+use crate::{
+    driver::{NativeDriver, RknnDriver},
+    error::RknnNnError,
+    matmul::{Matmul, MatmulTypes, NativeLayout},
+};
+
+use super::{features, Layer, Weights};
+
+/// Fully connected layer `y = x W^T + b`, computed by a float16 matmul with the weights
+/// resident on the NPU.
+///
+/// The features are padded to the block sizes of the NPU, and a matmul is created for each batch
+/// size the first time it is seen. Only the matmuls of the most recently used batch sizes are
+/// kept, see [`with_max_contexts`](Linear::with_max_contexts).
+pub struct Linear {
+    in_features: usize,
+    out_features: usize,
+    /// Transposed and padded weights, the B matrix of the matmuls.
+    weight: Vec<f16>,
+    bias: Option<Vec<f32>>,
+    driver: Arc<dyn RknnDriver>,
+    /// Matmuls by batch size, the least recently used first.
+    matmuls: Vec<(usize, Matmul<f16, f32>)>,
+    max_contexts: usize,
+}
+
+impl Linear {
+    /// Matmuls kept by default.
+    pub const DEFAULT_MAX_CONTEXTS: usize = 4;
+
+    /// Create a layer from a `out_features x in_features` row major weight, as stored by PyTorch.
+    pub fn new(
+        weight: &[f32],
+        bias: Option<&[f32]>,
+        in_features: usize,
+        out_features: usize,
+    ) -> Result<Self> {
+        Self::with_driver(
+            weight,
+            bias,
+            in_features,
+            out_features,
+            Arc::new(NativeDriver),
+        )
+    }
+
+    /// Create a layer running its matmuls through the given driver.
+    pub fn with_driver(
+        weight: &[f32],
+        bias: Option<&[f32]>,
+        in_features: usize,
+        out_features: usize,
+        driver: Arc<dyn RknnDriver>,
+    ) -> Result<Self> {
+        check_shape("weight", &[out_features, in_features], weight.len())?;
+        if let Some(bias) = bias {
+            check_shape("bias", &[out_features], bias.len())?;
+        }
+
+        let (k, n) = padded(in_features, out_features);
+        let mut transposed = vec![f16::ZERO; k * n];
+        for (row, values) in weight.chunks(in_features).enumerate() {
+            for (col, value) in values.iter().enumerate() {
+                transposed[col * n + row] = f16::from_f32(*value);
+            }
+        }
+        Ok(Self {
+            in_features,
+            out_features,
+            weight: transposed,
+            bias: bias.map(<[f32]>::to_vec),
+            driver,
+            matmuls: vec![],
+            max_contexts: Self::DEFAULT_MAX_CONTEXTS,
+        })
+    }
+
+    /// Load the `{name}.weight` and optional `{name}.bias` tensors.
+    pub fn from_weights(weights: &Weights, name: &str) -> Result<Self> {
+        Self::from_weights_with_driver(weights, name, Arc::new(NativeDriver))
+    }
+
+    /// Load a layer running its matmuls through the given driver.
+    pub fn from_weights_with_driver(
+        weights: &Weights,
+        name: &str,
+        driver: Arc<dyn RknnDriver>,
+    ) -> Result<Self> {
+        let weight_name = format!("{name}.weight");
+        let weight = weights.get(&weight_name)?;
+        let [out_features, in_features] = weight.shape[..] else {
+            return Err(RknnNnError::ShapeMismatch {
+                name: weight_name,
+                expected: vec![0, 0],
+                actual: weight.shape.clone(),
+            }
+            .into());
+        };
+        let bias_name = format!("{name}.bias");
+        let bias = match weights.get(&bias_name) {
+            Ok(bias) if bias.shape != [out_features] => {
+                return Err(RknnNnError::ShapeMismatch {
+                    name: bias_name,
+                    expected: vec![out_features],
+                    actual: bias.shape.clone(),
+                }
+                .into())
+            }
+            Ok(bias) => Some(bias.values.as_slice()),
+            Err(_) => None,
+        };
+        Self::with_driver(&weight.values, bias, in_features, out_features, driver)
+    }
+
+    pub fn in_features(&self) -> usize {
+        self.in_features
+    }
+
+    pub fn out_features(&self) -> usize {
+        self.out_features
+    }
+
+    /// Keep the matmuls of at most `max_contexts` batch sizes, [`Self::DEFAULT_MAX_CONTEXTS`] by
+    /// default, destroying the least recently used one to run another batch size. Callers running
+    /// many batch sizes, such as sequences of varying length, may rather pad them to a few sizes.
+    pub fn with_max_contexts(mut self, max_contexts: usize) -> Self {
+        self.max_contexts = max_contexts.max(1);
+        let evicted = self.matmuls.len().saturating_sub(self.max_contexts);
+        self.matmuls.drain(..evicted);
+        self
+    }
+
+    /// Number of matmuls kept, one per recently used batch size.
+    pub fn contexts(&self) -> usize {
+        self.matmuls.len()
+    }
+
+    /// Destroy the matmuls kept, they are created again when needed.
+    pub fn clear_contexts(&mut self) {
+        self.matmuls.clear();
+    }
+
+    /// Matmul of a batch size, created with the weights on first use.
+    fn matmul(&mut self, batch: usize) -> Result<&mut Matmul<f16, f32>> {
+        let matmul = match self.matmuls.iter().position(|(it, _)| *it == batch) {
+            Some(index) => self.matmuls.remove(index).1,
+            None => {
+                if self.matmuls.len() >= self.max_contexts {
+                    self.matmuls.remove(0);
+                }
+                let (k, n) = padded(self.in_features, self.out_features);
+                let mut matmul = Matmul::with_driver(batch, k, n, Arc::clone(&self.driver))?;
+                matmul.set_b(&self.weight)?;
+                matmul
+            }
+        };
+        self.matmuls.push((batch, matmul));
+        Ok(&mut self.matmuls.last_mut().unwrap().1)
+    }
+}
+
+impl Layer for Linear {
+    fn forward(&mut self, input: &[f32], batch: usize) -> Result<Vec<f32>> {
+        features(input, batch, Some(self.in_features))?;
+        let (k, n) = padded(self.in_features, self.out_features);
+        let mut a = vec![f16::ZERO; batch * k];
+        for (row, values) in input.chunks(self.in_features).enumerate() {
+            for (dst, value) in a[row * k..].iter_mut().zip(values) {
+                *dst = f16::from_f32(*value);
+            }
+        }
+
+        let mut c = vec![0.0; batch * n];
+        let matmul = self.matmul(batch)?;
+        matmul.set_a(&a)?;
+        matmul.exec()?;
+        matmul.get_output(&mut c)?;
+
+        let mut output = Vec::with_capacity(batch * self.out_features);
+        for row in c.chunks(n) {
+            let row = &row[..self.out_features];
+            match &self.bias {
+                Some(bias) => output.extend(row.iter().zip(bias).map(|(x, b)| x + b)),
+                None => output.extend_from_slice(row),
+            }
+        }
+        Ok(output)
+    }
+}
+
+/// Input and output features padded to the block sizes of a float16 B matrix.
+fn padded(in_features: usize, out_features: usize) -> (usize, usize) {
+    let layout = NativeLayout::new(&<f16 as MatmulTypes<f32>>::MM_TYPE);
+    (
+        in_features.next_multiple_of(layout.b_sub_k),
+        out_features.next_multiple_of(layout.b_sub_n),
+    )
+}
+
+fn check_shape(name: &str, expected: &[usize], len: usize) -> Result<(), RknnNnError> {
+    if expected.iter().product::<usize>() != len {
+        return Err(RknnNnError::ShapeMismatch {
+            name: name.to_string(),
+            expected: expected.to_vec(),
+            actual: vec![len],
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(super) mod test {
+    use std::sync::Arc;
+
+    use crate::{
+        driver::stub::{StubCall, StubDriver},
+        error::RknnNnError,
+        nn::{Layer, Tensor, Weights},
+    };
+
+    use super::Linear;
+
+    /// `x W^T + b` computed on the CPU.
+    pub(crate) fn reference(
+        input: &[f32],
+        weight: &[f32],
+        bias: Option<&[f32]>,
+        batch: usize,
+        in_features: usize,
+        out_features: usize,
+    ) -> Vec<f32> {
+        (0..batch * out_features)
+            .map(|idx| {
+                let (row, out) = (idx / out_features, idx % out_features);
+                let sum = (0..in_features)
+                    .map(|it| input[row * in_features + it] * weight[out * in_features + it])
+                    .sum::<f32>();
+                sum + bias.map_or(0.0, |bias| bias[out])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_linear() {
+        let (inputs, outputs) = (50, 20);
+        let weight = (0..outputs * inputs)
+            .map(|it| ((it % 9) as f32 - 4.0) / 8.0)
+            .collect::<Vec<_>>();
+        let bias = (0..outputs).map(|it| it as f32).collect::<Vec<_>>();
+        let driver = Arc::new(StubDriver::matmul());
+        let mut linear =
+            Linear::with_driver(&weight, Some(&bias), inputs, outputs, driver.clone()).unwrap();
+
+        for batch in [1, 4, 1] {
+            let input = (0..batch * inputs)
+                .map(|it| ((it % 11) as f32 - 5.0) / 4.0)
+                .collect::<Vec<_>>();
+            let output = linear.forward(&input, batch).unwrap();
+            let expected = reference(&input, &weight, Some(&bias), batch, inputs, outputs);
+            assert_eq!(output, expected);
+        }
+        // One matmul per batch size, the weights are only uploaded when it is created.
+        assert_eq!(linear.contexts(), 2);
+        assert_eq!(driver.call_count(StubCall::MatmulCreate), 2);
+        assert_eq!(driver.call_count(StubCall::MatmulSetIoMem), 6);
+
+        // Batch sizes beyond the limit evict the least recently used matmul.
+        let mut linear = linear.with_max_contexts(2);
+        for batch in [2, 1, 3] {
+            linear.forward(&vec![0.0; batch * inputs], batch).unwrap();
+        }
+        assert_eq!(linear.contexts(), 2);
+        assert_eq!(driver.call_count(StubCall::MatmulCreate), 4);
+        assert_eq!(driver.call_count(StubCall::MatmulDestroy), 2);
+        assert_eq!(driver.live_matmuls(), 2);
+        linear.clear_contexts();
+        assert_eq!(driver.live_matmuls(), 0);
+
+        let error = linear.forward(&[0.0; 49], 1).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<RknnNnError>(),
+            Some(&RknnNnError::InputMismatch {
+                expected: 50,
+                actual: 49,
+                batch: 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_linear_from_weights() {
+        let mut weights = Weights::new();
+        weights.insert("fc.weight", Tensor::new(vec![2, 3], vec![1.0; 6]));
+        weights.insert("fc.bias", Tensor::new(vec![3], vec![0.0; 3]));
+        weights.insert("head.weight", Tensor::new(vec![2, 3], vec![1.0; 6]));
+        let driver = Arc::new(StubDriver::matmul());
+
+        let error = Linear::from_weights_with_driver(&weights, "fc", driver.clone())
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.downcast_ref::<RknnNnError>(),
+            Some(&RknnNnError::ShapeMismatch {
+                name: "fc.bias".to_string(),
+                expected: vec![2],
+                actual: vec![3]
+            })
+        );
+
+        let mut head = Linear::from_weights_with_driver(&weights, "head", driver.clone()).unwrap();
+        assert_eq!((head.in_features(), head.out_features()), (3, 2));
+        assert_eq!(head.forward(&[1.0, 2.0, 3.0], 1).unwrap(), vec![6.0, 6.0]);
+
+        let error = Linear::from_weights_with_driver(&weights, "missing", driver)
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.downcast_ref::<RknnNnError>(),
+            Some(&RknnNnError::MissingTensor {
+                name: "missing.weight".to_string()
+            })
+        );
+    }
+}
@@ -0,0 +1,137 @@
+//! Small dense networks run with matmuls, without converting them to a RKNN model.
+//!
+//! [`Linear`] layers keep their weights on the NPU, the bias and the [`Activation`]s are computed
+//! on the CPU, and layers are chained with [`Sequential`]. Transformer encoders are built from
+//! [`MultiHeadAttention`], [`FeedForward`] and [`LayerNorm`] layers, and [`Conv2d`] lowers
+//! convolutions to matmuls. Weights are loaded from safetensors or numpy npz files with
+//! [`Weights`], behind the `safetensors` and `npz` features.
+
+use anyhow::Result;
+
+use crate::error::RknnNnError;
+
+pub use self::{
+    activation::Activation,
+    conv::{Conv2d, Conv2dParams, ConvPrecision},
+    linear::Linear,
+    norm::LayerNorm,
+    transformer::{EncoderLayer, FeedForward, MultiHeadAttention},
+    weights::{Tensor, Weights},
+};
+
+mod activation;
+mod conv;
+mod linear;
+mod norm;
+mod transformer;
+mod weights;
+
+/// A layer computing a batch of rows at once.
+pub trait Layer: Send {
+    /// Compute the outputs of `batch` rows, `input` holding the row major input features.
+    fn forward(&mut self, input: &[f32], batch: usize) -> Result<Vec<f32>>;
+}
+
+/// Layers run one after the other, each one computing the inputs of the next one.
+#[derive(Default)]
+pub struct Sequential {
+    layers: Vec<Box<dyn Layer>>,
+}
+
+impl Sequential {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a layer.
+    pub fn with(mut self, layer: impl Layer + 'static) -> Self {
+        self.push(layer);
+        self
+    }
+
+    /// Append a layer.
+    pub fn push(&mut self, layer: impl Layer + 'static) {
+        self.layers.push(Box::new(layer));
+    }
+
+    pub fn len(&self) -> usize {
+        self.layers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+}
+
+impl Layer for Sequential {
+    fn forward(&mut self, input: &[f32], batch: usize) -> Result<Vec<f32>> {
+        let mut values = input.to_vec();
+        for layer in &mut self.layers {
+            values = layer.forward(&values, batch)?;
+        }
+        Ok(values)
+    }
+}
+
+/// Number of features per row of `input`, which must hold `batch` rows of `expected` features
+/// when given.
+fn features(input: &[f32], batch: usize, expected: Option<usize>) -> Result<usize, RknnNnError> {
+    let error = |expected| RknnNnError::InputMismatch {
+        expected,
+        actual: input.len(),
+        batch,
+    };
+    match expected {
+        Some(expected) if input.len() != batch * expected => Err(error(expected)),
+        Some(expected) => Ok(expected),
+        None if batch == 0 || !input.len().is_multiple_of(batch) => {
+            Err(error(input.len() / batch.max(1)))
+        }
+        None => Ok(input.len() / batch),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::driver::stub::StubDriver;
+
+    use super::{linear::test::reference, Activation, Layer, Linear, Sequential};
+
+    #[test]
+    fn test_sequential() {
+        let (inputs, hidden, outputs, batch) = (20, 40, 5, 3);
+        let w1 = (0..hidden * inputs)
+            .map(|it| ((it % 9) as f32 - 4.0) / 8.0)
+            .collect::<Vec<_>>();
+        let b1 = (0..hidden).map(|it| it as f32 / 10.0).collect::<Vec<_>>();
+        let w2 = (0..outputs * hidden)
+            .map(|it| ((it % 7) as f32 - 3.0) / 4.0)
+            .collect::<Vec<_>>();
+        let input = (0..batch * inputs)
+            .map(|it| ((it % 11) as f32 - 5.0) / 4.0)
+            .collect::<Vec<_>>();
+
+        let driver = Arc::new(StubDriver::matmul());
+        let mut mlp = Sequential::new()
+            .with(Linear::with_driver(&w1, Some(&b1), inputs, hidden, driver.clone()).unwrap())
+            .with(Activation::Relu)
+            .with(Linear::with_driver(&w2, None, hidden, outputs, driver.clone()).unwrap())
+            .with(Activation::Softmax);
+        assert_eq!(mlp.len(), 4);
+        let output = mlp.forward(&input, batch).unwrap();
+
+        let mut expected = reference(&input, &w1, Some(&b1), batch, inputs, hidden);
+        Activation::Relu.apply(&mut expected, hidden);
+        let mut expected = reference(&expected, &w2, None, batch, hidden, outputs);
+        Activation::Softmax.apply(&mut expected, outputs);
+        assert_eq!(output.len(), batch * outputs);
+        for (output, expected) in output.iter().zip(expected) {
+            assert!((output - expected).abs() < 1e-3, "{output} != {expected}");
+        }
+        for row in output.chunks(outputs) {
+            assert!((row.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+        }
+    }
+}
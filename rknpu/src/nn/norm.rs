@@ -0,0 +1,90 @@
+use anyhow::Result;
+
+use crate::error::RknnNnError;
+
+use super::{features, Layer, Weights};
+
+/// Layer normalization over the features of each row, computed on the CPU.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayerNorm {
+    weight: Vec<f32>,
+    bias: Vec<f32>,
+    eps: f32,
+}
+
+impl LayerNorm {
+    /// Epsilon used by default, as in PyTorch.
+    pub const DEFAULT_EPS: f32 = 1e-5;
+
+    /// Create a normalization scaling the rows by `weight` and shifting them by `bias`.
+    pub fn new(weight: Vec<f32>, bias: Vec<f32>) -> Result<Self> {
+        if weight.len() != bias.len() {
+            return Err(RknnNnError::ShapeMismatch {
+                name: "bias".to_string(),
+                expected: vec![weight.len()],
+                actual: vec![bias.len()],
+            }
+            .into());
+        }
+        Ok(Self {
+            weight,
+            bias,
+            eps: Self::DEFAULT_EPS,
+        })
+    }
+
+    /// Load the `{name}.weight` and `{name}.bias` tensors.
+    pub fn from_weights(weights: &Weights, name: &str) -> Result<Self> {
+        let weight = weights.get(&format!("{name}.weight"))?;
+        let bias = weights.get(&format!("{name}.bias"))?;
+        Self::new(weight.values.clone(), bias.values.clone())
+    }
+
+    pub fn with_eps(mut self, eps: f32) -> Self {
+        self.eps = eps;
+        self
+    }
+
+    pub fn features(&self) -> usize {
+        self.weight.len()
+    }
+}
+
+impl Layer for LayerNorm {
+    fn forward(&mut self, input: &[f32], batch: usize) -> Result<Vec<f32>> {
+        let features = features(input, batch, Some(self.features()))?;
+        let mut output = Vec::with_capacity(input.len());
+        for row in input.chunks(features) {
+            let mean = row.iter().sum::<f32>() / features as f32;
+            let var = row.iter().map(|it| (it - mean).powi(2)).sum::<f32>() / features as f32;
+            let inv_std = 1.0 / (var + self.eps).sqrt();
+            output.extend(
+                row.iter()
+                    .zip(self.weight.iter().zip(&self.bias))
+                    .map(|(x, (w, b))| (x - mean) * inv_std * w + b),
+            );
+        }
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::nn::Layer;
+
+    use super::LayerNorm;
+
+    #[test]
+    fn test_layer_norm() {
+        let mut norm = LayerNorm::new(vec![1.0, 1.0, 2.0, 2.0], vec![0.0, 0.0, 0.0, 1.0]).unwrap();
+        let output = norm
+            .forward(&[1.0, 2.0, 3.0, 4.0, 5.0, 5.0, 5.0, 5.0], 2)
+            .unwrap();
+        // Mean 2.5 and variance 1.25 for the first row, a constant second row.
+        let expected = [-1.34164, -0.44721, 0.89443, 3.68328, 0.0, 0.0, 0.0, 1.0];
+        for (output, expected) in output.iter().zip(expected) {
+            assert!((output - expected).abs() < 1e-3, "{output} != {expected}");
+        }
+        assert!(LayerNorm::new(vec![1.0; 4], vec![0.0; 3]).is_err());
+    }
+}
@@ -0,0 +1,404 @@
+//! Encoder layers of transformers, with the projections and the attention products run on the
+//! NPU.
+
+use std::sync::Arc;
+
+use anyhow::{ensure, Result};
+use half::f16;
+
+use crate::{
+    driver::{NativeDriver, RknnDriver},
+    matmul::{RknnMatmulShape, TiledMatmul},
+};
+
+use super::{features, Activation, Layer, LayerNorm, Linear, Weights};
+
+/// Multi-head self attention over the tokens of a sequence, given as the rows of the input.
+///
+/// The Q, K, V and output projections are [`Linear`] layers. The `Q x K^T` and `P x V` products
+/// of all heads are computed head by head with the same matmul, created for each sequence length,
+/// so that memory and NPU work grow with the number of heads rather than with its square. The
+/// softmax is computed on the CPU.
+pub struct MultiHeadAttention {
+    heads: usize,
+    q: Linear,
+    k: Linear,
+    v: Linear,
+    out: Linear,
+    products: TiledMatmul<f16, f32>,
+}
+
+impl MultiHeadAttention {
+    /// Create an attention from its projections, which must all map `d_model` features to
+    /// `d_model` features, `d_model` being a multiple of `heads`.
+    pub fn new(heads: usize, q: Linear, k: Linear, v: Linear, out: Linear) -> Result<Self> {
+        Self::with_driver(heads, q, k, v, out, Arc::new(NativeDriver))
+    }
+
+    /// Create an attention running the attention products through the given driver.
+    pub fn with_driver(
+        heads: usize,
+        q: Linear,
+        k: Linear,
+        v: Linear,
+        out: Linear,
+        driver: Arc<dyn RknnDriver>,
+    ) -> Result<Self> {
+        let d_model = q.in_features();
+        for linear in [&q, &k, &v, &out] {
+            ensure!(
+                (linear.in_features(), linear.out_features()) == (d_model, d_model),
+                "Attention projections must map {d_model} to {d_model} features, got {} to {}",
+                linear.in_features(),
+                linear.out_features()
+            );
+        }
+        ensure!(
+            heads > 0 && d_model.is_multiple_of(heads),
+            "{d_model} features can't be split in {heads} heads"
+        );
+        Ok(Self {
+            heads,
+            q,
+            k,
+            v,
+            out,
+            products: TiledMatmul::with_driver(driver),
+        })
+    }
+
+    /// Load the `{name}.q_proj`, `{name}.k_proj`, `{name}.v_proj` and `{name}.out_proj` layers.
+    pub fn from_weights(weights: &Weights, name: &str, heads: usize) -> Result<Self> {
+        Self::from_weights_with_driver(weights, name, heads, Arc::new(NativeDriver))
+    }
+
+    /// Load an attention running its matmuls through the given driver.
+    pub fn from_weights_with_driver(
+        weights: &Weights,
+        name: &str,
+        heads: usize,
+        driver: Arc<dyn RknnDriver>,
+    ) -> Result<Self> {
+        let linear = |proj: &str| {
+            Linear::from_weights_with_driver(weights, &format!("{name}.{proj}"), driver.clone())
+        };
+        Self::with_driver(
+            heads,
+            linear("q_proj")?,
+            linear("k_proj")?,
+            linear("v_proj")?,
+            linear("out_proj")?,
+            driver.clone(),
+        )
+    }
+
+    pub fn heads(&self) -> usize {
+        self.heads
+    }
+
+    pub fn d_model(&self) -> usize {
+        self.q.in_features()
+    }
+}
+
+impl Layer for MultiHeadAttention {
+    fn forward(&mut self, input: &[f32], seq: usize) -> Result<Vec<f32>> {
+        let d_model = features(input, seq, Some(self.d_model()))?;
+        let d_head = d_model / self.heads;
+        let q = self.q.forward(input, seq)?;
+        let k = self.k.forward(input, seq)?;
+        let v = self.v.forward(input, seq)?;
+
+        // Values of a head, as a `seq x d_head` matrix or its transpose.
+        let head = |values: &[f32], head: usize, scale: f32| -> Vec<f16> {
+            let columns = head * d_head..(head + 1) * d_head;
+            values
+                .chunks(d_model)
+                .flat_map(|row| row[columns.clone()].iter())
+                .map(|it| f16::from_f32(it * scale))
+                .collect()
+        };
+        let transposed = |values: &[f32], head: usize| -> Vec<f16> {
+            (0..d_head * seq)
+                .map(|idx| f16::from_f32(values[(idx % seq) * d_model + head * d_head + idx / seq]))
+                .collect()
+        };
+
+        let scale = 1.0 / (d_head as f32).sqrt();
+        let mut scores = vec![0.0; seq * seq];
+        let mut head_output = vec![0.0; seq * d_head];
+        let mut output = vec![0.0; seq * d_model];
+        for h in 0..self.heads {
+            let shape = RknnMatmulShape::new(seq, d_head, seq);
+            self.products
+                .run(shape, &head(&q, h, scale), &transposed(&k, h), &mut scores)?;
+            Activation::Softmax.apply(&mut scores, seq);
+
+            let probs = scores
+                .iter()
+                .map(|it| f16::from_f32(*it))
+                .collect::<Vec<_>>();
+            let shape = RknnMatmulShape::new(seq, seq, d_head);
+            self.products
+                .run(shape, &probs, &head(&v, h, 1.0), &mut head_output)?;
+            for (row, values) in head_output.chunks(d_head).enumerate() {
+                output[row * d_model + h * d_head..][..d_head].copy_from_slice(values);
+            }
+        }
+        self.out.forward(&output, seq)
+    }
+}
+
+/// Position-wise feed-forward network `down(activation(up(x)))`.
+pub struct FeedForward {
+    up: Linear,
+    activation: Activation,
+    down: Linear,
+}
+
+impl FeedForward {
+    pub fn new(up: Linear, activation: Activation, down: Linear) -> Result<Self> {
+        ensure!(
+            up.out_features() == down.in_features() && down.out_features() == up.in_features(),
+            "Feed-forward layers of {}x{} and {}x{} features don't match",
+            up.in_features(),
+            up.out_features(),
+            down.in_features(),
+            down.out_features()
+        );
+        Ok(Self {
+            up,
+            activation,
+            down,
+        })
+    }
+}
+
+impl Layer for FeedForward {
+    fn forward(&mut self, input: &[f32], batch: usize) -> Result<Vec<f32>> {
+        let mut hidden = self.up.forward(input, batch)?;
+        self.activation.apply(&mut hidden, self.up.out_features());
+        self.down.forward(&hidden, batch)
+    }
+}
+
+/// Transformer encoder layer: self attention and feed-forward network, each one with a residual
+/// connection and a layer normalization.
+///
+/// The normalizations follow the residual connections, as in BERT, unless
+/// [`with_norm_first`](Self::with_norm_first) moves them before the attention and the
+/// feed-forward network.
+pub struct EncoderLayer {
+    attention: MultiHeadAttention,
+    attention_norm: LayerNorm,
+    feed_forward: FeedForward,
+    feed_forward_norm: LayerNorm,
+    norm_first: bool,
+}
+
+impl EncoderLayer {
+    pub fn new(
+        attention: MultiHeadAttention,
+        attention_norm: LayerNorm,
+        feed_forward: FeedForward,
+        feed_forward_norm: LayerNorm,
+    ) -> Self {
+        Self {
+            attention,
+            attention_norm,
+            feed_forward,
+            feed_forward_norm,
+            norm_first: false,
+        }
+    }
+
+    pub fn with_norm_first(mut self, norm_first: bool) -> Self {
+        self.norm_first = norm_first;
+        self
+    }
+}
+
+impl Layer for EncoderLayer {
+    fn forward(&mut self, input: &[f32], seq: usize) -> Result<Vec<f32>> {
+        let add = |mut x: Vec<f32>, y: Vec<f32>| {
+            x.iter_mut().zip(y).for_each(|(x, y)| *x += y);
+            x
+        };
+        if self.norm_first {
+            let normed = self.attention_norm.forward(input, seq)?;
+            let x = add(input.to_vec(), self.attention.forward(&normed, seq)?);
+            let normed = self.feed_forward_norm.forward(&x, seq)?;
+            let y = self.feed_forward.forward(&normed, seq)?;
+            Ok(add(x, y))
+        } else {
+            let attended = self.attention.forward(input, seq)?;
+            let x = self
+                .attention_norm
+                .forward(&add(input.to_vec(), attended), seq)?;
+            let y = self.feed_forward.forward(&x, seq)?;
+            self.feed_forward_norm.forward(&add(x, y), seq)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::{
+        driver::stub::{StubCall, StubDriver},
+        nn::{linear::test::reference, Activation, Layer, LayerNorm, Linear, Tensor, Weights},
+    };
+
+    use super::{EncoderLayer, FeedForward, MultiHeadAttention};
+
+    const D_MODEL: usize = 32;
+    const HEADS: usize = 4;
+    const D_FF: usize = 64;
+    const SEQ: usize = 7;
+
+    fn values(len: usize, seed: f32, scale: f32) -> Vec<f32> {
+        (0..len)
+            .map(|it| (it as f32 * seed).sin() * scale)
+            .collect()
+    }
+
+    fn weights() -> Weights {
+        let mut weights = Weights::new();
+        let projections = [("q_proj", 0.31), ("k_proj", 0.47), ("v_proj", 0.53)];
+        for (index, (name, seed)) in projections
+            .into_iter()
+            .chain([("out_proj", 0.61)])
+            .enumerate()
+        {
+            let weight = values(D_MODEL * D_MODEL, seed, 0.3);
+            let bias = values(D_MODEL, seed + index as f32, 0.1);
+            weights.insert(
+                format!("attn.{name}.weight"),
+                Tensor::new(vec![D_MODEL, D_MODEL], weight),
+            );
+            weights.insert(
+                format!("attn.{name}.bias"),
+                Tensor::new(vec![D_MODEL], bias),
+            );
+        }
+        weights
+    }
+
+    /// Attention computed on the CPU in f32.
+    fn attention_reference(weights: &Weights, input: &[f32]) -> Vec<f32> {
+        let linear = |name: &str, input: &[f32]| {
+            let weight = weights.get(&format!("attn.{name}.weight")).unwrap();
+            let bias = weights.get(&format!("attn.{name}.bias")).unwrap();
+            reference(
+                input,
+                &weight.values,
+                Some(&bias.values),
+                SEQ,
+                D_MODEL,
+                D_MODEL,
+            )
+        };
+        let (q, k, v) = (
+            linear("q_proj", input),
+            linear("k_proj", input),
+            linear("v_proj", input),
+        );
+        let d_head = D_MODEL / HEADS;
+        let mut output = vec![0.0; SEQ * D_MODEL];
+        for h in 0..HEADS {
+            let at =
+                |values: &[f32], row: usize, col: usize| values[row * D_MODEL + h * d_head + col];
+            for i in 0..SEQ {
+                let mut scores = (0..SEQ)
+                    .map(|j| {
+                        let dot = (0..d_head)
+                            .map(|c| at(&q, i, c) * at(&k, j, c))
+                            .sum::<f32>();
+                        dot / (d_head as f32).sqrt()
+                    })
+                    .collect::<Vec<_>>();
+                Activation::Softmax.apply(&mut scores, SEQ);
+                for c in 0..d_head {
+                    output[i * D_MODEL + h * d_head + c] =
+                        (0..SEQ).map(|j| scores[j] * at(&v, j, c)).sum();
+                }
+            }
+        }
+        linear("out_proj", &output)
+    }
+
+    fn assert_close(actual: &[f32], expected: &[f32], tolerance: f32) {
+        assert_eq!(actual.len(), expected.len());
+        let error = actual
+            .iter()
+            .zip(expected)
+            .fold(0.0_f32, |error, (a, e)| error.max((a - e).abs()));
+        assert!(error < tolerance, "max error {error}");
+    }
+
+    #[test]
+    fn test_attention() {
+        let weights = weights();
+        let driver = Arc::new(StubDriver::matmul());
+        let mut attention =
+            MultiHeadAttention::from_weights_with_driver(&weights, "attn", HEADS, driver.clone())
+                .unwrap();
+        assert_eq!((attention.heads(), attention.d_model()), (HEADS, D_MODEL));
+
+        let input = values(SEQ * D_MODEL, 0.23, 1.0);
+        let output = attention.forward(&input, SEQ).unwrap();
+        assert_close(&output, &attention_reference(&weights, &input), 1e-2);
+        // Both products of all heads are padded to the same shape, and share one matmul: one run
+        // per projection, and two per head.
+        assert_eq!(attention.products.contexts(), 1);
+        let runs = driver.call_count(StubCall::MatmulRun);
+        attention.forward(&input, SEQ).unwrap();
+        assert_eq!(driver.call_count(StubCall::MatmulRun) - runs, 4 + 2 * HEADS);
+    }
+
+    #[test]
+    fn test_attention_heads() {
+        let weights = weights();
+        let driver = Arc::new(StubDriver::matmul());
+        assert!(MultiHeadAttention::from_weights_with_driver(&weights, "attn", 5, driver).is_err());
+    }
+
+    #[test]
+    fn test_encoder_layer() {
+        let weights = weights();
+        let driver = Arc::new(StubDriver::matmul());
+        let attention =
+            MultiHeadAttention::from_weights_with_driver(&weights, "attn", HEADS, driver.clone())
+                .unwrap();
+        let (w1, b1) = (values(D_FF * D_MODEL, 0.71, 0.3), values(D_FF, 0.13, 0.1));
+        let (w2, b2) = (
+            values(D_MODEL * D_FF, 0.83, 0.2),
+            values(D_MODEL, 0.17, 0.1),
+        );
+        let up = Linear::with_driver(&w1, Some(&b1), D_MODEL, D_FF, driver.clone()).unwrap();
+        let down = Linear::with_driver(&w2, Some(&b2), D_FF, D_MODEL, driver.clone()).unwrap();
+        let feed_forward = FeedForward::new(up, Activation::Gelu, down).unwrap();
+        let norm = || LayerNorm::new(values(D_MODEL, 0.29, 1.0), values(D_MODEL, 0.37, 0.5));
+        let mut layer =
+            EncoderLayer::new(attention, norm().unwrap(), feed_forward, norm().unwrap());
+
+        let input = values(SEQ * D_MODEL, 0.23, 1.0);
+        let output = layer.forward(&input, SEQ).unwrap();
+
+        let mut norm = norm().unwrap();
+        let attended = attention_reference(&weights, &input);
+        let x = input
+            .iter()
+            .zip(attended)
+            .map(|(x, y)| x + y)
+            .collect::<Vec<_>>();
+        let x = norm.forward(&x, SEQ).unwrap();
+        let mut hidden = reference(&x, &w1, Some(&b1), SEQ, D_MODEL, D_FF);
+        Activation::Gelu.apply(&mut hidden, D_FF);
+        let y = reference(&hidden, &w2, Some(&b2), SEQ, D_FF, D_MODEL);
+        let x = x.iter().zip(y).map(|(x, y)| x + y).collect::<Vec<_>>();
+        let expected = norm.forward(&x, SEQ).unwrap();
+        assert_close(&output, &expected, 5e-2);
+    }
+}
@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+#[cfg(any(feature = "safetensors", feature = "npz"))]
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::error::RknnNnError;
+
+/// A row major f32 tensor.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tensor {
+    pub shape: Vec<usize>,
+    pub values: Vec<f32>,
+}
+
+impl Tensor {
+    pub fn new(shape: Vec<usize>, values: Vec<f32>) -> Self {
+        Self { shape, values }
+    }
+}
+
+/// Named tensors of a weight file. Float16, bfloat16 and float64 tensors are converted to f32.
+#[derive(Debug, Clone, Default)]
+pub struct Weights {
+    tensors: HashMap<String, Tensor>,
+}
+
+impl Weights {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, name: impl Into<String>, tensor: Tensor) {
+        self.tensors.insert(name.into(), tensor);
+    }
+
+    pub fn get(&self, name: &str) -> Result<&Tensor, RknnNnError> {
+        self.tensors
+            .get(name)
+            .ok_or_else(|| RknnNnError::MissingTensor {
+                name: name.to_string(),
+            })
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.tensors.keys().map(String::as_str)
+    }
+
+    /// Load the tensors of a safetensors file.
+    #[cfg(feature = "safetensors")]
+    pub fn from_safetensors(path: impl AsRef<Path>) -> Result<Self> {
+        use half::{bf16, f16};
+        use safetensors::{Dtype, SafeTensors};
+
+        let bytes = std::fs::read(path)?;
+        let file = SafeTensors::deserialize(&bytes)?;
+        let mut weights = Self::new();
+        for (name, view) in file.tensors() {
+            let data = view.data();
+            let values = match view.dtype() {
+                Dtype::F32 => from_le_bytes(data, f32::from_le_bytes),
+                Dtype::F16 => from_le_bytes(data, |it| f16::from_le_bytes(it).to_f32()),
+                Dtype::BF16 => from_le_bytes(data, |it| bf16::from_le_bytes(it).to_f32()),
+                Dtype::F64 => from_le_bytes(data, |it| f64::from_le_bytes(it) as f32),
+                dtype => {
+                    return Err(RknnNnError::UnsupportedDtype {
+                        name,
+                        dtype: format!("{dtype:?}"),
+                    }
+                    .into())
+                }
+            };
+            weights.insert(name, Tensor::new(view.shape().to_vec(), values));
+        }
+        Ok(weights)
+    }
+
+    /// Load the arrays of a numpy npz file, saved in C order.
+    #[cfg(feature = "npz")]
+    pub fn from_npz(path: impl AsRef<Path>) -> Result<Self> {
+        use anyhow::anyhow;
+        use npyz::{npz::NpzArchive, DType, Order, TypeChar};
+
+        let mut archive = NpzArchive::open(path)?;
+        let names = archive.array_names().map(String::from).collect::<Vec<_>>();
+        let mut weights = Self::new();
+        for name in names {
+            let array = archive
+                .by_name(&name)?
+                .ok_or_else(|| anyhow!("Array '{name}' vanished from the npz file"))?;
+            let dtype = array.dtype();
+            let unsupported = || RknnNnError::UnsupportedDtype {
+                name: name.clone(),
+                dtype: dtype.descr(),
+            };
+            if array.order() != Order::C {
+                return Err(anyhow!("Array '{name}' is not stored in C order"));
+            }
+            let shape = array.shape().iter().map(|it| *it as usize).collect();
+            let values = match &dtype {
+                DType::Plain(ty) if ty.type_char() == TypeChar::Float => match ty.size_field() {
+                    4 => array.into_vec::<f32>()?,
+                    8 => array
+                        .into_vec::<f64>()?
+                        .into_iter()
+                        .map(|it| it as f32)
+                        .collect(),
+                    _ => return Err(unsupported().into()),
+                },
+                _ => return Err(unsupported().into()),
+            };
+            weights.insert(name, Tensor::new(shape, values));
+        }
+        Ok(weights)
+    }
+}
+
+#[cfg(feature = "safetensors")]
+fn from_le_bytes<const N: usize>(data: &[u8], convert: impl Fn([u8; N]) -> f32) -> Vec<f32> {
+    data.chunks_exact(N)
+        .map(|it| convert(it.try_into().unwrap()))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::error::RknnNnError;
+
+    use super::{Tensor, Weights};
+
+    #[test]
+    fn test_missing_tensor() {
+        let mut weights = Weights::new();
+        weights.insert("fc.weight", Tensor::new(vec![1, 2], vec![1.0, 2.0]));
+        assert_eq!(weights.names().collect::<Vec<_>>(), ["fc.weight"]);
+        assert_eq!(weights.get("fc.weight").unwrap().values, [1.0, 2.0]);
+        assert_eq!(
+            weights.get("fc.bias"),
+            Err(RknnNnError::MissingTensor {
+                name: "fc.bias".to_string()
+            })
+        );
+    }
+
+    #[cfg(feature = "safetensors")]
+    #[test]
+    fn test_safetensors() {
+        use half::f16;
+        use safetensors::{serialize, tensor::TensorView, Dtype};
+
+        let weight = [1.0_f32, -2.0, 0.5, 4.0, 5.0, 6.0]
+            .iter()
+            .flat_map(|it| it.to_le_bytes())
+            .collect::<Vec<_>>();
+        let bias = [0.25_f32, -1.0]
+            .iter()
+            .flat_map(|it| f16::from_f32(*it).to_le_bytes())
+            .collect::<Vec<_>>();
+        let tensors = [
+            (
+                "fc.weight",
+                TensorView::new(Dtype::F32, vec![2, 3], &weight).unwrap(),
+            ),
+            (
+                "fc.bias",
+                TensorView::new(Dtype::F16, vec![2], &bias).unwrap(),
+            ),
+        ];
+        let path = std::env::temp_dir().join(format!("rknpu-{}.safetensors", std::process::id()));
+        std::fs::write(&path, serialize(tensors, &None).unwrap()).unwrap();
+
+        let weights = Weights::from_safetensors(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(
+            weights.get("fc.weight").unwrap(),
+            &Tensor::new(vec![2, 3], vec![1.0, -2.0, 0.5, 4.0, 5.0, 6.0])
+        );
+        assert_eq!(
+            weights.get("fc.bias").unwrap(),
+            &Tensor::new(vec![2], vec![0.25, -1.0])
+        );
+    }
+
+    #[cfg(feature = "npz")]
+    #[test]
+    fn test_npz() {
+        use std::{fs::File, io::BufWriter};
+
+        use npyz::{npz::NpzWriter, AutoSerialize, WriterBuilder};
+
+        fn write<T: AutoSerialize + Copy>(
+            npz: &mut NpzWriter<BufWriter<File>>,
+            name: &str,
+            shape: &[u64],
+            values: &[T],
+        ) {
+            let mut writer = npz
+                .array::<T>(name, Default::default())
+                .unwrap()
+                .default_dtype()
+                .shape(shape)
+                .begin_nd()
+                .unwrap();
+            writer.extend(values.iter().copied()).unwrap();
+            writer.finish().unwrap();
+        }
+
+        let path = std::env::temp_dir().join(format!("rknpu-{}.npz", std::process::id()));
+        let mut npz = NpzWriter::create(&path).unwrap();
+        write::<f32>(
+            &mut npz,
+            "fc.weight",
+            &[2, 3],
+            &[1.0, -2.0, 0.5, 4.0, 5.0, 6.0],
+        );
+        write::<f64>(&mut npz, "fc.bias", &[2], &[0.25, -1.0]);
+        drop(npz);
+        let weights = Weights::from_npz(&path).unwrap();
+        assert_eq!(
+            weights.get("fc.weight").unwrap(),
+            &Tensor::new(vec![2, 3], vec![1.0, -2.0, 0.5, 4.0, 5.0, 6.0])
+        );
+        assert_eq!(
+            weights.get("fc.bias").unwrap(),
+            &Tensor::new(vec![2], vec![0.25, -1.0])
+        );
+
+        let mut npz = NpzWriter::create(&path).unwrap();
+        write::<i32>(&mut npz, "steps", &[1], &[3]);
+        drop(npz);
+        let error = Weights::from_npz(&path).unwrap_err();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(
+            error.downcast_ref::<RknnNnError>(),
+            Some(&RknnNnError::UnsupportedDtype {
+                name: "steps".to_string(),
+                dtype: "'<i4'".to_string()
+            })
+        );
+    }
+}
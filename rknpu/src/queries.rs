@@ -1,6 +1,8 @@
 use anyhow::Result;
 use rknpu_sys::{
-    _rknn_query_cmd_RKNN_QUERY_INPUT_ATTR, _rknn_query_cmd_RKNN_QUERY_IN_OUT_NUM,
+    _rknn_query_cmd_RKNN_QUERY_CUSTOM_STRING, _rknn_query_cmd_RKNN_QUERY_INPUT_ATTR,
+    _rknn_query_cmd_RKNN_QUERY_IN_OUT_NUM, _rknn_query_cmd_RKNN_QUERY_MEM_SIZE,
+    _rknn_query_cmd_RKNN_QUERY_NATIVE_INPUT_ATTR, _rknn_query_cmd_RKNN_QUERY_NATIVE_OUTPUT_ATTR,
     _rknn_query_cmd_RKNN_QUERY_OUTPUT_ATTR, _rknn_query_cmd_RKNN_QUERY_PERF_DETAIL,
     _rknn_query_cmd_RKNN_QUERY_PERF_RUN, _rknn_query_cmd_RKNN_QUERY_SDK_VERSION,
 };
@@ -31,5 +33,13 @@ pub enum RknnQuery {
     RKNN_QUERY_PERF_RUN = _rknn_query_cmd_RKNN_QUERY_PERF_RUN,
     // Query the sdk & driver version
     RKNN_QUERY_SDK_VERSION = _rknn_query_cmd_RKNN_QUERY_SDK_VERSION,
+    // Query the weight & internal memory size.
+    RKNN_QUERY_MEM_SIZE = _rknn_query_cmd_RKNN_QUERY_MEM_SIZE,
+    // Query the custom string set at model conversion.
+    RKNN_QUERY_CUSTOM_STRING = _rknn_query_cmd_RKNN_QUERY_CUSTOM_STRING,
+    // Query the attribute of native input tensor.
+    RKNN_QUERY_NATIVE_INPUT_ATTR = _rknn_query_cmd_RKNN_QUERY_NATIVE_INPUT_ATTR,
+    // Query the attribute of native output tensor.
+    RKNN_QUERY_NATIVE_OUTPUT_ATTR = _rknn_query_cmd_RKNN_QUERY_NATIVE_OUTPUT_ATTR,
     // ...
 }
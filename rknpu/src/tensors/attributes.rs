@@ -7,7 +7,8 @@ use crate::queries::{QueryObject, RknnQuery};
 
 use super::types::{RknnTensorFormat, RknnTensorQuantFormat, RknnTensorType};
 
-#[derive(Debug, PartialEq, PartialOrd)]
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RknnTensorAttribute {
     // Index of the input/output tensor in the model.
     pub index: usize,
@@ -104,3 +105,45 @@ impl QueryObject for RknnOutputTensorAttribute {
         RknnQuery::RKNN_QUERY_OUTPUT_ATTR
     }
 }
+
+pub struct RknnNativeInputTensorAttribute(pub RknnTensorAttribute);
+
+impl QueryObject for RknnNativeInputTensorAttribute {
+    type RknnPrimitiveType = _rknn_tensor_attr;
+
+    fn primitive_init_value() -> Self::RknnPrimitiveType {
+        unsafe { std::mem::zeroed::<_rknn_tensor_attr>() }
+    }
+    fn from_primitive_type(value: Self::RknnPrimitiveType) -> Result<Self>
+    where
+        Self: Sized,
+    {
+        let inner: RknnTensorAttribute = value.try_into()?;
+        Ok(Self(inner))
+    }
+
+    fn query_flag() -> RknnQuery {
+        RknnQuery::RKNN_QUERY_NATIVE_INPUT_ATTR
+    }
+}
+
+pub struct RknnNativeOutputTensorAttribute(pub RknnTensorAttribute);
+
+impl QueryObject for RknnNativeOutputTensorAttribute {
+    type RknnPrimitiveType = _rknn_tensor_attr;
+
+    fn primitive_init_value() -> Self::RknnPrimitiveType {
+        unsafe { std::mem::zeroed::<_rknn_tensor_attr>() }
+    }
+    fn from_primitive_type(value: Self::RknnPrimitiveType) -> Result<Self>
+    where
+        Self: Sized,
+    {
+        let inner: RknnTensorAttribute = value.try_into()?;
+        Ok(Self(inner))
+    }
+
+    fn query_flag() -> RknnQuery {
+        RknnQuery::RKNN_QUERY_NATIVE_OUTPUT_ATTR
+    }
+}
@@ -13,7 +13,8 @@ use rknpu_sys::{
     _rknn_tensor_type_RKNN_TENSOR_UINT8,
 };
 
-#[derive(Debug, PartialEq, PartialOrd)]
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RknnTensorQuantFormat {
     None,
     DynamicFixedPoint(i8),
@@ -49,7 +50,8 @@ impl RknnTensorQuantFormat {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u32)]
 pub enum RknnTensorFormat {
     NCHW = _rknn_tensor_format_RKNN_TENSOR_NCHW,
@@ -59,7 +61,8 @@ pub enum RknnTensorFormat {
     MAX = _rknn_tensor_format_RKNN_TENSOR_FORMAT_MAX,
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u32)]
 pub enum RknnTensorType {
     F32 = _rknn_tensor_type_RKNN_TENSOR_FLOAT32,
@@ -74,3 +77,16 @@ pub enum RknnTensorType {
     BOOL = _rknn_tensor_type_RKNN_TENSOR_BOOL,
     MAX = _rknn_tensor_type_RKNN_TENSOR_TYPE_MAX,
 }
+
+impl RknnTensorType {
+    /// Size in bytes of one element of this type.
+    pub fn size(&self) -> usize {
+        match self {
+            Self::I8 | Self::U8 | Self::BOOL => 1,
+            Self::F16 | Self::I16 | Self::U16 => 2,
+            Self::F32 | Self::I32 | Self::U32 => 4,
+            Self::I64 => 8,
+            Self::MAX => 0,
+        }
+    }
+}